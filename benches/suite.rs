@@ -201,3 +201,37 @@ fn bench_ns2_rw(b: &mut Bencher) {
         e.write(&mut buf).unwrap();
     });
 }
+
+fn _large_document() -> String {
+    let mut data = String::from("<records>");
+    for i in 0..10_000 {
+        data.push_str(&format!(
+            "<record id=\"{}\"><name>record &amp; {}</name><value>{}</value></record>",
+            i,
+            i,
+            i * 7
+        ));
+    }
+    data.push_str("</records>");
+    data
+}
+
+fn _parse_large(b: &mut Bencher, backend: xmltree::Backend) {
+    let data = _large_document();
+    b.iter(|| {
+        let options = xmltree::ParseOptions::new().backend(backend);
+        let e = Element::parse_with_options(data.as_bytes(), Default::default(), options).unwrap();
+        assert_eq!(e.children.len(), 10_000);
+    });
+}
+
+#[bench]
+fn bench_large_xml_rs(b: &mut Bencher) {
+    _parse_large(b, xmltree::Backend::XmlRs);
+}
+
+#[cfg(feature = "quick-xml")]
+#[bench]
+fn bench_large_quick_xml(b: &mut Bencher) {
+    _parse_large(b, xmltree::Backend::QuickXml);
+}
@@ -0,0 +1,72 @@
+//! Reformats XML files in place with `Document::write_formatted`.
+//!
+//! ```text
+//! cargo run --example xmlfmt -- [--check] FILE...
+//! ```
+//!
+//! With `--check`, the files are left alone, and the command fails if any of them would change.
+
+use std::env;
+use std::fs::{self, File};
+use std::process;
+
+use xmltree::{Document, FormatOptions, ParseOptions, ParserConfig};
+
+fn main() {
+    let mut check = false;
+    let mut files = Vec::new();
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--check" => check = true,
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        eprintln!("usage: xmlfmt [--check] FILE...");
+        process::exit(2);
+    }
+
+    // Whitespace is kept while parsing, so that mixed content comes out unchanged
+    let config = ParserConfig::new().ignore_comments(false);
+    let parse_options = ParseOptions::new().preserve_whitespace(true);
+    let format_options = FormatOptions::new().wrap_attributes(true);
+
+    let mut failed = false;
+    for path in &files {
+        let result = File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| {
+                Document::parse_with_options(file, config.clone(), parse_options.clone())
+                    .map_err(|e| e.to_string())
+            })
+            .and_then(|doc| {
+                let mut out = Vec::new();
+                doc.write_formatted(&mut out, &format_options)
+                    .map_err(|e| e.to_string())?;
+                Ok(out)
+            });
+        let formatted = match result {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                failed = true;
+                continue;
+            }
+        };
+
+        let unchanged = fs::read(path).is_ok_and(|original| original == formatted);
+        if unchanged {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", path);
+            failed = true;
+        } else if let Err(e) = fs::write(path, formatted) {
+            eprintln!("{}: {}", path, e);
+            failed = true;
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
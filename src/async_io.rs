@@ -0,0 +1,359 @@
+use std::future::poll_fn;
+use std::io::{self, ErrorKind, Read};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::{FusedStream, Stream};
+use futures_io::{AsyncRead, AsyncWrite};
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::limits::Usage;
+use crate::{Element, ElementPredicate, ParseError, ParseOptions, ParserConfig, TreeBuilder};
+
+/// Reads `r` until the end
+pub(crate) async fn read_to_end<R: AsyncRead + Unpin>(mut r: R) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut chunk = [0; 8192];
+    loop {
+        match poll_fn(|cx| Pin::new(&mut r).poll_read(cx, &mut chunk)).await {
+            Ok(0) => return Ok(data),
+            Ok(n) => data.extend_from_slice(&chunk[..n]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Writes all of `data` to `w`, and flushes it
+pub(crate) async fn write_all<W: AsyncWrite + Unpin>(mut w: W, mut data: &[u8]) -> io::Result<()> {
+    while !data.is_empty() {
+        match poll_fn(|cx| Pin::new(&mut w).poll_write(cx, data)).await {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(n) => data = &data[n..],
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    poll_fn(|cx| Pin::new(&mut w).poll_flush(cx)).await
+}
+
+/// A stream of the elements of a document that match a predicate, created by
+/// [`Element::stream_async`].
+///
+/// This is the asynchronous version of [`ElementStream`](crate::ElementStream), and it behaves the
+/// same way: each matching element is built in full and everything else is thrown away,
+/// [`ParseLimits`](crate::ParseLimits) apply to each element returned, and the stream ends after
+/// the first error.
+///
+/// The document is read in chunks as the stream is polled, and only the data that the parser
+/// hasn't consumed yet is buffered.  The parser is only given data up to the end of a tag, comment,
+/// CDATA section or processing instruction, so the input must be in UTF-8 or another
+/// ASCII-compatible encoding.
+///
+/// ```
+/// use futures::executor::block_on;
+/// use futures::stream::TryStreamExt;
+/// use xmltree::Element;
+///
+/// let data = r#"<feed><meta/><record id="1"><v>a</v></record><record id="2"/></feed>"#;
+/// let records: Vec<Element> =
+///     block_on(Element::stream_async(data.as_bytes(), "record").try_collect()).unwrap();
+/// assert_eq!(records.len(), 2);
+/// assert_eq!(records[1].get_attribute("id"), Some("2"));
+/// ```
+pub struct AsyncElementStream<R, P> {
+    input: R,
+    reader: EventReader<Feed>,
+    predicate: P,
+    options: ParseOptions,
+    /// The matching element being built, once its start tag has been read
+    building: Option<(TreeBuilder, Usage)>,
+    chunk: Box<[u8]>,
+    done: bool,
+}
+
+impl<R: AsyncRead + Unpin, P: ElementPredicate> AsyncElementStream<R, P> {
+    pub(crate) fn new(
+        r: R,
+        config: ParserConfig,
+        options: ParseOptions,
+        predicate: P,
+    ) -> AsyncElementStream<R, P> {
+        // Running out of data in the middle of the root element must not end the document
+        let config = config.ignore_end_of_stream(true);
+        AsyncElementStream {
+            input: r,
+            reader: EventReader::new_with_config(Feed::default(), config),
+            predicate,
+            options,
+            building: None,
+            chunk: vec![0; 8192].into_boxed_slice(),
+            done: false,
+        }
+    }
+
+    fn fail(&mut self, e: ParseError) -> Poll<Option<Result<Element, ParseError>>> {
+        self.done = true;
+        Poll::Ready(Some(Err(e)))
+    }
+}
+
+impl<R: AsyncRead + Unpin, P: ElementPredicate + Unpin> Stream for AsyncElementStream<R, P> {
+    type Item = Result<Element, ParseError>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Result<Element, ParseError>>> {
+        let this = self.get_mut();
+        while !this.done {
+            if this.reader.source().needs_data() {
+                match Pin::new(&mut this.input).poll_read(cx, &mut this.chunk) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Ok(0)) => this.reader.source_mut().finish(),
+                    Poll::Ready(Ok(n)) => this.reader.source_mut().push(&this.chunk[..n]),
+                    Poll::Ready(Err(e)) if e.kind() == ErrorKind::Interrupted => (),
+                    Poll::Ready(Err(e)) => return this.fail(ParseError::MalformedXml(e.into())),
+                }
+                continue;
+            }
+
+            let event = match this.reader.next() {
+                Ok(event) => event,
+                // The parser stopped where the data ran out, and resumes once there is more
+                Err(_) if this.reader.source().starved => continue,
+                Err(e) => return this.fail(ParseError::MalformedXml(e)),
+            };
+            if let Some((builder, usage)) = &mut this.building {
+                match builder.event(event, &this.reader, &this.options, usage) {
+                    Ok(Some(elem)) => {
+                        this.building = None;
+                        return Poll::Ready(Some(Ok(elem)));
+                    }
+                    Ok(None) => continue,
+                    Err(e) => return this.fail(e),
+                }
+            }
+            match event {
+                XmlEvent::StartElement {
+                    name,
+                    attributes,
+                    namespace,
+                } => {
+                    let mut elem = Element::from_start_event(name, attributes, namespace);
+                    if !this.predicate.match_element(&elem) {
+                        continue;
+                    }
+                    elem.span = this.options.span_here(&this.reader);
+                    let mut usage = Usage::new(this.options.limits);
+                    let (attributes, bytes) = (elem.attributes.len(), elem.attribute_bytes());
+                    if let Err(e) = usage.add_element(&this.reader, 1, 0, attributes, bytes) {
+                        return this.fail(e);
+                    }
+                    this.building = Some((TreeBuilder::new(elem), usage));
+                }
+                XmlEvent::EndDocument => this.done = true,
+                _ => (),
+            }
+        }
+        Poll::Ready(None)
+    }
+}
+
+impl<R: AsyncRead + Unpin, P: ElementPredicate + Unpin> FusedStream for AsyncElementStream<R, P> {
+    fn is_terminated(&self) -> bool {
+        self.done
+    }
+}
+
+/// The input read so far, which the parser reads from.
+///
+/// xml-rs can't be suspended while it waits for more data, but with `ignore_end_of_stream` it
+/// can be stopped by running out of data just after the end of some markup inside the root
+/// element, and then resumed.  The data is only made available to the parser up to such a point,
+/// until the end of the input has been reached.
+#[derive(Default)]
+struct Feed {
+    buf: Vec<u8>,
+    /// The bytes before this index have been read by the parser
+    read: usize,
+    /// The bytes before this index can be read by the parser
+    available: usize,
+    scanner: Scanner,
+    eof: bool,
+    /// Whether the parser has stopped because it ran out of available data
+    starved: bool,
+}
+
+impl Feed {
+    fn needs_data(&self) -> bool {
+        !self.eof && self.read == self.available
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        if self.read > self.buf.len() / 2 {
+            self.buf.drain(..self.read);
+            self.available -= self.read;
+            self.read = 0;
+        }
+        let start = self.buf.len();
+        self.buf.extend_from_slice(data);
+        for (i, &b) in data.iter().enumerate() {
+            if self.scanner.scan(b) {
+                self.available = start + i + 1;
+            }
+        }
+        self.starved = false;
+    }
+
+    fn finish(&mut self) {
+        self.eof = true;
+        self.available = self.buf.len();
+        self.starved = false;
+    }
+}
+
+impl Read for Feed {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let data = &self.buf[self.read..self.available];
+        let n = data.len().min(out.len());
+        out[..n].copy_from_slice(&data[..n]);
+        self.read += n;
+        if n == 0 && !out.is_empty() && !self.eof {
+            self.starved = true;
+        }
+        Ok(n)
+    }
+}
+
+/// Follows the markup of a document byte by byte, to find the points where the parser can stop
+#[derive(Default)]
+struct Scanner {
+    state: State,
+    depth: usize,
+    /// Whether the end of the root element has been seen
+    finished: bool,
+}
+
+#[derive(Clone, Copy, Default)]
+enum State {
+    #[default]
+    Text,
+    /// After `<`
+    Open,
+    /// After `<!`
+    Bang,
+    /// After `<!-`
+    BangDash,
+    /// In a comment, after this many `-`
+    Comment(u8),
+    /// In a CDATA section, after this many `]`
+    CData(u8),
+    /// In a processing instruction, possibly just after `?`
+    Pi(bool),
+    /// In a DOCTYPE, inside this many `[` and maybe inside a quoted string
+    Doctype { brackets: usize, quote: Option<u8> },
+    /// In a start or end tag, maybe inside a quoted attribute value, possibly just after `/`
+    Tag {
+        end: bool,
+        quote: Option<u8>,
+        slash: bool,
+    },
+}
+
+impl Scanner {
+    /// Moves past `b`, and returns whether the parser can stop right after it
+    fn scan(&mut self, b: u8) -> bool {
+        self.state = match (self.state, b) {
+            (State::Text, b'<') => State::Open,
+            (State::Text, _) => State::Text,
+            (State::Open, b'/') => State::Tag {
+                end: true,
+                quote: None,
+                slash: false,
+            },
+            (State::Open, b'?') => State::Pi(false),
+            (State::Open, b'!') => State::Bang,
+            (State::Open, _) => State::Tag {
+                end: false,
+                quote: None,
+                slash: false,
+            },
+            (State::Bang, b'-') => State::BangDash,
+            (State::Bang, b'[') => State::CData(0),
+            (State::BangDash, b'-') => State::Comment(0),
+            (State::Bang, _) | (State::BangDash, _) => State::Doctype {
+                brackets: 0,
+                quote: None,
+            },
+            (State::Comment(dashes), b'>') if dashes >= 2 => return self.end_markup(),
+            (State::Comment(dashes), b'-') => State::Comment((dashes + 1).min(2)),
+            (State::Comment(_), _) => State::Comment(0),
+            (State::CData(brackets), b'>') if brackets >= 2 => return self.end_markup(),
+            (State::CData(brackets), b']') => State::CData((brackets + 1).min(2)),
+            (State::CData(_), _) => State::CData(0),
+            (State::Pi(true), b'>') => return self.end_markup(),
+            (State::Pi(_), b) => State::Pi(b == b'?'),
+            (State::Doctype { brackets, quote }, b) => match quote {
+                Some(q) if q == b => State::Doctype {
+                    brackets,
+                    quote: None,
+                },
+                Some(_) => self.state,
+                None => match b {
+                    b'"' | b'\'' => State::Doctype {
+                        brackets,
+                        quote: Some(b),
+                    },
+                    b'[' => State::Doctype {
+                        brackets: brackets + 1,
+                        quote: None,
+                    },
+                    b']' => State::Doctype {
+                        brackets: brackets.saturating_sub(1),
+                        quote: None,
+                    },
+                    b'>' if brackets == 0 => return self.end_markup(),
+                    _ => self.state,
+                },
+            },
+            (State::Tag { end, quote, slash }, b) => match quote {
+                Some(q) if q == b => State::Tag {
+                    end,
+                    quote: None,
+                    slash: false,
+                },
+                Some(_) => self.state,
+                None => match b {
+                    b'"' | b'\'' => State::Tag {
+                        end,
+                        quote: Some(b),
+                        slash: false,
+                    },
+                    b'>' => {
+                        if end {
+                            self.depth = self.depth.saturating_sub(1);
+                            self.finished |= self.depth == 0;
+                        } else if slash {
+                            self.finished |= self.depth == 0;
+                        } else {
+                            self.depth += 1;
+                        }
+                        return self.end_markup();
+                    }
+                    _ => State::Tag {
+                        end,
+                        quote: None,
+                        slash: b == b'/',
+                    },
+                },
+            },
+        };
+        false
+    }
+
+    fn end_markup(&mut self) -> bool {
+        self.state = State::Text;
+        self.depth > 0 && !self.finished
+    }
+}
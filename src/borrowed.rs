@@ -0,0 +1,633 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+
+use xml::namespace::Namespace;
+
+#[cfg(feature = "quick-xml")]
+use crate::limits::Usage;
+#[cfg(feature = "quick-xml")]
+use crate::quick::{InMemory, QuickEvent, QuickName, QuickReader};
+#[cfg(feature = "quick-xml")]
+use xml::common::Position;
+#[cfg(feature = "quick-xml")]
+use xml::reader::ParserConfig;
+
+use crate::debug::{self, DebugTree};
+use crate::{
+    AttributeMap, AttributeMapExt, AttributeName, Element, ElementPredicate, Span, XMLNode,
+};
+#[cfg(feature = "quick-xml")]
+use crate::{ParseError, ParseOptions};
+
+/// An element that borrows its strings from the document it was parsed from.
+///
+/// Parsing into an [`Element`] copies every name, attribute value and piece of text out of the
+/// input.  When the whole document is in memory, [`ElementRef::parse`] borrows all of the strings
+/// that appear in the input as they are instead, and only allocates for those that contain
+/// references like `&amp;`.  This makes reading large documents considerably cheaper, as long as
+/// the tree doesn't have to outlive the input.  Use [`ElementRef::into_owned`] to turn it into an
+/// `Element`.
+///
+/// Parsing into an `ElementRef` requires the `quick-xml` feature, as quick-xml is the only
+/// [`Backend`](crate::Backend) that can borrow from its input.  Without it, an `ElementRef` can
+/// still be made from an `Element`.
+///
+/// Like `Element`, `ElementRef` is cloned and dropped without recursion.
+///
+/// ```
+/// # #[cfg(feature = "quick-xml")] {
+/// use xmltree::ElementRef;
+///
+/// let input = String::from("<list><item id=\"1\">one &amp; two</item><item/></list>");
+/// let list = ElementRef::parse(&input).unwrap();
+/// let item = list.get_child("item").unwrap();
+/// assert_eq!(item.get_attribute("id"), Some("1"));
+/// assert_eq!(item.get_text().unwrap(), "one & two");
+///
+/// let owned = list.into_owned();
+/// drop(input);
+/// assert_eq!(owned.children.len(), 2);
+/// # }
+/// ```
+#[non_exhaustive]
+pub struct ElementRef<'a> {
+    /// This elements prefix, if any
+    pub prefix: Option<Cow<'a, str>>,
+
+    /// This elements namespace, if any
+    pub namespace: Option<Cow<'a, str>>,
+
+    /// The full list of namespaces, if any.  It is shared between all elements in the same
+    /// scope.
+    pub namespaces: Option<Arc<Namespace>>,
+
+    /// The name of the element.  Does not include any namespace info
+    pub name: Cow<'a, str>,
+
+    /// The attributes of the element.  With the quick-xml backend, they are in document order.
+    pub attributes: Vec<AttributeRef<'a>>,
+
+    /// Children
+    pub children: Vec<XMLNodeRef<'a>>,
+
+    /// Where this element was found in the source document, if parsed with
+    /// [`ParseOptions::track_positions`] enabled
+    pub span: Option<Span>,
+
+    /// Where each of the children was found in the source document, read through
+    /// [`ElementRef::child_span`]
+    pub(crate) child_spans: Vec<Span>,
+}
+
+/// An attribute of an [`ElementRef`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeRef<'a> {
+    /// The name of the attribute.  Does not include any namespace info
+    pub local_name: Cow<'a, str>,
+
+    /// The namespace URI of this attribute, if any
+    pub namespace: Option<Cow<'a, str>>,
+
+    /// The prefix of this attribute, if any
+    pub prefix: Option<Cow<'a, str>>,
+
+    /// The value of the attribute, with references replaced
+    pub value: Cow<'a, str>,
+}
+
+impl AttributeRef<'_> {
+    fn to_name(&self) -> AttributeName {
+        AttributeName {
+            local_name: self.local_name.to_string(),
+            namespace: self.namespace.as_ref().map(|ns| ns.to_string()),
+            prefix: self.prefix.as_ref().map(|p| p.to_string()),
+        }
+    }
+}
+
+/// A node of an [`ElementRef`], like an [`XMLNode`] with borrowed strings
+#[derive(Debug, Clone)]
+pub enum XMLNodeRef<'a> {
+    Element(ElementRef<'a>),
+    Comment(Cow<'a, str>),
+    CData(Cow<'a, str>),
+    Text(Cow<'a, str>),
+    ProcessingInstruction(Cow<'a, str>, Option<Cow<'a, str>>),
+    /// Whitespace-only text, only produced when [`ParseOptions::preserve_whitespace`] is enabled
+    Whitespace(Cow<'a, str>),
+}
+
+impl<'a> XMLNodeRef<'a> {
+    pub fn as_element(&self) -> Option<&ElementRef<'a>> {
+        if let XMLNodeRef::Element(e) = self {
+            Some(e)
+        } else {
+            None
+        }
+    }
+    pub fn as_comment(&self) -> Option<&str> {
+        if let XMLNodeRef::Comment(c) = self {
+            Some(c)
+        } else {
+            None
+        }
+    }
+    pub fn as_cdata(&self) -> Option<&str> {
+        if let XMLNodeRef::CData(c) = self {
+            Some(c)
+        } else {
+            None
+        }
+    }
+    pub fn as_text(&self) -> Option<&str> {
+        if let XMLNodeRef::Text(c) = self {
+            Some(c)
+        } else {
+            None
+        }
+    }
+
+    /// Copies all borrowed strings, and returns the node as an `XMLNode`
+    pub fn into_owned(self) -> XMLNode {
+        match self {
+            XMLNodeRef::Element(elem) => XMLNode::Element(elem.into_owned()),
+            XMLNodeRef::Comment(s) => XMLNode::Comment(s.into_owned()),
+            XMLNodeRef::CData(s) => XMLNode::CData(s.into_owned()),
+            XMLNodeRef::Text(s) => XMLNode::Text(s.into_owned()),
+            XMLNodeRef::ProcessingInstruction(name, data) => {
+                XMLNode::ProcessingInstruction(name.into_owned(), data.map(Cow::into_owned))
+            }
+            XMLNodeRef::Whitespace(s) => XMLNode::Whitespace(s.into_owned()),
+        }
+    }
+
+    /// The number of bytes of content in this node, as counted by
+    /// [`ParseLimits::max_text_bytes`](crate::ParseLimits::max_text_bytes)
+    #[cfg(feature = "quick-xml")]
+    fn content_len(&self) -> usize {
+        match self {
+            XMLNodeRef::Element(_) => 0,
+            XMLNodeRef::Comment(s)
+            | XMLNodeRef::CData(s)
+            | XMLNodeRef::Text(s)
+            | XMLNodeRef::Whitespace(s) => s.len(),
+            XMLNodeRef::ProcessingInstruction(name, data) => {
+                name.len() + data.as_ref().map_or(0, |d| d.len())
+            }
+        }
+    }
+}
+
+impl<'a> ElementRef<'a> {
+    /// Parses a document into its root element, like [`Element::parse`], borrowing from `input`
+    #[cfg(feature = "quick-xml")]
+    pub fn parse(input: &'a str) -> Result<ElementRef<'a>, ParseError> {
+        let config = ParserConfig::new().ignore_comments(false);
+        ElementRef::parse_with_config(input, config)
+    }
+
+    #[cfg(feature = "quick-xml")]
+    pub fn parse_with_config(
+        input: &'a str,
+        config: ParserConfig,
+    ) -> Result<ElementRef<'a>, ParseError> {
+        ElementRef::parse_with_options(input, config, ParseOptions::new())
+    }
+
+    /// Parses a document into its root element, using the given parser configuration and
+    /// tree-building options.
+    ///
+    /// The document is always parsed with quick-xml, whatever `options.backend` says, and is
+    /// always read as UTF-8, whatever encoding the XML declaration names.
+    #[cfg(feature = "quick-xml")]
+    pub fn parse_with_options(
+        input: &'a str,
+        config: ParserConfig,
+        options: ParseOptions,
+    ) -> Result<ElementRef<'a>, ParseError> {
+        let mut reader = QuickReader::from_str(input, config);
+        parse_events(&mut reader, &options)
+    }
+
+    #[cfg(feature = "quick-xml")]
+    fn from_start_event(
+        name: QuickName<'a>,
+        attributes: Vec<(QuickName<'a>, Cow<'a, str>)>,
+        namespace: Option<Arc<Namespace>>,
+    ) -> ElementRef<'a> {
+        ElementRef {
+            prefix: name.prefix,
+            namespace: name.namespace,
+            namespaces: namespace.filter(|namespace| !namespace.is_essentially_empty()),
+            name: name.local_name,
+            attributes: attributes
+                .into_iter()
+                .map(|(name, value)| AttributeRef {
+                    local_name: name.local_name,
+                    namespace: name.namespace,
+                    prefix: name.prefix,
+                    value,
+                })
+                .collect(),
+            children: Vec::new(),
+            span: None,
+            child_spans: Vec::new(),
+        }
+    }
+
+    /// The number of bytes taken up by the names and values of the attributes
+    #[cfg(feature = "quick-xml")]
+    pub(crate) fn attribute_bytes(&self) -> usize {
+        self.attributes
+            .iter()
+            .map(|attr| {
+                attr.prefix.as_ref().map_or(0, |prefix| prefix.len())
+                    + attr.local_name.len()
+                    + attr.value.len()
+            })
+            .sum()
+    }
+
+    /// Where the child at `index` was found in the source document, like
+    /// [`Element::child_span`]
+    pub fn child_span(&self, index: usize) -> Option<Span> {
+        match self.children.get(index)? {
+            XMLNodeRef::Element(child) => child.span,
+            _ if self.child_spans.len() == self.children.len() => {
+                self.child_spans.get(index).copied()
+            }
+            _ => None,
+        }
+    }
+
+    /// Find a child element that matches the predicate, like [`Element::get_child`]
+    pub fn get_child<P: ElementPredicate>(&self, k: P) -> Option<&ElementRef<'a>> {
+        self.children
+            .iter()
+            .filter_map(XMLNodeRef::as_element)
+            .find(|e| k.match_element_ref(e))
+    }
+
+    /// Returns the inner text/cdata of this element, if any.
+    ///
+    /// If there are multiple text/cdata nodes, they will be all concatenated into one string.
+    pub fn get_text(&self) -> Option<Cow<'_, str>> {
+        let mut text_nodes = self
+            .children
+            .iter()
+            .filter_map(|node| node.as_text().or_else(|| node.as_cdata()));
+        let first = text_nodes.next()?;
+        match text_nodes.next() {
+            None => Some(Cow::Borrowed(first)),
+            Some(second) => {
+                let mut full_text = String::from(first);
+                full_text.push_str(second);
+                text_nodes.for_each(|text| full_text.push_str(text));
+                Some(Cow::Owned(full_text))
+            }
+        }
+    }
+
+    /// Returns the value of the attribute with the given local name, preferring the one without
+    /// a namespace, like [`Element::get_attribute`]
+    pub fn get_attribute(&self, name: &str) -> Option<&str> {
+        self.get_attribute_ns(name, None).or_else(|| {
+            self.attributes
+                .iter()
+                .filter(|attr| attr.local_name == name)
+                .min_by(|a, b| a.namespace.cmp(&b.namespace))
+                .map(|attr| &*attr.value)
+        })
+    }
+
+    /// Returns the value of the attribute with the given local name and namespace.
+    ///
+    /// Pass `None` as the namespace to look up an attribute without a namespace.
+    pub fn get_attribute_ns(&self, name: &str, namespace: Option<&str>) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|attr| attr.local_name == name && attr.namespace.as_deref() == namespace)
+            .map(|attr| &*attr.value)
+    }
+
+    /// Checks if this element matches the predicate.
+    pub fn matches<P: ElementPredicate>(&self, k: P) -> bool {
+        k.match_element_ref(self)
+    }
+
+    /// Removes all children of this element, and drops them without recursion, like
+    /// [`Element::clear_children`]
+    pub fn clear_children(&mut self) {
+        let mut nodes = mem::take(&mut self.children);
+        self.child_spans.clear();
+        while let Some(node) = nodes.pop() {
+            if let XMLNodeRef::Element(mut elem) = node {
+                nodes.append(&mut elem.children);
+            }
+        }
+    }
+
+    /// Copies all borrowed strings, and returns the element as an `Element`
+    pub fn into_owned(mut self) -> Element {
+        let children = mem::take(&mut self.children);
+        let mut stack = vec![(children.into_iter(), self.take_shallow())];
+        loop {
+            let (children, elem) = stack.last_mut().expect("element stack is never empty");
+            match children.next() {
+                Some(XMLNodeRef::Element(mut child)) => {
+                    let grandchildren = mem::take(&mut child.children);
+                    stack.push((grandchildren.into_iter(), child.take_shallow()));
+                }
+                Some(node) => elem.children.push(node.into_owned()),
+                None => {
+                    let (_, finished) = stack.pop().expect("element stack is never empty");
+                    match stack.last_mut() {
+                        Some((_, parent)) => parent.children.push(XMLNode::Element(finished)),
+                        None => return finished,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Moves everything but the children of this element into an `Element`
+    fn take_shallow(&mut self) -> Element {
+        let mut attributes = AttributeMap::allocate(self.attributes.len());
+        for attr in self.attributes.drain(..) {
+            attributes.insert(attr.to_name(), attr.value.into_owned());
+        }
+        Element {
+            prefix: self.prefix.take().map(Cow::into_owned),
+            namespace: self.namespace.take().map(Cow::into_owned),
+            namespaces: self
+                .namespaces
+                .take()
+                .map(|ns| Arc::try_unwrap(ns).unwrap_or_else(|ns| Namespace::clone(&ns))),
+            name: mem::take(&mut self.name).into_owned(),
+            attributes,
+            children: Vec::new(),
+            span: self.span,
+            child_spans: mem::take(&mut self.child_spans),
+        }
+    }
+
+    /// Copies everything but the children of this element into an `Element`, for predicates
+    /// that can only look at elements
+    pub(crate) fn to_shallow_element(&self) -> Element {
+        let mut attributes = AttributeMap::allocate(self.attributes.len());
+        for attr in &self.attributes {
+            attributes.insert(attr.to_name(), attr.value.to_string());
+        }
+        Element {
+            prefix: self.prefix.as_ref().map(|p| p.to_string()),
+            namespace: self.namespace.as_ref().map(|ns| ns.to_string()),
+            namespaces: self.namespaces.as_ref().map(|ns| Namespace::clone(ns)),
+            name: self.name.to_string(),
+            attributes,
+            children: Vec::new(),
+            span: self.span,
+            child_spans: Vec::new(),
+        }
+    }
+
+    /// Clones everything but the children of this element
+    fn clone_shallow(&self) -> ElementRef<'a> {
+        ElementRef {
+            prefix: self.prefix.clone(),
+            namespace: self.namespace.clone(),
+            namespaces: self.namespaces.clone(),
+            name: self.name.clone(),
+            attributes: self.attributes.clone(),
+            children: Vec::with_capacity(self.children.len()),
+            span: self.span,
+            child_spans: self.child_spans.clone(),
+        }
+    }
+}
+
+impl Clone for ElementRef<'_> {
+    fn clone(&self) -> Self {
+        let mut stack = vec![(self.children.iter(), self.clone_shallow())];
+        loop {
+            let (children, copy) = stack.last_mut().expect("clone stack is never empty");
+            match children.next() {
+                Some(XMLNodeRef::Element(child)) => {
+                    stack.push((child.children.iter(), child.clone_shallow()));
+                }
+                Some(node) => copy.children.push(node.clone()),
+                None => {
+                    let (_, finished) = stack.pop().expect("clone stack is never empty");
+                    match stack.last_mut() {
+                        Some((_, parent)) => parent.children.push(XMLNodeRef::Element(finished)),
+                        None => return finished,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ElementRef<'_> {
+    fn drop(&mut self) {
+        self.clear_children();
+    }
+}
+
+impl fmt::Debug for ElementRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        debug::fmt_tree(self, f)
+    }
+}
+
+impl<'a> DebugTree for ElementRef<'a> {
+    type Node = XMLNodeRef<'a>;
+
+    const NAME: &'static str = "ElementRef";
+
+    fn head(&self) -> [(&'static str, &dyn fmt::Debug); 5] {
+        [
+            ("prefix", &self.prefix),
+            ("namespace", &self.namespace),
+            ("namespaces", &self.namespaces),
+            ("name", &self.name),
+            ("attributes", &self.attributes),
+        ]
+    }
+
+    fn tail(&self) -> [(&'static str, &dyn fmt::Debug); 2] {
+        [("span", &self.span), ("child_spans", &self.child_spans)]
+    }
+
+    fn children(&self) -> &[XMLNodeRef<'a>] {
+        &self.children
+    }
+
+    fn as_element<'n>(node: &'n XMLNodeRef<'a>) -> Option<&'n ElementRef<'a>> {
+        node.as_element()
+    }
+}
+
+/// Wraps all strings of an element, which can then outlive any input
+impl From<Element> for ElementRef<'static> {
+    fn from(mut root: Element) -> ElementRef<'static> {
+        fn shallow(elem: &mut Element) -> ElementRef<'static> {
+            let attributes = mem::take(&mut elem.attributes)
+                .into_iter()
+                .map(|(name, value)| AttributeRef {
+                    local_name: Cow::Owned(name.local_name),
+                    namespace: name.namespace.map(Cow::Owned),
+                    prefix: name.prefix.map(Cow::Owned),
+                    value: Cow::Owned(value),
+                })
+                .collect();
+            ElementRef {
+                prefix: elem.prefix.take().map(Cow::Owned),
+                namespace: elem.namespace.take().map(Cow::Owned),
+                namespaces: elem.namespaces.take().map(Arc::new),
+                name: Cow::Owned(mem::take(&mut elem.name)),
+                attributes,
+                children: Vec::new(),
+                span: elem.span,
+                child_spans: mem::take(&mut elem.child_spans),
+            }
+        }
+
+        let children = mem::take(&mut root.children);
+        let mut stack = vec![(children.into_iter(), shallow(&mut root))];
+        loop {
+            let (children, elem) = stack.last_mut().expect("element stack is never empty");
+            match children.next() {
+                Some(XMLNode::Element(mut child)) => {
+                    let grandchildren = mem::take(&mut child.children);
+                    stack.push((grandchildren.into_iter(), shallow(&mut child)));
+                }
+                Some(node) => elem.children.push(match node {
+                    XMLNode::Element(_) => unreachable!("elements are handled above"),
+                    XMLNode::Comment(s) => XMLNodeRef::Comment(Cow::Owned(s)),
+                    XMLNode::CData(s) => XMLNodeRef::CData(Cow::Owned(s)),
+                    XMLNode::Text(s) => XMLNodeRef::Text(Cow::Owned(s)),
+                    XMLNode::ProcessingInstruction(name, data) => {
+                        XMLNodeRef::ProcessingInstruction(Cow::Owned(name), data.map(Cow::Owned))
+                    }
+                    XMLNode::Whitespace(s) => XMLNodeRef::Whitespace(Cow::Owned(s)),
+                }),
+                None => {
+                    let (_, finished) = stack.pop().expect("element stack is never empty");
+                    match stack.last_mut() {
+                        Some((_, parent)) => parent.children.push(XMLNodeRef::Element(finished)),
+                        None => return finished,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a> From<ElementRef<'a>> for Element {
+    fn from(elem: ElementRef<'a>) -> Element {
+        elem.into_owned()
+    }
+}
+
+/// Reads a whole document, and returns its root element
+#[cfg(feature = "quick-xml")]
+fn parse_events<'a>(
+    reader: &mut QuickReader<'a, InMemory<'a>>,
+    options: &ParseOptions,
+) -> Result<ElementRef<'a>, ParseError> {
+    let mut root = None;
+    let mut usage = Usage::new(options.limits);
+    let mut root_nodes = 0;
+    loop {
+        let content_len = match reader.pull().map_err(ParseError::MalformedXml)? {
+            QuickEvent::StartElement {
+                name,
+                attributes,
+                namespace,
+            } => {
+                let mut elem = ElementRef::from_start_event(name, attributes, namespace);
+                let (attributes, bytes) = (elem.attributes.len(), elem.attribute_bytes());
+                usage.add_element(reader, 1, root_nodes, attributes, bytes)?;
+                elem.span = options.span_here(reader);
+                let elem = build(reader, elem, options, &mut usage)?;
+                root = root.or(Some(elem));
+                root_nodes += 1;
+                continue;
+            }
+            QuickEvent::Comment(s) | QuickEvent::Characters(s) | QuickEvent::CData(s) => s.len(),
+            QuickEvent::ProcessingInstruction { name, data } => {
+                name.len() + data.map_or(0, |d| d.len())
+            }
+            QuickEvent::EndDocument => return root.ok_or(ParseError::NoRootElement),
+            QuickEvent::Whitespace(..)
+            | QuickEvent::StartDocument { .. }
+            | QuickEvent::EndElement { .. } => continue,
+        };
+        usage.add_node(reader, root_nodes, content_len)?;
+        root_nodes += 1;
+    }
+}
+
+/// Reads the contents of `root`, whose start tag has just been read, up to and including its end
+/// tag, like the `TreeBuilder` does for `Element`s
+#[cfg(feature = "quick-xml")]
+fn build<'a>(
+    reader: &mut QuickReader<'a, InMemory<'a>>,
+    root: ElementRef<'a>,
+    options: &ParseOptions,
+    usage: &mut Usage,
+) -> Result<ElementRef<'a>, ParseError> {
+    let mut stack = vec![root];
+    loop {
+        let event = reader.pull().map_err(ParseError::MalformedXml)?;
+        let depth = stack.len();
+        let elem = stack.last_mut().expect("element stack is never empty");
+        let node = match event {
+            QuickEvent::EndElement { .. } => {
+                // quick-xml has already checked that the end tag matches
+                if let Some(ref mut span) = elem.span {
+                    span.end_tag = reader.position();
+                }
+                let finished = stack.pop().expect("element stack is never empty");
+                match stack.last_mut() {
+                    Some(parent) => {
+                        parent.child_spans.extend(finished.span);
+                        parent.children.push(XMLNodeRef::Element(finished));
+                        continue;
+                    }
+                    None => return Ok(finished),
+                }
+            }
+            QuickEvent::StartElement {
+                name,
+                attributes,
+                namespace,
+            } => {
+                let mut child = ElementRef::from_start_event(name, attributes, namespace);
+                let (attributes, bytes) = (child.attributes.len(), child.attribute_bytes());
+                usage.add_element(reader, depth + 1, elem.children.len(), attributes, bytes)?;
+                child.span = options.span_here(reader);
+                stack.push(child);
+                continue;
+            }
+            QuickEvent::Characters(s) => XMLNodeRef::Text(s),
+            QuickEvent::Whitespace(s) if options.preserve_whitespace => XMLNodeRef::Whitespace(s),
+            QuickEvent::Whitespace(..) => continue,
+            QuickEvent::Comment(s) => XMLNodeRef::Comment(s),
+            QuickEvent::CData(s) => XMLNodeRef::CData(s),
+            QuickEvent::ProcessingInstruction { name, data } => {
+                XMLNodeRef::ProcessingInstruction(name, data)
+            }
+            QuickEvent::StartDocument { .. } | QuickEvent::EndDocument => {
+                return Err(ParseError::UnexpectedDocumentEvent {
+                    position: reader.position(),
+                })
+            }
+        };
+        usage.add_node(reader, elem.children.len(), node.content_len())?;
+        elem.child_spans.extend(options.span_here(reader));
+        elem.children.push(node);
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use xml::namespace::{NS_XMLNS_PREFIX, NS_XML_PREFIX};
+
+use crate::{Document, Element, Error, XMLNode};
+
+/// Options for [`Element::write_canonical`] and [`Document::write_canonical`], selecting one of
+/// the four standard canonicalization algorithms.
+///
+/// The default is [Canonical XML 1.0](https://www.w3.org/TR/2001/REC-xml-c14n-20010315) without
+/// comments.  Enable [`exclusive`](CanonicalOptions::exclusive) for
+/// [Exclusive XML Canonicalization](https://www.w3.org/TR/xml-exc-c14n/), which only declares
+/// the namespaces that are actually used by each element, so that a signed subtree keeps the same
+/// canonical form when it is moved into another document.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CanonicalOptions {
+    /// Use Exclusive XML Canonicalization instead of Canonical XML 1.0.
+    ///
+    /// Default is false.
+    pub exclusive: bool,
+
+    /// Keep comments in the output.  Without this, comments are left out.
+    ///
+    /// Default is false.
+    pub with_comments: bool,
+
+    /// The `InclusiveNamespaces PrefixList` of Exclusive XML Canonicalization: prefixes whose
+    /// declarations are handled as in Canonical XML 1.0, even where they are not used.  The
+    /// default namespace is given as `#default`.
+    ///
+    /// Ignored unless [`CanonicalOptions::exclusive`] is set.  Default is empty.
+    pub inclusive_prefixes: Vec<String>,
+}
+
+impl CanonicalOptions {
+    /// Returns the default options, for Canonical XML 1.0 without comments
+    pub fn new() -> CanonicalOptions {
+        CanonicalOptions::default()
+    }
+
+    /// Sets [`CanonicalOptions::exclusive`]
+    pub fn exclusive(mut self, value: bool) -> CanonicalOptions {
+        self.exclusive = value;
+        self
+    }
+
+    /// Sets [`CanonicalOptions::with_comments`]
+    pub fn with_comments(mut self, value: bool) -> CanonicalOptions {
+        self.with_comments = value;
+        self
+    }
+
+    /// Sets [`CanonicalOptions::inclusive_prefixes`]
+    pub fn inclusive_prefixes<I, S>(mut self, prefixes: I) -> CanonicalOptions
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.inclusive_prefixes = prefixes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// The URI identifying the selected algorithm, as used in the `Algorithm` attribute of
+    /// XML-DSig `CanonicalizationMethod` and `Transform` elements
+    pub fn algorithm(&self) -> &'static str {
+        match (self.exclusive, self.with_comments) {
+            (false, false) => "http://www.w3.org/TR/2001/REC-xml-c14n-20010315",
+            (false, true) => "http://www.w3.org/TR/2001/REC-xml-c14n-20010315#WithComments",
+            (true, false) => "http://www.w3.org/2001/10/xml-exc-c14n#",
+            (true, true) => "http://www.w3.org/2001/10/xml-exc-c14n#WithComments",
+        }
+    }
+
+    fn is_inclusive_prefix(&self, prefix: &str) -> bool {
+        self.inclusive_prefixes
+            .iter()
+            .any(|p| p == prefix || (prefix.is_empty() && p == "#default"))
+    }
+}
+
+/// Prefix to namespace URI bindings, with `""` as the key of the default namespace
+type Bindings = BTreeMap<String, String>;
+
+/// Writes the canonical form of `root` and its descendants
+pub(crate) fn write_element<W: Write>(
+    w: &mut W,
+    root: &Element,
+    options: &CanonicalOptions,
+) -> Result<(), Error> {
+    // Walk the tree with an explicit stack, so that deep trees can't overflow the call stack.
+    // Each level keeps the namespaces in scope and those declared by the output so far.
+    let (in_scope, rendered) = write_start(w, root, &Bindings::new(), &Bindings::new(), options)?;
+    let mut stack = vec![(root, root.children.iter(), in_scope, rendered)];
+    while let Some((elem, children, in_scope, rendered)) = stack.last_mut() {
+        match children.next() {
+            Some(XMLNode::Element(child)) => {
+                let (in_scope, rendered) = write_start(w, child, in_scope, rendered, options)?;
+                stack.push((child, child.children.iter(), in_scope, rendered));
+            }
+            Some(node) => write_node(w, node, options)?,
+            None => {
+                write!(w, "</{}>", qualified_name(&elem.prefix, &elem.name))?;
+                stack.pop();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the canonical form of a whole document.
+///
+/// The XML declaration and the DOCTYPE are left out, and the nodes around the root element are
+/// separated from it by line feeds.
+pub(crate) fn write_document<W: Write>(
+    w: &mut W,
+    doc: &Document,
+    options: &CanonicalOptions,
+) -> Result<(), Error> {
+    for node in doc.prolog.iter().filter(|n| is_top_level(n, options)) {
+        write_node(w, node, options)?;
+        w.write_all(b"\n")?;
+    }
+    write_element(w, &doc.root, options)?;
+    for node in doc.epilog.iter().filter(|n| is_top_level(n, options)) {
+        w.write_all(b"\n")?;
+        write_node(w, node, options)?;
+    }
+    Ok(())
+}
+
+/// Whether a node outside the root element appears in the canonical form
+fn is_top_level(node: &XMLNode, options: &CanonicalOptions) -> bool {
+    match node {
+        XMLNode::Comment(_) => options.with_comments,
+        XMLNode::ProcessingInstruction(..) => true,
+        _ => false,
+    }
+}
+
+fn write_node<W: Write>(
+    w: &mut W,
+    node: &XMLNode,
+    options: &CanonicalOptions,
+) -> Result<(), Error> {
+    match node {
+        XMLNode::Element(elem) => write_element(w, elem, options)?,
+        XMLNode::Text(text) | XMLNode::CData(text) | XMLNode::Whitespace(text) => {
+            w.write_all(escape_text(text).as_bytes())?
+        }
+        XMLNode::Comment(comment) => {
+            if options.with_comments {
+                write!(w, "<!--{}-->", comment)?
+            }
+        }
+        // The whitespace separating the target from the data is not part of the data
+        XMLNode::ProcessingInstruction(name, data) => match data.as_deref().map(str::trim_start) {
+            Some(data) if !data.is_empty() => write!(w, "<?{} {}?>", name, data)?,
+            _ => write!(w, "<?{}?>", name)?,
+        },
+    }
+    Ok(())
+}
+
+/// Writes the start tag of `elem`, given the namespaces in scope at and declared by its parent.
+/// Returns the same for `elem` itself.
+fn write_start<W: Write>(
+    w: &mut W,
+    elem: &Element,
+    parent_in_scope: &Bindings,
+    parent_rendered: &Bindings,
+    options: &CanonicalOptions,
+) -> Result<(Bindings, Bindings), Error> {
+    // The prefixes used by the element and its attributes.  Elements built in code may use a
+    // prefix without declaring it, so the bindings from the names themselves take precedence.
+    let mut used = Bindings::new();
+    used.insert(
+        elem.prefix.clone().unwrap_or_default(),
+        elem.namespace.clone().unwrap_or_default(),
+    );
+    for name in elem.attributes.keys() {
+        if let (Some(prefix), Some(namespace)) = (&name.prefix, &name.namespace) {
+            used.insert(prefix.clone(), namespace.clone());
+        }
+    }
+
+    let mut in_scope = parent_in_scope.clone();
+    if let Some(ref namespaces) = elem.namespaces {
+        for (prefix, uri) in namespaces {
+            in_scope.insert(prefix.to_owned(), uri.to_owned());
+        }
+    }
+    in_scope.extend(used.iter().map(|(p, u)| (p.clone(), u.clone())));
+    in_scope.remove(NS_XML_PREFIX);
+    in_scope.remove(NS_XMLNS_PREFIX);
+
+    // Declarations are only output where they differ from what the output already declares.  An
+    // empty default namespace is the same as having none, and other prefixes can't be undeclared.
+    let mut rendered = parent_rendered.clone();
+    let mut declarations = Vec::new();
+    for (prefix, uri) in &in_scope {
+        let visible =
+            !options.exclusive || used.contains_key(prefix) || options.is_inclusive_prefix(prefix);
+        let current = rendered.get(prefix).map_or("", |u| u.as_str());
+        let changed = if prefix.is_empty() {
+            uri != current
+        } else {
+            !uri.is_empty() && rendered.get(prefix) != Some(uri)
+        };
+        if visible && changed {
+            declarations.push((prefix, uri));
+        }
+    }
+    for &(prefix, uri) in &declarations {
+        rendered.insert(prefix.clone(), uri.clone());
+    }
+
+    // Attributes are sorted by namespace URI, then local name, with unqualified ones first
+    let mut attributes: Vec<_> = elem.attributes.iter().collect();
+    attributes.sort_by(|(a, _), (b, _)| {
+        let a_ns = a.prefix.as_ref().and(a.namespace.as_deref()).unwrap_or("");
+        let b_ns = b.prefix.as_ref().and(b.namespace.as_deref()).unwrap_or("");
+        (a_ns, &a.local_name).cmp(&(b_ns, &b.local_name))
+    });
+
+    write!(w, "<{}", qualified_name(&elem.prefix, &elem.name))?;
+    for (prefix, uri) in declarations {
+        if prefix.is_empty() {
+            write!(w, " xmlns=\"{}\"", escape_attribute(uri))?;
+        } else {
+            write!(w, " xmlns:{}=\"{}\"", prefix, escape_attribute(uri))?;
+        }
+    }
+    for (name, value) in attributes {
+        write!(
+            w,
+            " {}=\"{}\"",
+            qualified_name(&name.prefix, &name.local_name),
+            escape_attribute(value)
+        )?;
+    }
+    w.write_all(b">")?;
+    Ok((in_scope, rendered))
+}
+
+fn qualified_name(prefix: &Option<String>, name: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{}:{}", prefix, name),
+        None => name.to_owned(),
+    }
+}
+
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '\r' => escaped.push_str("&#xD;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn escape_attribute(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' => escaped.push_str("&#x9;"),
+            '\n' => escaped.push_str("&#xA;"),
+            '\r' => escaped.push_str("&#xD;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,644 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use serde::de::value::StringDeserializer;
+use serde::de::{
+    self, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess, VariantAccess,
+    Visitor,
+};
+use serde::forward_to_deserialize_any;
+
+use crate::{Element, XMLNode};
+
+/// Error returned when converting between an [`Element`] and a Rust value fails
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerdeError {
+    message: String,
+}
+
+impl SerdeError {
+    pub(crate) fn new<T: Into<String>>(message: T) -> SerdeError {
+        SerdeError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for SerdeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for SerdeError {}
+
+impl de::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> SerdeError {
+        SerdeError::new(msg.to_string())
+    }
+}
+
+impl serde::ser::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> SerdeError {
+        SerdeError::new(msg.to_string())
+    }
+}
+
+/// Deserializes a value from an already parsed element.
+///
+/// The element itself corresponds to the value, so its name is not checked.  Struct fields and
+/// map keys are looked up as follows:
+///
+/// * a key starting with `@`, such as `@id`, is the attribute with the rest of the name.
+///   Attributes in a namespace are keyed with their prefix, such as `@xlink:href`, or with their
+///   namespace URI in braces, such as `@{http://www.w3.org/1999/xlink}href`, if they have no prefix.
+///   A struct field can also name an attribute that has a prefix by its namespace URI.
+/// * a struct field named `@xmlns:` followed by a prefix, such as `@xmlns:xlink`, is the namespace
+///   URI bound to that prefix
+/// * `$text` is the text content of the element
+/// * any other key is a child element of that name.  All children with the name are collected
+///   when the field is a sequence, such as a `Vec`
+///
+/// An enum is read from the name of the first child element, or from the text content for unit
+/// variants.  Attributes and text are parsed into numbers and booleans as needed, and a sequence
+/// of those is read from whitespace-separated text.
+///
+/// Fields that are sequences need `#[serde(default)]` to allow for there being no elements.
+///
+/// ```
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// struct Book {
+///     #[serde(rename = "@year")]
+///     year: u32,
+///     title: String,
+///     #[serde(rename = "author", default)]
+///     authors: Vec<String>,
+/// }
+///
+/// let data = "<book year='1994'><title>Design Patterns</title><author>Gamma</author><author>Helm</author></book>";
+/// let elem = xmltree::Element::parse(data.as_bytes()).unwrap();
+/// let book: Book = xmltree::from_element(&elem).unwrap();
+/// assert_eq!(book.year, 1994);
+/// assert_eq!(book.authors, ["Gamma", "Helm"]);
+/// ```
+pub fn from_element<'de, T: de::Deserialize<'de>>(elem: &'de Element) -> Result<T, SerdeError> {
+    T::deserialize(ElementDeserializer(elem))
+}
+
+/// Returns the text and CDATA content of an element, without that of its descendants
+fn text_content(elem: &Element) -> Cow<'_, str> {
+    let mut texts = elem.children.iter().filter_map(|node| match node {
+        XMLNode::Text(s) | XMLNode::CData(s) | XMLNode::Whitespace(s) => Some(s.as_str()),
+        _ => None,
+    });
+    match (texts.next(), texts.next()) {
+        (None, _) => Cow::Borrowed(""),
+        (Some(first), None) => Cow::Borrowed(first),
+        (Some(first), Some(second)) => {
+            let mut text = String::from(first);
+            text.push_str(second);
+            texts.for_each(|s| text.push_str(s));
+            Cow::Owned(text)
+        }
+    }
+}
+
+fn child_elements(elem: &Element) -> impl Iterator<Item = &Element> {
+    elem.children.iter().filter_map(|node| match node {
+        XMLNode::Element(e) => Some(e),
+        _ => None,
+    })
+}
+
+/// Deserializes from a single element
+struct ElementDeserializer<'de>(&'de Element);
+
+/// Forwards the methods for scalar types to the deserializer of the element's text
+macro_rules! forward_to_text {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+                TextDeserializer(text_content(self.0)).$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ElementDeserializer<'de> {
+    type Error = SerdeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        if self.0.attributes.is_empty() && child_elements(self.0).next().is_none() {
+            TextDeserializer(text_content(self.0)).deserialize_any(visitor)
+        } else {
+            self.deserialize_map(visitor)
+        }
+    }
+
+    forward_to_text! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+        deserialize_f64 deserialize_char deserialize_str deserialize_string deserialize_bytes
+        deserialize_byte_buf deserialize_identifier
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        GroupDeserializer(vec![self.0]).deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        visitor.visit_map(ElementMapAccess::new(self.0, &[]))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        visitor.visit_map(ElementMapAccess::new(self.0, fields))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        visitor.visit_unit()
+    }
+}
+
+impl<'de> EnumAccess<'de> for ElementDeserializer<'de> {
+    type Error = SerdeError;
+    type Variant = Variant<'de>;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(
+        self,
+        seed: S,
+    ) -> Result<(S::Value, Variant<'de>), SerdeError> {
+        match child_elements(self.0).next() {
+            Some(child) => {
+                let name: StringDeserializer<SerdeError> = child.name.clone().into_deserializer();
+                let children = child_elements(self.0)
+                    .filter(|e| e.name == child.name)
+                    .collect();
+                Ok((seed.deserialize(name)?, Variant::Elements(children)))
+            }
+            None => {
+                let name = TextDeserializer(Cow::Owned(text_content(self.0).trim().to_owned()));
+                Ok((seed.deserialize(name)?, Variant::Unit))
+            }
+        }
+    }
+}
+
+/// The content of an enum variant: the elements named after it, or nothing for a unit variant
+/// given as text
+enum Variant<'de> {
+    Elements(Vec<&'de Element>),
+    Unit,
+}
+
+impl<'de> Variant<'de> {
+    fn elements(self) -> Result<GroupDeserializer<'de>, SerdeError> {
+        match self {
+            Variant::Elements(elements) => Ok(GroupDeserializer(elements)),
+            Variant::Unit => Err(SerdeError::new(
+                "expected an element for a variant with data",
+            )),
+        }
+    }
+}
+
+impl<'de> VariantAccess<'de> for Variant<'de> {
+    type Error = SerdeError;
+
+    fn unit_variant(self) -> Result<(), SerdeError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<S: DeserializeSeed<'de>>(
+        self,
+        seed: S,
+    ) -> Result<S::Value, SerdeError> {
+        seed.deserialize(self.elements()?)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        de::Deserializer::deserialize_seq(self.elements()?, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        de::Deserializer::deserialize_map(self.elements()?, visitor)
+    }
+}
+
+/// Deserializes from all child elements with the same name
+///
+/// This is a sequence if a sequence is asked for, and otherwise the first of the elements.
+struct GroupDeserializer<'de>(Vec<&'de Element>);
+
+impl<'de> GroupDeserializer<'de> {
+    fn first(&self) -> ElementDeserializer<'de> {
+        ElementDeserializer(self.0[0])
+    }
+}
+
+macro_rules! forward_to_first {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+                self.first().$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for GroupDeserializer<'de> {
+    type Error = SerdeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        if self.0.len() > 1 {
+            self.deserialize_seq(visitor)
+        } else {
+            self.first().deserialize_any(visitor)
+        }
+    }
+
+    forward_to_first! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+        deserialize_f64 deserialize_char deserialize_str deserialize_string deserialize_bytes
+        deserialize_byte_buf deserialize_identifier deserialize_unit deserialize_map
+        deserialize_ignored_any
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        self.first().deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        visitor.visit_seq(ElementSeqAccess(self.0.into_iter()))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        self.first().deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        self.first().deserialize_enum(name, variants, visitor)
+    }
+}
+
+struct ElementSeqAccess<'de>(std::vec::IntoIter<&'de Element>);
+
+impl<'de> SeqAccess<'de> for ElementSeqAccess<'de> {
+    type Error = SerdeError;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Result<Option<S::Value>, SerdeError> {
+        match self.0.next() {
+            Some(elem) => seed.deserialize(ElementDeserializer(elem)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+/// The value of a key of an element
+enum Entry<'de> {
+    Text(Cow<'de, str>),
+    Elements(Vec<&'de Element>),
+}
+
+/// Presents the attributes, child elements and text of an element as a map
+struct ElementMapAccess<'de> {
+    entries: std::vec::IntoIter<(String, Entry<'de>)>,
+    value: Option<Entry<'de>>,
+}
+
+impl<'de> ElementMapAccess<'de> {
+    /// Collects the entries of `elem`.  For a struct, `fields` are the names of its fields, which
+    /// decide how attributes are keyed when there is a choice.
+    fn new(elem: &'de Element, fields: &[&str]) -> ElementMapAccess<'de> {
+        let mut entries: Vec<(String, Entry<'de>)> = elem
+            .attributes
+            .iter()
+            .map(|(name, value)| {
+                let uri_key = |namespace| format!("@{{{}}}{}", namespace, name.local_name);
+                let key = match (&name.prefix, &name.namespace) {
+                    (Some(prefix), Some(namespace)) => {
+                        let by_prefix = format!("@{}:{}", prefix, name.local_name);
+                        let by_uri = uri_key(namespace);
+                        if !fields.contains(&&*by_prefix) && fields.contains(&&*by_uri) {
+                            by_uri
+                        } else {
+                            by_prefix
+                        }
+                    }
+                    (_, Some(namespace)) => uri_key(namespace),
+                    (_, None) => format!("@{}", name.local_name),
+                };
+                (key, Entry::Text(Cow::Borrowed(value.as_str())))
+            })
+            .collect();
+        // Namespace declarations are only read by structs that ask for them
+        for field in fields {
+            let uri = field
+                .strip_prefix("@xmlns:")
+                .and_then(|prefix| elem.namespaces.as_ref()?.get(prefix));
+            if let Some(uri) = uri {
+                entries.push((field.to_string(), Entry::Text(Cow::Borrowed(uri))));
+            }
+        }
+        let attributes = entries.len();
+        for child in child_elements(elem) {
+            let group = entries[attributes..]
+                .iter_mut()
+                .find(|(name, _)| *name == child.name);
+            match group {
+                Some((_, Entry::Elements(elements))) => elements.push(child),
+                _ => entries.push((child.name.clone(), Entry::Elements(vec![child]))),
+            }
+        }
+        let text = text_content(elem);
+        if !text.trim().is_empty() {
+            entries.push(("$text".to_owned(), Entry::Text(text)));
+        }
+        ElementMapAccess {
+            entries: entries.into_iter(),
+            value: None,
+        }
+    }
+}
+
+impl<'de> MapAccess<'de> for ElementMapAccess<'de> {
+    type Error = SerdeError;
+
+    fn next_key_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Result<Option<S::Value>, SerdeError> {
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                let key: StringDeserializer<SerdeError> = key.into_deserializer();
+                seed.deserialize(key).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Result<S::Value, SerdeError> {
+        match self.value.take() {
+            Some(Entry::Text(text)) => seed.deserialize(TextDeserializer(text)),
+            Some(Entry::Elements(elements)) => seed.deserialize(GroupDeserializer(elements)),
+            None => Err(SerdeError::new("value requested before key")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+/// Deserializes from the value of an attribute or the text of an element
+struct TextDeserializer<'de>(Cow<'de, str>);
+
+impl<'de> TextDeserializer<'de> {
+    fn parse<T: std::str::FromStr>(&self, what: &str) -> Result<T, SerdeError> {
+        self.0
+            .trim()
+            .parse()
+            .map_err(|_| SerdeError::new(format!("invalid {}: `{}`", what, self.0)))
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident, $what:expr;)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+                visitor.$visit(self.parse($what)?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for TextDeserializer<'de> {
+    type Error = SerdeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self.0 {
+            Cow::Borrowed(s) => visitor.visit_borrowed_str(s),
+            Cow::Owned(s) => visitor.visit_string(s),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self.0.trim() {
+            "true" | "1" => visitor.visit_bool(true),
+            "false" | "0" => visitor.visit_bool(false),
+            _ => Err(SerdeError::new(format!("invalid boolean: `{}`", self.0))),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8, "integer";
+        deserialize_i16 => visit_i16, "integer";
+        deserialize_i32 => visit_i32, "integer";
+        deserialize_i64 => visit_i64, "integer";
+        deserialize_u8 => visit_u8, "integer";
+        deserialize_u16 => visit_u16, "integer";
+        deserialize_u32 => visit_u32, "integer";
+        deserialize_u64 => visit_u64, "integer";
+        deserialize_f32 => visit_f32, "number";
+        deserialize_f64 => visit_f64, "number";
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        let mut chars = self.0.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => visitor.visit_char(c),
+            _ => Err(SerdeError::new(format!("invalid character: `{}`", self.0))),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        let items: Vec<TextDeserializer<'de>> = match self.0 {
+            Cow::Borrowed(s) => s
+                .split_whitespace()
+                .map(|item| TextDeserializer(Cow::Borrowed(item)))
+                .collect(),
+            Cow::Owned(s) => s
+                .split_whitespace()
+                .map(|item| TextDeserializer(Cow::Owned(item.to_owned())))
+                .collect(),
+        };
+        visitor.visit_seq(de::value::SeqDeserializer::new(items.into_iter()))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        let variant: StringDeserializer<SerdeError> = self.0.trim().to_owned().into_deserializer();
+        visitor.visit_enum(variant)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf identifier tuple_struct map struct
+    }
+}
+
+impl<'de> IntoDeserializer<'de, SerdeError> for TextDeserializer<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
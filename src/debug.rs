@@ -0,0 +1,156 @@
+//! `Debug` for elements without recursion, for trees too deep for a derived implementation
+
+use std::fmt;
+
+/// An element that can be written by [`fmt_tree`]
+pub(crate) trait DebugTree: Sized {
+    /// The node type of the children
+    type Node: fmt::Debug;
+
+    /// The name of the element type
+    const NAME: &'static str;
+
+    /// The fields that come before `children`, in declaration order
+    fn head(&self) -> [(&'static str, &dyn fmt::Debug); 5];
+
+    /// The fields that come after `children`, in declaration order
+    fn tail(&self) -> [(&'static str, &dyn fmt::Debug); 2];
+
+    fn children(&self) -> &[Self::Node];
+
+    /// Returns the element wrapped in `node`, which must be the `Element` variant
+    fn as_element(node: &Self::Node) -> Option<&Self>;
+}
+
+/// Writes `root` exactly like `#[derive(Debug)]` would, with or without `{:#?}`, but keeps the
+/// elements still being written on a stack instead of recursing into them
+pub(crate) fn fmt_tree<E: DebugTree>(root: &E, f: &mut fmt::Formatter) -> fmt::Result {
+    let mut out = Output {
+        pretty: f.alternate(),
+        f,
+    };
+    // Each level of nesting adds the fields of an element and the `Element(` around it
+    const LEVEL: usize = 12;
+    out.start(root, 0)?;
+    let mut stack = vec![(root, root.children().iter())];
+    while !stack.is_empty() {
+        let indent = LEVEL * (stack.len() - 1);
+        let (elem, children) = stack.last_mut().expect("stack is not empty");
+        let elem = *elem;
+        let separator = if children.len() == elem.children().len() {
+            ""
+        } else {
+            ", "
+        };
+        match children.next() {
+            Some(node) => {
+                out.next(indent + 8, separator)?;
+                match E::as_element(node) {
+                    Some(child) => {
+                        out.f.write_str("Element(")?;
+                        out.next(indent + LEVEL, "")?;
+                        out.start(child, indent + LEVEL)?;
+                        stack.push((child, child.children().iter()));
+                    }
+                    None => {
+                        out.value(node, indent + 8)?;
+                        out.comma()?;
+                    }
+                }
+            }
+            None => {
+                out.finish(elem, indent)?;
+                stack.pop();
+                if !stack.is_empty() {
+                    out.comma()?;
+                    out.next(indent - 4, "")?;
+                    out.f.write_str(")")?;
+                    out.comma()?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+struct Output<'a, 'b> {
+    f: &'a mut fmt::Formatter<'b>,
+    pretty: bool,
+}
+
+impl Output<'_, '_> {
+    /// Writes the name and the fields of `elem` up to the opening bracket of its children
+    fn start<E: DebugTree>(&mut self, elem: &E, indent: usize) -> fmt::Result {
+        write!(self.f, "{} {{", E::NAME)?;
+        for (i, (name, value)) in elem.head().iter().enumerate() {
+            self.field(name, *value, indent + 4, if i == 0 { " " } else { ", " })?;
+        }
+        self.next(indent + 4, ", ")?;
+        self.f.write_str("children: [")?;
+        if elem.children().is_empty() {
+            self.f.write_str("]")?;
+            self.comma()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the closing bracket of the children of `elem`, and the rest of the element
+    fn finish<E: DebugTree>(&mut self, elem: &E, indent: usize) -> fmt::Result {
+        if !elem.children().is_empty() {
+            self.next(indent + 4, "")?;
+            self.f.write_str("]")?;
+            self.comma()?;
+        }
+        for (name, value) in elem.tail().iter() {
+            self.field(name, *value, indent + 4, ", ")?;
+        }
+        self.next(indent, " ")?;
+        self.f.write_str("}")
+    }
+
+    fn field(
+        &mut self,
+        name: &str,
+        value: &dyn fmt::Debug,
+        indent: usize,
+        separator: &str,
+    ) -> fmt::Result {
+        self.next(indent, separator)?;
+        write!(self.f, "{}: ", name)?;
+        self.value(value, indent)?;
+        self.comma()
+    }
+
+    /// Starts a new line at `indent` when pretty-printing, and writes `separator` otherwise
+    fn next(&mut self, indent: usize, separator: &str) -> fmt::Result {
+        if self.pretty {
+            write!(self.f, "\n{:indent$}", "", indent = indent)
+        } else {
+            self.f.write_str(separator)
+        }
+    }
+
+    /// Writes a value that contains no elements, with all lines but the first at `indent`
+    fn value(&mut self, value: &dyn fmt::Debug, indent: usize) -> fmt::Result {
+        if !self.pretty {
+            return write!(self.f, "{:?}", value);
+        }
+        let text = format!("{:#?}", value);
+        let mut lines = text.split('\n');
+        self.f.write_str(lines.next().unwrap_or_default())?;
+        for line in lines {
+            self.next(indent, "")?;
+            self.f.write_str(line)?;
+        }
+        Ok(())
+    }
+
+    /// Ends an item of a struct or list, which only takes a comma when pretty-printing
+    fn comma(&mut self) -> fmt::Result {
+        if self.pretty {
+            self.f.write_str(",")
+        } else {
+            Ok(())
+        }
+    }
+}
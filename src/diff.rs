@@ -0,0 +1,709 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::{Edge, Element, XMLNode};
+
+/// Options for [`diff_with_options`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffOptions {
+    /// Don't report attributes that only changed order.
+    ///
+    /// Attribute order is only kept with the `attribute-order` feature, and should not be
+    /// compared otherwise.  Default is true.
+    pub ignore_attribute_order: bool,
+
+    /// Ignore whitespace-only text, and whitespace at the start and end of text.
+    ///
+    /// Default is false.
+    pub ignore_whitespace: bool,
+
+    /// Ignore comments.
+    ///
+    /// Default is false.
+    pub ignore_comments: bool,
+}
+
+impl Default for DiffOptions {
+    fn default() -> DiffOptions {
+        DiffOptions {
+            ignore_attribute_order: true,
+            ignore_whitespace: false,
+            ignore_comments: false,
+        }
+    }
+}
+
+impl DiffOptions {
+    /// Returns the default options
+    pub fn new() -> DiffOptions {
+        DiffOptions::default()
+    }
+
+    /// Sets [`DiffOptions::ignore_attribute_order`]
+    pub fn ignore_attribute_order(mut self, value: bool) -> DiffOptions {
+        self.ignore_attribute_order = value;
+        self
+    }
+
+    /// Sets [`DiffOptions::ignore_whitespace`]
+    pub fn ignore_whitespace(mut self, value: bool) -> DiffOptions {
+        self.ignore_whitespace = value;
+        self
+    }
+
+    /// Sets [`DiffOptions::ignore_comments`]
+    pub fn ignore_comments(mut self, value: bool) -> DiffOptions {
+        self.ignore_comments = value;
+        self
+    }
+}
+
+/// One step of the edit script returned by [`diff`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// Where the change is, as an XPath location path such as `/config/server[2]/@port`.
+    ///
+    /// Removed nodes are located in the old tree, everything else in the new tree.
+    pub path: String,
+
+    pub kind: ChangeKind,
+}
+
+/// What changed, see [`Change`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// The node was inserted
+    Inserted(XMLNode),
+    /// The node was removed
+    Removed(XMLNode),
+    /// The node, unchanged, was moved to another position among its siblings
+    Moved {
+        /// The index of the node in the children of the old parent element
+        from: usize,
+        /// The index of the node in the children of the new parent element
+        to: usize,
+    },
+    /// The attribute was added, with this value
+    AttributeAdded(String),
+    /// The attribute, which had this value, was removed
+    AttributeRemoved(String),
+    /// The value of the attribute changed
+    AttributeChanged { old: String, new: String },
+    /// The element has the same attributes, but in a different order.  Only reported when
+    /// [`DiffOptions::ignore_attribute_order`] is disabled
+    AttributesReordered,
+    /// The content of a text node, CDATA section, comment or processing instruction changed
+    TextChanged { old: String, new: String },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ChangeKind::Inserted(_) => write!(f, "inserted {}", self.path),
+            ChangeKind::Removed(_) => write!(f, "removed {}", self.path),
+            ChangeKind::Moved { from, to } => {
+                write!(f, "moved {} from index {} to {}", self.path, from, to)
+            }
+            ChangeKind::AttributeAdded(ref value) => {
+                write!(f, "added {} = {:?}", self.path, value)
+            }
+            ChangeKind::AttributeRemoved(ref value) => {
+                write!(f, "removed {} = {:?}", self.path, value)
+            }
+            ChangeKind::AttributesReordered => write!(f, "reordered attributes of {}", self.path),
+            ChangeKind::AttributeChanged { ref old, ref new }
+            | ChangeKind::TextChanged { ref old, ref new } => {
+                write!(f, "changed {} from {:?} to {:?}", self.path, old, new)
+            }
+        }
+    }
+}
+
+/// Compares two trees, using the default [`DiffOptions`]
+///
+/// See [`diff_with_options`].
+pub fn diff(old: &Element, new: &Element) -> Vec<Change> {
+    diff_with_options(old, new, &DiffOptions::new())
+}
+
+/// Compares two trees, returning the changes that turn `old` into `new`, in document order.
+///
+/// Elements and attributes are compared by namespace URI and local name, so changing a
+/// namespace prefix or moving a namespace declaration is not a change.  The children of two
+/// elements are matched up by first finding the longest sequence of identical children, then
+/// pairing up the remaining children of the same kind and name, whose differences are
+/// reported recursively.  Identical elements that were not matched up are reported as moved.
+///
+/// ```
+/// use xmltree::{diff, ChangeKind, Element};
+///
+/// let old = r#"<config><a/><b/><port>80</port><host name="a"/></config>"#;
+/// let new = r#"<config><b/><a/><port>8080</port><host name="b"/></config>"#;
+/// let old = Element::parse(old.as_bytes()).unwrap();
+/// let new = Element::parse(new.as_bytes()).unwrap();
+/// let changes = diff(&old, &new);
+/// assert_eq!(changes[0].kind, ChangeKind::Moved { from: 0, to: 1 });
+///
+/// let described: Vec<_> = changes.iter().map(|c| c.to_string()).collect();
+/// assert_eq!(described, [
+///     "moved /config/a from index 0 to 1",
+///     r#"changed /config/port/text() from "80" to "8080""#,
+///     r#"changed /config/host/@name from "a" to "b""#,
+/// ]);
+/// ```
+pub fn diff_with_options(old: &Element, new: &Element, options: &DiffOptions) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let old_path = format!("/{}", qualified_name(old));
+    let new_path = format!("/{}", qualified_name(new));
+    if !same_name(old, new) {
+        changes.push(Change {
+            path: old_path,
+            kind: ChangeKind::Removed(XMLNode::Element(old.clone())),
+        });
+        changes.push(Change {
+            path: new_path,
+            kind: ChangeKind::Inserted(XMLNode::Element(new.clone())),
+        });
+        return changes;
+    }
+
+    // Comparing elements produces more work in document order, which is kept on a stack so that
+    // deep trees can't overflow the call stack
+    let fingerprints = fingerprints(&[old, new], options);
+    let mut work = vec![Work::Compare(old, new, old_path, new_path)];
+    while let Some(item) = work.pop() {
+        match item {
+            Work::Emit(change) => changes.push(*change),
+            Work::Compare(old, new, old_path, new_path) => {
+                let mut items = compare_attributes(old, new, &new_path, options);
+                items.extend(compare_children(
+                    old,
+                    new,
+                    &old_path,
+                    &new_path,
+                    options,
+                    &fingerprints,
+                ));
+                work.extend(items.into_iter().rev());
+            }
+        }
+    }
+    changes
+}
+
+enum Work<'a> {
+    // Boxed, as a change may hold a whole element
+    Emit(Box<Change>),
+    Compare(&'a Element, &'a Element, String, String),
+}
+
+impl<'a> Work<'a> {
+    fn emit(path: String, kind: ChangeKind) -> Work<'a> {
+        Work::Emit(Box::new(Change { path, kind }))
+    }
+}
+
+fn compare_attributes<'a>(
+    old: &Element,
+    new: &Element,
+    path: &str,
+    options: &DiffOptions,
+) -> Vec<Work<'a>> {
+    let mut items = Vec::new();
+    let emit = |name: &dyn fmt::Display, kind| Work::emit(format!("{}/@{}", path, name), kind);
+    let names: BTreeSet<_> = old.attributes.keys().chain(new.attributes.keys()).collect();
+    for name in names {
+        match (old.attributes.get(name), new.attributes.get(name)) {
+            (Some(a), Some(b)) if a != b => items.push(emit(
+                name,
+                ChangeKind::AttributeChanged {
+                    old: a.clone(),
+                    new: b.clone(),
+                },
+            )),
+            (Some(a), None) => items.push(emit(name, ChangeKind::AttributeRemoved(a.clone()))),
+            (None, Some(b)) => items.push(emit(name, ChangeKind::AttributeAdded(b.clone()))),
+            _ => (),
+        }
+    }
+
+    if !options.ignore_attribute_order {
+        let common = |a: &Element, b: &Element| {
+            a.attributes
+                .keys()
+                .filter(|k| b.attributes.contains_key(*k))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        if common(old, new) != common(new, old) {
+            items.push(Work::emit(path.to_owned(), ChangeKind::AttributesReordered));
+        }
+    }
+    items
+}
+
+/// A child that takes part in the comparison, with its position in the unfiltered children
+struct Child<'a> {
+    node: &'a XMLNode,
+    index: usize,
+    path: String,
+    /// Children of either element are in the same class exactly when they are identical
+    class: usize,
+}
+
+/// The children seen so far in the comparison of two elements, one for each class of identical
+/// children, by fingerprint
+#[derive(Default)]
+struct Classes<'a> {
+    representatives: HashMap<u64, Vec<(usize, &'a XMLNode)>>,
+    count: usize,
+}
+
+impl<'a> Classes<'a> {
+    /// Returns the class of `node`.  A fingerprint only finds candidates, which are confirmed by
+    /// comparing the subtrees, so that a hash collision can't hide a change.
+    fn class(&mut self, node: &'a XMLNode, fingerprint: u64, options: &DiffOptions) -> usize {
+        let candidates = self.representatives.entry(fingerprint).or_default();
+        match candidates
+            .iter()
+            .find(|&&(_, other)| identical(node, other, options))
+        {
+            Some(&(class, _)) => class,
+            None => {
+                candidates.push((self.count, node));
+                self.count += 1;
+                self.count - 1
+            }
+        }
+    }
+}
+
+fn compare_children<'a>(
+    old: &'a Element,
+    new: &'a Element,
+    old_path: &str,
+    new_path: &str,
+    options: &DiffOptions,
+    fingerprints: &Fingerprints,
+) -> Vec<Work<'a>> {
+    let mut classes = Classes::default();
+    let old_children = children(old, old_path, options, fingerprints, &mut classes);
+    let new_children = children(new, new_path, options, fingerprints, &mut classes);
+
+    // Identical children anchor the comparison, and the gaps between them are compared pairwise
+    let anchors =
+        longest_common_subsequence(&old_children, &new_children, |a, b| a.class == b.class);
+    let mut pairs = Vec::new();
+    let mut unmatched_old = Vec::new();
+    let mut unmatched_new = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (anchor_i, anchor_j) in anchors
+        .into_iter()
+        .chain(std::iter::once((old_children.len(), new_children.len())))
+    {
+        let old_gap = &old_children[i..anchor_i];
+        let new_gap = &new_children[j..anchor_j];
+        let similar =
+            longest_common_subsequence(old_gap, new_gap, |a, b| same_kind(a.node, b.node));
+        let (mut gi, mut gj) = (0, 0);
+        for (si, sj) in similar
+            .into_iter()
+            .chain(std::iter::once((old_gap.len(), new_gap.len())))
+        {
+            unmatched_old.extend((i + gi..i + si).map(|k| (k, pairs.len())));
+            unmatched_new.extend((j + gj..j + sj).map(|k| (k, pairs.len())));
+            if si < old_gap.len() {
+                pairs.push((i + si, j + sj));
+            }
+            gi = si + 1;
+            gj = sj + 1;
+        }
+        i = anchor_i + 1;
+        j = anchor_j + 1;
+    }
+
+    // Unmatched children that are identical were moved.  Whitespace is not worth reporting as
+    // moved, it just ends up in a different place when the elements around it are reordered.
+    let mut unmatched_by_class: HashMap<usize, VecDeque<usize>> = HashMap::new();
+    for (t, &(ni, _)) in unmatched_new.iter().enumerate() {
+        unmatched_by_class
+            .entry(new_children[ni].class)
+            .or_default()
+            .push_back(t);
+    }
+    let mut moved = vec![false; unmatched_new.len()];
+    let mut moved_to = Vec::with_capacity(unmatched_old.len());
+    for &(oi, _) in &unmatched_old {
+        let target = unmatched_by_class
+            .get_mut(&old_children[oi].class)
+            .and_then(|targets| targets.pop_front());
+        if let Some(t) = target {
+            moved[t] = true;
+        }
+        moved_to.push(target.map(|t| unmatched_new[t].0));
+    }
+    let unmatched_new: Vec<_> = unmatched_new
+        .into_iter()
+        .zip(moved)
+        .filter(|&(_, moved)| !moved)
+        .map(|(unmatched, _)| unmatched)
+        .collect();
+
+    // Report everything in the order of the old children, with insertions before the pair that
+    // follows them
+    let mut items = Vec::new();
+    let mut old_iter = unmatched_old.into_iter().zip(moved_to).peekable();
+    let mut new_iter = unmatched_new.into_iter().peekable();
+    for (p, &(oi, ni)) in pairs
+        .iter()
+        .chain(std::iter::once(&(usize::MAX, 0)))
+        .enumerate()
+    {
+        while let Some(((k, _), moved_to)) = old_iter.next_if(|&((_, before), _)| before == p) {
+            let child = &old_children[k];
+            items.push(match moved_to {
+                Some(_) if is_whitespace(child.node) => continue,
+                Some(n) => Work::emit(
+                    new_children[n].path.clone(),
+                    ChangeKind::Moved {
+                        from: child.index,
+                        to: new_children[n].index,
+                    },
+                ),
+                None => Work::emit(child.path.clone(), ChangeKind::Removed(child.node.clone())),
+            });
+        }
+        while let Some((k, _)) = new_iter.next_if(|&(_, before)| before == p) {
+            let child = &new_children[k];
+            items.push(Work::emit(
+                child.path.clone(),
+                ChangeKind::Inserted(child.node.clone()),
+            ));
+        }
+        if oi == usize::MAX {
+            break;
+        }
+        let (a, b) = (&old_children[oi], &new_children[ni]);
+        match (a.node, b.node) {
+            (XMLNode::Element(x), XMLNode::Element(y)) => {
+                items.push(Work::Compare(x, y, a.path.clone(), b.path.clone()))
+            }
+            (x, y) => {
+                let (old_text, new_text) = (content(x, options), content(y, options));
+                if old_text != new_text {
+                    items.push(Work::emit(
+                        b.path.clone(),
+                        ChangeKind::TextChanged {
+                            old: old_text.to_owned(),
+                            new: new_text.to_owned(),
+                        },
+                    ));
+                }
+            }
+        }
+    }
+    items
+}
+
+/// Returns the children of `elem` that are compared, with their paths and classes
+fn children<'a>(
+    elem: &'a Element,
+    path: &str,
+    options: &DiffOptions,
+    fingerprints: &Fingerprints,
+    classes: &mut Classes<'a>,
+) -> Vec<Child<'a>> {
+    let segments = segments(&elem.children);
+    elem.children
+        .iter()
+        .zip(segments)
+        .enumerate()
+        .filter(|(_, (node, _))| is_compared(node, options))
+        .map(|(index, (node, segment))| {
+            let fingerprint = match node {
+                XMLNode::Element(elem) => fingerprints[&(elem as *const Element)],
+                node => leaf_fingerprint(node, options),
+            };
+            Child {
+                node,
+                index,
+                path: format!("{}/{}", path, segment),
+                class: classes.class(node, fingerprint, options),
+            }
+        })
+        .collect()
+}
+
+fn is_compared(node: &XMLNode, options: &DiffOptions) -> bool {
+    match node {
+        XMLNode::Comment(_) => !options.ignore_comments,
+        XMLNode::Whitespace(_) => !options.ignore_whitespace,
+        XMLNode::Text(_) => !options.ignore_whitespace || !is_whitespace(node),
+        _ => true,
+    }
+}
+
+/// The XPath location steps of `nodes`, with a position wherever a step alone is ambiguous
+fn segments(nodes: &[XMLNode]) -> Vec<String> {
+    let steps: Vec<String> = nodes
+        .iter()
+        .map(|node| match node {
+            XMLNode::Element(elem) => qualified_name(elem),
+            XMLNode::Text(_) | XMLNode::CData(_) | XMLNode::Whitespace(_) => "text()".to_owned(),
+            XMLNode::Comment(_) => "comment()".to_owned(),
+            XMLNode::ProcessingInstruction(..) => "processing-instruction()".to_owned(),
+        })
+        .collect();
+    let mut totals: HashMap<&str, usize> = HashMap::new();
+    for step in &steps {
+        *totals.entry(step).or_default() += 1;
+    }
+    let mut positions: HashMap<&str, usize> = HashMap::new();
+    steps
+        .iter()
+        .map(|step| {
+            if totals[step.as_str()] > 1 {
+                let position = positions.entry(step).or_default();
+                *position += 1;
+                format!("{}[{}]", step, position)
+            } else {
+                step.clone()
+            }
+        })
+        .collect()
+}
+
+fn qualified_name(elem: &Element) -> String {
+    match elem.prefix {
+        Some(ref prefix) => format!("{}:{}", prefix, elem.name),
+        None => elem.name.clone(),
+    }
+}
+
+fn same_name(a: &Element, b: &Element) -> bool {
+    a.name == b.name && a.namespace == b.namespace
+}
+
+/// Whether two nodes can be compared with each other, rather than one replacing the other
+fn same_kind(a: &XMLNode, b: &XMLNode) -> bool {
+    match (a, b) {
+        (XMLNode::Element(x), XMLNode::Element(y)) => same_name(x, y),
+        (XMLNode::ProcessingInstruction(x, _), XMLNode::ProcessingInstruction(y, _)) => x == y,
+        (XMLNode::Comment(_), XMLNode::Comment(_)) => true,
+        (x, y) => is_text(x) && is_text(y),
+    }
+}
+
+fn is_whitespace(node: &XMLNode) -> bool {
+    match node {
+        XMLNode::Whitespace(_) => true,
+        XMLNode::Text(text) => text.trim().is_empty(),
+        _ => false,
+    }
+}
+
+fn is_text(node: &XMLNode) -> bool {
+    matches!(
+        node,
+        XMLNode::Text(_) | XMLNode::CData(_) | XMLNode::Whitespace(_)
+    )
+}
+
+/// The compared content of a node that is not an element
+fn content<'a>(node: &'a XMLNode, options: &DiffOptions) -> &'a str {
+    match node {
+        XMLNode::Element(_) => "",
+        XMLNode::Text(text) | XMLNode::CData(text) | XMLNode::Whitespace(text) => {
+            if options.ignore_whitespace {
+                text.trim()
+            } else {
+                text
+            }
+        }
+        XMLNode::Comment(comment) => comment,
+        XMLNode::ProcessingInstruction(_, data) => data.as_deref().unwrap_or(""),
+    }
+}
+
+/// Whether two nodes are the same in everything that is compared
+fn identical(a: &XMLNode, b: &XMLNode, options: &DiffOptions) -> bool {
+    let mut stack = vec![(a, b)];
+    while let Some((a, b)) = stack.pop() {
+        match (a, b) {
+            (XMLNode::Element(x), XMLNode::Element(y)) => {
+                if !same_name(x, y) || attributes(x, options) != attributes(y, options) {
+                    return false;
+                }
+                let mut xs = x.children.iter().filter(|n| is_compared(n, options));
+                let mut ys = y.children.iter().filter(|n| is_compared(n, options));
+                loop {
+                    match (xs.next(), ys.next()) {
+                        (Some(x), Some(y)) => stack.push((x, y)),
+                        (None, None) => break,
+                        _ => return false,
+                    }
+                }
+            }
+            (XMLNode::Element(_), _) | (_, XMLNode::Element(_)) => return false,
+            (x, y) => {
+                if !same_kind(x, y) || content(x, options) != content(y, options) {
+                    return false;
+                }
+            }
+        }
+    }
+    true
+}
+
+/// The compared attributes of an element, as namespace URI, local name and value
+fn attributes<'a>(
+    elem: &'a Element,
+    options: &DiffOptions,
+) -> Vec<(&'a Option<String>, &'a String, &'a String)> {
+    let mut attributes: Vec<_> = elem
+        .attributes
+        .iter()
+        .map(|(k, v)| (&k.namespace, &k.local_name, v))
+        .collect();
+    if options.ignore_attribute_order {
+        attributes.sort();
+    }
+    attributes
+}
+
+/// Hashes of everything about the elements of the compared trees that is compared, so that
+/// identical subtrees can be found without comparing them over and over.
+///
+/// They are keyed by address, which is stable as the trees are borrowed for the whole comparison.
+type Fingerprints = HashMap<*const Element, u64>;
+
+/// Computes the fingerprints of all elements in `roots` in a single pass, combining the
+/// fingerprints of the children of each element
+fn fingerprints(roots: &[&Element], options: &DiffOptions) -> Fingerprints {
+    let mut fingerprints = HashMap::new();
+    for root in roots {
+        // The hashers of the elements being traversed, innermost last
+        let mut hashers: Vec<DefaultHasher> = Vec::new();
+        for edge in root.traverse() {
+            match edge {
+                Edge::Start(elem) => {
+                    let mut hasher = DefaultHasher::new();
+                    0u8.hash(&mut hasher);
+                    elem.namespace.hash(&mut hasher);
+                    elem.name.hash(&mut hasher);
+                    attributes(elem, options).hash(&mut hasher);
+                    hashers.push(hasher);
+                }
+                Edge::End(elem) => {
+                    let fingerprint = hashers.pop().expect("element was started").finish();
+                    fingerprints.insert(elem as *const Element, fingerprint);
+                    if let Some(parent) = hashers.last_mut() {
+                        1u8.hash(parent);
+                        fingerprint.hash(parent);
+                    }
+                }
+                Edge::Leaf(node) => {
+                    if is_compared(node, options) {
+                        let parent = hashers.last_mut().expect("leaves are inside elements");
+                        leaf_fingerprint(node, options).hash(parent);
+                    }
+                }
+            }
+        }
+    }
+    fingerprints
+}
+
+fn leaf_fingerprint(node: &XMLNode, options: &DiffOptions) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    match node {
+        XMLNode::Comment(_) => 2u8.hash(&mut hasher),
+        XMLNode::ProcessingInstruction(target, _) => {
+            3u8.hash(&mut hasher);
+            target.hash(&mut hasher);
+        }
+        _ => 4u8.hash(&mut hasher),
+    }
+    content(node, options).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Returns the index pairs of a longest common subsequence of `a` and `b`, in order
+///
+/// This is Hirschberg's algorithm, which only needs memory linear in the length of `b`.  Common
+/// prefixes and suffixes, which are most of the children of similar elements, are matched up
+/// straight away.
+fn longest_common_subsequence<T, F>(a: &[T], b: &[T], eq: F) -> Vec<(usize, usize)>
+where
+    F: Fn(&T, &T) -> bool,
+{
+    let mut pairs = Vec::new();
+    // The ranges of `a` and `b` that are still to be matched up, kept on a stack instead of
+    // recursing
+    let mut work = vec![(0, a.len(), 0, b.len())];
+    while let Some((mut a_start, mut a_end, mut b_start, mut b_end)) = work.pop() {
+        while a_start < a_end && b_start < b_end && eq(&a[a_start], &b[b_start]) {
+            pairs.push((a_start, b_start));
+            a_start += 1;
+            b_start += 1;
+        }
+        while a_start < a_end && b_start < b_end && eq(&a[a_end - 1], &b[b_end - 1]) {
+            a_end -= 1;
+            b_end -= 1;
+            pairs.push((a_end, b_end));
+        }
+        if a_start == a_end || b_start == b_end {
+            continue;
+        }
+        if a_end - a_start == 1 {
+            if let Some(j) = (b_start..b_end).find(|&j| eq(&a[a_start], &b[j])) {
+                pairs.push((a_start, j));
+            }
+            continue;
+        }
+
+        // Split `b` where the halves of `a` have the longest common subsequences with its parts
+        let mid = a_start + (a_end - a_start) / 2;
+        let len = b_end - b_start;
+        let forward = lcs_lengths(mid - a_start, len, |i, j| {
+            eq(&a[a_start + i], &b[b_start + j])
+        });
+        let backward = lcs_lengths(a_end - mid, len, |i, j| {
+            eq(&a[a_end - 1 - i], &b[b_end - 1 - j])
+        });
+        let split = b_start
+            + (0..=len)
+                .max_by_key(|&k| (forward[k] + backward[len - k], std::cmp::Reverse(k)))
+                .expect("range is not empty");
+        work.push((mid, a_end, split, b_end));
+        work.push((a_start, mid, b_start, split));
+    }
+    pairs.sort_unstable();
+    pairs
+}
+
+/// Returns the lengths of the longest common subsequences of the first `a_len` items of one
+/// sequence and every prefix of the first `b_len` items of another, which are equal when `eq`
+/// says so for their indexes
+fn lcs_lengths<F>(a_len: usize, b_len: usize, eq: F) -> Vec<usize>
+where
+    F: Fn(usize, usize) -> bool,
+{
+    let mut row = vec![0; b_len + 1];
+    for i in 0..a_len {
+        // The value of row[j] before this row was filled in
+        let mut diagonal = 0;
+        for j in 0..b_len {
+            let above = row[j + 1];
+            row[j + 1] = if eq(i, j) {
+                diagonal + 1
+            } else {
+                above.max(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row
+}
@@ -0,0 +1,270 @@
+use std::io::{Read, Write};
+
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::canonical;
+use crate::encoding::Encoder;
+use crate::format;
+use crate::limits::Usage;
+#[cfg(feature = "quick-xml")]
+use crate::quick::QuickReader;
+use crate::{
+    build, Backend, CanonicalOptions, Element, EmitterConfig, Error, Events, FormatOptions,
+    ParseError, ParseOptions, ParserConfig, XMLNode, XmlVersion,
+};
+
+/// Represents a complete XML document.
+///
+/// Unlike [`Element::parse`], which only returns the root element, a `Document` keeps everything
+/// outside of the root element as well: the XML declaration, the DOCTYPE, and any comments or
+/// processing instructions before or after the root element.  Writing a parsed `Document` back
+/// out reproduces all of these, in their original order, each on its own line.
+///
+/// # Example
+///
+/// ```
+/// use xmltree::Document;
+///
+/// let data = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+/// <!-- generated file, do not edit -->
+/// <config><entry key="a" /></config>
+/// "#;
+///
+/// let doc = Document::parse(data.as_bytes()).unwrap();
+/// assert_eq!(doc.standalone, Some(true));
+/// assert_eq!(doc.prolog[0].as_comment(), Some(" generated file, do not edit "));
+/// assert_eq!(doc.root.name, "config");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Document {
+    /// The XML version from the declaration.  Defaults to 1.0 if there is no declaration
+    pub version: XmlVersion,
+
+    /// The encoding from the declaration.  Defaults to "UTF-8" if there is no declaration
+    ///
+    /// The document is written out in this encoding, which has to be UTF-8, ISO-8859-1 or
+    /// US-ASCII.  Writing fails for other encodings, and for characters the encoding can't
+    /// represent; set this to "UTF-8" to write such a document.
+    pub encoding: String,
+
+    /// The `standalone` flag from the declaration, if any
+    pub standalone: Option<bool>,
+
+    /// The full DOCTYPE declaration, including the `<!DOCTYPE` and `>` delimiters, if any
+    pub doctype: Option<String>,
+
+    /// The number of `prolog` nodes that come before the DOCTYPE declaration
+    ///
+    /// When writing, the DOCTYPE is placed between `prolog[..doctype_index]` and the rest of the
+    /// prolog.  An index past the end of the prolog places it right before the root element.
+    pub doctype_index: usize,
+
+    /// Comments and processing instructions that appear before the root element
+    pub prolog: Vec<XMLNode>,
+
+    /// The root element
+    pub root: Element,
+
+    /// Comments and processing instructions that appear after the root element
+    pub epilog: Vec<XMLNode>,
+}
+
+impl Document {
+    /// Create a new document with the given root element
+    ///
+    /// The document is declared as XML 1.0 in UTF-8, with no DOCTYPE and no other top-level nodes.
+    pub fn new(root: Element) -> Document {
+        Document {
+            version: XmlVersion::Version10,
+            encoding: "UTF-8".to_owned(),
+            standalone: None,
+            doctype: None,
+            doctype_index: 0,
+            prolog: Vec::new(),
+            root,
+            epilog: Vec::new(),
+        }
+    }
+
+    /// Parses some data into a Document
+    ///
+    /// Comments are kept, so that they can be written back out.
+    pub fn parse<R: Read>(r: R) -> Result<Document, ParseError> {
+        let parser_config = ParserConfig::new().ignore_comments(false);
+        Document::parse_with_config(r, parser_config)
+    }
+
+    pub fn parse_with_config<R: Read>(
+        r: R,
+        parser_config: ParserConfig,
+    ) -> Result<Document, ParseError> {
+        Document::parse_with_options(r, parser_config, ParseOptions::new())
+    }
+
+    /// Parses some data into a Document, using the given parser configuration and tree-building
+    /// options
+    pub fn parse_with_options<R: Read>(
+        r: R,
+        parser_config: ParserConfig,
+        options: ParseOptions,
+    ) -> Result<Document, ParseError> {
+        match options.backend {
+            Backend::XmlRs => {
+                let mut reader = EventReader::new_with_config(r, parser_config);
+                Document::parse_events(&mut reader, &options)
+            }
+            #[cfg(feature = "quick-xml")]
+            Backend::QuickXml => {
+                let mut reader = QuickReader::new(r, parser_config);
+                Document::parse_events(&mut reader, &options)
+            }
+        }
+    }
+
+    fn parse_events<E: Events>(
+        reader: &mut E,
+        options: &ParseOptions,
+    ) -> Result<Document, ParseError> {
+        let mut usage = Usage::new(options.limits);
+        let mut doc = Document::new(Element::new(""));
+        let mut root = None;
+        loop {
+            let node = match reader.next_event() {
+                Ok(XmlEvent::StartDocument {
+                    version,
+                    encoding,
+                    standalone,
+                }) => {
+                    doc.version = version;
+                    doc.encoding = encoding;
+                    doc.standalone = standalone;
+                    continue;
+                }
+                Ok(XmlEvent::StartElement {
+                    name,
+                    attributes,
+                    namespace,
+                }) => {
+                    let mut elem = Element::from_start_event(name, attributes, namespace);
+                    let (attributes, bytes) = (elem.attributes.len(), elem.attribute_bytes());
+                    usage.add_element(reader, 1, doc.prolog.len(), attributes, bytes)?;
+                    elem.span = options.span_here(reader);
+                    if doc.doctype.is_none() {
+                        doc.doctype = reader.doctype().map(|d| d.to_owned());
+                        doc.doctype_index = doc.prolog.len();
+                    }
+                    root = Some(build(reader, elem, options, &mut usage)?);
+                    continue;
+                }
+                Ok(XmlEvent::Comment(s)) => XMLNode::Comment(s),
+                Ok(XmlEvent::Characters(s)) => XMLNode::Text(s),
+                Ok(XmlEvent::CData(s)) => XMLNode::CData(s),
+                Ok(XmlEvent::ProcessingInstruction { name, data }) => {
+                    XMLNode::ProcessingInstruction(name, data)
+                }
+                Ok(XmlEvent::Whitespace(..)) | Ok(XmlEvent::EndElement { .. }) => continue,
+                Ok(XmlEvent::EndDocument) => break,
+                Err(e) => return Err(ParseError::MalformedXml(e)),
+            };
+            let nodes = if root.is_none() {
+                // The parser has no DOCTYPE event, but has read the DOCTYPE by the time it reports
+                // the first node after it
+                if doc.doctype.is_none() {
+                    doc.doctype = reader.doctype().map(|d| d.to_owned());
+                    doc.doctype_index = doc.prolog.len();
+                }
+                &mut doc.prolog
+            } else {
+                &mut doc.epilog
+            };
+            usage.add_node(reader, nodes.len(), node.content_len())?;
+            nodes.push(node);
+        }
+
+        doc.root = root.ok_or(ParseError::NoRootElement)?;
+        Ok(doc)
+    }
+
+    /// Writes out this document, including its declaration, DOCTYPE and top-level nodes
+    pub fn write<W: Write>(&self, w: W) -> Result<(), Error> {
+        self.write_with_config(w, EmitterConfig::new())
+    }
+
+    /// Writes out this document using the provided configuration
+    ///
+    /// The document is written in, and declared as, its [`Document::encoding`].  If
+    /// `write_document_declaration` is disabled in the configuration, the XML declaration is
+    /// omitted, but the DOCTYPE and all other nodes are still written.
+    pub fn write_with_config<W: Write>(&self, w: W, config: EmitterConfig) -> Result<(), Error> {
+        use xml::writer::events::XmlEvent;
+        use xml::writer::EventWriter;
+
+        let write_document_declaration = config.write_document_declaration;
+        let perform_indent = config.perform_indent;
+        let w = Encoder::new(w, &self.encoding)?;
+        let mut emitter = EventWriter::new_with_config(w, config);
+        if write_document_declaration {
+            emitter.write(XmlEvent::StartDocument {
+                version: self.version,
+                encoding: Some(&self.encoding),
+                standalone: self.standalone,
+            })?;
+        }
+
+        // Top-level nodes go on their own lines.  The emitter already does this when indenting,
+        // except for the DOCTYPE, which it doesn't know about.
+        let mut started = write_document_declaration;
+        let mut new_line = |emitter: &mut EventWriter<Encoder<W>>, raw: bool| {
+            let result = if started && (raw || !perform_indent) {
+                emitter.inner_mut().write_all(b"\n")
+            } else {
+                Ok(())
+            };
+            started = true;
+            result
+        };
+        let doctype_index = self.doctype_index.min(self.prolog.len());
+        for node in &self.prolog[..doctype_index] {
+            new_line(&mut emitter, false)?;
+            node._write(&mut emitter)?;
+        }
+        if let Some(ref doctype) = self.doctype {
+            // xml-rs has no event for the DOCTYPE, so write it straight to the output
+            new_line(&mut emitter, true)?;
+            emitter.inner_mut().write_all(doctype.as_bytes())?;
+        }
+        for node in &self.prolog[doctype_index..] {
+            new_line(&mut emitter, false)?;
+            node._write(&mut emitter)?;
+        }
+        new_line(&mut emitter, false)?;
+        self.root._write(&mut emitter)?;
+        for node in &self.epilog {
+            new_line(&mut emitter, false)?;
+            node._write(&mut emitter)?;
+        }
+        Ok(())
+    }
+
+    /// Writes out the canonical form of this document, see [`Element::write_canonical`]
+    ///
+    /// The XML declaration and the DOCTYPE are not part of the canonical form.  Comments and
+    /// processing instructions around the root element are, each on its own line.
+    pub fn write_canonical<W: Write>(
+        &self,
+        mut w: W,
+        options: &CanonicalOptions,
+    ) -> Result<(), Error> {
+        canonical::write_document(&mut w, self, options)
+    }
+
+    /// Writes out this document laid out by xmltree's own formatter, see [`FormatOptions`]
+    ///
+    /// The DOCTYPE and the nodes around the root element each go on their own line, and the
+    /// output ends with [`FormatOptions::line_separator`], as expected of a text file.  Like
+    /// [`Document::write`], the document is written in its [`Document::encoding`].
+    pub fn write_formatted<W: Write>(&self, w: W, options: &FormatOptions) -> Result<(), Error> {
+        let mut w = Encoder::new(w, &self.encoding)?;
+        format::write_document(&mut w, self, options)
+    }
+}
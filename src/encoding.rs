@@ -0,0 +1,107 @@
+use std::io::{self, Write};
+
+/// The encodings that documents can be written in, and read in by the quick-xml backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Utf8,
+    Latin1,
+    Ascii,
+}
+
+impl Encoding {
+    /// Looks up an encoding by the name used in XML declarations, as xml-rs does
+    pub(crate) fn from_label(label: &str) -> Option<Encoding> {
+        let is = |names: &[&str]| names.iter().any(|name| label.eq_ignore_ascii_case(name));
+        if is(&["utf-8", "utf8"]) {
+            Some(Encoding::Utf8)
+        } else if is(&["iso-8859-1", "latin1"]) {
+            Some(Encoding::Latin1)
+        } else if is(&["ascii", "us-ascii"]) {
+            Some(Encoding::Ascii)
+        } else {
+            None
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Latin1 => "ISO-8859-1",
+            Encoding::Ascii => "US-ASCII",
+        }
+    }
+
+    /// The highest code point the encoding can represent
+    fn max(self) -> char {
+        match self {
+            Encoding::Utf8 => char::MAX,
+            Encoding::Latin1 => '\u{FF}',
+            Encoding::Ascii => '\u{7F}',
+        }
+    }
+}
+
+/// Converts the UTF-8 written to it to the encoding of a document.
+///
+/// Characters the encoding can't represent are an error, as the encoder can't tell whether they
+/// are in text, where a character reference would do, or in a name or comment.
+pub(crate) struct Encoder<W> {
+    inner: W,
+    encoding: Encoding,
+    /// The start of a character whose remaining bytes have not been written yet
+    partial: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Returns an encoder for the encoding named `label`, or an error if documents can't be
+    /// written in it
+    pub(crate) fn new(inner: W, label: &str) -> io::Result<Encoder<W>> {
+        let encoding = Encoding::from_label(label).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("can't write documents in encoding `{}`", label),
+            )
+        })?;
+        Ok(Encoder {
+            inner,
+            encoding,
+            partial: Vec::new(),
+        })
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.encoding == Encoding::Utf8 {
+            return self.inner.write(buf);
+        }
+        self.partial.extend_from_slice(buf);
+        let (text, rest) = match std::str::from_utf8(&self.partial) {
+            Ok(text) => (text, 0),
+            // A character split between writes is finished by the next one
+            Err(e) if e.error_len().is_none() => {
+                let valid = e.valid_up_to();
+                let text = std::str::from_utf8(&self.partial[..valid]).expect("checked above");
+                (text, self.partial.len() - valid)
+            }
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        let mut out = Vec::with_capacity(text.len());
+        for c in text.chars() {
+            if c > self.encoding.max() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("`{}` can't be written in {}", c, self.encoding.name()),
+                ));
+            }
+            out.push(c as u8);
+        }
+        self.inner.write_all(&out)?;
+        self.partial.drain(..self.partial.len() - rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
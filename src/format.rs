@@ -0,0 +1,694 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use xml::namespace::{NS_XMLNS_PREFIX, NS_XML_PREFIX};
+
+use crate::{Document, Element, Error, XMLNode};
+
+/// Options for [`Element::write_formatted`] and [`Document::write_formatted`].
+///
+/// Unlike [`EmitterConfig`](crate::EmitterConfig), which is passed on to xml-rs, these options
+/// are implemented by xmltree itself, and lay out the whole tree at once.  Each element starts on
+/// a new line, indented by its depth, except where that would change the content of the document:
+///
+/// * Elements with mixed content, where text sits next to other nodes, are written as they are,
+///   on one line.
+/// * So are elements with `xml:space="preserve"`, and everything inside them.
+/// * Whitespace between elements is dropped everywhere else, since the indentation replaces it.
+///
+/// Formatting an already formatted tree gives the same output, so this can be used to normalize
+/// XML files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatOptions {
+    /// Write an XML declaration before the root element.
+    ///
+    /// Default is true.
+    pub write_document_declaration: bool,
+
+    /// The string written once per level of indentation.
+    ///
+    /// Default is two spaces.
+    pub indent_string: String,
+
+    /// The string written at the end of each line.
+    ///
+    /// Default is `"\n"`.
+    pub line_separator: String,
+
+    /// The column that [`FormatOptions::inline_short_elements`] and
+    /// [`FormatOptions::wrap_attributes`] try to stay within, counted in characters.
+    ///
+    /// Default is 80.
+    pub max_width: usize,
+
+    /// Write an element with child elements on a single line when it fits within
+    /// [`FormatOptions::max_width`], like `<point><x>1</x><y>2</y></point>`.
+    ///
+    /// Default is false.
+    pub inline_short_elements: bool,
+
+    /// Write elements that only contain text on a single line, however long they are.  Otherwise
+    /// the text of those longer than [`FormatOptions::max_width`] goes on its own line, indented
+    /// one level deeper, which changes the whitespace around it.
+    ///
+    /// Default is true.
+    pub collapse_text_only_elements: bool,
+
+    /// When a start tag doesn't fit within [`FormatOptions::max_width`], write each of its
+    /// attributes on its own line.
+    ///
+    /// Default is false.
+    pub wrap_attributes: bool,
+
+    /// Line up wrapped attributes after the element name, instead of indenting them one level
+    /// deeper than the element.
+    ///
+    /// Default is false.
+    pub align_attributes: bool,
+
+    /// Write attributes sorted by their qualified name, instead of in the order of
+    /// [`Element::attributes`].  Namespace declarations are always sorted, and come first.
+    ///
+    /// Default is false.
+    pub sort_attributes: bool,
+
+    /// Write elements without content as a single empty-element tag, like `<a />`.
+    ///
+    /// Default is true.
+    pub normalize_empty_elements: bool,
+
+    /// Write a space before the `/>` of empty-element tags.
+    ///
+    /// Default is true.
+    pub pad_self_closing: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> FormatOptions {
+        FormatOptions {
+            write_document_declaration: true,
+            indent_string: "  ".to_owned(),
+            line_separator: "\n".to_owned(),
+            max_width: 80,
+            inline_short_elements: false,
+            collapse_text_only_elements: true,
+            wrap_attributes: false,
+            align_attributes: false,
+            sort_attributes: false,
+            normalize_empty_elements: true,
+            pad_self_closing: true,
+        }
+    }
+}
+
+impl FormatOptions {
+    /// Returns the default options
+    pub fn new() -> FormatOptions {
+        FormatOptions::default()
+    }
+
+    /// Sets [`FormatOptions::write_document_declaration`]
+    pub fn write_document_declaration(mut self, value: bool) -> FormatOptions {
+        self.write_document_declaration = value;
+        self
+    }
+
+    /// Sets [`FormatOptions::indent_string`]
+    pub fn indent_string<S: Into<String>>(mut self, value: S) -> FormatOptions {
+        self.indent_string = value.into();
+        self
+    }
+
+    /// Sets [`FormatOptions::line_separator`]
+    pub fn line_separator<S: Into<String>>(mut self, value: S) -> FormatOptions {
+        self.line_separator = value.into();
+        self
+    }
+
+    /// Sets [`FormatOptions::max_width`]
+    pub fn max_width(mut self, value: usize) -> FormatOptions {
+        self.max_width = value;
+        self
+    }
+
+    /// Sets [`FormatOptions::inline_short_elements`]
+    pub fn inline_short_elements(mut self, value: bool) -> FormatOptions {
+        self.inline_short_elements = value;
+        self
+    }
+
+    /// Sets [`FormatOptions::collapse_text_only_elements`]
+    pub fn collapse_text_only_elements(mut self, value: bool) -> FormatOptions {
+        self.collapse_text_only_elements = value;
+        self
+    }
+
+    /// Sets [`FormatOptions::wrap_attributes`]
+    pub fn wrap_attributes(mut self, value: bool) -> FormatOptions {
+        self.wrap_attributes = value;
+        self
+    }
+
+    /// Sets [`FormatOptions::align_attributes`]
+    pub fn align_attributes(mut self, value: bool) -> FormatOptions {
+        self.align_attributes = value;
+        self
+    }
+
+    /// Sets [`FormatOptions::sort_attributes`]
+    pub fn sort_attributes(mut self, value: bool) -> FormatOptions {
+        self.sort_attributes = value;
+        self
+    }
+
+    /// Sets [`FormatOptions::normalize_empty_elements`]
+    pub fn normalize_empty_elements(mut self, value: bool) -> FormatOptions {
+        self.normalize_empty_elements = value;
+        self
+    }
+
+    /// Sets [`FormatOptions::pad_self_closing`]
+    pub fn pad_self_closing(mut self, value: bool) -> FormatOptions {
+        self.pad_self_closing = value;
+        self
+    }
+}
+
+/// Prefix to namespace URI bindings declared by the output, with `""` as the key of the default
+/// namespace
+type Bindings = BTreeMap<String, String>;
+
+/// Writes `root` and its descendants, after the XML declaration if enabled
+pub(crate) fn write_element<W: Write>(
+    w: &mut W,
+    root: &Element,
+    options: &FormatOptions,
+) -> Result<(), Error> {
+    let mut formatter = Formatter::new(w, options);
+    if options.write_document_declaration {
+        formatter.start_line(0)?;
+        write!(formatter.w, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+    }
+    formatter.tree(root)
+}
+
+/// Writes a whole document, with each node outside the root element on its own line and a line
+/// separator at the end
+pub(crate) fn write_document<W: Write>(
+    w: &mut W,
+    doc: &Document,
+    options: &FormatOptions,
+) -> Result<(), Error> {
+    let mut formatter = Formatter::new(w, options);
+    if options.write_document_declaration {
+        formatter.start_line(0)?;
+        write!(
+            formatter.w,
+            r#"<?xml version="{}" encoding="{}""#,
+            doc.version, doc.encoding
+        )?;
+        if let Some(standalone) = doc.standalone {
+            let value = if standalone { "yes" } else { "no" };
+            write!(formatter.w, r#" standalone="{}""#, value)?;
+        }
+        formatter.w.write_all(b"?>")?;
+    }
+    let doctype_index = doc.doctype_index.min(doc.prolog.len());
+    for node in &doc.prolog[..doctype_index] {
+        formatter.leaf(node, 0)?;
+    }
+    if let Some(ref doctype) = doc.doctype {
+        formatter.start_line(0)?;
+        formatter.w.write_all(doctype.as_bytes())?;
+    }
+    for node in &doc.prolog[doctype_index..] {
+        formatter.leaf(node, 0)?;
+    }
+    formatter.tree(&doc.root)?;
+    for node in &doc.epilog {
+        formatter.leaf(node, 0)?;
+    }
+    formatter.w.write_all(options.line_separator.as_bytes())?;
+    Ok(())
+}
+
+/// Writes a sequence of nodes without a declaration, each on its own line
+pub(crate) fn write_fragment<W: Write>(
+    w: &mut W,
+    nodes: &[XMLNode],
+    options: &FormatOptions,
+) -> Result<(), Error> {
+    if is_mixed(nodes) {
+        // Like the content of an element with mixed content, the nodes are written as they are
+        let mut out = String::new();
+        for node in nodes {
+            match node {
+                XMLNode::Element(elem) => {
+                    write_inline(&mut out, elem, &Bindings::new(), true, None, options);
+                }
+                _ => write_leaf(&mut out, node),
+            }
+        }
+        w.write_all(out.as_bytes())?;
+        return Ok(());
+    }
+    let mut formatter = Formatter::new(w, options);
+    for node in nodes {
+        match node {
+            XMLNode::Element(elem) => formatter.tree(elem)?,
+            _ => formatter.leaf(node, 0)?,
+        }
+    }
+    Ok(())
+}
+
+struct Formatter<'a, W> {
+    w: &'a mut W,
+    options: &'a FormatOptions,
+    at_start: bool,
+}
+
+impl<'a, W: Write> Formatter<'a, W> {
+    fn new(w: &'a mut W, options: &'a FormatOptions) -> Self {
+        Formatter {
+            w,
+            options,
+            at_start: true,
+        }
+    }
+
+    /// Starts a new line indented to `depth`, unless nothing has been written yet
+    fn start_line(&mut self, depth: usize) -> Result<(), Error> {
+        if !self.at_start {
+            self.w.write_all(self.options.line_separator.as_bytes())?;
+        }
+        self.at_start = false;
+        for _ in 0..depth {
+            self.w.write_all(self.options.indent_string.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn column(&self, depth: usize) -> usize {
+        depth * self.options.indent_string.chars().count()
+    }
+
+    /// Writes `root` and its descendants, one line per node where possible
+    fn tree(&mut self, root: &Element) -> Result<(), Error> {
+        // Walk the tree with an explicit stack, so that deep trees can't overflow the call stack.
+        // Only elements that are laid out over several lines are pushed.
+        let mut stack = Vec::new();
+        if let Some(scope) = self.element(root, 0, &Bindings::new())? {
+            stack.push((root, root.children.iter(), scope));
+        }
+        loop {
+            let depth = stack.len();
+            let (elem, children, scope) = match stack.last_mut() {
+                Some(frame) => frame,
+                None => return Ok(()),
+            };
+            match children.next() {
+                Some(XMLNode::Element(child)) => {
+                    if let Some(child_scope) = self.element(child, depth, scope)? {
+                        stack.push((child, child.children.iter(), child_scope));
+                    }
+                }
+                Some(node) => self.leaf(node, depth)?,
+                None => {
+                    let name = qualified_name(&elem.prefix, &elem.name);
+                    self.start_line(depth - 1)?;
+                    write!(self.w, "</{}>", name)?;
+                    stack.pop();
+                }
+            }
+        }
+    }
+
+    /// Writes a node other than an element on its own line
+    fn leaf(&mut self, node: &XMLNode, depth: usize) -> Result<(), Error> {
+        if is_ignorable(node) {
+            return Ok(());
+        }
+        self.start_line(depth)?;
+        let mut out = String::new();
+        match node {
+            XMLNode::Text(text) => escape_text(&mut out, text.trim()),
+            _ => write_leaf(&mut out, node),
+        }
+        self.w.write_all(out.as_bytes())?;
+        Ok(())
+    }
+
+    /// Writes `elem` on a new line.  If it is laid out over several lines, only its start tag is
+    /// written, and the namespaces it declares are returned so that its children can be written
+    /// next.
+    fn element(
+        &mut self,
+        elem: &Element,
+        depth: usize,
+        parent_scope: &Bindings,
+    ) -> Result<Option<Bindings>, Error> {
+        self.start_line(depth)?;
+        let column = self.column(depth);
+        let mut out = String::new();
+
+        if is_verbatim(elem) {
+            write_inline(&mut out, elem, parent_scope, true, None, self.options);
+            self.w.write_all(out.as_bytes())?;
+            return Ok(None);
+        }
+
+        let mut content = elem.children.iter().filter(|n| !is_ignorable(n)).peekable();
+        if content.peek().is_none() {
+            let (declarations, _) = declarations(elem, parent_scope);
+            write_start(
+                &mut out,
+                elem,
+                &declarations,
+                Some(column),
+                true,
+                self.options,
+            );
+            self.w.write_all(out.as_bytes())?;
+            return Ok(None);
+        }
+
+        let text_only = content.all(|n| matches!(n, XMLNode::Text(_) | XMLNode::CData(_)));
+        if text_only && self.options.collapse_text_only_elements {
+            write_inline(&mut out, elem, parent_scope, false, None, self.options);
+            self.w.write_all(out.as_bytes())?;
+            return Ok(None);
+        }
+        if text_only || self.options.inline_short_elements {
+            let limit = self.options.max_width.saturating_sub(column);
+            if write_inline(
+                &mut out,
+                elem,
+                parent_scope,
+                false,
+                Some(limit),
+                self.options,
+            ) {
+                self.w.write_all(out.as_bytes())?;
+                return Ok(None);
+            }
+            out.clear();
+        }
+
+        let (declarations, scope) = declarations(elem, parent_scope);
+        write_start(
+            &mut out,
+            elem,
+            &declarations,
+            Some(column),
+            false,
+            self.options,
+        );
+        self.w.write_all(out.as_bytes())?;
+        Ok(Some(scope))
+    }
+}
+
+/// Writes `root` and its descendants on a single line, keeping whitespace between elements if
+/// `verbatim` is set or an element needs it.
+///
+/// Returns false, leaving `out` incomplete, if the result would be longer than `limit` characters
+/// or span several lines.
+fn write_inline(
+    out: &mut String,
+    root: &Element,
+    parent_scope: &Bindings,
+    verbatim: bool,
+    limit: Option<usize>,
+    options: &FormatOptions,
+) -> bool {
+    let fits = |out: &String| match limit {
+        Some(limit) => !out.contains('\n') && out.chars().count() <= limit,
+        None => true,
+    };
+
+    let mut stack: Vec<(&Element, std::slice::Iter<XMLNode>, Bindings, bool)> = Vec::new();
+    let mut next = Some(root);
+    loop {
+        if let Some(elem) = next.take() {
+            let parent = stack.last();
+            let scope = parent.map_or(parent_scope, |(_, _, scope, _)| scope);
+            let verbatim = parent.map_or(verbatim, |&(_, _, _, v)| v) || is_verbatim(elem);
+            let (declarations, scope) = declarations(elem, scope);
+            let empty = if verbatim {
+                elem.children.is_empty()
+            } else {
+                elem.children.iter().all(is_ignorable)
+            };
+            write_start(out, elem, &declarations, None, empty, options);
+            if !empty {
+                stack.push((elem, elem.children.iter(), scope, verbatim));
+            }
+            if !fits(out) {
+                return false;
+            }
+        }
+
+        let (elem, children, _, verbatim) = match stack.last_mut() {
+            Some(frame) => frame,
+            None => return true,
+        };
+        match children.next() {
+            Some(XMLNode::Element(child)) => next = Some(child),
+            Some(node) if !*verbatim && is_ignorable(node) => (),
+            Some(node) => write_leaf(out, node),
+            None => {
+                out.push_str("</");
+                out.push_str(&qualified_name(&elem.prefix, &elem.name));
+                out.push('>');
+                stack.pop();
+            }
+        }
+        if !fits(out) {
+            return false;
+        }
+    }
+}
+
+/// Writes a node other than an element, as it is
+fn write_leaf(out: &mut String, node: &XMLNode) {
+    match node {
+        XMLNode::Element(_) => unreachable!("elements are written by the caller"),
+        XMLNode::Text(text) | XMLNode::Whitespace(text) => escape_text(out, text),
+        XMLNode::CData(text) => {
+            out.push_str("<![CDATA[");
+            out.push_str(text);
+            out.push_str("]]>");
+        }
+        XMLNode::Comment(comment) => {
+            out.push_str("<!--");
+            out.push_str(comment);
+            out.push_str("-->");
+        }
+        XMLNode::ProcessingInstruction(name, data) => {
+            out.push_str("<?");
+            out.push_str(name);
+            if let Some(data) = data {
+                out.push(' ');
+                out.push_str(data.trim_start());
+            }
+            out.push_str("?>");
+        }
+    }
+}
+
+/// Writes the start tag of `elem`, or its empty-element tag if `empty` is set and enabled.  With
+/// a `column`, the attributes are wrapped if the tag doesn't fit.
+fn write_start(
+    out: &mut String,
+    elem: &Element,
+    declarations: &[(String, String)],
+    column: Option<usize>,
+    empty: bool,
+    options: &FormatOptions,
+) {
+    let name = qualified_name(&elem.prefix, &elem.name);
+    let mut items: Vec<String> = declarations
+        .iter()
+        .map(|(prefix, uri)| {
+            let mut item = if prefix.is_empty() {
+                "xmlns=\"".to_owned()
+            } else {
+                format!("xmlns:{}=\"", prefix)
+            };
+            escape_attribute(&mut item, uri);
+            item.push('"');
+            item
+        })
+        .collect();
+    let mut attributes: Vec<_> = elem
+        .attributes
+        .iter()
+        .map(|(name, value)| (qualified_name(&name.prefix, &name.local_name), value))
+        .collect();
+    if options.sort_attributes {
+        attributes.sort();
+    }
+    for (name, value) in attributes {
+        let mut item = format!("{}=\"", name);
+        escape_attribute(&mut item, value);
+        item.push('"');
+        items.push(item);
+    }
+
+    let self_closing = empty && options.normalize_empty_elements;
+    let end = match (self_closing, options.pad_self_closing) {
+        (true, true) => " />",
+        (true, false) => "/>",
+        (false, _) => ">",
+    };
+    let width = 1
+        + name.chars().count()
+        + items.iter().map(|i| 1 + i.chars().count()).sum::<usize>()
+        + end.len();
+    let wrap = match column {
+        Some(column) => {
+            options.wrap_attributes && items.len() > 1 && column + width > options.max_width
+        }
+        None => false,
+    };
+
+    out.push('<');
+    out.push_str(&name);
+    if wrap {
+        let column = column.unwrap_or(0);
+        let indent = if options.align_attributes {
+            " ".repeat(column + name.chars().count() + 2)
+        } else {
+            let mut indent = " ".repeat(column);
+            indent.push_str(&options.indent_string);
+            indent
+        };
+        for (i, item) in items.iter().enumerate() {
+            if i == 0 && options.align_attributes {
+                out.push(' ');
+            } else {
+                out.push_str(&options.line_separator);
+                out.push_str(&indent);
+            }
+            out.push_str(item);
+        }
+    } else {
+        for item in &items {
+            out.push(' ');
+            out.push_str(item);
+        }
+    }
+    out.push_str(end);
+    if !self_closing && empty {
+        out.push_str("</");
+        out.push_str(&name);
+        out.push('>');
+    }
+}
+
+/// Works out the namespace declarations `elem` needs, given those of its parent.  Returns the
+/// declarations and the bindings in scope of `elem`.
+fn declarations(elem: &Element, parent_scope: &Bindings) -> (Vec<(String, String)>, Bindings) {
+    // Elements built in code may use a prefix without declaring it, so the bindings from the
+    // names themselves take precedence
+    let mut wanted = Bindings::new();
+    if let Some(ref namespaces) = elem.namespaces {
+        for (prefix, uri) in namespaces {
+            if prefix != NS_XML_PREFIX && prefix != NS_XMLNS_PREFIX {
+                wanted.insert(prefix.to_owned(), uri.to_owned());
+            }
+        }
+    }
+    wanted.insert(
+        elem.prefix.clone().unwrap_or_default(),
+        elem.namespace.clone().unwrap_or_default(),
+    );
+    for name in elem.attributes.keys() {
+        if let (Some(prefix), Some(namespace)) = (&name.prefix, &name.namespace) {
+            if prefix != NS_XML_PREFIX {
+                wanted.insert(prefix.clone(), namespace.clone());
+            }
+        }
+    }
+
+    // An empty default namespace is the same as having none, and other prefixes can't be
+    // undeclared in XML 1.0
+    let mut scope = parent_scope.clone();
+    let mut declarations = Vec::new();
+    for (prefix, uri) in wanted {
+        let current = scope.get(&prefix).map_or("", |u| u.as_str());
+        if uri != current && (prefix.is_empty() || !uri.is_empty()) {
+            scope.insert(prefix.clone(), uri.clone());
+            declarations.push((prefix, uri));
+        }
+    }
+    (declarations, scope)
+}
+
+/// Whether a node can be left out between elements, because the indentation replaces it
+fn is_ignorable(node: &XMLNode) -> bool {
+    match node {
+        XMLNode::Whitespace(_) => true,
+        XMLNode::Text(text) => text.trim().is_empty(),
+        _ => false,
+    }
+}
+
+/// Whether the content of `elem` has to be written exactly as it is: with `xml:space="preserve"`
+/// or mixed content, where adding whitespace would change the text
+fn is_verbatim(elem: &Element) -> bool {
+    let preserve = elem.attributes.iter().any(|(name, value)| {
+        name.local_name == "space"
+            && name.prefix.as_deref() == Some(NS_XML_PREFIX)
+            && value == "preserve"
+    });
+    preserve || is_mixed(&elem.children)
+}
+
+/// Whether `nodes` mix text with other nodes
+fn is_mixed(nodes: &[XMLNode]) -> bool {
+    let has_text = nodes
+        .iter()
+        .any(|n| matches!(n, XMLNode::Text(_) | XMLNode::CData(_)) && !is_ignorable(n));
+    let has_other = nodes.iter().any(|n| {
+        matches!(
+            n,
+            XMLNode::Element(_) | XMLNode::Comment(_) | XMLNode::ProcessingInstruction(..)
+        )
+    });
+    has_text && has_other
+}
+
+fn qualified_name(prefix: &Option<String>, name: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{}:{}", prefix, name),
+        None => name.to_owned(),
+    }
+}
+
+fn escape_text(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn escape_attribute(out: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\t' => out.push_str("&#x9;"),
+            '\n' => out.push_str("&#xA;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}
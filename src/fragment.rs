@@ -0,0 +1,65 @@
+use std::fmt;
+use std::io::Write;
+
+use crate::{format, EmitterConfig, Error, FormatOptions, XMLNode};
+
+/// A sequence of nodes, such as the children of an element, that can be written out without a
+/// document around it.
+///
+/// Formatting a `Fragment` with `{}` writes the nodes as they are, and `{:#}` pretty-prints them
+/// with the default [`FormatOptions`], like [`Element`](crate::Element) does.  Nodes that mix
+/// text with elements are written as they are, like the content of an element would be.
+///
+/// ```
+/// use xmltree::{Element, Fragment};
+///
+/// let elem: Element = "<p>Some <b>bold</b> text</p>".parse().unwrap();
+/// assert_eq!(Fragment(&elem.children).to_string(), "Some <b>bold</b> text");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fragment<'a>(pub &'a [XMLNode]);
+
+impl<'a> Fragment<'a> {
+    /// Writes out the nodes, without a document declaration
+    pub fn write<W: Write>(&self, w: W) -> Result<(), Error> {
+        self.write_with_config(w, EmitterConfig::new())
+    }
+
+    /// Writes out the nodes using the provided configuration.  The document declaration is never
+    /// written, whatever the configuration says.
+    pub fn write_with_config<W: Write>(&self, w: W, config: EmitterConfig) -> Result<(), Error> {
+        let config = config.write_document_declaration(false);
+        let mut emitter = xml::writer::EventWriter::new_with_config(w, config);
+        for node in self.0 {
+            node._write(&mut emitter)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> From<&'a [XMLNode]> for Fragment<'a> {
+    fn from(nodes: &'a [XMLNode]) -> Fragment<'a> {
+        Fragment(nodes)
+    }
+}
+
+impl<'a> From<&'a XMLNode> for Fragment<'a> {
+    fn from(node: &'a XMLNode) -> Fragment<'a> {
+        Fragment(std::slice::from_ref(node))
+    }
+}
+
+impl fmt::Display for Fragment<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = Vec::new();
+        let result = if f.alternate() {
+            let options = FormatOptions::new().write_document_declaration(false);
+            format::write_fragment(&mut out, self.0, &options)
+        } else {
+            self.write(&mut out)
+        };
+        result.map_err(|_| fmt::Error)?;
+        // Both writers only ever write UTF-8
+        f.write_str(&String::from_utf8_lossy(&out))
+    }
+}
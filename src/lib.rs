@@ -1,6 +1,34 @@
 //! A simple library for parsing an XML file into an in-memory tree structure
 //!
-//! Not recommended for large XML files, as it will load the entire file into memory.
+//! Not recommended for large XML files, as it will load the entire file into memory.  For large
+//! documents made of many small records, [`Element::stream`] builds one record at a time instead,
+//! and [`TreeWriter`] writes them out one at a time.  The `async` feature adds versions of parsing,
+//! writing and streaming that work with the `AsyncRead` and `AsyncWrite` traits of `futures-io`.
+//! The `quick-xml` feature adds the faster quick-xml parser, which [`ParseOptions::backend`] can
+//! select instead of xml-rs; see [`Backend`].  With it, [`ElementRef`] reads documents that are
+//! already in memory without copying their text.
+//!
+//! [`Element::parse`] only keeps the root element.  To also keep the XML declaration, the DOCTYPE
+//! and any comments around the root element, parse into a [`Document`] instead.
+//!
+//! Trees can be queried with XPath 1.0 expressions using the [`xpath`] module.  With the `serde`
+//! feature enabled, [`from_element`] and [`to_element`] convert between elements and Rust types.
+//! The [`FromElement`] and [`IntoElement`] traits do the same without serde, and can be derived
+//! with the `derive` feature.
+//!
+//! [`Element::write_canonical`] writes out the Canonical XML form of a tree, which is stable
+//! enough to compare byte for byte or to compute digests for XML signatures.  The `xmldsig`
+//! module, enabled by the `xmldsig` feature, builds on it to sign and verify documents.
+//!
+//! [`Element::write_formatted`] pretty-prints a tree with the layout options of [`FormatOptions`],
+//! independently of the xml-rs writer.
+//!
+//! [`diff`] compares two trees and describes where they differ, and [`Patch`] applies changes
+//! written in the RFC 5261 XML patch format.
+//!
+//! Elements own their children and can't reach their parents.  Converting an element into a
+//! [`Tree`] gives every node a parent and siblings to navigate to, and makes moving nodes around
+//! cheap.
 //!
 //! # Example
 //!
@@ -22,7 +50,7 @@
 //! {
 //!     // get first `name` element
 //!     let name = names_element.get_mut_child("name").expect("Can't find name element");
-//!     name.attributes.insert("suffix".to_owned(), "mr".to_owned());
+//!     name.attributes.insert("suffix".into(), "mr".to_owned());
 //! }
 //! names_element.write(File::create("result.xml").unwrap());
 //!
@@ -51,14 +79,77 @@ pub type AttributeMap<K, V> = std::collections::HashMap<K, V>;
 compile_error!("`attribute-order` and `attribute-sorted` are mutually exclusive — pick one");
 
 use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::io::{Read, Write};
+use std::str::FromStr;
+
+#[cfg(feature = "async")]
+mod async_io;
+mod borrowed;
+mod canonical;
+#[cfg(feature = "serde")]
+mod de;
+mod debug;
+mod diff;
+mod document;
+mod encoding;
+mod format;
+mod fragment;
+mod limits;
+mod mapping;
+mod patch;
+#[cfg(feature = "quick-xml")]
+mod quick;
+mod selector;
+#[cfg(feature = "serde")]
+mod ser;
+mod stream;
+mod traversal;
+mod tree;
+mod writer;
+#[cfg(feature = "xmldsig")]
+pub mod xmldsig;
+pub mod xpath;
 
+#[cfg(feature = "async")]
+pub use async_io::AsyncElementStream;
+pub use borrowed::{AttributeRef, ElementRef, XMLNodeRef};
+pub use canonical::CanonicalOptions;
+#[cfg(feature = "serde")]
+pub use de::{from_element, SerdeError};
+pub use diff::{diff, diff_with_options, Change, ChangeKind, DiffOptions};
+pub use document::Document;
+pub use format::FormatOptions;
+pub use fragment::Fragment;
+use limits::Usage;
+pub use limits::{Limit, ParseLimits};
+#[doc(hidden)]
+pub use mapping::__derive;
+pub use mapping::{FromElement, FromElementError, FromElementErrorKind, IntoElement};
+pub use patch::{Patch, PatchError, PatchErrorKind};
+pub use selector::{Selector, SelectorError};
+#[cfg(feature = "serde")]
+pub use ser::to_element;
+pub use stream::ElementStream;
+pub use traversal::{Descendants, DescendantsMut, Edge, FindAll, Traverse};
+pub use tree::{Ancestors, Children, NodeId, Tree};
+pub use writer::TreeWriter;
+use xml::attribute::OwnedAttribute;
+use xml::common::Position;
+pub use xml::common::{TextPosition, XmlVersion};
+use xml::name::OwnedName;
 pub use xml::namespace::Namespace;
+use xml::namespace::{NS_NO_PREFIX, NS_XMLNS_PREFIX, NS_XML_PREFIX, NS_XML_URI};
 pub use xml::reader::ParserConfig;
 use xml::reader::{EventReader, XmlEvent};
 pub use xml::writer::{EmitterConfig, Error};
+#[cfg(feature = "derive")]
+pub use xmltree_derive::{FromElement, IntoElement};
 
+// Elements are the most common nodes, so boxing them would cost more than it saves
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum XMLNode {
     Element(Element),
@@ -66,6 +157,111 @@ pub enum XMLNode {
     CData(String),
     Text(String),
     ProcessingInstruction(String, Option<String>),
+    /// Whitespace-only text, only produced when [`ParseOptions::preserve_whitespace`] is enabled
+    Whitespace(String),
+}
+
+/// The name of an attribute, qualified with its namespace.
+///
+/// Two attribute names are equal if they have the same local name and namespace URI.  The prefix
+/// is only used when writing the attribute back out, so `xlink:href` and `xl:href` refer to the
+/// same attribute as long as both prefixes are bound to the same namespace.
+///
+/// Attributes without a prefix can be created from a `&str` or `String`, so inserting a plain
+/// attribute is as simple as `elem.attributes.insert("id".into(), "foo".to_owned())`.
+#[derive(Debug, Clone)]
+pub struct AttributeName {
+    /// The name of the attribute.  Does not include any namespace info
+    pub local_name: String,
+
+    /// The namespace URI of this attribute, if any
+    pub namespace: Option<String>,
+
+    /// The prefix of this attribute, if any
+    pub prefix: Option<String>,
+}
+
+impl AttributeName {
+    /// Create an attribute name with no namespace and no prefix
+    pub fn local<S: Into<String>>(local_name: S) -> AttributeName {
+        AttributeName {
+            local_name: local_name.into(),
+            namespace: None,
+            prefix: None,
+        }
+    }
+
+    /// Create an attribute name in the given namespace, written out with the given prefix
+    pub fn qualified<S, N, P>(local_name: S, namespace: N, prefix: P) -> AttributeName
+    where
+        S: Into<String>,
+        N: Into<String>,
+        P: Into<String>,
+    {
+        AttributeName {
+            local_name: local_name.into(),
+            namespace: Some(namespace.into()),
+            prefix: Some(prefix.into()),
+        }
+    }
+}
+
+impl PartialEq for AttributeName {
+    fn eq(&self, other: &AttributeName) -> bool {
+        self.local_name == other.local_name && self.namespace == other.namespace
+    }
+}
+
+impl Eq for AttributeName {}
+
+impl Hash for AttributeName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.local_name.hash(state);
+        self.namespace.hash(state);
+    }
+}
+
+impl PartialOrd for AttributeName {
+    fn partial_cmp(&self, other: &AttributeName) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for AttributeName {
+    fn cmp(&self, other: &AttributeName) -> Ordering {
+        (&self.namespace, &self.local_name).cmp(&(&other.namespace, &other.local_name))
+    }
+}
+
+impl fmt::Display for AttributeName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.prefix {
+            Some(ref p) => write!(f, "{}:{}", p, self.local_name),
+            None => f.write_str(&self.local_name),
+        }
+    }
+}
+
+impl From<&str> for AttributeName {
+    fn from(s: &str) -> AttributeName {
+        AttributeName::local(s)
+    }
+}
+
+impl From<String> for AttributeName {
+    fn from(s: String) -> AttributeName {
+        AttributeName::local(s)
+    }
+}
+
+impl From<xml::name::OwnedName> for AttributeName {
+    fn from(name: xml::name::OwnedName) -> AttributeName {
+        AttributeName {
+            local_name: name.local_name,
+            namespace: name.namespace,
+            prefix: name.prefix,
+        }
+    }
 }
 
 trait AttributeMapExt {
@@ -129,10 +325,200 @@ impl XMLNode {
             None
         }
     }
+    pub fn as_whitespace(&self) -> Option<&str> {
+        if let XMLNode::Whitespace(c) = self {
+            Some(c)
+        } else {
+            None
+        }
+    }
+}
+
+impl XMLNode {
+    /// The number of bytes of content in this node, as counted by [`ParseLimits::max_text_bytes`]
+    fn content_len(&self) -> usize {
+        match self {
+            XMLNode::Element(_) => 0,
+            XMLNode::Comment(s) | XMLNode::CData(s) | XMLNode::Text(s) | XMLNode::Whitespace(s) => {
+                s.len()
+            }
+            XMLNode::ProcessingInstruction(name, data) => {
+                name.len() + data.as_ref().map_or(0, |d| d.len())
+            }
+        }
+    }
+
+    /// Parses a fragment of XML, such as the content of an element: any number of elements, text,
+    /// comments, CDATA sections and processing instructions, in any order.
+    ///
+    /// Prefixes the fragment uses without declaring them are resolved with `namespaces`, as if
+    /// the fragment were the content of an element where they are in scope, and a default
+    /// namespace in `namespaces` applies to elements without a prefix.  The parsed elements keep
+    /// these bindings in [`Element::namespaces`], so that they can be inserted into a tree that
+    /// declares them.  Comments are kept.
+    ///
+    /// ```
+    /// use xmltree::{Element, XMLNode};
+    ///
+    /// let mut doc: Element = r#"<doc xmlns:x="urn:x"><x:a/></doc>"#.parse().unwrap();
+    /// let nodes = XMLNode::parse_fragment("<x:b/>text", doc.namespaces.as_ref().unwrap()).unwrap();
+    /// assert_eq!(nodes[0].as_element().unwrap().namespace.as_deref(), Some("urn:x"));
+    /// assert_eq!(nodes[1].as_text(), Some("text"));
+    ///
+    /// doc.children.extend(nodes);
+    /// assert_eq!(doc.to_string(), r#"<doc xmlns:x="urn:x"><x:a /><x:b />text</doc>"#);
+    /// ```
+    pub fn parse_fragment(data: &str, namespaces: &Namespace) -> Result<Vec<XMLNode>, ParseError> {
+        let config = ParserConfig::new().ignore_comments(false);
+        XMLNode::parse_fragment_with_options(data, namespaces, config, ParseOptions::new())
+    }
+
+    /// Parses a fragment of XML like [`XMLNode::parse_fragment`], using the given parser
+    /// configuration and tree-building options
+    ///
+    /// The fragment is parsed as the content of an element, so it can be nested one level less
+    /// deep than [`ParseLimits::max_depth`] allows.
+    pub fn parse_fragment_with_options(
+        data: &str,
+        namespaces: &Namespace,
+        config: ParserConfig,
+        options: ParseOptions,
+    ) -> Result<Vec<XMLNode>, ParseError> {
+        // xml-rs only parses documents, so wrap the fragment in an element that declares the
+        // namespaces
+        let mut start = String::from("<xmltree-fragment");
+        for (prefix, uri) in namespaces {
+            let uri = uri
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('"', "&quot;");
+            match prefix {
+                NS_XML_PREFIX | NS_XMLNS_PREFIX => (),
+                NS_NO_PREFIX if uri.is_empty() => (),
+                NS_NO_PREFIX => start.push_str(&format!(" xmlns=\"{}\"", uri)),
+                _ => start.push_str(&format!(" xmlns:{}=\"{}\"", prefix, uri)),
+            }
+        }
+        start.push('>');
+        let input = || {
+            start
+                .as_bytes()
+                .chain(data.as_bytes())
+                .chain(&b"</xmltree-fragment>"[..])
+        };
+        // Positions on the first line are off by the length of the wrapper's start tag, and
+        // errors in the wrapper's end tag are reported at the end of the fragment
+        let offset = start.chars().count() as u64;
+        let end = TextPosition {
+            row: data.matches('\n').count() as u64,
+            column: data.rsplit('\n').next().unwrap_or_default().chars().count() as u64,
+        };
+        let mut nodes = Element::parse_all_with_options(input(), config, options)
+            .map_err(|e| e.shifted(offset, end))?;
+        // The fragment can close the wrapper itself, with content after it that is not part of
+        // the wrapper.  The wrapper's own end tag then needs another element to close.
+        if nodes.len() != 1 {
+            let mut reader = EventReader::new(input());
+            let mut depth = 0usize;
+            let position = loop {
+                match reader.next() {
+                    Ok(XmlEvent::StartElement { .. }) => depth += 1,
+                    Ok(XmlEvent::EndElement { .. }) if depth == 1 => break reader.position(),
+                    Ok(XmlEvent::EndElement { .. }) => depth -= 1,
+                    Ok(XmlEvent::EndDocument) | Err(_) => break reader.position(),
+                    Ok(_) => (),
+                }
+            };
+            let error = (&position, "the fragment closes the element it is parsed in");
+            return Err(ParseError::MalformedXml(error.into()).shifted(offset, end));
+        }
+        let mut wrapper = match nodes.pop() {
+            Some(XMLNode::Element(wrapper)) => wrapper,
+            _ => return Err(ParseError::NoRootElement),
+        };
+        let mut nodes = std::mem::take(&mut wrapper.children);
+
+        let mut stack: Vec<&mut XMLNode> = nodes.iter_mut().collect();
+        while let Some(node) = stack.pop() {
+            if let XMLNode::Element(elem) = node {
+                for span in elem.span.iter_mut().chain(&mut elem.child_spans) {
+                    for position in [&mut span.start, &mut span.end_tag] {
+                        if position.row == 0 {
+                            position.column -= offset;
+                        }
+                    }
+                }
+                stack.extend(elem.children.iter_mut());
+            }
+        }
+        Ok(nodes)
+    }
+
+    /// Writes out this node on its own, without a document declaration
+    pub fn write<W: Write>(&self, w: W) -> Result<(), Error> {
+        Fragment::from(self).write(w)
+    }
+
+    /// Writes out this node on its own using the provided configuration, see
+    /// [`Fragment::write_with_config`]
+    pub fn write_with_config<W: Write>(&self, w: W, config: EmitterConfig) -> Result<(), Error> {
+        Fragment::from(self).write_with_config(w, config)
+    }
+
+    pub(crate) fn _write<B: Write>(
+        &self,
+        emitter: &mut xml::writer::EventWriter<B>,
+    ) -> Result<(), Error> {
+        use xml::writer::events::XmlEvent;
+
+        match self {
+            XMLNode::Element(elem) => elem._write(emitter),
+            XMLNode::Text(text) | XMLNode::Whitespace(text) => {
+                emitter.write(XmlEvent::Characters(text))
+            }
+            XMLNode::Comment(comment) => emitter.write(XmlEvent::Comment(comment)),
+            XMLNode::CData(comment) => emitter.write(XmlEvent::CData(comment)),
+            XMLNode::ProcessingInstruction(name, data) => {
+                emitter.write(XmlEvent::ProcessingInstruction {
+                    name,
+                    data: data.as_deref(),
+                })
+            }
+        }
+    }
+}
+
+/// Writes the node without a document declaration.  With `{:#}`, it is pretty-printed with the
+/// default [`FormatOptions`].
+impl fmt::Display for XMLNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&Fragment::from(self), f)
+    }
+}
+
+/// The location of a node in the source document.
+///
+/// Positions are zero-based, as reported by xml-rs; add one to each for the usual human-readable
+/// line and column numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    /// The position of the start tag, or of the start of a node other than an element
+    pub start: TextPosition,
+    /// The position of the end tag.  For an empty element like `<a/>`, and for nodes other than
+    /// elements, this is the same as `start`
+    ///
+    /// This is where the end tag begins, not where it ends: the parser doesn't report the length
+    /// of the tag.
+    pub end_tag: TextPosition,
 }
 
 /// Represents an XML element.
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// `Clone`, `PartialEq`, `Debug` and `Drop` are implemented without recursion, so arbitrarily deep
+/// trees can be handled without overflowing the stack.
+///
+/// Elements are created with [`Element::new`] or by parsing, as more fields may be added.
+#[non_exhaustive]
 pub struct Element {
     /// This elements prefix, if any
     pub prefix: Option<String>,
@@ -155,94 +541,464 @@ pub struct Element {
     /// * If the "attribute-order" feature is enabled, then this is an [IndexMap](https://docs.rs/indexmap/2/indexmap/),
     ///   which will retain item insertion order.
     /// * If the "attribute-sorted" feature is enabled, then this is a [`std::collections::BTreeMap`], which maintains keys in sorted order.
-    pub attributes: AttributeMap<String, String>,
+    ///
+    /// Keys are [`AttributeName`]s, so attributes in different namespaces never collide.  Use
+    /// [`Element::get_attribute`] or [`Element::get_attribute_ns`] for lookups.
+    pub attributes: AttributeMap<AttributeName, String>,
 
     /// Children
     pub children: Vec<XMLNode>,
+
+    /// Where this element was found in the source document
+    ///
+    /// Only set when parsing with [`ParseOptions::track_positions`] enabled.  The span is not
+    /// part of the element's identity, so it is ignored when comparing elements.
+    pub span: Option<Span>,
+
+    /// Where each of the children was found in the source document, in the same order as
+    /// `children` as parsed.  Read through [`Element::child_span`], which notices when it no
+    /// longer lines up with `children`.
+    pub(crate) child_spans: Vec<Span>,
+}
+
+impl Element {
+    /// Clones everything but the children of this element
+    fn clone_shallow(&self) -> Element {
+        Element {
+            prefix: self.prefix.clone(),
+            namespace: self.namespace.clone(),
+            namespaces: self.namespaces.clone(),
+            name: self.name.clone(),
+            attributes: self.attributes.clone(),
+            children: Vec::with_capacity(self.children.len()),
+            span: self.span,
+            child_spans: self.child_spans.clone(),
+        }
+    }
+
+    /// Compares everything but the children of this element
+    fn eq_shallow(&self, other: &Element) -> bool {
+        self.prefix == other.prefix
+            && self.namespace == other.namespace
+            && self.namespaces == other.namespaces
+            && self.name == other.name
+            && self.attributes == other.attributes
+            && self.children.len() == other.children.len()
+    }
+}
+
+impl Clone for Element {
+    fn clone(&self) -> Element {
+        let mut stack = vec![(self.children.iter(), self.clone_shallow())];
+        loop {
+            let (children, copy) = stack.last_mut().expect("clone stack is never empty");
+            match children.next() {
+                Some(XMLNode::Element(child)) => {
+                    stack.push((child.children.iter(), child.clone_shallow()));
+                }
+                Some(node) => copy.children.push(node.clone()),
+                None => {
+                    let (_, finished) = stack.pop().expect("clone stack is never empty");
+                    match stack.last_mut() {
+                        Some((_, parent)) => parent.children.push(XMLNode::Element(finished)),
+                        None => return finished,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl PartialEq for Element {
+    fn eq(&self, other: &Element) -> bool {
+        let mut stack = vec![(self, other)];
+        while let Some((a, b)) = stack.pop() {
+            if !a.eq_shallow(b) {
+                return false;
+            }
+            for pair in a.children.iter().zip(&b.children) {
+                match pair {
+                    (XMLNode::Element(x), XMLNode::Element(y)) => stack.push((x, y)),
+                    (x, y) => {
+                        if x != y {
+                            return false;
+                        }
+                    }
+                }
+            }
+        }
+        true
+    }
+}
+
+impl Eq for Element {}
+
+/// Writes the element without a document declaration.  With `{:#}`, it is pretty-printed with the
+/// default [`FormatOptions`].
+///
+/// ```
+/// use xmltree::Element;
+///
+/// let elem: Element = "<a><b>text</b></a>".parse().unwrap();
+/// assert_eq!(elem.to_string(), "<a><b>text</b></a>");
+/// assert_eq!(format!("{:#}", elem), "<a>\n  <b>text</b>\n</a>");
+/// ```
+impl fmt::Display for Element {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = Vec::new();
+        let result = if f.alternate() {
+            let options = FormatOptions::new().write_document_declaration(false);
+            self.write_formatted(&mut out, &options)
+        } else {
+            self.write_with_config(
+                &mut out,
+                EmitterConfig::new().write_document_declaration(false),
+            )
+        };
+        result.map_err(|_| fmt::Error)?;
+        // Both writers only ever write UTF-8
+        f.write_str(&String::from_utf8_lossy(&out))
+    }
+}
+
+/// Parses a document into its root element, like [`Element::parse`]
+impl FromStr for Element {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Element, ParseError> {
+        Element::parse(s.as_bytes())
+    }
+}
+
+impl Drop for Element {
+    fn drop(&mut self) {
+        self.clear_children();
+    }
+}
+
+impl fmt::Debug for Element {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        debug::fmt_tree(self, f)
+    }
+}
+
+impl debug::DebugTree for Element {
+    type Node = XMLNode;
+
+    const NAME: &'static str = "Element";
+
+    fn head(&self) -> [(&'static str, &dyn fmt::Debug); 5] {
+        [
+            ("prefix", &self.prefix),
+            ("namespace", &self.namespace),
+            ("namespaces", &self.namespaces),
+            ("name", &self.name),
+            ("attributes", &self.attributes),
+        ]
+    }
+
+    fn tail(&self) -> [(&'static str, &dyn fmt::Debug); 2] {
+        [("span", &self.span), ("child_spans", &self.child_spans)]
+    }
+
+    fn children(&self) -> &[XMLNode] {
+        &self.children
+    }
+
+    fn as_element(node: &XMLNode) -> Option<&Element> {
+        node.as_element()
+    }
 }
 
 /// Errors that can occur parsing XML
 #[derive(Debug)]
 pub enum ParseError {
-    /// The XML is invalid
+    /// The XML is invalid, for example because an end tag does not match the element that is
+    /// currently open
     MalformedXml(xml::reader::Error),
-    /// This library is unable to process this XML. This can occur if, for
-    /// example, the XML contains processing instructions.
-    CannotParse,
+    /// The document does not contain a root element
+    NoRootElement,
+    /// The parser reported the start or end of a document inside of an element
+    UnexpectedDocumentEvent {
+        /// The position of the unexpected event
+        position: TextPosition,
+    },
+    /// The document exceeds one of the configured [`ParseLimits`]
+    LimitExceeded {
+        /// The limit that was exceeded
+        limit: Limit,
+        /// The position at which the limit was exceeded
+        position: TextPosition,
+    },
+}
+
+impl ParseError {
+    /// Moves positions on the first line `offset` columns to the left, and positions past `end`
+    /// back to it
+    fn shifted(self, offset: u64, end: TextPosition) -> ParseError {
+        let shift = |mut position: TextPosition| {
+            if position.row == 0 {
+                position.column = position.column.saturating_sub(offset);
+            }
+            std::cmp::min_by_key(position, end, |p| (p.row, p.column))
+        };
+        match self {
+            ParseError::MalformedXml(e) => match e.kind() {
+                xml::reader::ErrorKind::Syntax(msg) => {
+                    let msg = msg.clone();
+                    ParseError::MalformedXml((&shift(e.position()), msg).into())
+                }
+                _ => ParseError::MalformedXml(e),
+            },
+            ParseError::NoRootElement => ParseError::NoRootElement,
+            ParseError::UnexpectedDocumentEvent { position } => {
+                ParseError::UnexpectedDocumentEvent {
+                    position: shift(position),
+                }
+            }
+            ParseError::LimitExceeded { limit, position } => ParseError::LimitExceeded {
+                limit,
+                position: shift(position),
+            },
+        }
+    }
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             ParseError::MalformedXml(ref e) => write!(f, "Malformed XML. {}", e),
-            ParseError::CannotParse => write!(f, "Cannot parse"),
+            ParseError::NoRootElement => write!(f, "No root element"),
+            ParseError::UnexpectedDocumentEvent { position } => {
+                write!(f, "{}: Unexpected start or end of document", position)
+            }
+            ParseError::LimitExceeded { limit, position } => {
+                write!(f, "{}: Maximum {} exceeded", position, limit)
+            }
         }
     }
 }
 
 impl std::error::Error for ParseError {
-    fn description(&self) -> &str {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match *self {
-            ParseError::MalformedXml(..) => "Malformed XML",
-            ParseError::CannotParse => "Cannot parse",
+            ParseError::MalformedXml(ref e) => Some(e),
+            _ => None,
         }
     }
+}
 
-    fn cause(&self) -> Option<&dyn std::error::Error> {
-        match *self {
-            ParseError::MalformedXml(ref e) => Some(e),
-            ParseError::CannotParse => None,
+/// Options that control how the tree is built from the events of the XML parser.
+///
+/// These complement the xml-rs [`ParserConfig`], which configures the parser itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Keep whitespace-only text as [`XMLNode::Whitespace`] nodes, instead of dropping it.
+    ///
+    /// Writing a tree parsed this way reproduces the original formatting, which allows for
+    /// minimal-diff edits of hand-maintained files.  This only has an effect if the parser reports
+    /// whitespace, which it does unless `trim_whitespace` or `whitespace_to_characters` is enabled
+    /// in the `ParserConfig`.
+    ///
+    /// Default is false.
+    pub preserve_whitespace: bool,
+
+    /// Record where each element was found in the source, in [`Element::span`], and where each
+    /// of its children was found, see [`Element::child_span`].
+    ///
+    /// Default is false.
+    pub track_positions: bool,
+
+    /// Limits on the size of the tree, for parsing untrusted input.
+    ///
+    /// Default is no limits.
+    pub limits: ParseLimits,
+
+    /// The XML parser that produces the events.
+    ///
+    /// Default is [`Backend::XmlRs`], even with the `quick-xml` feature enabled, so that enabling
+    /// the feature doesn't change how documents are parsed.
+    pub backend: Backend,
+}
+
+impl ParseOptions {
+    /// Returns the default options
+    pub fn new() -> ParseOptions {
+        ParseOptions::default()
+    }
+
+    /// Sets [`ParseOptions::preserve_whitespace`]
+    pub fn preserve_whitespace(mut self, value: bool) -> ParseOptions {
+        self.preserve_whitespace = value;
+        self
+    }
+
+    /// Sets [`ParseOptions::track_positions`]
+    pub fn track_positions(mut self, value: bool) -> ParseOptions {
+        self.track_positions = value;
+        self
+    }
+
+    /// Sets [`ParseOptions::limits`]
+    pub fn limits(mut self, value: ParseLimits) -> ParseOptions {
+        self.limits = value;
+        self
+    }
+
+    /// Sets [`ParseOptions::backend`]
+    pub fn backend(mut self, value: Backend) -> ParseOptions {
+        self.backend = value;
+        self
+    }
+
+    /// Returns the span of the event the reader has just produced, if positions are tracked
+    fn span_here<P: Position>(&self, reader: &P) -> Option<Span> {
+        if self.track_positions {
+            let position = reader.position();
+            Some(Span {
+                start: position,
+                end_tag: position,
+            })
+        } else {
+            None
         }
     }
 }
 
-fn build<B: Read>(reader: &mut EventReader<B>, mut elem: Element) -> Result<Element, ParseError> {
+/// The XML parsers that trees can be built with.
+///
+/// Both backends build the same trees from the same input, honor the same [`ParserConfig`]
+/// settings and report malformed documents as [`ParseError::MalformedXml`].  quick-xml is
+/// considerably faster on large documents, but it has a few limitations:
+///
+/// * The input must be UTF-8, ASCII or ISO-8859-1.  UTF-16 documents are rejected.
+/// * Entities declared in the DTD can only be replaced with text, not markup.
+/// * The messages and positions of errors differ.
+/// * Elements are always reported at the `<` of their tags in [`Element::span`], while xml-rs
+///   reports some of them (the root element of a document without an XML declaration, and elements
+///   right after text starting with a reference) at the end of their start or end tag.
+///
+/// xml-rs is the default.  The backend is used by [`Element::parse_with_options`],
+/// [`Element::parse_all_with_options`], [`Document::parse_with_options`] and the functions built
+/// on them.  [`Element::stream`] always uses xml-rs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[non_exhaustive]
+pub enum Backend {
+    /// The xml-rs pull parser, which is always available
+    #[default]
+    XmlRs,
+    /// The quick-xml parser, available with the `quick-xml` feature
+    #[cfg(feature = "quick-xml")]
+    QuickXml,
+}
+
+/// A source of xml-rs events to build trees from, implemented by each parsing [`Backend`]
+pub(crate) trait Events: Position {
+    fn next_event(&mut self) -> Result<XmlEvent, xml::reader::Error>;
+
+    /// The DOCTYPE declaration of the document, if one has been read
+    fn doctype(&self) -> Option<&str>;
+}
+
+impl<R: Read> Events for EventReader<R> {
+    fn next_event(&mut self) -> Result<XmlEvent, xml::reader::Error> {
+        self.next()
+    }
+
+    fn doctype(&self) -> Option<&str> {
+        EventReader::doctype(self)
+    }
+}
+
+/// Reads the contents of `root`, whose start tag has just been read, up to and including its end
+/// tag.
+///
+/// Open elements are kept on an explicit stack rather than the call stack, so that deeply nested
+/// documents can't cause a stack overflow.
+fn build<E: Events>(
+    reader: &mut E,
+    root: Element,
+    options: &ParseOptions,
+    usage: &mut Usage,
+) -> Result<Element, ParseError> {
+    let mut builder = TreeBuilder::new(root);
     loop {
-        match reader.next() {
-            Ok(XmlEvent::EndElement { ref name }) => {
-                if name.local_name == elem.name {
-                    return Ok(elem);
-                } else {
-                    return Err(ParseError::CannotParse);
+        let event = reader.next_event().map_err(ParseError::MalformedXml)?;
+        if let Some(elem) = builder.event(event, reader, options, usage)? {
+            return Ok(elem);
+        }
+    }
+}
+
+/// Builds an element from the events that follow its start tag, one event at a time
+pub(crate) struct TreeBuilder {
+    /// The element being built, and the descendants whose end tag hasn't been read yet
+    stack: Vec<Element>,
+}
+
+impl TreeBuilder {
+    pub(crate) fn new(root: Element) -> TreeBuilder {
+        TreeBuilder { stack: vec![root] }
+    }
+
+    /// Adds `event` to the tree, and returns the root element once its end tag has been read
+    pub(crate) fn event<P: Position>(
+        &mut self,
+        event: XmlEvent,
+        reader: &P,
+        options: &ParseOptions,
+        usage: &mut Usage,
+    ) -> Result<Option<Element>, ParseError> {
+        let depth = self.stack.len();
+        let elem = self.stack.last_mut().expect("element stack is never empty");
+        let node = match event {
+            XmlEvent::EndElement { .. } => {
+                // The parser has already checked that the end tag matches
+                if let Some(ref mut span) = elem.span {
+                    span.end_tag = reader.position();
                 }
+                let finished = self.stack.pop().expect("element stack is never empty");
+                return match self.stack.last_mut() {
+                    Some(parent) => {
+                        parent.child_spans.extend(finished.span);
+                        parent.children.push(XMLNode::Element(finished));
+                        Ok(None)
+                    }
+                    None => Ok(Some(finished)),
+                };
             }
-            Ok(XmlEvent::StartElement {
+            XmlEvent::StartElement {
                 name,
                 attributes,
                 namespace,
-            }) => {
-                let mut attr_map = AttributeMap::new();
-                for attr in attributes {
-                    attr_map.insert(attr.name.local_name, attr.value);
-                }
-
-                let new_elem = Element {
-                    prefix: name.prefix,
-                    namespace: name.namespace,
-                    namespaces: if namespace.is_essentially_empty() {
-                        None
-                    } else {
-                        Some(namespace)
-                    },
-                    name: name.local_name,
-                    attributes: attr_map,
-                    children: Vec::new(),
-                };
-                elem.children
-                    .push(XMLNode::Element(build(reader, new_elem)?));
+            } => {
+                let mut new_elem = Element::from_start_event(name, attributes, namespace);
+                let (attributes, bytes) = (new_elem.attributes.len(), new_elem.attribute_bytes());
+                usage.add_element(reader, depth + 1, elem.children.len(), attributes, bytes)?;
+                new_elem.span = options.span_here(reader);
+                self.stack.push(new_elem);
+                return Ok(None);
             }
-            Ok(XmlEvent::Characters(s)) => elem.children.push(XMLNode::Text(s)),
-            Ok(XmlEvent::Whitespace(..)) => (),
-            Ok(XmlEvent::Comment(s)) => elem.children.push(XMLNode::Comment(s)),
-            Ok(XmlEvent::CData(s)) => elem.children.push(XMLNode::CData(s)),
-            Ok(XmlEvent::ProcessingInstruction { name, data }) => elem
-                .children
-                .push(XMLNode::ProcessingInstruction(name, data)),
-            Ok(XmlEvent::StartDocument { .. }) | Ok(XmlEvent::EndDocument) => {
-                return Err(ParseError::CannotParse)
+            // xml-rs can report text that `trim_whitespace` left empty, which quick-xml drops
+            XmlEvent::Characters(s) if s.is_empty() => return Ok(None),
+            XmlEvent::Characters(s) => XMLNode::Text(s),
+            XmlEvent::Whitespace(s) if options.preserve_whitespace => XMLNode::Whitespace(s),
+            XmlEvent::Whitespace(..) => return Ok(None),
+            XmlEvent::Comment(s) => XMLNode::Comment(s),
+            XmlEvent::CData(s) => XMLNode::CData(s),
+            XmlEvent::ProcessingInstruction { name, data } => {
+                XMLNode::ProcessingInstruction(name, data)
             }
-            Err(e) => return Err(ParseError::MalformedXml(e)),
-        }
+            XmlEvent::StartDocument { .. } | XmlEvent::EndDocument => {
+                return Err(ParseError::UnexpectedDocumentEvent {
+                    position: reader.position(),
+                })
+            }
+        };
+        usage.add_node(reader, elem.children.len(), node.content_len())?;
+        elem.child_spans.extend(options.span_here(reader));
+        elem.children.push(node);
+        Ok(None)
     }
 }
 
@@ -258,9 +1014,48 @@ impl Element {
             namespaces: None,
             attributes: AttributeMap::new(),
             children: Vec::new(),
+            span: None,
+            child_spans: Vec::new(),
         }
     }
 
+    /// Creates an element, without children, from the contents of a `StartElement` event
+    fn from_start_event(
+        name: OwnedName,
+        attributes: Vec<OwnedAttribute>,
+        namespace: Namespace,
+    ) -> Element {
+        let mut attr_map = AttributeMap::allocate(attributes.len());
+        for attr in attributes {
+            attr_map.insert(attr.name.into(), attr.value);
+        }
+
+        Element {
+            prefix: name.prefix,
+            namespace: name.namespace,
+            namespaces: if namespace.is_essentially_empty() {
+                None
+            } else {
+                Some(namespace)
+            },
+            name: name.local_name,
+            attributes: attr_map,
+            children: Vec::new(),
+            span: None,
+            child_spans: Vec::new(),
+        }
+    }
+
+    /// The number of bytes taken up by the names and values of the attributes
+    pub(crate) fn attribute_bytes(&self) -> usize {
+        self.attributes
+            .iter()
+            .map(|(name, value)| {
+                name.prefix.as_ref().map_or(0, String::len) + name.local_name.len() + value.len()
+            })
+            .sum()
+    }
+
     /// Parses some data into a list of `XMLNode`s
     ///
     /// This is useful when you want to capture comments or processing instructions that appear
@@ -274,92 +1069,222 @@ impl Element {
         r: R,
         parser_config: ParserConfig,
     ) -> Result<Vec<XMLNode>, ParseError> {
-        let mut reader = EventReader::new_with_config(r, parser_config);
+        Element::parse_all_with_options(r, parser_config, ParseOptions::new())
+    }
+
+    /// Parses some data into a list of `XMLNode`s, using the given parser configuration and
+    /// tree-building options
+    pub fn parse_all_with_options<R: Read>(
+        r: R,
+        parser_config: ParserConfig,
+        options: ParseOptions,
+    ) -> Result<Vec<XMLNode>, ParseError> {
+        match options.backend {
+            Backend::XmlRs => {
+                let mut reader = EventReader::new_with_config(r, parser_config);
+                Element::parse_all_events(&mut reader, &options)
+            }
+            #[cfg(feature = "quick-xml")]
+            Backend::QuickXml => {
+                let mut reader = quick::QuickReader::new(r, parser_config);
+                Element::parse_all_events(&mut reader, &options)
+            }
+        }
+    }
+
+    fn parse_all_events<E: Events>(
+        reader: &mut E,
+        options: &ParseOptions,
+    ) -> Result<Vec<XMLNode>, ParseError> {
+        let mut usage = Usage::new(options.limits);
         let mut root_nodes = Vec::new();
         loop {
-            match reader.next() {
+            let node = match reader.next_event() {
                 Ok(XmlEvent::StartElement {
                     name,
                     attributes,
                     namespace,
                 }) => {
-                    let mut attr_map = AttributeMap::allocate(attributes.len());
-                    for attr in attributes {
-                        attr_map.insert(attr.name.local_name, attr.value);
-                    }
-
-                    let root = Element {
-                        prefix: name.prefix,
-                        namespace: name.namespace,
-                        namespaces: if namespace.is_essentially_empty() {
-                            None
-                        } else {
-                            Some(namespace)
-                        },
-                        name: name.local_name,
-                        attributes: attr_map,
-                        children: Vec::new(),
-                    };
-                    root_nodes.push(XMLNode::Element(build(&mut reader, root)?));
+                    let mut root = Element::from_start_event(name, attributes, namespace);
+                    let (attributes, bytes) = (root.attributes.len(), root.attribute_bytes());
+                    usage.add_element(reader, 1, root_nodes.len(), attributes, bytes)?;
+                    root.span = options.span_here(reader);
+                    let root = build(reader, root, options, &mut usage)?;
+                    root_nodes.push(XMLNode::Element(root));
+                    continue;
                 }
-                Ok(XmlEvent::Comment(comment_string)) => {
-                    root_nodes.push(XMLNode::Comment(comment_string))
-                }
-                Ok(XmlEvent::Characters(text_string)) => {
-                    root_nodes.push(XMLNode::Text(text_string))
-                }
-                Ok(XmlEvent::CData(cdata_string)) => root_nodes.push(XMLNode::CData(cdata_string)),
+                Ok(XmlEvent::Comment(comment_string)) => XMLNode::Comment(comment_string),
+                Ok(XmlEvent::Characters(text_string)) => XMLNode::Text(text_string),
+                Ok(XmlEvent::CData(cdata_string)) => XMLNode::CData(cdata_string),
                 Ok(XmlEvent::Whitespace(..)) | Ok(XmlEvent::StartDocument { .. }) => continue,
                 Ok(XmlEvent::ProcessingInstruction { name, data }) => {
-                    root_nodes.push(XMLNode::ProcessingInstruction(name, data))
+                    XMLNode::ProcessingInstruction(name, data)
                 }
-                Ok(XmlEvent::EndElement { .. }) => (),
+                Ok(XmlEvent::EndElement { .. }) => continue,
                 Ok(XmlEvent::EndDocument) => return Ok(root_nodes),
                 Err(e) => return Err(ParseError::MalformedXml(e)),
-            }
+            };
+            usage.add_node(reader, root_nodes.len(), node.content_len())?;
+            root_nodes.push(node);
         }
     }
 
+    /// Reads a document as a stream, and returns the elements that match `predicate` one at a
+    /// time, with their children.  Everything else in the document is skipped.
+    ///
+    /// This keeps memory use bounded by the size of the largest matching element, so it can be used
+    /// on documents too large to parse as a whole.  See [`ElementStream`] for details.
+    pub fn stream<R: Read, P: ElementPredicate>(r: R, predicate: P) -> ElementStream<R, P> {
+        let config = ParserConfig::new().ignore_comments(false);
+        Element::stream_with_options(r, config, ParseOptions::new(), predicate)
+    }
+
+    /// Reads a document as a stream like [`Element::stream`], using the given parser
+    /// configuration and tree-building options
+    pub fn stream_with_options<R: Read, P: ElementPredicate>(
+        r: R,
+        config: ParserConfig,
+        options: ParseOptions,
+        predicate: P,
+    ) -> ElementStream<R, P> {
+        ElementStream::new(r, config, options, predicate)
+    }
+
+    /// Reads a document asynchronously as a stream, like [`Element::stream`].  See
+    /// [`AsyncElementStream`] for details.
+    #[cfg(feature = "async")]
+    pub fn stream_async<R, P>(r: R, predicate: P) -> AsyncElementStream<R, P>
+    where
+        R: futures_io::AsyncRead + Unpin,
+        P: ElementPredicate,
+    {
+        let config = ParserConfig::new().ignore_comments(false);
+        Element::stream_async_with_options(r, config, ParseOptions::new(), predicate)
+    }
+
+    /// Reads a document asynchronously as a stream like [`Element::stream_async`], using the
+    /// given parser configuration and tree-building options
+    #[cfg(feature = "async")]
+    pub fn stream_async_with_options<R, P>(
+        r: R,
+        config: ParserConfig,
+        options: ParseOptions,
+        predicate: P,
+    ) -> AsyncElementStream<R, P>
+    where
+        R: futures_io::AsyncRead + Unpin,
+        P: ElementPredicate,
+    {
+        AsyncElementStream::new(r, config, options, predicate)
+    }
+
     /// Parses some data into an Element
+    ///
+    /// Returns `ParseError::NoRootElement` if the data contains no element at all.
     pub fn parse<R: Read>(r: R) -> Result<Element, ParseError> {
-        let nodes = Element::parse_all(r)?;
-        for node in nodes {
-            if let XMLNode::Element(elem) = node {
-                return Ok(elem);
-            }
-        }
-        // This assume the underlying xml library throws an error on no root element
-        unreachable!();
+        let parser_config = ParserConfig::new().ignore_comments(false);
+        Element::parse_with_config(r, parser_config)
     }
 
     pub fn parse_with_config<R: Read>(r: R, config: ParserConfig) -> Result<Element, ParseError> {
-        let nodes = Element::parse_all_with_config(r, config)?;
-        for node in nodes {
-            if let XMLNode::Element(elem) = node {
-                return Ok(elem);
-            }
-        }
-        // This assume the underlying xml library throws an error on no root element
-        unreachable!();
+        Element::parse_with_options(r, config, ParseOptions::new())
     }
 
-    fn _write<B: Write>(&self, emitter: &mut xml::writer::EventWriter<B>) -> Result<(), Error> {
-        use xml::attribute::Attribute;
-        use xml::name::Name;
-        use xml::writer::events::XmlEvent;
+    /// Parses some data into an Element, using the given parser configuration and tree-building
+    /// options
+    pub fn parse_with_options<R: Read>(
+        r: R,
+        config: ParserConfig,
+        options: ParseOptions,
+    ) -> Result<Element, ParseError> {
+        let nodes = Element::parse_all_with_options(r, config, options)?;
+        nodes
+            .into_iter()
+            .find_map(|node| match node {
+                XMLNode::Element(elem) => Some(elem),
+                _ => None,
+            })
+            .ok_or(ParseError::NoRootElement)
+    }
 
-        let mut name = Name::local(&self.name);
+    /// Parses some data read asynchronously into an Element, like [`Element::parse`].
+    ///
+    /// The whole input is read before it is parsed.  To process a large document as it is read,
+    /// use [`Element::stream_async`] instead.
+    #[cfg(feature = "async")]
+    pub async fn parse_async<R: futures_io::AsyncRead + Unpin>(
+        r: R,
+    ) -> Result<Element, ParseError> {
+        let config = ParserConfig::new().ignore_comments(false);
+        Element::parse_async_with_options(r, config, ParseOptions::new()).await
+    }
+
+    /// Parses some data read asynchronously into an Element, using the given parser configuration
+    /// and tree-building options
+    #[cfg(feature = "async")]
+    pub async fn parse_async_with_options<R: futures_io::AsyncRead + Unpin>(
+        r: R,
+        config: ParserConfig,
+        options: ParseOptions,
+    ) -> Result<Element, ParseError> {
+        let data = async_io::read_to_end(r)
+            .await
+            .map_err(|e| ParseError::MalformedXml(e.into()))?;
+        Element::parse_with_options(&data[..], config, options)
+    }
+
+    fn xml_name(&self) -> xml::name::Name<'_> {
+        let mut name = xml::name::Name::local(&self.name);
         if let Some(ref ns) = self.namespace {
             name.namespace = Some(ns);
         }
         if let Some(ref p) = self.prefix {
             name.prefix = Some(p);
         }
+        name
+    }
+
+    fn _write<B: Write>(&self, emitter: &mut xml::writer::EventWriter<B>) -> Result<(), Error> {
+        use xml::writer::events::XmlEvent;
+
+        // Walk the tree with an explicit stack, so that deep trees can't overflow the call stack
+        self.write_start(emitter)?;
+        let mut stack = vec![(self, self.children.iter())];
+        while let Some((elem, children)) = stack.last_mut() {
+            match children.next() {
+                Some(XMLNode::Element(child)) => {
+                    child.write_start(emitter)?;
+                    stack.push((child, child.children.iter()));
+                }
+                Some(node) => node._write(emitter)?,
+                None => {
+                    emitter.write(XmlEvent::EndElement {
+                        name: Some(elem.xml_name()),
+                    })?;
+                    stack.pop();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_start<B: Write>(
+        &self,
+        emitter: &mut xml::writer::EventWriter<B>,
+    ) -> Result<(), Error> {
+        use xml::attribute::Attribute;
+        use xml::name::Name;
+        use xml::writer::events::XmlEvent;
 
         let mut attributes = Vec::with_capacity(self.attributes.len());
         for (k, v) in &self.attributes {
             attributes.push(Attribute {
-                name: Name::local(k),
+                name: Name {
+                    local_name: &k.local_name,
+                    namespace: k.namespace.as_deref(),
+                    prefix: k.prefix.as_deref(),
+                },
                 value: v,
             });
         }
@@ -372,29 +1297,10 @@ impl Element {
         };
 
         emitter.write(XmlEvent::StartElement {
-            name,
+            name: self.xml_name(),
             attributes: Cow::Owned(attributes),
             namespace,
-        })?;
-        for node in &self.children {
-            match node {
-                XMLNode::Element(elem) => elem._write(emitter)?,
-                XMLNode::Text(text) => emitter.write(XmlEvent::Characters(text))?,
-                XMLNode::Comment(comment) => emitter.write(XmlEvent::Comment(comment))?,
-                XMLNode::CData(comment) => emitter.write(XmlEvent::CData(comment))?,
-                XMLNode::ProcessingInstruction(name, data) => match data.to_owned() {
-                    Some(string) => emitter.write(XmlEvent::ProcessingInstruction {
-                        name,
-                        data: Some(&string),
-                    })?,
-                    None => emitter.write(XmlEvent::ProcessingInstruction { name, data: None })?,
-                },
-            }
-            // elem._write(emitter)?;
-        }
-        emitter.write(XmlEvent::EndElement { name: Some(name) })?;
-
-        Ok(())
+        })
     }
 
     /// Writes out this element as the root element in an new XML document
@@ -420,6 +1326,99 @@ impl Element {
         self._write(&mut emitter)
     }
 
+    /// Writes out this element as the root element in a new XML document to an asynchronous
+    /// writer, and flushes it.
+    ///
+    /// The document is serialized in memory first, and then written out.
+    #[cfg(feature = "async")]
+    pub async fn write_async<W: futures_io::AsyncWrite + Unpin>(&self, w: W) -> Result<(), Error> {
+        self.write_async_with_config(w, EmitterConfig::new()).await
+    }
+
+    /// Writes out this element to an asynchronous writer like [`Element::write_async`], using the
+    /// provided configuration
+    #[cfg(feature = "async")]
+    pub async fn write_async_with_config<W: futures_io::AsyncWrite + Unpin>(
+        &self,
+        w: W,
+        config: EmitterConfig,
+    ) -> Result<(), Error> {
+        let mut data = Vec::new();
+        self.write_with_config(&mut data, config)?;
+        async_io::write_all(w, &data).await?;
+        Ok(())
+    }
+
+    /// Writes out the canonical form of this element, as defined by Canonical XML 1.0 or
+    /// Exclusive XML Canonicalization depending on `options`.
+    ///
+    /// The canonical form doesn't depend on the order of [`Element::attributes`] or on where
+    /// namespaces were declared, so it can be compared byte for byte or digested for a signature.
+    /// The element is canonicalized as the apex of a document subset: the namespaces in its
+    /// [`Element::namespaces`] are taken to be in scope, but `xml:` attributes of ancestors are not
+    /// inherited.
+    ///
+    /// Whitespace between elements is part of the canonical form, so parse with
+    /// [`ParseOptions::preserve_whitespace`] when the result has to match other implementations.
+    ///
+    /// ```
+    /// use xmltree::{CanonicalOptions, Element};
+    ///
+    /// let elem = Element::parse(r#"<a xmlns:x="urn:x" z="1" b='2'><b/></a>"#.as_bytes()).unwrap();
+    /// let mut out = Vec::new();
+    /// elem.write_canonical(&mut out, &CanonicalOptions::new()).unwrap();
+    /// assert_eq!(out, br#"<a xmlns:x="urn:x" b="2" z="1"><b></b></a>"#);
+    ///
+    /// out.clear();
+    /// elem.write_canonical(&mut out, &CanonicalOptions::new().exclusive(true)).unwrap();
+    /// assert_eq!(out, br#"<a b="2" z="1"><b></b></a>"#);
+    /// ```
+    pub fn write_canonical<W: Write>(
+        &self,
+        mut w: W,
+        options: &CanonicalOptions,
+    ) -> Result<(), Error> {
+        canonical::write_element(&mut w, self, options)
+    }
+
+    /// Writes out this element laid out by xmltree's own formatter, see [`FormatOptions`]
+    ///
+    /// ```
+    /// use xmltree::{Element, FormatOptions};
+    ///
+    /// let elem = Element::parse(r#"<list><item id="1">one</item><item id="2"/></list>"#.as_bytes()).unwrap();
+    /// let mut out = Vec::new();
+    /// let options = FormatOptions::new().write_document_declaration(false);
+    /// elem.write_formatted(&mut out, &options).unwrap();
+    /// assert_eq!(
+    ///     String::from_utf8(out).unwrap(),
+    ///     "<list>\n  <item id=\"1\">one</item>\n  <item id=\"2\" />\n</list>",
+    /// );
+    /// ```
+    pub fn write_formatted<W: Write>(
+        &self,
+        mut w: W,
+        options: &FormatOptions,
+    ) -> Result<(), Error> {
+        format::write_element(&mut w, self, options)
+    }
+
+    /// Where the child at `index` was found in the source document
+    ///
+    /// Spans are only recorded when parsing with [`ParseOptions::track_positions`] enabled.  A
+    /// child element carries its own [`Element::span`] wherever it is moved, but the spans of
+    /// other children are kept by index, so they are only returned while `children` has as many
+    /// nodes as it had when parsed.
+    pub fn child_span(&self, index: usize) -> Option<Span> {
+        match self.children.get(index)? {
+            XMLNode::Element(child) => child.span,
+            _ if self.child_spans.len() == self.children.len() => {
+                self.child_spans.get(index).copied()
+            }
+            _ => None,
+        }
+    }
+
     /// Find a child element with the given name and return a reference to it.
     ///
     /// Both `&str` and `String` implement `ElementPredicate` and can be used to search for child
@@ -450,6 +1449,42 @@ impl Element {
             .find(|e| k.match_element(e))
     }
 
+    /// Removes all children of this element, and drops them without recursion.
+    ///
+    /// Each descendant is detached from its children before it is dropped, so that dropping a
+    /// very deep tree doesn't overflow the stack.  This is also what dropping an element does.
+    pub fn clear_children(&mut self) {
+        let mut nodes = std::mem::take(&mut self.children);
+        self.child_spans.clear();
+        while let Some(node) = nodes.pop() {
+            if let XMLNode::Element(mut elem) = node {
+                nodes.append(&mut elem.children);
+            }
+        }
+    }
+
+    /// Returns the prefix to write attributes in `namespace` with, declaring a new one on this
+    /// element if no prefix is bound to it yet
+    pub(crate) fn attribute_prefix(&mut self, namespace: &str) -> String {
+        if namespace == NS_XML_URI {
+            return NS_XML_PREFIX.to_owned();
+        }
+        let namespaces = self.namespaces.get_or_insert_with(Namespace::empty);
+        // Attributes without a prefix are in no namespace, so the default namespace won't do
+        let bound = namespaces
+            .iter()
+            .find(|&(prefix, uri)| uri == namespace && prefix != NS_NO_PREFIX);
+        if let Some((prefix, _)) = bound {
+            return prefix.to_owned();
+        }
+        let prefix = (0..)
+            .map(|i| format!("ns{}", i))
+            .find(|prefix| !namespaces.contains(prefix))
+            .expect("there are more prefixes than namespaces");
+        namespaces.put(prefix.as_str(), namespace);
+        prefix
+    }
+
     /// Find a child element with the given name, remove and return it.
     pub fn take_child<P: ElementPredicate>(&mut self, k: P) -> Option<Element> {
         let index = self.children.iter().position(|e| match e {
@@ -465,6 +1500,35 @@ impl Element {
         }
     }
 
+    /// Returns an iterator over all descendant elements, depth-first and in document order.
+    ///
+    /// This element itself is not included.  Use [`Descendants::breadth_first`] to visit the
+    /// elements level by level instead.
+    pub fn descendants(&self) -> Descendants<'_> {
+        Descendants::new(self)
+    }
+
+    /// Visits all descendant elements mutably, depth-first and in document order.
+    ///
+    /// See [`DescendantsMut`] for how to use this.
+    pub fn descendants_mut(&mut self) -> DescendantsMut<'_> {
+        DescendantsMut::new(self)
+    }
+
+    /// Returns an iterator over all descendant elements that match the predicate.
+    ///
+    /// Unlike [`Element::get_child`], this searches the whole subtree, not just the direct
+    /// children.
+    pub fn find_all<P: ElementPredicate>(&self, k: P) -> FindAll<'_, P> {
+        FindAll::new(self, k)
+    }
+
+    /// Returns a depth-first traversal of this element and all nodes in it, with an edge at the
+    /// start and at the end of every element.
+    pub fn traverse(&self) -> Traverse<'_> {
+        Traverse::new(self)
+    }
+
     /// Returns the inner text/cdata of this element, if any.
     ///
     /// If there are multiple text/cdata nodes, they will be all concatenated into one string.
@@ -487,10 +1551,54 @@ impl Element {
         }
     }
 
+    /// Returns the value of the attribute with the given local name, preferring the one without
+    /// a namespace.
+    ///
+    /// Only if there is no attribute without a namespace is one in a namespace returned, and if
+    /// there are several, the one with the smallest namespace URI wins.  So `href` is returned
+    /// for `<a href="1" xlink:href="2"/>`, and `xlink:href` for `<a xlink:href="2"/>`.  Use
+    /// [`Element::get_attribute_ns`] to pick a namespace explicitly.
+    pub fn get_attribute(&self, name: &str) -> Option<&str> {
+        self.get_attribute_ns(name, None).or_else(|| {
+            self.attributes
+                .iter()
+                .filter(|(k, _)| k.local_name == name)
+                .min_by(|(a, _), (b, _)| a.namespace.cmp(&b.namespace))
+                .map(|(_, v)| v.as_str())
+        })
+    }
+
+    /// Returns the value of the attribute with the given local name and namespace.
+    ///
+    /// Pass `None` as the namespace to look up an attribute without a namespace.
+    pub fn get_attribute_ns(&self, name: &str, namespace: Option<&str>) -> Option<&str> {
+        self.attributes
+            .get(&AttributeName {
+                local_name: name.to_owned(),
+                namespace: namespace.map(|ns| ns.to_owned()),
+                prefix: None,
+            })
+            .map(|v| v.as_str())
+    }
+
     /// Checks if this element matches the predicate.
     pub fn matches<P: ElementPredicate>(&self, k: P) -> bool {
         k.match_element(self)
     }
+
+    /// Returns this element and all of its descendants that match a CSS selector, in document
+    /// order
+    ///
+    /// See [`Selector`] for the supported syntax.  To run the same query many times, parse it
+    /// once with [`Selector::parse`] instead.
+    pub fn select(&self, selector: &str) -> Result<Vec<&Element>, SelectorError> {
+        Ok(Selector::parse(selector)?.select(self))
+    }
+
+    /// Returns the first element that matches a CSS selector, like [`Element::select`]
+    pub fn select_first(&self, selector: &str) -> Result<Option<&Element>, SelectorError> {
+        Ok(Selector::parse(selector)?.select_first(self))
+    }
 }
 
 /// A predicate for matching elements.
@@ -499,6 +1607,14 @@ impl Element {
 /// tag name and namespace.
 pub trait ElementPredicate {
     fn match_element(&self, e: &Element) -> bool;
+
+    /// Checks an [`ElementRef`].
+    ///
+    /// By default, this copies the element without its children, and checks the copy with
+    /// `match_element`.  Predicates that can look at an `ElementRef` directly should override it.
+    fn match_element_ref(&self, e: &ElementRef<'_>) -> bool {
+        self.match_element(&e.to_shallow_element())
+    }
 }
 
 // Unfortunately,
@@ -518,11 +1634,15 @@ where
     }
 }
 
-impl<'a> ElementPredicate for &'a str {
+impl ElementPredicate for &str {
     /// Search by tag name
     fn match_element(&self, e: &Element) -> bool {
         (*self,).match_element(e)
     }
+
+    fn match_element_ref(&self, e: &ElementRef<'_>) -> bool {
+        e.name == *self
+    }
 }
 
 impl<'a> ElementPredicate for Cow<'a, str> {
@@ -530,6 +1650,10 @@ impl<'a> ElementPredicate for Cow<'a, str> {
     fn match_element(&self, e: &Element) -> bool {
         (&**self,).match_element(e)
     }
+
+    fn match_element_ref(&self, e: &ElementRef<'_>) -> bool {
+        e.name == *self
+    }
 }
 
 impl ElementPredicate for String {
@@ -537,6 +1661,10 @@ impl ElementPredicate for String {
     fn match_element(&self, e: &Element) -> bool {
         (&**self,).match_element(e)
     }
+
+    fn match_element_ref(&self, e: &ElementRef<'_>) -> bool {
+        e.name == **self
+    }
 }
 
 impl<TN, NS> ElementPredicate for (TN, NS)
@@ -552,4 +1680,12 @@ where
                 .map(|ns| ns == &self.1)
                 .unwrap_or(false)
     }
+
+    fn match_element_ref(&self, e: &ElementRef<'_>) -> bool {
+        e.name.to_string() == self.0
+            && e.namespace
+                .as_ref()
+                .map(|ns| ns.to_string() == self.1)
+                .unwrap_or(false)
+    }
 }
@@ -0,0 +1,189 @@
+use std::fmt;
+
+use xml::common::Position;
+
+use crate::ParseError;
+
+/// Upper bounds on the size of the tree built while parsing.
+///
+/// These protect against documents that would otherwise exhaust memory, which makes them useful
+/// when parsing XML from untrusted sources.  Every limit is disabled (`None`) by default.  If a
+/// limit is exceeded, parsing stops with [`ParseError::LimitExceeded`].
+///
+/// The limits are checked as the tree is built from the events of the parser, which has already
+/// read each node in full by then.  A single huge text node, comment or start tag is buffered by
+/// the parser before `max_text_bytes` or `max_attributes` can reject it.  To also bound the memory
+/// used by the parser, limit the size of the input itself, for example with [`std::io::Read::take`].
+///
+/// The limits are passed to the parser as part of the [`ParseOptions`](crate::ParseOptions):
+///
+/// ```
+/// use xmltree::{Element, Limit, ParseError, ParseLimits, ParseOptions, ParserConfig};
+///
+/// let limits = ParseLimits::new().max_depth(2);
+/// let options = ParseOptions::new().limits(limits);
+///
+/// let result = Element::parse_with_options("<a><b><c/></b></a>".as_bytes(), ParserConfig::new(), options);
+/// match result {
+///     Err(ParseError::LimitExceeded { limit, .. }) => assert_eq!(limit, Limit::Depth),
+///     _ => panic!("expected the depth limit to be hit"),
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// The maximum nesting depth of elements.  The root element is at depth 1
+    pub max_depth: Option<usize>,
+
+    /// The maximum number of child nodes of a single element
+    pub max_children: Option<usize>,
+
+    /// The maximum number of attributes on a single element
+    pub max_attributes: Option<usize>,
+
+    /// The maximum number of bytes of text, CDATA, comments, processing instruction data and
+    /// attribute names and values, summed over the whole document
+    pub max_text_bytes: Option<usize>,
+
+    /// The maximum number of nodes of any kind in the whole document
+    pub max_nodes: Option<usize>,
+}
+
+impl ParseLimits {
+    /// Returns limits with every limit disabled
+    pub fn new() -> ParseLimits {
+        ParseLimits::default()
+    }
+
+    /// Sets [`ParseLimits::max_depth`]
+    pub fn max_depth(mut self, value: usize) -> ParseLimits {
+        self.max_depth = Some(value);
+        self
+    }
+
+    /// Sets [`ParseLimits::max_children`]
+    pub fn max_children(mut self, value: usize) -> ParseLimits {
+        self.max_children = Some(value);
+        self
+    }
+
+    /// Sets [`ParseLimits::max_attributes`]
+    pub fn max_attributes(mut self, value: usize) -> ParseLimits {
+        self.max_attributes = Some(value);
+        self
+    }
+
+    /// Sets [`ParseLimits::max_text_bytes`]
+    pub fn max_text_bytes(mut self, value: usize) -> ParseLimits {
+        self.max_text_bytes = Some(value);
+        self
+    }
+
+    /// Sets [`ParseLimits::max_nodes`]
+    pub fn max_nodes(mut self, value: usize) -> ParseLimits {
+        self.max_nodes = Some(value);
+        self
+    }
+}
+
+/// Identifies which of the [`ParseLimits`] was exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Limit {
+    /// [`ParseLimits::max_depth`]
+    Depth,
+    /// [`ParseLimits::max_children`]
+    Children,
+    /// [`ParseLimits::max_attributes`]
+    Attributes,
+    /// [`ParseLimits::max_text_bytes`]
+    TextBytes,
+    /// [`ParseLimits::max_nodes`]
+    Nodes,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Limit::Depth => "element depth",
+            Limit::Children => "number of children",
+            Limit::Attributes => "number of attributes",
+            Limit::TextBytes => "text size",
+            Limit::Nodes => "number of nodes",
+        })
+    }
+}
+
+/// Keeps track of the size of the tree built so far, and checks it against the limits
+pub(crate) struct Usage {
+    limits: ParseLimits,
+    nodes: usize,
+    text_bytes: usize,
+}
+
+impl Usage {
+    pub(crate) fn new(limits: ParseLimits) -> Usage {
+        Usage {
+            limits,
+            nodes: 0,
+            text_bytes: 0,
+        }
+    }
+
+    /// Accounts for an element at `depth` with `siblings` nodes before it, and `attributes`
+    /// attributes whose names and values take up `attribute_bytes` bytes
+    pub(crate) fn add_element<P: Position>(
+        &mut self,
+        reader: &P,
+        depth: usize,
+        siblings: usize,
+        attributes: usize,
+        attribute_bytes: usize,
+    ) -> Result<(), ParseError> {
+        check(reader, self.limits.max_depth, depth, Limit::Depth)?;
+        check(
+            reader,
+            self.limits.max_attributes,
+            attributes,
+            Limit::Attributes,
+        )?;
+        self.add_node(reader, siblings, attribute_bytes)
+    }
+
+    /// Accounts for a node with `text_len` bytes of content and `siblings` nodes before it
+    pub(crate) fn add_node<P: Position>(
+        &mut self,
+        reader: &P,
+        siblings: usize,
+        text_len: usize,
+    ) -> Result<(), ParseError> {
+        self.nodes += 1;
+        self.text_bytes += text_len;
+        check(
+            reader,
+            self.limits.max_children,
+            siblings + 1,
+            Limit::Children,
+        )?;
+        check(reader, self.limits.max_nodes, self.nodes, Limit::Nodes)?;
+        check(
+            reader,
+            self.limits.max_text_bytes,
+            self.text_bytes,
+            Limit::TextBytes,
+        )
+    }
+}
+
+fn check<P: Position>(
+    reader: &P,
+    max: Option<usize>,
+    value: usize,
+    limit: Limit,
+) -> Result<(), ParseError> {
+    match max {
+        Some(max) if value > max => Err(ParseError::LimitExceeded {
+            limit,
+            position: reader.position(),
+        }),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,320 @@
+use std::fmt;
+
+use crate::Element;
+
+/// Types that can be built from an [`Element`].
+///
+/// This is usually derived with `#[derive(FromElement)]`, which needs the `derive` feature.  The
+/// fields of the struct are mapped with `#[xml(...)]` attributes:
+///
+/// * `#[xml(attr = "id")]` reads the attribute `id`, using [`FromStr`](std::str::FromStr)
+/// * `#[xml(child = "item")]` reads the child element `item`, using `FromElement`.  For a `Vec`,
+///   all children with that name are read
+/// * `#[xml(text)]` reads the text content of the element, using [`FromStr`](std::str::FromStr)
+/// * `#[xml(namespace = "...")]` restricts `attr` and `child` to a namespace.  Without it, `attr`
+///   only reads an attribute that is in no namespace
+///
+/// A field without any of these is read from the child element with the same name as the field.
+/// `Option` fields may be missing from the element, all other fields are required.
+///
+/// ```
+/// # #[cfg(feature = "derive")] {
+/// use xmltree::{Element, FromElement};
+///
+/// #[derive(FromElement)]
+/// struct Item {
+///     #[xml(attr = "sku")]
+///     sku: String,
+///     #[xml(attr = "qty")]
+///     quantity: Option<u32>,
+/// }
+///
+/// #[derive(FromElement)]
+/// struct Order {
+///     #[xml(attr = "id")]
+///     id: u64,
+///     #[xml(child = "item")]
+///     items: Vec<Item>,
+///     note: Option<String>,
+/// }
+///
+/// let data = r#"<order id="12"><item sku="a-1" qty="2"/><item/></order>"#;
+/// let elem = Element::parse(data.as_bytes()).unwrap();
+/// let err = Order::from_element(&elem).err().unwrap();
+/// assert_eq!(err.to_string(), "missing attribute `item[2]/@sku`");
+/// # }
+/// ```
+pub trait FromElement: Sized {
+    fn from_element(elem: &Element) -> Result<Self, FromElementError>;
+}
+
+/// Types that can be turned into an [`Element`].
+///
+/// This is usually derived with `#[derive(IntoElement)]`, using the same `#[xml(...)]` field
+/// attributes as [`FromElement`].  The element is named after the struct, which can be changed
+/// with `#[xml(name = "...")]` on the struct, and put in a namespace with
+/// `#[xml(namespace = "...")]`.  `None` fields are left out.
+///
+/// When the value becomes a child element, it is renamed to the name given by the parent's
+/// `child` attribute, so that the implementations for scalar types such as `String` can return
+/// an element without a name.
+pub trait IntoElement {
+    fn into_element(self) -> Element;
+}
+
+/// Error returned when an element does not have the structure a [`FromElement`] type expects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FromElementError {
+    /// Where the error occurred, relative to the element that was being converted.
+    ///
+    /// This is a path of child elements, with the position among children of the same name when
+    /// there can be several, optionally followed by an attribute, such as `items/item[2]/@id`.
+    /// It is empty for the element itself.
+    pub path: String,
+
+    pub kind: FromElementErrorKind,
+}
+
+/// What went wrong, see [`FromElementError`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FromElementErrorKind {
+    /// A required child element is missing
+    MissingElement,
+    /// A required attribute is missing
+    MissingAttribute,
+    /// An attribute or text could not be parsed into the field's type
+    InvalidValue {
+        /// The attribute value or text
+        value: String,
+        /// The error from parsing it
+        message: String,
+    },
+}
+
+impl FromElementError {
+    /// Returns an error at the element itself
+    pub fn new(kind: FromElementErrorKind) -> FromElementError {
+        FromElementError {
+            path: String::new(),
+            kind,
+        }
+    }
+
+    /// Prefixes the path with `segment`, for an error that occurred in a child element
+    pub fn within(mut self, segment: &str) -> FromElementError {
+        self.path = if self.path.is_empty() {
+            segment.to_owned()
+        } else {
+            format!("{}/{}", segment, self.path)
+        };
+        self
+    }
+}
+
+impl fmt::Display for FromElementError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "."
+        } else {
+            &self.path
+        };
+        match self.kind {
+            FromElementErrorKind::MissingElement => write!(f, "missing element `{}`", path),
+            FromElementErrorKind::MissingAttribute => write!(f, "missing attribute `{}`", path),
+            FromElementErrorKind::InvalidValue {
+                ref value,
+                ref message,
+            } => write!(f, "invalid value {:?} at `{}`: {}", value, path, message),
+        }
+    }
+}
+
+impl std::error::Error for FromElementError {}
+
+macro_rules! impl_text_mapping {
+    ($($t:ty)*) => {
+        $(
+            impl FromElement for $t {
+                /// Parses the text content of the element
+                fn from_element(elem: &Element) -> Result<Self, FromElementError> {
+                    __derive::parse(&elem.get_text().unwrap_or_default())
+                }
+            }
+
+            impl IntoElement for $t {
+                /// Returns an element without a name, holding the value as text
+                fn into_element(self) -> Element {
+                    let mut elem = Element::new("");
+                    __derive::push_text(&mut elem, &self);
+                    elem
+                }
+            }
+        )*
+    };
+}
+
+impl_text_mapping! {
+    String bool char i8 i16 i32 i64 i128 isize u8 u16 u32 u64 u128 usize f32 f64
+}
+
+/// Functions used by the code generated by the derive macros
+#[doc(hidden)]
+pub mod __derive {
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    use super::{FromElement, FromElementError, FromElementErrorKind, IntoElement};
+    use crate::{AttributeName, Element, Namespace, XMLNode};
+    use xml::namespace::NS_NO_PREFIX;
+
+    pub fn parse<T>(value: &str) -> Result<T, FromElementError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        value.parse().map_err(|e: T::Err| {
+            FromElementError::new(FromElementErrorKind::InvalidValue {
+                value: value.to_owned(),
+                message: e.to_string(),
+            })
+        })
+    }
+
+    fn matches(elem: &Element, name: &str, namespace: Option<&str>) -> bool {
+        match namespace {
+            Some(namespace) => elem.matches((name, namespace)),
+            None => elem.matches(name),
+        }
+    }
+
+    pub fn optional_attribute<T>(
+        elem: &Element,
+        name: &str,
+        namespace: Option<&str>,
+    ) -> Result<Option<T>, FromElementError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match elem.get_attribute_ns(name, namespace) {
+            Some(value) => parse(value)
+                .map(Some)
+                .map_err(|e| e.within(&format!("@{}", name))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn attribute<T>(
+        elem: &Element,
+        name: &str,
+        namespace: Option<&str>,
+    ) -> Result<T, FromElementError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        optional_attribute(elem, name, namespace)?.ok_or_else(|| {
+            FromElementError::new(FromElementErrorKind::MissingAttribute)
+                .within(&format!("@{}", name))
+        })
+    }
+
+    pub fn optional_child<T: FromElement>(
+        elem: &Element,
+        name: &str,
+        namespace: Option<&str>,
+    ) -> Result<Option<T>, FromElementError> {
+        let child = match namespace {
+            Some(namespace) => elem.get_child((name, namespace)),
+            None => elem.get_child(name),
+        };
+        match child {
+            Some(child) => T::from_element(child).map(Some).map_err(|e| e.within(name)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn child<T: FromElement>(
+        elem: &Element,
+        name: &str,
+        namespace: Option<&str>,
+    ) -> Result<T, FromElementError> {
+        optional_child(elem, name, namespace)?
+            .ok_or_else(|| FromElementError::new(FromElementErrorKind::MissingElement).within(name))
+    }
+
+    pub fn children<T: FromElement>(
+        elem: &Element,
+        name: &str,
+        namespace: Option<&str>,
+    ) -> Result<Vec<T>, FromElementError> {
+        elem.children
+            .iter()
+            .filter_map(|node| node.as_element())
+            .filter(|child| matches(child, name, namespace))
+            .enumerate()
+            .map(|(i, child)| {
+                T::from_element(child).map_err(|e| e.within(&format!("{}[{}]", name, i + 1)))
+            })
+            .collect()
+    }
+
+    pub fn optional_text<T>(elem: &Element) -> Result<Option<T>, FromElementError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        elem.get_text().map(|text| parse(&text)).transpose()
+    }
+
+    pub fn text<T>(elem: &Element) -> Result<T, FromElementError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        parse(&elem.get_text().unwrap_or_default())
+    }
+
+    pub fn set_attribute<T: Display + ?Sized>(
+        elem: &mut Element,
+        name: &str,
+        namespace: Option<&str>,
+        value: &T,
+    ) {
+        let name = match namespace {
+            Some(namespace) => {
+                let prefix = elem.attribute_prefix(namespace);
+                AttributeName::qualified(name, namespace, prefix)
+            }
+            None => AttributeName::local(name),
+        };
+        elem.attributes.insert(name, value.to_string());
+    }
+
+    pub fn push_child<T: IntoElement>(
+        elem: &mut Element,
+        name: &str,
+        namespace: Option<&str>,
+        value: T,
+    ) {
+        let mut child = value.into_element();
+        child.name = name.to_owned();
+        if let Some(namespace) = namespace {
+            set_namespace(&mut child, namespace);
+        }
+        elem.children.push(XMLNode::Element(child));
+    }
+
+    /// Puts the element in a namespace, declared as the default namespace so it is written out
+    pub fn set_namespace(elem: &mut Element, namespace: &str) {
+        // Keep the prefixes `set_attribute` may already have declared
+        elem.namespaces
+            .get_or_insert_with(Namespace::empty)
+            .force_put(NS_NO_PREFIX, namespace);
+        elem.namespace = Some(namespace.to_owned());
+    }
+
+    pub fn push_text<T: Display + ?Sized>(elem: &mut Element, value: &T) {
+        elem.children.push(XMLNode::Text(value.to_string()));
+    }
+}
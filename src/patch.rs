@@ -0,0 +1,778 @@
+use std::fmt;
+use std::io::Read;
+use std::ptr;
+
+use crate::xpath::{Context, Node, XPath, XPathError};
+use crate::{AttributeName, Element, Namespace, ParseOptions, ParserConfig, XMLNode};
+
+/// A set of changes in the [RFC 5261](https://www.rfc-editor.org/rfc/rfc5261) XML patch format.
+///
+/// A patch document has a root element, usually `<diff>`, with `<add>`, `<replace>` and
+/// `<remove>` operations as its children.  Each operation locates a single node with the XPath
+/// expression in its `sel` attribute, and changes it:
+///
+/// * `<add sel="...">` adds its content as the last children of the selected element.  With
+///   `pos="prepend"` they become its first children instead, and with `pos="before"` or
+///   `pos="after"` its siblings.  With `type="@name"` it adds an attribute instead, and with
+///   `type="namespace::prefix"` a namespace declaration, whose value is the text content.
+/// * `<replace sel="...">` replaces the selected element, comment or processing instruction with
+///   the one it contains, or the value of an attribute, text node or namespace declaration with
+///   its text content.
+/// * `<remove sel="...">` removes the selected node.  With `ws="before"`, `ws="after"` or
+///   `ws="both"`, the whitespace text next to a removed element is removed too.
+///
+/// As required by the RFC, `sel` is evaluated against the document root, and resolves prefixes
+/// with the namespace declarations in scope of the operation element.  Names without a prefix
+/// match elements in the default namespace of the patch, if it declares one.  Any XPath 1.0
+/// expression can be used, not just the subset the RFC requires.
+///
+/// Since an [`Element`] has no XML prolog, nodes can't be added next to the root element.
+///
+/// ```
+/// use xmltree::{Element, Patch};
+///
+/// let mut doc = Element::parse(r#"<doc><note>old</note></doc>"#.as_bytes()).unwrap();
+/// let patch = Patch::parse(r#"
+///     <diff>
+///         <add sel="doc/note" type="@lang">en</add>
+///         <replace sel="doc/note/text()">new</replace>
+///         <add sel="doc"><note id="b"/></add>
+///     </diff>"#.as_bytes()).unwrap();
+/// patch.apply(&mut doc).unwrap();
+///
+/// let mut out = Vec::new();
+/// doc.write_with_config(&mut out, xmltree::EmitterConfig::new().write_document_declaration(false)).unwrap();
+/// assert_eq!(
+///     String::from_utf8(out).unwrap(),
+///     r#"<doc><note lang="en">new</note><note id="b" /></doc>"#,
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct Patch {
+    operations: Vec<Operation>,
+}
+
+#[derive(Debug, Clone)]
+struct Operation {
+    directive: Directive,
+    sel: String,
+    xpath: XPath,
+    /// The namespaces in scope of the operation element
+    namespaces: Namespace,
+    content: Vec<XMLNode>,
+}
+
+#[derive(Debug, Clone)]
+enum Directive {
+    Add(Option<Position>, AddType),
+    Replace,
+    Remove(Option<Whitespace>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Position {
+    Before,
+    After,
+    Prepend,
+}
+
+#[derive(Debug, Clone)]
+enum AddType {
+    Nodes,
+    Attribute(AttributeName),
+    Namespace(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Whitespace {
+    Before,
+    After,
+    Both,
+}
+
+/// Errors that can occur reading or applying a [`Patch`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchError {
+    pub kind: PatchErrorKind,
+
+    /// The position of the failed operation among the operations of the patch, if the error is
+    /// about a single operation
+    pub operation: Option<usize>,
+
+    /// A description of the problem
+    pub message: String,
+}
+
+/// The kinds of errors defined by RFC 5261
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchErrorKind {
+    /// An attribute of an operation has an invalid value, or an added attribute already exists
+    InvalidAttributeValue,
+    /// The patch document is not well-formed, or an operation is missing something it needs
+    InvalidDiffFormat,
+    /// A namespace prefix is not declared, or is declared already
+    InvalidNamespacePrefix,
+    /// A namespace URI is not valid
+    InvalidNamespaceUri,
+    /// The selected node, or the content of the operation, has the wrong type for the operation
+    InvalidNodeTypes,
+    /// The patch contains an element that is not an operation
+    InvalidPatchDirective,
+    /// The operation would remove the root element or add a sibling to it
+    InvalidRootElementOperation,
+    /// There is no whitespace to remove next to the removed node
+    InvalidWhitespaceDirective,
+    /// `sel` doesn't select exactly one node
+    UnlocatedNode,
+}
+
+impl PatchErrorKind {
+    /// The name of the element that reports this error in RFC 5261, such as `unlocated-node`
+    pub fn element_name(self) -> &'static str {
+        match self {
+            PatchErrorKind::InvalidAttributeValue => "invalid-attribute-value",
+            PatchErrorKind::InvalidDiffFormat => "invalid-diff-format",
+            PatchErrorKind::InvalidNamespacePrefix => "invalid-namespace-prefix",
+            PatchErrorKind::InvalidNamespaceUri => "invalid-namespace-uri",
+            PatchErrorKind::InvalidNodeTypes => "invalid-node-types",
+            PatchErrorKind::InvalidPatchDirective => "invalid-patch-directive",
+            PatchErrorKind::InvalidRootElementOperation => "invalid-root-element-operation",
+            PatchErrorKind::InvalidWhitespaceDirective => "invalid-whitespace-directive",
+            PatchErrorKind::UnlocatedNode => "unlocated-node",
+        }
+    }
+}
+
+impl PatchError {
+    fn new<S: Into<String>>(kind: PatchErrorKind, message: S) -> PatchError {
+        PatchError {
+            kind,
+            operation: None,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.kind.element_name())?;
+        if let Some(operation) = self.operation {
+            write!(f, " in operation {}", operation + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+impl Patch {
+    /// Parses a patch document
+    pub fn parse<R: Read>(r: R) -> Result<Patch, PatchError> {
+        // Whitespace and comments in added content are added too
+        let config = ParserConfig::new().ignore_comments(false);
+        let options = ParseOptions::new().preserve_whitespace(true);
+        let diff = Element::parse_with_options(r, config, options)
+            .map_err(|e| PatchError::new(PatchErrorKind::InvalidDiffFormat, e.to_string()))?;
+        Patch::from_element(&diff)
+    }
+
+    /// Reads the operations of a patch document that was already parsed.
+    ///
+    /// The document should be parsed with comments and whitespace kept, since they are part of
+    /// the content that `<add>` and `<replace>` operations insert.
+    pub fn from_element(diff: &Element) -> Result<Patch, PatchError> {
+        let operations = diff
+            .children
+            .iter()
+            .filter_map(|node| node.as_element())
+            .enumerate()
+            .map(|(i, elem)| {
+                Operation::from_element(elem).map_err(|e| PatchError {
+                    operation: Some(i),
+                    ..e
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Patch { operations })
+    }
+
+    /// Applies all operations of the patch to `elem`, in order.
+    ///
+    /// If any operation fails, `elem` is left unchanged.
+    pub fn apply(&self, elem: &mut Element) -> Result<(), PatchError> {
+        let mut patched = elem.clone();
+        for (i, operation) in self.operations.iter().enumerate() {
+            operation.apply(&mut patched).map_err(|e| PatchError {
+                operation: Some(i),
+                ..e
+            })?;
+        }
+        *elem = patched;
+        Ok(())
+    }
+}
+
+impl Operation {
+    fn from_element(elem: &Element) -> Result<Operation, PatchError> {
+        let invalid_value = |name: &str, value: &str| {
+            PatchError::new(
+                PatchErrorKind::InvalidAttributeValue,
+                format!("invalid value {:?} for `{}`", value, name),
+            )
+        };
+        let namespaces = elem.namespaces.clone().unwrap_or_else(Namespace::empty);
+
+        let directive = match elem.name.as_str() {
+            "add" => {
+                let position = match elem.get_attribute("pos") {
+                    None => None,
+                    Some("before") => Some(Position::Before),
+                    Some("after") => Some(Position::After),
+                    Some("prepend") => Some(Position::Prepend),
+                    Some(other) => return Err(invalid_value("pos", other)),
+                };
+                let add_type = match elem.get_attribute("type") {
+                    None => AddType::Nodes,
+                    Some(t) => {
+                        if let Some(name) = t.strip_prefix('@') {
+                            AddType::Attribute(attribute_name(name, &namespaces)?)
+                        } else if let Some(prefix) = t.strip_prefix("namespace::") {
+                            AddType::Namespace(prefix.to_owned())
+                        } else {
+                            return Err(invalid_value("type", t));
+                        }
+                    }
+                };
+                Directive::Add(position, add_type)
+            }
+            "replace" => Directive::Replace,
+            "remove" => {
+                let whitespace = match elem.get_attribute("ws") {
+                    None => None,
+                    Some("before") => Some(Whitespace::Before),
+                    Some("after") => Some(Whitespace::After),
+                    Some("both") => Some(Whitespace::Both),
+                    Some(other) => return Err(invalid_value("ws", other)),
+                };
+                if elem.children.iter().any(|n| n.as_whitespace().is_none()) {
+                    return Err(PatchError::new(
+                        PatchErrorKind::InvalidDiffFormat,
+                        "`remove` can't have any content",
+                    ));
+                }
+                Directive::Remove(whitespace)
+            }
+            other => {
+                return Err(PatchError::new(
+                    PatchErrorKind::InvalidPatchDirective,
+                    format!("unknown operation `{}`", other),
+                ))
+            }
+        };
+
+        let sel = elem.get_attribute("sel").ok_or_else(|| {
+            PatchError::new(PatchErrorKind::InvalidDiffFormat, "missing `sel` attribute")
+        })?;
+        // Selectors are evaluated against the document root, which XPath only does for absolute
+        // paths here
+        let absolute = if sel.starts_with('/') || sel.starts_with("id(") {
+            sel.to_owned()
+        } else {
+            format!("/{}", sel)
+        };
+        let xpath = XPath::compile(&absolute).map_err(|e| {
+            PatchError::new(
+                PatchErrorKind::InvalidDiffFormat,
+                format!("invalid selector {:?}: {}", sel, e),
+            )
+        })?;
+
+        Ok(Operation {
+            directive,
+            sel: sel.to_owned(),
+            xpath,
+            namespaces,
+            content: elem.children.clone(),
+        })
+    }
+
+    fn apply(&self, root: &mut Element) -> Result<(), PatchError> {
+        let target = self.locate(root)?;
+        match self.directive {
+            Directive::Add(position, ref add_type) => self.add(root, target, position, add_type),
+            Directive::Replace => self.replace(root, target),
+            Directive::Remove(whitespace) => remove(root, target, whitespace),
+        }
+    }
+
+    /// Finds the single node selected by `sel`
+    fn locate(&self, root: &Element) -> Result<Target, PatchError> {
+        let mut context = Context::new().namespaces(&self.namespaces);
+        if let Some(uri) = self.namespaces.get("").filter(|uri| !uri.is_empty()) {
+            context = context.default_element_namespace(uri);
+        }
+        let unlocated = |message: String| PatchError::new(PatchErrorKind::UnlocatedNode, message);
+        let nodes = match self.xpath.evaluate_with_context(root, &context) {
+            Ok(value) => value.into_nodes(),
+            Err(XPathError::UnknownPrefix(prefix)) => {
+                return Err(PatchError::new(
+                    PatchErrorKind::InvalidNamespacePrefix,
+                    format!("undeclared prefix `{}` in {:?}", prefix, self.sel),
+                ))
+            }
+            Err(e) => return Err(unlocated(format!("{:?}: {}", self.sel, e))),
+        };
+        match nodes.as_deref() {
+            Some([node]) => Ok(Target::find(root, node)),
+            Some([]) => Err(unlocated(format!("{:?} selects nothing", self.sel))),
+            Some(_) => Err(unlocated(format!(
+                "{:?} selects more than one node",
+                self.sel
+            ))),
+            None => Err(unlocated(format!("{:?} does not select nodes", self.sel))),
+        }
+    }
+
+    /// The text content of the operation, for attribute values and the like
+    fn text(&self) -> String {
+        self.content
+            .iter()
+            .filter_map(|node| match node {
+                XMLNode::Text(s) | XMLNode::CData(s) | XMLNode::Whitespace(s) => Some(s.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The content of the operation, ready to be inserted into an element with the given
+    /// namespaces in scope
+    fn content(&self, target_scope: Option<&Namespace>) -> Vec<XMLNode> {
+        let mut content = self.content.clone();
+        for node in &mut content {
+            if let XMLNode::Element(elem) = node {
+                adopt(elem, &self.namespaces, target_scope);
+            }
+        }
+        content
+    }
+
+    /// The single node of a kind that replaces another, ignoring whitespace around it
+    fn single_node(&self) -> Result<&XMLNode, PatchError> {
+        let mut nodes = self.content.iter().filter(|node| match node {
+            XMLNode::Whitespace(_) => false,
+            XMLNode::Text(s) => !s.trim().is_empty(),
+            _ => true,
+        });
+        match (nodes.next(), nodes.next()) {
+            (Some(node), None) => Ok(node),
+            _ => Err(PatchError::new(
+                PatchErrorKind::InvalidNodeTypes,
+                "the content must be a single node",
+            )),
+        }
+    }
+
+    fn add(
+        &self,
+        root: &mut Element,
+        target: Target,
+        position: Option<Position>,
+        add_type: &AddType,
+    ) -> Result<(), PatchError> {
+        let path = match target {
+            Target::Node(path) => path,
+            Target::Root => return Err(root_error("add to the document root")),
+            _ => return Err(node_types_error("add to an attribute or namespace")),
+        };
+
+        match add_type {
+            AddType::Nodes => (),
+            AddType::Attribute(name) => {
+                let elem = element_at(root, &path).ok_or_else(|| {
+                    node_types_error("add an attribute to something other than an element")
+                })?;
+                if elem.attributes.contains_key(name) {
+                    return Err(PatchError::new(
+                        PatchErrorKind::InvalidAttributeValue,
+                        format!("attribute `{}` already exists", name),
+                    ));
+                }
+                elem.attributes.insert(name.clone(), self.text());
+                return Ok(());
+            }
+            AddType::Namespace(prefix) => {
+                let uri = self.text();
+                if uri.is_empty() {
+                    return Err(PatchError::new(
+                        PatchErrorKind::InvalidNamespaceUri,
+                        "the namespace URI is empty",
+                    ));
+                }
+                let parent_scope = parent_of(root, &path).and_then(|p| p.namespaces.clone());
+                let elem = element_at(root, &path).ok_or_else(|| {
+                    node_types_error("add a namespace to something other than an element")
+                })?;
+                let declared_here = elem
+                    .namespaces
+                    .as_ref()
+                    .and_then(|ns| ns.get(prefix))
+                    .is_some_and(|current| {
+                        parent_scope.as_ref().and_then(|p| p.get(prefix)) != Some(current)
+                    });
+                if declared_here {
+                    return Err(PatchError::new(
+                        PatchErrorKind::InvalidNamespacePrefix,
+                        format!("prefix `{}` is already declared", prefix),
+                    ));
+                }
+                rebind(elem, prefix, Some(&uri));
+                return Ok(());
+            }
+        }
+
+        match position {
+            None | Some(Position::Prepend) => {
+                let elem = element_at(root, &path)
+                    .ok_or_else(|| node_types_error("add children to a non-element"))?;
+                let content = self.content(elem.namespaces.as_ref());
+                let at = if position.is_some() {
+                    0
+                } else {
+                    elem.children.len()
+                };
+                elem.children.splice(at..at, content);
+            }
+            Some(position) => {
+                let (index, parent_path) = match path.split_last() {
+                    Some((&index, parent_path)) => (index, parent_path),
+                    None => return Err(root_error("add siblings to the root element")),
+                };
+                let parent = element_at(root, parent_path).expect("parent is an element");
+                let content = self.content(parent.namespaces.as_ref());
+                let at = if position == Position::Before {
+                    index
+                } else {
+                    index + 1
+                };
+                parent.children.splice(at..at, content);
+            }
+        }
+        Ok(())
+    }
+
+    fn replace(&self, root: &mut Element, target: Target) -> Result<(), PatchError> {
+        match target {
+            Target::Root => Err(root_error("replace the document root")),
+            Target::Attribute(path, name) => {
+                let elem = element_at(root, &path).expect("attribute of an element");
+                elem.attributes.insert(name, self.text());
+                Ok(())
+            }
+            Target::Namespace(path, prefix) => {
+                let uri = self.text();
+                if uri.is_empty() {
+                    return Err(PatchError::new(
+                        PatchErrorKind::InvalidNamespaceUri,
+                        "the namespace URI is empty",
+                    ));
+                }
+                let elem = element_at(root, &path).expect("namespace of an element");
+                rebind(elem, &prefix, Some(&uri));
+                Ok(())
+            }
+            Target::Node(path) => {
+                let parent_scope = parent_of(root, &path).and_then(|p| p.namespaces.clone());
+                let node = match path.split_last() {
+                    Some((&index, parent_path)) => {
+                        let parent = element_at(root, parent_path).expect("parent is an element");
+                        &mut parent.children[index]
+                    }
+                    None => {
+                        // The root element can only be replaced by another element
+                        let new = match self.single_node()? {
+                            XMLNode::Element(e) => e,
+                            _ => {
+                                return Err(root_error(
+                                    "replace the root element with a non-element",
+                                ))
+                            }
+                        };
+                        let mut new = new.clone();
+                        adopt(&mut new, &self.namespaces, None);
+                        *root = new;
+                        return Ok(());
+                    }
+                };
+                match node {
+                    XMLNode::Text(_) | XMLNode::CData(_) | XMLNode::Whitespace(_) => {
+                        *node = XMLNode::Text(self.text());
+                    }
+                    XMLNode::Element(_) => match self.single_node()? {
+                        XMLNode::Element(new) => {
+                            let mut new = new.clone();
+                            adopt(&mut new, &self.namespaces, parent_scope.as_ref());
+                            *node = XMLNode::Element(new);
+                        }
+                        _ => return Err(node_types_error("replace an element with a non-element")),
+                    },
+                    XMLNode::Comment(_) => match self.single_node()? {
+                        new @ XMLNode::Comment(_) => *node = new.clone(),
+                        _ => return Err(node_types_error("replace a comment with a non-comment")),
+                    },
+                    XMLNode::ProcessingInstruction(..) => match self.single_node()? {
+                        new @ XMLNode::ProcessingInstruction(..) => *node = new.clone(),
+                        _ => {
+                            return Err(node_types_error(
+                                "replace a processing instruction with something else",
+                            ))
+                        }
+                    },
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn remove(root: &mut Element, target: Target, ws: Option<Whitespace>) -> Result<(), PatchError> {
+    let path = match target {
+        Target::Root => return Err(root_error("remove the document root")),
+        Target::Attribute(path, name) => {
+            let elem = element_at(root, &path).expect("attribute of an element");
+            // `retain` keeps the order of the other attributes with every kind of `AttributeMap`
+            elem.attributes.retain(|key, _| *key != name);
+            return Ok(());
+        }
+        Target::Namespace(path, prefix) => {
+            let elem = element_at(root, &path).expect("namespace of an element");
+            let in_use = elem.prefix.as_deref().unwrap_or("") == prefix
+                || elem
+                    .attributes
+                    .keys()
+                    .any(|name| name.prefix.as_deref() == Some(&prefix));
+            if in_use {
+                return Err(PatchError::new(
+                    PatchErrorKind::InvalidNamespacePrefix,
+                    format!("prefix `{}` is still used", prefix),
+                ));
+            }
+            rebind(elem, &prefix, None);
+            return Ok(());
+        }
+        Target::Node(path) => path,
+    };
+
+    let (index, parent_path) = match path.split_last() {
+        Some((&index, parent_path)) => (index, parent_path),
+        None => return Err(root_error("remove the root element")),
+    };
+    let parent = element_at(root, parent_path).expect("parent is an element");
+    if ws.is_some()
+        && !matches!(
+            parent.children[index],
+            XMLNode::Element(_) | XMLNode::Comment(_) | XMLNode::ProcessingInstruction(..)
+        )
+    {
+        return Err(PatchError::new(
+            PatchErrorKind::InvalidWhitespaceDirective,
+            "`ws` can only be used when removing an element, comment or processing instruction",
+        ));
+    }
+
+    let is_whitespace = |node: Option<&XMLNode>| match node {
+        Some(XMLNode::Whitespace(_)) => true,
+        Some(XMLNode::Text(s)) => s.trim().is_empty(),
+        _ => false,
+    };
+    let before = matches!(ws, Some(Whitespace::Before) | Some(Whitespace::Both));
+    let after = matches!(ws, Some(Whitespace::After) | Some(Whitespace::Both));
+    let children = &parent.children;
+    if (before && (index == 0 || !is_whitespace(children.get(index - 1))))
+        || (after && !is_whitespace(children.get(index + 1)))
+    {
+        return Err(PatchError::new(
+            PatchErrorKind::InvalidWhitespaceDirective,
+            "there is no whitespace to remove",
+        ));
+    }
+
+    let start = if before { index - 1 } else { index };
+    let end = if after { index + 2 } else { index + 1 };
+    parent.children.drain(start..end);
+    Ok(())
+}
+
+/// The location of a selected node, as indices into the children of elements starting from the
+/// root element
+enum Target {
+    /// The document root, above the root element
+    Root,
+    /// An element, or another node that is a child of an element.  The root element has an
+    /// empty path
+    Node(Vec<usize>),
+    Attribute(Vec<usize>, AttributeName),
+    Namespace(Vec<usize>, String),
+}
+
+impl Target {
+    /// Finds `node`, which was selected from `root`, by its address
+    fn find(root: &Element, node: &Node) -> Target {
+        if let Node::Root(_) = node {
+            return Target::Root;
+        }
+        let same_str = |a: &str, b: &str| ptr::eq(a, b);
+        let mut stack = vec![(root, Vec::new())];
+        while let Some((elem, path)) = stack.pop() {
+            match *node {
+                Node::Element(e) if ptr::eq(e, elem) => return Target::Node(path),
+                Node::Attribute(name, _) => {
+                    if let Some(key) = elem.attributes.keys().find(|k| ptr::eq(*k, name)) {
+                        return Target::Attribute(path, key.clone());
+                    }
+                }
+                Node::Namespace(prefix, uri) => {
+                    let found = elem
+                        .namespaces
+                        .iter()
+                        .flatten()
+                        .any(|(_, u)| same_str(u, uri));
+                    if found {
+                        return Target::Namespace(path, prefix.to_owned());
+                    }
+                }
+                _ => (),
+            }
+            for (i, child) in elem.children.iter().enumerate() {
+                let mut child_path = path.clone();
+                child_path.push(i);
+                let found = match (child, node) {
+                    (XMLNode::Element(e), _) => {
+                        stack.push((e, child_path));
+                        continue;
+                    }
+                    (
+                        XMLNode::Text(s) | XMLNode::CData(s) | XMLNode::Whitespace(s),
+                        Node::Text(t),
+                    ) => same_str(s, t),
+                    (XMLNode::Comment(s), Node::Comment(t)) => same_str(s, t),
+                    (XMLNode::ProcessingInstruction(s, _), Node::ProcessingInstruction(t, _)) => {
+                        same_str(s, t)
+                    }
+                    _ => false,
+                };
+                if found {
+                    return Target::Node(child_path);
+                }
+            }
+        }
+        unreachable!("selected nodes are part of the tree")
+    }
+}
+
+fn element_at<'a>(root: &'a mut Element, path: &[usize]) -> Option<&'a mut Element> {
+    let mut elem = root;
+    for &index in path {
+        elem = elem.children[index].as_mut_element()?;
+    }
+    Some(elem)
+}
+
+/// Binds `prefix` to `uri` on `elem`, or unbinds it, and on the descendants that inherit its
+/// current binding, since every parsed element keeps all the namespaces in scope
+fn rebind(elem: &mut Element, prefix: &str, uri: Option<&str>) {
+    let old = elem
+        .namespaces
+        .as_ref()
+        .and_then(|ns| ns.get(prefix))
+        .map(str::to_owned);
+    let mut stack = vec![elem];
+    while let Some(e) = stack.pop() {
+        if e.namespaces.as_ref().and_then(|ns| ns.get(prefix)) != old.as_deref() {
+            continue;
+        }
+        let namespaces = e.namespaces.get_or_insert_with(Namespace::empty);
+        match uri {
+            Some(uri) => namespaces.0.insert(prefix.to_owned(), uri.to_owned()),
+            None => namespaces.0.remove(prefix),
+        };
+        stack.extend(e.children.iter_mut().filter_map(XMLNode::as_mut_element));
+    }
+}
+
+fn parent_of<'a>(root: &'a mut Element, path: &[usize]) -> Option<&'a mut Element> {
+    let (_, parent_path) = path.split_last()?;
+    element_at(root, parent_path)
+}
+
+/// Resolves the name given in `type="@name"`
+fn attribute_name(name: &str, namespaces: &Namespace) -> Result<AttributeName, PatchError> {
+    match name.split_once(':') {
+        Some((prefix, local_name)) => match namespaces.get(prefix) {
+            Some(uri) => Ok(AttributeName::qualified(local_name, uri, prefix)),
+            None => Err(PatchError::new(
+                PatchErrorKind::InvalidNamespacePrefix,
+                format!("undeclared prefix `{}`", prefix),
+            )),
+        },
+        None => Ok(AttributeName::local(name)),
+    }
+}
+
+/// Moves an element from the patch, where `patch_scope` was in scope of its parent, into a tree
+/// where `target_scope` is in scope of its new parent.
+///
+/// The namespace declarations of the patch document itself are left behind, only those made
+/// inside the added content and those the content needs are kept.
+fn adopt(elem: &mut Element, patch_scope: &Namespace, target_scope: Option<&Namespace>) {
+    // Walk the new subtree with paths rather than references, so that it can be changed
+    let empty = Namespace::empty();
+    let mut stack = vec![(
+        Vec::new(),
+        patch_scope.clone(),
+        target_scope.unwrap_or(&empty).clone(),
+    )];
+    while let Some((path, patch_scope, target_scope)) = stack.pop() {
+        let e = element_at(elem, &path).expect("path to an element");
+        let original = e.namespaces.take().unwrap_or_else(Namespace::empty);
+        let mut namespaces = target_scope;
+        for (prefix, uri) in &original {
+            if patch_scope.get(prefix) != Some(uri) {
+                namespaces.0.insert(prefix.to_owned(), uri.to_owned());
+            }
+        }
+        let own = (
+            e.prefix.clone().unwrap_or_default(),
+            e.namespace.clone().unwrap_or_default(),
+        );
+        let used = e
+            .attributes
+            .keys()
+            .filter_map(|name| Some((name.prefix.clone()?, name.namespace.clone()?)));
+        for (prefix, uri) in std::iter::once(own).chain(used) {
+            let declared = namespaces.get(&prefix).unwrap_or("");
+            if declared != uri && prefix != "xml" {
+                namespaces.0.insert(prefix, uri);
+            }
+        }
+
+        for (i, child) in e.children.iter().enumerate() {
+            if child.as_element().is_some() {
+                let mut child_path = path.clone();
+                child_path.push(i);
+                stack.push((child_path, original.clone(), namespaces.clone()));
+            }
+        }
+        e.namespaces = Some(namespaces);
+    }
+}
+
+fn root_error(what: &str) -> PatchError {
+    PatchError::new(
+        PatchErrorKind::InvalidRootElementOperation,
+        format!("can't {}", what),
+    )
+}
+
+fn node_types_error(what: &str) -> PatchError {
+    PatchError::new(PatchErrorKind::InvalidNodeTypes, format!("can't {}", what))
+}
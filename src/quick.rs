@@ -0,0 +1,1058 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Read};
+use std::mem;
+use std::sync::Arc;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use xml::attribute::OwnedAttribute;
+use xml::common::{Position, TextPosition, XmlVersion};
+use xml::name::OwnedName;
+use xml::namespace::{
+    Namespace, NamespaceStack, NS_NO_PREFIX, NS_XMLNS_PREFIX, NS_XMLNS_URI, NS_XML_PREFIX,
+    NS_XML_URI,
+};
+use xml::reader::{Error, ParserConfig, XmlEvent};
+
+use crate::encoding::Encoding;
+use crate::Events;
+
+/// The maximum nesting of entity references in the values of entities
+const MAX_ENTITY_DEPTH: u8 = 10;
+
+/// The maximum length of the text that a reference to an entity expands to
+const MAX_ENTITY_LENGTH: usize = 1_000_000;
+
+/// An event read by a [`QuickReader`], like an xml-rs `XmlEvent`.  Strings are borrowed from the
+/// input when it is in memory and they appear in it as they are, and owned otherwise.
+#[derive(Debug)]
+pub(crate) enum QuickEvent<'i> {
+    StartDocument {
+        version: XmlVersion,
+        encoding: String,
+        standalone: Option<bool>,
+    },
+    EndDocument,
+    StartElement {
+        name: QuickName<'i>,
+        attributes: Vec<(QuickName<'i>, Cow<'i, str>)>,
+        /// All namespaces in scope, shared by the elements in the same scope.  `None` if no open
+        /// element declares any.
+        namespace: Option<Arc<Namespace>>,
+    },
+    EndElement {
+        name: QuickName<'i>,
+    },
+    ProcessingInstruction {
+        name: Cow<'i, str>,
+        data: Option<Cow<'i, str>>,
+    },
+    CData(Cow<'i, str>),
+    Comment(Cow<'i, str>),
+    Characters(Cow<'i, str>),
+    Whitespace(Cow<'i, str>),
+}
+
+impl QuickEvent<'_> {
+    fn into_xml(self) -> XmlEvent {
+        match self {
+            QuickEvent::StartDocument {
+                version,
+                encoding,
+                standalone,
+            } => XmlEvent::StartDocument {
+                version,
+                encoding,
+                standalone,
+            },
+            QuickEvent::EndDocument => XmlEvent::EndDocument,
+            QuickEvent::StartElement {
+                name,
+                attributes,
+                namespace,
+            } => XmlEvent::StartElement {
+                name: name.into_owned(),
+                attributes: attributes
+                    .into_iter()
+                    .map(|(name, value)| OwnedAttribute {
+                        name: name.into_owned(),
+                        value: value.into_owned(),
+                    })
+                    .collect(),
+                namespace: match namespace {
+                    Some(namespace) => Namespace::clone(&namespace),
+                    None => Namespace::empty(),
+                },
+            },
+            QuickEvent::EndElement { name } => XmlEvent::EndElement {
+                name: name.into_owned(),
+            },
+            QuickEvent::ProcessingInstruction { name, data } => XmlEvent::ProcessingInstruction {
+                name: name.into_owned(),
+                data: data.map(Cow::into_owned),
+            },
+            QuickEvent::CData(s) => XmlEvent::CData(s.into_owned()),
+            QuickEvent::Comment(s) => XmlEvent::Comment(s.into_owned()),
+            QuickEvent::Characters(s) => XmlEvent::Characters(s.into_owned()),
+            QuickEvent::Whitespace(s) => XmlEvent::Whitespace(s.into_owned()),
+        }
+    }
+}
+
+/// A qualified name in a [`QuickEvent`]
+#[derive(Debug, Clone)]
+pub(crate) struct QuickName<'i> {
+    pub(crate) local_name: Cow<'i, str>,
+    pub(crate) namespace: Option<Cow<'i, str>>,
+    pub(crate) prefix: Option<Cow<'i, str>>,
+}
+
+impl QuickName<'_> {
+    fn into_owned(self) -> OwnedName {
+        OwnedName {
+            local_name: self.local_name.into_owned(),
+            namespace: self.namespace.map(Cow::into_owned),
+            prefix: self.prefix.map(Cow::into_owned),
+        }
+    }
+}
+
+/// Where a [`QuickReader`] reads a document from
+pub(crate) trait Source<'i> {
+    /// Reads the next event, into `buf` if the input isn't in memory
+    fn read_event<'b>(&mut self, buf: &'b mut Vec<u8>) -> quick_xml::Result<Event<'b>>
+    where
+        'i: 'b;
+
+    /// Returns the position of the next event
+    fn start_position(&mut self) -> TextPosition;
+
+    /// Reads the rest of the input in `encoding`
+    fn set_encoding(&mut self, encoding: Encoding);
+
+    /// Returns `s`, which is part of the event read last, borrowed from the input if possible
+    fn keep(&self, s: &str) -> Cow<'i, str>;
+}
+
+/// A document read from an `io::Read`, which events can't borrow from
+pub(crate) struct Buffered<R: Read> {
+    reader: Reader<Counted<BufReader<R>>>,
+}
+
+impl<R: Read> Source<'static> for Buffered<R> {
+    fn read_event<'b>(&mut self, buf: &'b mut Vec<u8>) -> quick_xml::Result<Event<'b>>
+    where
+        'static: 'b,
+    {
+        self.reader.read_event_into(buf)
+    }
+
+    /// After text, quick-xml has already consumed the `<` that starts the markup following it, so
+    /// that has to be counted back.
+    fn start_position(&mut self) -> TextPosition {
+        let counted = self.reader.get_ref();
+        let mut position = counted.position;
+        let ahead = counted.consumed - self.reader.buffer_position();
+        position.column -= ahead;
+        position
+    }
+
+    fn set_encoding(&mut self, encoding: Encoding) {
+        self.reader.get_mut().encoding = encoding;
+    }
+
+    fn keep(&self, s: &str) -> Cow<'static, str> {
+        Cow::Owned(s.to_owned())
+    }
+}
+
+/// A document that is in memory as a whole, which events borrow from
+pub(crate) struct InMemory<'i> {
+    reader: Reader<&'i [u8]>,
+    input: &'i str,
+    /// The bytes of the input before this index have been counted in `position`
+    counted: usize,
+    position: TextPosition,
+}
+
+impl<'i> Source<'i> for InMemory<'i> {
+    fn read_event<'b>(&mut self, _buf: &'b mut Vec<u8>) -> quick_xml::Result<Event<'b>>
+    where
+        'i: 'b,
+    {
+        self.reader.read_event()
+    }
+
+    fn start_position(&mut self) -> TextPosition {
+        let offset = self.reader.buffer_position() as usize;
+        if offset > self.counted {
+            advance(
+                &mut self.position,
+                &self.input.as_bytes()[self.counted..offset],
+            );
+            self.counted = offset;
+        }
+        self.position
+    }
+
+    /// The input is a `str`, so it has been decoded already
+    fn set_encoding(&mut self, _encoding: Encoding) {}
+
+    fn keep(&self, s: &str) -> Cow<'i, str> {
+        // Events read from a slice refer to the slice itself, unless quick-xml had to change them
+        let offset = (s.as_ptr() as usize).wrapping_sub(self.input.as_ptr() as usize);
+        match self.input.get(offset..offset.wrapping_add(s.len())) {
+            Some(borrowed) => Cow::Borrowed(borrowed),
+            None => Cow::Owned(s.to_owned()),
+        }
+    }
+}
+
+/// Reads a document with quick-xml, and produces the same events as the xml-rs `EventReader`
+/// would for it.
+pub(crate) struct QuickReader<'i, S> {
+    source: S,
+    config: ParserConfig,
+    buf: Vec<u8>,
+    /// Events that have been read but not returned yet, with their positions
+    pending: VecDeque<(QuickEvent<'i>, TextPosition)>,
+    /// The position of the event returned last
+    position: TextPosition,
+    /// The prefixes and URIs of the namespaces declared by the open elements, innermost last
+    bindings: Vec<(Cow<'i, str>, Cow<'i, str>)>,
+    /// All namespaces in scope for each open element that declares namespaces, innermost last
+    scopes: Vec<Arc<Namespace>>,
+    /// The names of the elements that are open, and the number of namespaces they declare
+    open: Vec<(QuickName<'i>, usize)>,
+    /// Text read since the last event, which is returned as a single event, and where it starts
+    text: Cow<'i, str>,
+    text_position: TextPosition,
+    /// Whether xml-rs would report the text read since the last event as whitespace.  It decides
+    /// that from the characters read since the last markup, including those of CDATA sections, but
+    /// only looks at the last character of the values of references other than DTD entities.
+    whitespace: bool,
+    /// The general entities declared in the DTD, with their values
+    entities: HashMap<String, String>,
+    doctype: Option<String>,
+    version: XmlVersion,
+    started: bool,
+    seen_root: bool,
+    done: bool,
+}
+
+impl<R: Read> QuickReader<'static, Buffered<R>> {
+    pub(crate) fn new(r: R, config: ParserConfig) -> QuickReader<'static, Buffered<R>> {
+        let mut reader = Reader::from_reader(Counted::new(BufReader::new(r)));
+        reader.config_mut().check_comments = true;
+        QuickReader::with_source(Buffered { reader }, config)
+    }
+}
+
+impl<'i> QuickReader<'i, InMemory<'i>> {
+    /// Creates a reader whose events borrow from `input`
+    pub(crate) fn from_str(input: &'i str, config: ParserConfig) -> QuickReader<'i, InMemory<'i>> {
+        let mut reader = Reader::from_str(input);
+        reader.config_mut().check_comments = true;
+        let source = InMemory {
+            reader,
+            input,
+            // xml-rs doesn't count the byte order mark
+            counted: if input.starts_with('\u{feff}') { 3 } else { 0 },
+            position: TextPosition::new(),
+        };
+        QuickReader::with_source(source, config)
+    }
+}
+
+impl<'i, S: Source<'i>> QuickReader<'i, S> {
+    fn with_source(source: S, config: ParserConfig) -> QuickReader<'i, S> {
+        QuickReader {
+            source,
+            config,
+            buf: Vec::new(),
+            pending: VecDeque::new(),
+            position: TextPosition::new(),
+            bindings: Vec::new(),
+            scopes: Vec::new(),
+            open: Vec::new(),
+            text: Cow::Borrowed(""),
+            text_position: TextPosition::new(),
+            whitespace: true,
+            entities: HashMap::new(),
+            doctype: None,
+            version: XmlVersion::Version10,
+            started: false,
+            seen_root: false,
+            done: false,
+        }
+    }
+
+    /// Returns the next event
+    pub(crate) fn pull(&mut self) -> Result<QuickEvent<'i>, Error> {
+        loop {
+            if let Some((event, position)) = self.pending.pop_front() {
+                self.position = position;
+                return Ok(event);
+            }
+            if self.done {
+                return Ok(QuickEvent::EndDocument);
+            }
+            self.read()?;
+        }
+    }
+
+    /// Reads the next quick-xml event, and queues the events it results in
+    fn read(&mut self) -> Result<(), Error> {
+        let start = self.source.start_position();
+        let mut buf = mem::take(&mut self.buf);
+        buf.clear();
+        let result = match self.source.read_event(&mut buf) {
+            Ok(event) => self.event(event, start),
+            Err(e) => Err(error(start, e.to_string())),
+        };
+        self.buf = buf;
+        result
+    }
+
+    fn event(&mut self, event: Event, start: TextPosition) -> Result<(), Error> {
+        if !self.started {
+            self.started = true;
+            if let Event::Decl(decl) = event {
+                let version = match &*decl.version().map_err(|e| error(start, e.to_string()))? {
+                    b"1.0" => XmlVersion::Version10,
+                    b"1.1" => XmlVersion::Version11,
+                    other => {
+                        let other = String::from_utf8_lossy(other);
+                        return Err(error(start, format!("Invalid XML version: {}", other)));
+                    }
+                };
+                let encoding = match decl.encoding() {
+                    Some(encoding) => {
+                        let encoding = encoding.map_err(|e| error(start, e.to_string()))?;
+                        let encoding = decode(&encoding, start)?.to_owned();
+                        match Encoding::from_label(&encoding) {
+                            Some(decoding) => self.source.set_encoding(decoding),
+                            None => {
+                                let message = format!("Unsupported encoding: {}", encoding);
+                                return Err(error(start, message));
+                            }
+                        }
+                        encoding
+                    }
+                    None => "UTF-8".to_owned(),
+                };
+                let standalone = match decl.standalone() {
+                    Some(standalone) => {
+                        match &*standalone.map_err(|e| error(start, e.to_string()))? {
+                            b"yes" => Some(true),
+                            b"no" => Some(false),
+                            other => {
+                                let other = String::from_utf8_lossy(other);
+                                let message =
+                                    format!("Invalid standalone declaration value: {}", other);
+                                return Err(error(start, message));
+                            }
+                        }
+                    }
+                    None => None,
+                };
+                self.version = version;
+                let event = QuickEvent::StartDocument {
+                    version,
+                    encoding,
+                    standalone,
+                };
+                self.pending.push_back((event, start));
+                return Ok(());
+            }
+            let event = QuickEvent::StartDocument {
+                version: XmlVersion::Version10,
+                encoding: "UTF-8".to_owned(),
+                standalone: None,
+            };
+            self.pending.push_back((event, start));
+        }
+
+        match event {
+            Event::Start(tag) => self.start_element(&tag, start)?,
+            Event::Empty(tag) => {
+                self.start_element(&tag, start)?;
+                self.end_element(start);
+            }
+            Event::End(_) => {
+                self.flush_text()?;
+                self.end_element(start);
+            }
+            Event::Text(text) => {
+                let text = decode(&text, start)?;
+                check_chars(text, start)?;
+                if text.contains("]]>") {
+                    return Err(error(start, "Unexpected token: ]]>"));
+                }
+                let text = self.source.keep(text);
+                self.push_text(text, start);
+            }
+            Event::GeneralRef(reference) => {
+                if self.open.is_empty() {
+                    return Err(error(start, "Unexpected token: &"));
+                }
+                let name = decode(&reference, start)?;
+                let mut value = String::new();
+                self.expand_reference(name, true, start, 0, &mut value)?;
+                if self.entities.contains_key(name) && !is_builtin_entity(name, &self.config) {
+                    self.push_text(Cow::Owned(value), start);
+                } else {
+                    let whitespace = self.whitespace;
+                    self.push_text(Cow::Owned(value), start);
+                    let last = self.text.bytes().last().unwrap_or(0);
+                    self.whitespace = whitespace && is_whitespace(last);
+                }
+            }
+            Event::CData(cdata) => {
+                if self.open.is_empty() {
+                    return Err(error(start, "Unexpected token: <![CDATA["));
+                }
+                let cdata = decode(&cdata, start)?;
+                check_chars(cdata, start)?;
+                if self.config.cdata_to_characters {
+                    if !self.config.coalesce_characters {
+                        self.flush_text()?;
+                    }
+                    let cdata = self.source.keep(cdata);
+                    self.push_text(cdata, start);
+                } else {
+                    self.flush_text()?;
+                    self.whitespace = cdata.bytes().all(is_whitespace);
+                    let cdata = self.source.keep(cdata);
+                    self.pending.push_back((QuickEvent::CData(cdata), start));
+                }
+            }
+            Event::Comment(comment) => {
+                let comment = decode(&comment, start)?;
+                check_chars(comment, start)?;
+                if comment.ends_with('-') {
+                    return Err(error(start, "Unexpected token '--' before '-'"));
+                }
+                if self.config.ignore_comments {
+                    if !self.config.coalesce_characters {
+                        self.flush_text()?;
+                    }
+                } else {
+                    self.flush_text()?;
+                    let comment = self.source.keep(comment);
+                    self.pending
+                        .push_back((QuickEvent::Comment(comment), start));
+                }
+            }
+            Event::PI(pi) => {
+                let name = decode(pi.target(), start)?;
+                if name.eq_ignore_ascii_case("xml") {
+                    let message = format!("Invalid processing instruction: <?{}", name);
+                    return Err(error(start, message));
+                }
+                // The content starts with the whitespace that separates it from the name
+                let data = match pi.content() {
+                    [] => None,
+                    content => {
+                        let data = decode(&content[1..], start)?;
+                        check_chars(data, start)?;
+                        Some(self.source.keep(data))
+                    }
+                };
+                self.flush_text()?;
+                let event = QuickEvent::ProcessingInstruction {
+                    name: self.source.keep(name),
+                    data,
+                };
+                self.pending.push_back((event, start));
+            }
+            Event::Decl(_) => {
+                let message = "Invalid processing instruction: <?xml\nThe XML spec only allows \
+                               \"<?xml\" at the very beginning of the file";
+                return Err(error(start, message));
+            }
+            Event::DocType(doctype) => {
+                if self.seen_root || self.doctype.is_some() {
+                    return Err(error(start, "Unexpected token: <!DOCTYPE"));
+                }
+                self.flush_text()?;
+                let doctype = decode(&doctype, start)?;
+                self.declare_entities(doctype, start)?;
+                self.doctype = Some(format!("<!DOCTYPE {}>", doctype));
+            }
+            Event::Eof => {
+                self.flush_text()?;
+                if !self.open.is_empty() {
+                    return Err(error(
+                        start,
+                        "Unexpected end of stream: still inside the root element",
+                    ));
+                }
+                if !self.seen_root {
+                    return Err(error(
+                        start,
+                        "Unexpected end of stream: no root element found",
+                    ));
+                }
+                self.position = start;
+                self.done = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn start_element(&mut self, tag: &BytesStart, start: TextPosition) -> Result<(), Error> {
+        self.flush_text()?;
+        let qname = decode(tag.name().into_inner(), start)?;
+        check_name(qname, start)?;
+
+        // Namespace declarations apply to the element itself and to all of its attributes, so
+        // they are collected first
+        let bound = self.bindings.len();
+        let mut attributes = Vec::new();
+        for attribute in tag.attributes() {
+            let attribute = attribute.map_err(|e| error(start, e.to_string()))?;
+            let key = decode(attribute.key.into_inner(), start)?;
+            check_name(key, start)?;
+            let raw = decode(&attribute.value, start)?;
+            check_chars(raw, start)?;
+            if raw.contains('<') {
+                return Err(error(start, "Unexpected token '<' in an attribute value"));
+            }
+            let value = if raw.contains('&') {
+                let mut value = String::with_capacity(raw.len());
+                self.unescape(raw, false, start, 0, &mut value)?;
+                Cow::Owned(value)
+            } else {
+                self.source.keep(raw)
+            };
+
+            if key == NS_XMLNS_PREFIX {
+                self.bindings.push((Cow::Borrowed(NS_NO_PREFIX), value));
+            } else if let Some(prefix) = key.strip_prefix("xmlns:") {
+                if prefix == NS_XMLNS_PREFIX {
+                    return Err(error(start, "Cannot redefine XMLNS prefix"));
+                }
+                if prefix == NS_XML_PREFIX && value != NS_XML_URI {
+                    return Err(error(start, "The xml prefix cannot be rebound"));
+                }
+                if value.is_empty() {
+                    return Err(error(start, format!("Cannot undefine prefix '{}'", prefix)));
+                }
+                self.bindings.push((self.source.keep(prefix), value));
+            } else {
+                attributes.push((key, value));
+            }
+        }
+        let declared = self.bindings.len() - bound;
+
+        let name = self.resolve(qname, true, start)?;
+        let attributes = attributes
+            .into_iter()
+            .map(|(key, value)| Ok((self.resolve(key, false, start)?, value)))
+            .collect::<Result<_, Error>>()?;
+        // Without any declarations in scope, the namespace would only contain the predefined
+        // prefixes, which trees don't keep anyway
+        let namespace = if declared > 0 {
+            let mut namespace = match self.scopes.last() {
+                Some(outer) => Namespace::clone(outer),
+                None => NamespaceStack::default().squash(),
+            };
+            for (prefix, uri) in &self.bindings[bound..] {
+                namespace.force_put(&**prefix, &**uri);
+            }
+            let namespace = Arc::new(namespace);
+            self.scopes.push(Arc::clone(&namespace));
+            Some(namespace)
+        } else {
+            self.scopes.last().cloned()
+        };
+        let event = QuickEvent::StartElement {
+            name: name.clone(),
+            attributes,
+            namespace,
+        };
+        self.pending.push_back((event, start));
+        self.open.push((name, declared));
+        self.seen_root = true;
+        Ok(())
+    }
+
+    fn end_element(&mut self, start: TextPosition) {
+        // quick-xml has already checked that the end tag matches an open element
+        let (name, declared) = self.open.pop().expect("end tags are matched by quick-xml");
+        if declared > 0 {
+            self.bindings.truncate(self.bindings.len() - declared);
+            self.scopes.pop();
+        }
+        self.pending
+            .push_back((QuickEvent::EndElement { name }, start));
+    }
+
+    /// Splits a qualified name, and looks up the namespace of its prefix.  Unprefixed attributes
+    /// have no namespace, while unprefixed elements are in the default namespace.
+    fn resolve(
+        &self,
+        qname: &str,
+        element: bool,
+        start: TextPosition,
+    ) -> Result<QuickName<'i>, Error> {
+        let (prefix, local_name) = match qname.split_once(':') {
+            Some((prefix, local_name)) => (Some(prefix), local_name),
+            None => (None, qname),
+        };
+        let namespace = match prefix {
+            Some(NS_XMLNS_PREFIX) if element => {
+                return Err(error(start, "'xmlns' cannot be an element name prefix"));
+            }
+            Some(prefix) => match self.lookup(prefix) {
+                Some(namespace) => Some(namespace.clone()),
+                None if prefix == NS_XML_PREFIX => Some(Cow::Borrowed(NS_XML_URI)),
+                None if prefix == NS_XMLNS_PREFIX => Some(Cow::Borrowed(NS_XMLNS_URI)),
+                None => {
+                    let kind = if element { "Element" } else { "Attribute" };
+                    let message = format!("{} {} prefix is unbound", kind, qname);
+                    return Err(error(start, message));
+                }
+            },
+            None if element => self
+                .lookup(NS_NO_PREFIX)
+                .filter(|namespace| !namespace.is_empty())
+                .cloned(),
+            None => None,
+        };
+        Ok(QuickName {
+            local_name: self.source.keep(local_name),
+            namespace,
+            prefix: prefix.map(|prefix| self.source.keep(prefix)),
+        })
+    }
+
+    /// Returns the URI bound to `prefix` by the innermost declaration in scope
+    fn lookup(&self, prefix: &str) -> Option<&Cow<'i, str>> {
+        self.bindings
+            .iter()
+            .rev()
+            .find(|(bound, _)| bound == prefix)
+            .map(|(_, uri)| uri)
+    }
+
+    fn push_text(&mut self, text: Cow<'i, str>, start: TextPosition) {
+        self.whitespace &= text.bytes().all(is_whitespace);
+        if self.text.is_empty() {
+            self.text_position = start;
+            self.text = text;
+        } else {
+            self.text.to_mut().push_str(&text);
+        }
+    }
+
+    /// Queues the text read since the last event as a `Characters` or `Whitespace` event,
+    /// depending on the configuration
+    fn flush_text(&mut self) -> Result<(), Error> {
+        let whitespace = mem::replace(&mut self.whitespace, true);
+        if self.text.is_empty() {
+            return Ok(());
+        }
+        let text = mem::take(&mut self.text);
+        let position = self.text_position;
+        if self.open.is_empty() {
+            if !text.bytes().all(is_whitespace) {
+                let message = format!("Unexpected characters outside the root element: {}", text);
+                return Err(error(position, message));
+            }
+            // xml-rs only reports whitespace before the root element, and only as characters
+            if self.config.ignore_root_level_whitespace
+                || self.seen_root
+                || !self.config.whitespace_to_characters
+            {
+                return Ok(());
+            }
+        }
+
+        let event = if whitespace && !self.config.whitespace_to_characters {
+            if self.config.trim_whitespace {
+                return Ok(());
+            }
+            QuickEvent::Whitespace(text)
+        } else if self.config.trim_whitespace {
+            let trimmed = match text {
+                Cow::Borrowed(text) => Cow::Borrowed(trim(text)),
+                Cow::Owned(text) => Cow::Owned(trim(&text).to_owned()),
+            };
+            if trimmed.is_empty() {
+                return Ok(());
+            }
+            QuickEvent::Characters(trimmed)
+        } else {
+            QuickEvent::Characters(text)
+        };
+        self.pending.push_back((event, position));
+        Ok(())
+    }
+
+    /// Appends `raw` to `out`, with entity and character references replaced.  `content` is true
+    /// for text in elements, and false for attribute values.
+    fn unescape(
+        &self,
+        raw: &str,
+        content: bool,
+        start: TextPosition,
+        depth: u8,
+        out: &mut String,
+    ) -> Result<(), Error> {
+        let mut rest = raw;
+        while let Some(amp) = rest.find('&') {
+            out.push_str(&rest[..amp]);
+            let end = match rest[amp..].find(';') {
+                Some(end) => amp + end,
+                None => return Err(error(start, "Unterminated entity reference")),
+            };
+            self.expand_reference(&rest[amp + 1..end], content, start, depth, out)?;
+            rest = &rest[end + 1..];
+        }
+        out.push_str(rest);
+        Ok(())
+    }
+
+    /// Appends the value of the reference `&name;` to `out`
+    fn expand_reference(
+        &self,
+        name: &str,
+        content: bool,
+        start: TextPosition,
+        depth: u8,
+        out: &mut String,
+    ) -> Result<(), Error> {
+        if let Some(number) = name.strip_prefix('#') {
+            out.push(self.char_reference(number, start)?);
+            return Ok(());
+        }
+        if let Some(value) = predefined_entity(name)
+            .or_else(|| self.config.extra_entities.get(name).map(String::as_str))
+        {
+            out.push_str(value);
+            return Ok(());
+        }
+        match self.entities.get(name) {
+            Some(_) if depth >= MAX_ENTITY_DEPTH => {
+                Err(error(start, "Entity expansion is too deeply nested"))
+            }
+            Some(value) if content && value.contains('<') => {
+                let message = format!("Entity {} contains markup, which is not supported", name);
+                Err(error(start, message))
+            }
+            Some(value) => {
+                self.unescape(value, content, start, depth + 1, out)?;
+                if out.len() > MAX_ENTITY_LENGTH {
+                    return Err(error(start, "Entity expansion is too long"));
+                }
+                Ok(())
+            }
+            None => Err(error(start, format!("Unexpected entity: {}", name))),
+        }
+    }
+
+    /// Returns the character of a reference like `&#65;` or `&#x41;`, given the part after `#`
+    fn char_reference(&self, number: &str, start: TextPosition) -> Result<char, Error> {
+        let code = match number.strip_prefix('x') {
+            Some(hex) if !hex.is_empty() && hex.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                u32::from_str_radix(hex, 16).ok()
+            }
+            None if !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()) => {
+                number.parse().ok()
+            }
+            _ => None,
+        };
+        let code = match code {
+            Some(code) => code,
+            None => return Err(error(start, format!("Invalid numeric entity: {}", number))),
+        };
+        let restricted_allowed = self.version == XmlVersion::Version11;
+        match char::from_u32(code) {
+            Some(c) if is_char(c) || (restricted_allowed && ('\u{1}'..='\u{1f}').contains(&c)) => {
+                Ok(c)
+            }
+            _ if self.config.replace_unknown_entity_references => Ok('\u{fffd}'),
+            _ => Err(error(start, format!("Invalid character U+{:04X}", code))),
+        }
+    }
+
+    /// Reads the declarations of general entities with literal values in the internal subset of
+    /// the DTD.  Everything else in the DTD is skipped.
+    fn declare_entities(&mut self, doctype: &str, start: TextPosition) -> Result<(), Error> {
+        let mut rest = doctype;
+        while let Some(open) = rest.find('<') {
+            rest = &rest[open..];
+            if let Some(comment) = rest.strip_prefix("<!--") {
+                rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+                continue;
+            }
+            if let Some(pi) = rest.strip_prefix("<?") {
+                rest = pi.find("?>").map_or("", |end| &pi[end + 2..]);
+                continue;
+            }
+            let declaration = match rest.strip_prefix("<!ENTITY") {
+                Some(declaration) => {
+                    declaration.trim_start_matches(|c: char| c.is_ascii_whitespace())
+                }
+                None => {
+                    rest = skip_declaration(&rest[1..]);
+                    continue;
+                }
+            };
+            rest = skip_declaration(declaration);
+            if declaration.starts_with('%') {
+                continue;
+            }
+            let name_end = declaration
+                .find(|c: char| c.is_ascii_whitespace())
+                .unwrap_or(declaration.len());
+            let name = &declaration[..name_end];
+            let definition = declaration[name_end..].trim_start();
+            let quote = match definition.chars().next() {
+                Some(quote @ '"') | Some(quote @ '\'') => quote,
+                // External entities are not read
+                _ => continue,
+            };
+            let literal = &definition[1..];
+            let literal = &literal[..literal.find(quote).unwrap_or(literal.len())];
+
+            // Character references are replaced right away, and entity references when the
+            // entity is used
+            let mut value = String::with_capacity(literal.len());
+            let mut chars = literal;
+            while let Some(amp) = chars.find("&#") {
+                value.push_str(&chars[..amp]);
+                let end = match chars[amp..].find(';') {
+                    Some(end) => amp + end,
+                    None => return Err(error(start, "Unterminated entity reference")),
+                };
+                value.push(self.char_reference(&chars[amp + 2..end], start)?);
+                chars = &chars[end + 1..];
+            }
+            value.push_str(chars);
+
+            if predefined_entity(name).is_none() {
+                self.entities.entry(name.to_owned()).or_insert(value);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S> Position for QuickReader<'_, S> {
+    fn position(&self) -> TextPosition {
+        self.position
+    }
+}
+
+impl<R: Read> Events for QuickReader<'static, Buffered<R>> {
+    fn next_event(&mut self) -> Result<XmlEvent, Error> {
+        self.pull().map(QuickEvent::into_xml)
+    }
+
+    fn doctype(&self) -> Option<&str> {
+        self.doctype.as_deref()
+    }
+}
+
+/// Converts the input to UTF-8 if necessary, and keeps track of the position of the data
+/// consumed, counted the way xml-rs does: rows are separated by `\n`, and columns count
+/// characters.
+struct Counted<B> {
+    inner: B,
+    encoding: Encoding,
+    /// Input converted to UTF-8, if it is in another encoding
+    decoded: Vec<u8>,
+    /// The bytes of `decoded` before this index have been consumed
+    read: usize,
+    position: TextPosition,
+    /// The number of bytes consumed
+    consumed: u64,
+    started: bool,
+}
+
+impl<B: BufRead> Counted<B> {
+    fn new(inner: B) -> Counted<B> {
+        Counted {
+            inner,
+            encoding: Encoding::Utf8,
+            decoded: Vec::new(),
+            read: 0,
+            position: TextPosition::new(),
+            consumed: 0,
+            started: false,
+        }
+    }
+}
+
+impl<B: BufRead> Read for Counted<B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.fill_buf()?;
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<B: BufRead> BufRead for Counted<B> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.encoding == Encoding::Utf8 {
+            return self.inner.fill_buf();
+        }
+        if self.read == self.decoded.len() {
+            self.decoded.clear();
+            self.read = 0;
+            let data = self.inner.fill_buf()?;
+            if self.encoding == Encoding::Ascii && !data.is_ascii() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "char is not ASCII",
+                ));
+            }
+            for &b in data {
+                // Latin-1 maps every byte to the code point with the same value
+                let mut utf8 = [0; 2];
+                self.decoded
+                    .extend_from_slice(char::from(b).encode_utf8(&mut utf8).as_bytes());
+            }
+            let n = data.len();
+            self.inner.consume(n);
+        }
+        Ok(&self.decoded[self.read..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // The data being consumed has been returned by `fill_buf` already, so this doesn't read
+        let data = if self.encoding == Encoding::Utf8 {
+            self.inner.fill_buf().unwrap_or_default()
+        } else {
+            &self.decoded[self.read..]
+        };
+        let mut data = &data[..amt.min(data.len())];
+        if !self.started && !data.is_empty() {
+            self.started = true;
+            // xml-rs doesn't count the byte order mark
+            data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+        }
+        advance(&mut self.position, data);
+        self.consumed += amt as u64;
+        if self.encoding == Encoding::Utf8 {
+            self.inner.consume(amt);
+        } else {
+            self.read += amt;
+        }
+    }
+}
+
+fn advance(position: &mut TextPosition, data: &[u8]) {
+    let line = match data.iter().rposition(|&b| b == b'\n') {
+        Some(last) => {
+            position.row += data.iter().filter(|&&b| b == b'\n').count() as u64;
+            position.column = 0;
+            &data[last + 1..]
+        }
+        None => data,
+    };
+    // Continuation bytes of UTF-8 sequences are part of the same character
+    position.column += line.iter().filter(|&&b| b & 0xC0 != 0x80).count() as u64;
+}
+
+fn error(position: TextPosition, message: impl Into<Cow<'static, str>>) -> Error {
+    Error::from((&position, message.into()))
+}
+
+fn decode(bytes: &[u8], start: TextPosition) -> Result<&str, Error> {
+    std::str::from_utf8(bytes).map_err(|e| error(start, e.to_string()))
+}
+
+fn predefined_entity(name: &str) -> Option<&'static str> {
+    match name {
+        "lt" => Some("<"),
+        "gt" => Some(">"),
+        "amp" => Some("&"),
+        "apos" => Some("'"),
+        "quot" => Some("\""),
+        _ => None,
+    }
+}
+
+/// Whether `name` is replaced before the entities declared in the DTD are looked at
+fn is_builtin_entity(name: &str, config: &ParserConfig) -> bool {
+    predefined_entity(name).is_some() || config.extra_entities.contains_key(name)
+}
+
+fn is_whitespace(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+/// Removes the whitespace that xml-rs trims from both ends of `s`
+fn trim(s: &str) -> &str {
+    s.trim_matches(|c: char| c.is_ascii() && is_whitespace(c as u8))
+}
+
+/// Whether `c` may appear in an XML 1.0 document
+fn is_char(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r' | ' '..='\u{d7ff}' | '\u{e000}'..='\u{fffd}' | '\u{10000}'..)
+}
+
+fn check_chars(s: &str, start: TextPosition) -> Result<(), Error> {
+    // Only control characters and U+FFFE and U+FFFF can be invalid in UTF-8 text, so the check
+    // only needs to decode characters that start with one of these bytes
+    let bytes = s.as_bytes();
+    let suspicious = |b: &u8| *b < 0x20 || *b == 0xEF;
+    let mut offset = 0;
+    while let Some(i) = bytes[offset..].iter().position(suspicious) {
+        let i = offset + i;
+        let c = s[i..]
+            .chars()
+            .next()
+            .expect("the index is at a character boundary");
+        if !is_char(c) {
+            let message = format!("Invalid character U+{:04X}", c as u32);
+            return Err(error(start, message));
+        }
+        offset = i + 1;
+    }
+    Ok(())
+}
+
+fn check_name(qname: &str, start: TextPosition) -> Result<(), Error> {
+    fn is_name(name: &str) -> bool {
+        let mut chars = name.chars();
+        let first = match chars.next() {
+            Some(c) => c,
+            None => return false,
+        };
+        (first.is_ascii_alphabetic() || first == '_' || !first.is_ascii())
+            && chars
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_') || !c.is_ascii())
+    }
+    let valid = match qname.split_once(':') {
+        Some((prefix, local_name)) => is_name(prefix) && is_name(local_name),
+        None => is_name(qname),
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(error(start, format!("Invalid qualified name: {}", qname)))
+    }
+}
+
+/// Returns what follows the `>` that ends a markup declaration, skipping over quoted strings
+fn skip_declaration(declaration: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in declaration.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if q == c => quote = None,
+            (Some(_), _) => (),
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '>') => return &declaration[i + 1..],
+            (None, _) => (),
+        }
+    }
+    ""
+}
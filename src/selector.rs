@@ -0,0 +1,696 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::iter::Peekable;
+use std::num::IntErrorKind;
+use std::str::CharIndices;
+
+use crate::{Element, ElementPredicate, XMLNode};
+
+/// A compiled CSS selector, as used by [`Element::select`].
+///
+/// The supported syntax is a subset of CSS Selectors Level 3:
+///
+/// * type selectors (`rect`) and the universal selector (`*`)
+/// * `#id` and `.class`, which look at the `id` and `class` attributes
+/// * attribute selectors: `[a]`, `[a=b]`, `[a~=b]`, `[a|=b]`, `[a^=b]`, `[a$=b]` and `[a*=b]`
+/// * the descendant (` `), child (`>`), next-sibling (`+`) and subsequent-sibling (`~`)
+///   combinators
+/// * `:first-child`, `:last-child`, `:only-child`, `:nth-child(an+b)` and
+///   `:nth-last-child(an+b)`
+/// * lists of selectors separated by commas
+///
+/// There is no syntax for namespaces: a type selector matches elements with that local name in
+/// any namespace, and attribute selectors only match attributes that are not in a namespace.
+///
+/// ```
+/// use xmltree::{Element, Selector};
+///
+/// let svg = Element::parse(r#"<svg><g class="layer top"><rect id="a"/><circle/></g></svg>"#.as_bytes()).unwrap();
+///
+/// let selector = Selector::parse("g.layer > rect + circle").unwrap();
+/// assert_eq!(selector.select(&svg)[0].name, "circle");
+/// assert_eq!(svg.select_first("#a").unwrap().unwrap().name, "rect");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selector {
+    alternatives: Vec<Complex>,
+}
+
+/// Error returned when a selector can't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectorError {
+    /// The byte offset in the selector at which the error was found
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid selector at offset {}: {}",
+            self.position, self.message
+        )
+    }
+}
+
+impl std::error::Error for SelectorError {}
+
+/// A sequence of compound selectors joined by combinators, such as `a > b c`
+///
+/// The combinator of each part relates it to the part before it.  The combinator of the first
+/// part is unused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Complex {
+    parts: Vec<(Combinator, Compound)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Combinator {
+    Descendant,
+    Child,
+    NextSibling,
+    SubsequentSibling,
+}
+
+/// Simple selectors that all have to match the same element, such as `a.b[c]:first-child`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Compound {
+    predicates: Vec<Simple>,
+    positions: Vec<Nth>,
+}
+
+/// A simple selector that only depends on the element itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Simple {
+    Type(String),
+    Id(String),
+    Class(String),
+    Attribute(String, Option<(AttributeOp, String)>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AttributeOp {
+    Equals,
+    Includes,
+    DashMatch,
+    Prefix,
+    Suffix,
+    Substring,
+}
+
+/// A `:nth-child(an+b)` or `:nth-last-child(an+b)` condition.  `a` and `b` are within the range of
+/// an `i32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Nth {
+    a: i64,
+    b: i64,
+    from_end: bool,
+}
+
+impl ElementPredicate for Simple {
+    fn match_element(&self, e: &Element) -> bool {
+        match self {
+            Simple::Type(name) => e.matches(name.as_str()),
+            Simple::Id(id) => e.get_attribute_ns("id", None) == Some(id.as_str()),
+            Simple::Class(class) => e
+                .get_attribute_ns("class", None)
+                .is_some_and(|classes| classes.split_whitespace().any(|c| c == class)),
+            Simple::Attribute(name, condition) => {
+                let value = match e.get_attribute_ns(name, None) {
+                    Some(value) => value,
+                    None => return false,
+                };
+                let (op, expected) = match condition {
+                    Some((op, expected)) => (op, expected.as_str()),
+                    None => return true,
+                };
+                match op {
+                    AttributeOp::Equals => value == expected,
+                    AttributeOp::Includes => value.split_whitespace().any(|v| v == expected),
+                    AttributeOp::DashMatch => {
+                        value == expected
+                            || (value.starts_with(expected)
+                                && value[expected.len()..].starts_with('-'))
+                    }
+                    AttributeOp::Prefix => !expected.is_empty() && value.starts_with(expected),
+                    AttributeOp::Suffix => !expected.is_empty() && value.ends_with(expected),
+                    AttributeOp::Substring => !expected.is_empty() && value.contains(expected),
+                }
+            }
+        }
+    }
+}
+
+impl ElementPredicate for Compound {
+    /// Checks everything but the position of the element among its siblings
+    fn match_element(&self, e: &Element) -> bool {
+        self.predicates.iter().all(|p| p.match_element(e))
+    }
+}
+
+impl Nth {
+    fn matches(&self, index: usize, count: usize) -> bool {
+        let position = if self.from_end {
+            count - index
+        } else {
+            index + 1
+        };
+        let n = match i64::try_from(position)
+            .ok()
+            .and_then(|p| p.checked_sub(self.b))
+        {
+            Some(n) => n,
+            None => return false,
+        };
+        if self.a == 0 {
+            n == 0
+        } else {
+            // `b` fits in an `i32`, so `n` is far from `i64::MIN`, and dividing it can't overflow
+            n % self.a == 0 && n / self.a >= 0
+        }
+    }
+}
+
+fn child_elements(elem: &Element) -> Vec<&Element> {
+    elem.children
+        .iter()
+        .filter_map(|node| match node {
+            XMLNode::Element(e) => Some(e),
+            _ => None,
+        })
+        .collect()
+}
+
+impl Selector {
+    /// Parses a selector, or a comma separated list of selectors
+    pub fn parse(selector: &str) -> Result<Selector, SelectorError> {
+        Parser::new(selector).parse()
+    }
+
+    /// Returns all elements matching this selector, in document order
+    ///
+    /// `root` and all of its descendants are candidates.  `root` is treated as the root of the
+    /// document, so combinators never look above it, and it counts as an only child.
+    pub fn select<'a>(&self, root: &'a Element) -> Vec<&'a Element> {
+        let mut found = Vec::new();
+        self.visit(root, |e| {
+            found.push(e);
+            false
+        });
+        found
+    }
+
+    /// Returns the first element matching this selector, in document order
+    pub fn select_first<'a>(&self, root: &'a Element) -> Option<&'a Element> {
+        let mut found = None;
+        self.visit(root, |e| {
+            found = Some(e);
+            true
+        });
+        found
+    }
+
+    /// Calls `f` with each matching element, until it returns `true`
+    fn visit<'a, F: FnMut(&'a Element) -> bool>(&self, root: &'a Element, mut f: F) {
+        let mut matcher = Matcher {
+            selector: self,
+            levels: vec![Level::new(vec![root])],
+        };
+        loop {
+            let depth = matcher.levels.len() - 1;
+            let level = matcher
+                .levels
+                .last_mut()
+                .expect("the root level is never popped");
+            let index = level.next;
+            let elem = match level.elements.get(index) {
+                Some(&elem) => elem,
+                None if depth == 0 => return,
+                None => {
+                    matcher.levels.pop();
+                    continue;
+                }
+            };
+            level.next += 1;
+            if matcher.matches(depth, index) && f(elem) {
+                return;
+            }
+            matcher.levels.push(Level::new(child_elements(elem)));
+        }
+    }
+}
+
+/// Matches the elements of a tree against a selector, in document order.
+///
+/// The results of matching the elements around a candidate against the parts of the selector are
+/// kept for as long as those elements are around, so that each element is only matched against
+/// each part once however many candidates below or after it look at it.
+struct Matcher<'s, 'a> {
+    selector: &'s Selector,
+    /// The siblings of the current candidate and of each of its ancestors, from the root down
+    levels: Vec<Level<'a>>,
+}
+
+/// The child elements of an ancestor of the current candidate
+///
+/// The results are keyed by the index of the alternative in the selector, and the number of its
+/// parts that are matched.
+struct Level<'a> {
+    elements: Vec<&'a Element>,
+    /// The index of the next element to visit.  The element before it is the ancestor of the
+    /// levels below this one, or the current candidate.
+    next: usize,
+    /// Whether the element at an index matches
+    matched: HashMap<(usize, usize, usize), bool>,
+    /// Whether any element before an index matches
+    preceding: HashMap<(usize, usize, usize), bool>,
+    /// Whether any ancestor of the elements of this level matches
+    above: HashMap<(usize, usize), bool>,
+}
+
+impl<'a> Level<'a> {
+    fn new(elements: Vec<&'a Element>) -> Level<'a> {
+        Level {
+            elements,
+            next: 0,
+            matched: HashMap::new(),
+            preceding: HashMap::new(),
+            above: HashMap::new(),
+        }
+    }
+
+    /// The element of this level that is being visited
+    fn current(&self) -> usize {
+        self.next - 1
+    }
+}
+
+impl Matcher<'_, '_> {
+    /// Checks whether the element at `index` of the level at `depth` matches the selector
+    fn matches(&mut self, depth: usize, index: usize) -> bool {
+        (0..self.selector.alternatives.len()).any(|alternative| {
+            let parts = self.selector.alternatives[alternative].parts.len();
+            self.matches_part(alternative, parts, depth, index)
+        })
+    }
+
+    /// Checks whether an element matches the first `parts` parts of an alternative: the last of
+    /// them itself, and the earlier ones with the elements around it
+    fn matches_part(
+        &mut self,
+        alternative: usize,
+        parts: usize,
+        depth: usize,
+        index: usize,
+    ) -> bool {
+        let key = (alternative, parts, index);
+        if let Some(&matched) = self.levels[depth].matched.get(&key) {
+            return matched;
+        }
+        let matched = self.check_part(alternative, parts, depth, index);
+        self.levels[depth].matched.insert(key, matched);
+        matched
+    }
+
+    fn check_part(&mut self, alternative: usize, parts: usize, depth: usize, index: usize) -> bool {
+        let selector = self.selector;
+        let (combinator, compound) = &selector.alternatives[alternative].parts[parts - 1];
+        let level = &self.levels[depth];
+        if !compound.match_element(level.elements[index]) {
+            return false;
+        }
+        let count = level.elements.len();
+        if !compound
+            .positions
+            .iter()
+            .all(|nth| nth.matches(index, count))
+        {
+            return false;
+        }
+
+        if parts == 1 {
+            return true;
+        }
+        match combinator {
+            Combinator::Descendant => self.any_above(alternative, parts - 1, depth),
+            Combinator::Child => {
+                depth > 0 && {
+                    let parent = self.levels[depth - 1].current();
+                    self.matches_part(alternative, parts - 1, depth - 1, parent)
+                }
+            }
+            Combinator::NextSibling => {
+                index > 0 && self.matches_part(alternative, parts - 1, depth, index - 1)
+            }
+            Combinator::SubsequentSibling => {
+                self.any_preceding(alternative, parts - 1, depth, index)
+            }
+        }
+    }
+
+    /// Checks whether any ancestor of the elements at `depth` matches the first `parts` parts of
+    /// an alternative
+    fn any_above(&mut self, alternative: usize, parts: usize, depth: usize) -> bool {
+        let key = (alternative, parts);
+        // Start from the deepest level that is already known, and work down from there
+        let mut known = depth;
+        while known > 0 && !self.levels[known].above.contains_key(&key) {
+            known -= 1;
+        }
+        let mut found = self.levels[known].above.get(&key).copied().unwrap_or(false);
+        for ancestor in known..depth {
+            if !found {
+                let index = self.levels[ancestor].current();
+                found = self.matches_part(alternative, parts, ancestor, index);
+            }
+            self.levels[ancestor + 1].above.insert(key, found);
+        }
+        found
+    }
+
+    /// Checks whether any element before `index` at `depth` matches the first `parts` parts of
+    /// an alternative
+    fn any_preceding(
+        &mut self,
+        alternative: usize,
+        parts: usize,
+        depth: usize,
+        index: usize,
+    ) -> bool {
+        let key = |i| (alternative, parts, i);
+        let mut known = index;
+        while known > 0 && !self.levels[depth].preceding.contains_key(&key(known)) {
+            known -= 1;
+        }
+        let mut found = self.levels[depth]
+            .preceding
+            .get(&key(known))
+            .copied()
+            .unwrap_or(false);
+        for sibling in known..index {
+            if !found {
+                found = self.matches_part(alternative, parts, depth, sibling);
+            }
+            self.levels[depth].preceding.insert(key(sibling + 1), found);
+        }
+        found
+    }
+}
+
+struct Parser<'s> {
+    input: &'s str,
+    chars: Peekable<CharIndices<'s>>,
+}
+
+impl<'s> Parser<'s> {
+    fn new(input: &'s str) -> Parser<'s> {
+        Parser {
+            input,
+            chars: input.char_indices().peekable(),
+        }
+    }
+
+    fn error<T>(&mut self, message: &str) -> Result<T, SelectorError> {
+        Err(SelectorError {
+            position: self.offset(),
+            message: message.to_owned(),
+        })
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map_or(self.input.len(), |&(i, _)| i)
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.chars.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), SelectorError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            self.error(&format!("expected `{}`", c))
+        }
+    }
+
+    /// Skips whitespace, and returns whether there was any
+    fn skip_whitespace(&mut self) -> bool {
+        let mut skipped = false;
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.chars.next();
+            skipped = true;
+        }
+        skipped
+    }
+
+    fn parse(mut self) -> Result<Selector, SelectorError> {
+        let mut alternatives = Vec::new();
+        loop {
+            self.skip_whitespace();
+            alternatives.push(self.complex()?);
+            if !self.eat(',') {
+                break;
+            }
+        }
+        if self.peek().is_some() {
+            return self.error("unexpected character");
+        }
+        Ok(Selector { alternatives })
+    }
+
+    fn complex(&mut self) -> Result<Complex, SelectorError> {
+        let mut parts = vec![(Combinator::Descendant, self.compound()?)];
+        loop {
+            let whitespace = self.skip_whitespace();
+            let combinator = match self.peek() {
+                Some('>') => Combinator::Child,
+                Some('+') => Combinator::NextSibling,
+                Some('~') => Combinator::SubsequentSibling,
+                Some(',') | None => break,
+                Some(_) if whitespace => Combinator::Descendant,
+                Some(_) => return self.error("unexpected character"),
+            };
+            if combinator != Combinator::Descendant {
+                self.chars.next();
+                self.skip_whitespace();
+            }
+            parts.push((combinator, self.compound()?));
+        }
+        Ok(Complex { parts })
+    }
+
+    fn compound(&mut self) -> Result<Compound, SelectorError> {
+        let mut compound = Compound {
+            predicates: Vec::new(),
+            positions: Vec::new(),
+        };
+        let mut empty = true;
+        if self.eat('*') {
+            empty = false;
+        } else if self.peek().is_some_and(is_ident_start) {
+            compound.predicates.push(Simple::Type(self.ident()?));
+            empty = false;
+        }
+        loop {
+            match self.peek() {
+                Some('#') => {
+                    self.chars.next();
+                    compound.predicates.push(Simple::Id(self.ident()?));
+                }
+                Some('.') => {
+                    self.chars.next();
+                    compound.predicates.push(Simple::Class(self.ident()?));
+                }
+                Some('[') => {
+                    self.chars.next();
+                    compound.predicates.push(self.attribute()?);
+                }
+                Some(':') => {
+                    self.chars.next();
+                    self.pseudo_class(&mut compound.positions)?;
+                }
+                _ => break,
+            }
+            empty = false;
+        }
+        if empty {
+            return self.error("expected a selector");
+        }
+        Ok(compound)
+    }
+
+    fn ident(&mut self) -> Result<String, SelectorError> {
+        let mut ident = String::new();
+        loop {
+            match self.peek() {
+                Some('\\') => {
+                    self.chars.next();
+                    match self.chars.next() {
+                        Some((_, c)) => ident.push(c),
+                        None => return self.error("expected an escaped character"),
+                    }
+                }
+                Some(c) if is_ident_start(c) || c.is_ascii_digit() || c == '-' => {
+                    self.chars.next();
+                    ident.push(c);
+                }
+                _ => break,
+            }
+        }
+        if ident.is_empty() {
+            return self.error("expected a name");
+        }
+        Ok(ident)
+    }
+
+    fn attribute(&mut self) -> Result<Simple, SelectorError> {
+        self.skip_whitespace();
+        let name = self.ident()?;
+        self.skip_whitespace();
+        if self.eat(']') {
+            return Ok(Simple::Attribute(name, None));
+        }
+        let op = match self.chars.next().map(|(_, c)| c) {
+            Some('=') => AttributeOp::Equals,
+            Some(c) => {
+                let op = match c {
+                    '~' => AttributeOp::Includes,
+                    '|' => AttributeOp::DashMatch,
+                    '^' => AttributeOp::Prefix,
+                    '$' => AttributeOp::Suffix,
+                    '*' => AttributeOp::Substring,
+                    _ => return self.error("expected an attribute operator"),
+                };
+                self.expect('=')?;
+                op
+            }
+            None => return self.error("expected `]`"),
+        };
+        self.skip_whitespace();
+        let value = match self.peek() {
+            Some(quote) if quote == '"' || quote == '\'' => self.string(quote)?,
+            _ => self.ident()?,
+        };
+        self.skip_whitespace();
+        self.expect(']')?;
+        Ok(Simple::Attribute(name, Some((op, value))))
+    }
+
+    fn string(&mut self, quote: char) -> Result<String, SelectorError> {
+        self.chars.next();
+        let mut value = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, c)) if c == quote => return Ok(value),
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, c)) => value.push(c),
+                    None => break,
+                },
+                Some((_, c)) => value.push(c),
+                None => break,
+            }
+        }
+        self.error("unterminated string")
+    }
+
+    fn pseudo_class(&mut self, positions: &mut Vec<Nth>) -> Result<(), SelectorError> {
+        let start = self.offset();
+        let name = self.ident()?.to_ascii_lowercase();
+        let (a, b, from_end) = match name.as_str() {
+            "first-child" => (0, 1, false),
+            "last-child" => (0, 1, true),
+            "nth-child" | "nth-last-child" => {
+                self.expect('(')?;
+                self.skip_whitespace();
+                let (a, b) = self.nth()?;
+                self.skip_whitespace();
+                self.expect(')')?;
+                (a, b, name == "nth-last-child")
+            }
+            "only-child" => {
+                // The same as `:first-child:last-child`
+                positions.push(Nth {
+                    a: 0,
+                    b: 1,
+                    from_end: false,
+                });
+                (0, 1, true)
+            }
+            _ => {
+                return Err(SelectorError {
+                    position: start,
+                    message: format!("unsupported pseudo-class `:{}`", name),
+                })
+            }
+        };
+        positions.push(Nth { a, b, from_end });
+        Ok(())
+    }
+
+    /// Parses the `an+b` argument of `:nth-child()`
+    fn nth(&mut self) -> Result<(i64, i64), SelectorError> {
+        let start = self.offset();
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            if c == ')' {
+                break;
+            }
+            self.chars.next();
+            if !c.is_whitespace() {
+                text.push(c.to_ascii_lowercase());
+            }
+        }
+        let invalid = || SelectorError {
+            position: start,
+            message: "expected an argument of the form `an+b`".to_owned(),
+        };
+        let number = |text: &str| {
+            text.parse::<i32>()
+                .map(i64::from)
+                .map_err(|e| match e.kind() {
+                    IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => SelectorError {
+                        position: start,
+                        message: "`an+b` argument out of range".to_owned(),
+                    },
+                    _ => invalid(),
+                })
+        };
+        match text.as_str() {
+            "odd" => return Ok((2, 1)),
+            "even" => return Ok((2, 0)),
+            _ => (),
+        }
+        let (a, b) = match text.find('n') {
+            Some(i) => {
+                let a = match &text[..i] {
+                    "" | "+" => 1,
+                    "-" => -1,
+                    a => number(a)?,
+                };
+                let b = match &text[i + 1..] {
+                    "" => 0,
+                    b if b.starts_with('+') || b.starts_with('-') => number(b)?,
+                    _ => return Err(invalid()),
+                };
+                (a, b)
+            }
+            None => (0, number(&text)?),
+        };
+        Ok((a, b))
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_' || c == '-' || !c.is_ascii()
+}
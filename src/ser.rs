@@ -0,0 +1,891 @@
+use serde::ser::{self, Impossible, Serialize};
+
+use xml::namespace::{Namespace, NS_XML_PREFIX, NS_XML_URI};
+
+use crate::de::SerdeError;
+use crate::{AttributeName, Element, XMLNode};
+
+/// Serializes a value into a new element.
+///
+/// The value must be a struct, which becomes an element named after the struct.  The naming
+/// conventions are the same as for [`from_element`](crate::from_element): fields starting with
+/// `@` become attributes, a field named `$text` becomes the text content, and all other fields
+/// become child elements.  A field holding a sequence becomes one child element per item, and a
+/// field that is `None` is left out.
+///
+/// Values that end up as attributes or text must be scalars, or sequences of scalars, which are
+/// joined with spaces.
+///
+/// Attributes in a namespace are named like `from_element` reads them.  `@{urn:x}href` is put in
+/// the namespace `urn:x`, with a prefix that is declared on the element if needed.  `@x:href` uses
+/// the prefix `x`, which must have been declared by an earlier `@xmlns:x` field holding the
+/// namespace URI.
+///
+/// ```
+/// use serde::Serialize;
+///
+/// #[derive(Serialize)]
+/// struct Book {
+///     #[serde(rename = "@year")]
+///     year: u32,
+///     title: String,
+///     #[serde(rename = "author")]
+///     authors: Vec<String>,
+/// }
+///
+/// let book = Book {
+///     year: 1994,
+///     title: "Design Patterns".to_owned(),
+///     authors: vec!["Gamma".to_owned(), "Helm".to_owned()],
+/// };
+/// let elem = xmltree::to_element(&book).unwrap();
+/// assert_eq!(elem.name, "Book");
+/// assert_eq!(elem.get_attribute("year"), Some("1994"));
+/// assert_eq!(elem.find_all("author").count(), 2);
+/// ```
+pub fn to_element<T: Serialize + ?Sized>(value: &T) -> Result<Element, SerdeError> {
+    value.serialize(RootSerializer)
+}
+
+fn unsupported<T>(what: &str) -> Result<T, SerdeError> {
+    Err(SerdeError::new(format!(
+        "{} cannot be serialized here",
+        what
+    )))
+}
+
+/// Serializes the top-level value, which has to be a struct
+struct RootSerializer;
+
+impl ser::Serializer for RootSerializer {
+    type Ok = Element;
+    type Error = SerdeError;
+    type SerializeSeq = Impossible<Element, SerdeError>;
+    type SerializeTuple = Impossible<Element, SerdeError>;
+    type SerializeTupleStruct = Impossible<Element, SerdeError>;
+    type SerializeTupleVariant = Impossible<Element, SerdeError>;
+    type SerializeMap = Impossible<Element, SerdeError>;
+    type SerializeStruct = StructSerializer;
+    type SerializeStructVariant = Impossible<Element, SerdeError>;
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        _len: usize,
+    ) -> Result<StructSerializer, SerdeError> {
+        Ok(StructSerializer::new(Element::new(name)))
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<Element, SerdeError> {
+        Ok(Element::new(name))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Element, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<Element, SerdeError> {
+        unsupported("a top-level boolean")
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<Element, SerdeError> {
+        unsupported("a top-level number")
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<Element, SerdeError> {
+        unsupported("a top-level number")
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<Element, SerdeError> {
+        unsupported("a top-level number")
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<Element, SerdeError> {
+        unsupported("a top-level number")
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<Element, SerdeError> {
+        unsupported("a top-level number")
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<Element, SerdeError> {
+        unsupported("a top-level number")
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<Element, SerdeError> {
+        unsupported("a top-level number")
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<Element, SerdeError> {
+        unsupported("a top-level number")
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Element, SerdeError> {
+        unsupported("a top-level number")
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Element, SerdeError> {
+        unsupported("a top-level number")
+    }
+
+    fn serialize_char(self, _v: char) -> Result<Element, SerdeError> {
+        unsupported("a top-level character")
+    }
+
+    fn serialize_str(self, _v: &str) -> Result<Element, SerdeError> {
+        unsupported("a top-level string")
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Element, SerdeError> {
+        unsupported("a top-level byte array")
+    }
+
+    fn serialize_none(self) -> Result<Element, SerdeError> {
+        unsupported("a top-level `None`")
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Element, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Element, SerdeError> {
+        unsupported("a top-level unit")
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<Element, SerdeError> {
+        unsupported("a top-level enum")
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Element, SerdeError> {
+        unsupported("a top-level enum")
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, SerdeError> {
+        unsupported("a top-level sequence")
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, SerdeError> {
+        unsupported("a top-level tuple")
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, SerdeError> {
+        unsupported("a top-level tuple struct")
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, SerdeError> {
+        unsupported("a top-level enum")
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, SerdeError> {
+        unsupported("a top-level map")
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, SerdeError> {
+        unsupported("a top-level enum")
+    }
+}
+
+/// Adds a field or map entry to an element, following the naming conventions
+fn add_entry<T: Serialize + ?Sized>(
+    elem: &mut Element,
+    key: &str,
+    value: &T,
+) -> Result<(), SerdeError> {
+    if let Some(attribute) = key.strip_prefix('@') {
+        if let Some(text) = value.serialize(TextSerializer)? {
+            if let Some(prefix) = attribute.strip_prefix("xmlns:") {
+                elem.namespaces
+                    .get_or_insert_with(Namespace::empty)
+                    .force_put(prefix, text);
+            } else {
+                let name = attribute_name(elem, attribute)?;
+                elem.attributes.insert(name, text);
+            }
+        }
+        Ok(())
+    } else if key == "$text" {
+        if let Some(text) = value.serialize(TextSerializer)? {
+            elem.children.push(XMLNode::Text(text));
+        }
+        Ok(())
+    } else {
+        value.serialize(ChildSerializer {
+            parent: elem,
+            name: key,
+        })
+    }
+}
+
+/// Returns the name of an attribute keyed as `local`, `prefix:local` or `{namespace}local`
+fn attribute_name(elem: &mut Element, key: &str) -> Result<AttributeName, SerdeError> {
+    if let Some(qualified) = key.strip_prefix('{') {
+        let (namespace, local_name) = qualified.split_once('}').ok_or_else(|| {
+            SerdeError::new(format!("missing `}}` after the namespace of `@{}`", key))
+        })?;
+        let prefix = elem.attribute_prefix(namespace);
+        return Ok(AttributeName::qualified(local_name, namespace, prefix));
+    }
+    let (prefix, local_name) = match key.split_once(':') {
+        Some(split) => split,
+        None => return Ok(AttributeName::local(key)),
+    };
+    let namespace = if prefix == NS_XML_PREFIX {
+        Some(NS_XML_URI)
+    } else {
+        elem.namespaces.as_ref().and_then(|ns| ns.get(prefix))
+    };
+    match namespace {
+        Some(namespace) => Ok(AttributeName::qualified(local_name, namespace, prefix)),
+        None => Err(SerdeError::new(format!(
+            "undeclared namespace prefix `{}` in `@{}`",
+            prefix, key
+        ))),
+    }
+}
+
+/// Builds an element from the fields of a struct or the entries of a map
+struct StructSerializer {
+    elem: Element,
+    key: Option<String>,
+}
+
+impl StructSerializer {
+    fn new(elem: Element) -> StructSerializer {
+        StructSerializer { elem, key: None }
+    }
+}
+
+impl ser::SerializeStruct for StructSerializer {
+    type Ok = Element;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        add_entry(&mut self.elem, key, value)
+    }
+
+    fn end(self) -> Result<Element, SerdeError> {
+        Ok(self.elem)
+    }
+}
+
+impl ser::SerializeMap for StructSerializer {
+    type Ok = Element;
+    type Error = SerdeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerdeError> {
+        match key.serialize(TextSerializer)? {
+            Some(key) => {
+                self.key = Some(key);
+                Ok(())
+            }
+            None => unsupported("an empty map key"),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        match self.key.take() {
+            Some(key) => add_entry(&mut self.elem, &key, value),
+            None => Err(SerdeError::new("map value serialized before its key")),
+        }
+    }
+
+    fn end(self) -> Result<Element, SerdeError> {
+        Ok(self.elem)
+    }
+}
+
+/// Appends the child elements for a field named `name` to `parent`
+struct ChildSerializer<'a> {
+    parent: &'a mut Element,
+    name: &'a str,
+}
+
+impl<'a> ChildSerializer<'a> {
+    fn push_text(self, text: String) -> Result<(), SerdeError> {
+        let mut child = Element::new(self.name);
+        child.children.push(XMLNode::Text(text));
+        self.parent.children.push(XMLNode::Element(child));
+        Ok(())
+    }
+
+    fn push_empty(self) -> Result<(), SerdeError> {
+        self.parent
+            .children
+            .push(XMLNode::Element(Element::new(self.name)));
+        Ok(())
+    }
+}
+
+impl<'a> ser::Serializer for ChildSerializer<'a> {
+    type Ok = ();
+    type Error = SerdeError;
+    type SerializeSeq = SeqSerializer<'a>;
+    type SerializeTuple = SeqSerializer<'a>;
+    type SerializeTupleStruct = SeqSerializer<'a>;
+    type SerializeTupleVariant = NestedSeqSerializer<'a>;
+    type SerializeMap = NestedSerializer<'a>;
+    type SerializeStruct = NestedSerializer<'a>;
+    type SerializeStructVariant = NestedSerializer<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), SerdeError> {
+        self.push_text(v.to_string())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), SerdeError> {
+        self.push_text(v.to_string())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), SerdeError> {
+        self.push_text(v.to_string())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), SerdeError> {
+        self.push_text(v.to_string())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), SerdeError> {
+        self.push_text(v.to_string())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), SerdeError> {
+        self.push_text(v.to_string())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), SerdeError> {
+        self.push_text(v.to_string())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), SerdeError> {
+        self.push_text(v.to_string())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), SerdeError> {
+        self.push_text(v.to_string())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), SerdeError> {
+        self.push_text(v.to_string())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), SerdeError> {
+        self.push_text(v.to_string())
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), SerdeError> {
+        self.push_text(v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), SerdeError> {
+        self.push_text(v.to_owned())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<(), SerdeError> {
+        unsupported("a byte array")
+    }
+
+    fn serialize_none(self) -> Result<(), SerdeError> {
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), SerdeError> {
+        self.push_empty()
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), SerdeError> {
+        self.push_empty()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), SerdeError> {
+        self.push_text(variant.to_owned())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        let mut child = Element::new(self.name);
+        value.serialize(ChildSerializer {
+            parent: &mut child,
+            name: variant,
+        })?;
+        self.parent.children.push(XMLNode::Element(child));
+        Ok(())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<SeqSerializer<'a>, SerdeError> {
+        Ok(SeqSerializer {
+            parent: self.parent,
+            name: self.name,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer<'a>, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer<'a>, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<NestedSeqSerializer<'a>, SerdeError> {
+        Ok(NestedSeqSerializer {
+            parent: self.parent,
+            elem: Element::new(self.name),
+            name: variant,
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<NestedSerializer<'a>, SerdeError> {
+        Ok(NestedSerializer {
+            parent: self.parent,
+            wrapper: None,
+            inner: StructSerializer::new(Element::new(self.name)),
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<NestedSerializer<'a>, SerdeError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<NestedSerializer<'a>, SerdeError> {
+        Ok(NestedSerializer {
+            parent: self.parent,
+            wrapper: Some(Element::new(self.name)),
+            inner: StructSerializer::new(Element::new(variant)),
+        })
+    }
+}
+
+/// Serializes each item of a sequence as a separate child element with the same name
+struct SeqSerializer<'a> {
+    parent: &'a mut Element,
+    name: &'a str,
+}
+
+impl<'a> ser::SerializeSeq for SeqSerializer<'a> {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        value.serialize(ChildSerializer {
+            parent: self.parent,
+            name: self.name,
+        })
+    }
+
+    fn end(self) -> Result<(), SerdeError> {
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeTuple for SeqSerializer<'a> {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<(), SerdeError> {
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeTupleStruct for SeqSerializer<'a> {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<(), SerdeError> {
+        Ok(())
+    }
+}
+
+/// Serializes the items of a tuple variant as child elements named after the variant, inside
+/// the element for the field
+struct NestedSeqSerializer<'a> {
+    parent: &'a mut Element,
+    elem: Element,
+    name: &'static str,
+}
+
+impl<'a> ser::SerializeTupleVariant for NestedSeqSerializer<'a> {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        value.serialize(ChildSerializer {
+            parent: &mut self.elem,
+            name: self.name,
+        })
+    }
+
+    fn end(self) -> Result<(), SerdeError> {
+        self.parent.children.push(XMLNode::Element(self.elem));
+        Ok(())
+    }
+}
+
+/// Serializes a struct, map or struct variant into a child element
+///
+/// For struct variants, the element named after the variant is wrapped in the element for the
+/// field.
+struct NestedSerializer<'a> {
+    parent: &'a mut Element,
+    wrapper: Option<Element>,
+    inner: StructSerializer,
+}
+
+impl<'a> NestedSerializer<'a> {
+    fn finish(self) -> Result<(), SerdeError> {
+        let elem = ser::SerializeStruct::end(self.inner)?;
+        let elem = match self.wrapper {
+            Some(mut wrapper) => {
+                wrapper.children.push(XMLNode::Element(elem));
+                wrapper
+            }
+            None => elem,
+        };
+        self.parent.children.push(XMLNode::Element(elem));
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeStruct for NestedSerializer<'a> {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> Result<(), SerdeError> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeStructVariant for NestedSerializer<'a> {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> Result<(), SerdeError> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeMap for NestedSerializer<'a> {
+    type Ok = ();
+    type Error = SerdeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerdeError> {
+        ser::SerializeMap::serialize_key(&mut self.inner, key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        ser::SerializeMap::serialize_value(&mut self.inner, value)
+    }
+
+    fn end(self) -> Result<(), SerdeError> {
+        self.finish()
+    }
+}
+
+/// Serializes a value to the text of an attribute or element
+///
+/// Returns `None` for values that produce no text at all, such as `None`.
+struct TextSerializer;
+
+impl ser::Serializer for TextSerializer {
+    type Ok = Option<String>;
+    type Error = SerdeError;
+    type SerializeSeq = TextSeqSerializer;
+    type SerializeTuple = TextSeqSerializer;
+    type SerializeTupleStruct = TextSeqSerializer;
+    type SerializeTupleVariant = Impossible<Option<String>, SerdeError>;
+    type SerializeMap = Impossible<Option<String>, SerdeError>;
+    type SerializeStruct = Impossible<Option<String>, SerdeError>;
+    type SerializeStructVariant = Impossible<Option<String>, SerdeError>;
+
+    fn serialize_bool(self, v: bool) -> Result<Option<String>, SerdeError> {
+        Ok(Some(v.to_string()))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Option<String>, SerdeError> {
+        Ok(Some(v.to_string()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Option<String>, SerdeError> {
+        Ok(Some(v.to_string()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Option<String>, SerdeError> {
+        Ok(Some(v.to_string()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Option<String>, SerdeError> {
+        Ok(Some(v.to_string()))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Option<String>, SerdeError> {
+        Ok(Some(v.to_string()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Option<String>, SerdeError> {
+        Ok(Some(v.to_string()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Option<String>, SerdeError> {
+        Ok(Some(v.to_string()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Option<String>, SerdeError> {
+        Ok(Some(v.to_string()))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Option<String>, SerdeError> {
+        Ok(Some(v.to_string()))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Option<String>, SerdeError> {
+        Ok(Some(v.to_string()))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Option<String>, SerdeError> {
+        Ok(Some(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Option<String>, SerdeError> {
+        Ok(Some(v.to_owned()))
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Option<String>, SerdeError> {
+        unsupported("a byte array")
+    }
+
+    fn serialize_none(self) -> Result<Option<String>, SerdeError> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(
+        self,
+        value: &T,
+    ) -> Result<Option<String>, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Option<String>, SerdeError> {
+        Ok(None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Option<String>, SerdeError> {
+        Ok(None)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Option<String>, SerdeError> {
+        Ok(Some(variant.to_owned()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Option<String>, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Option<String>, SerdeError> {
+        unsupported("an enum variant with data as text")
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<TextSeqSerializer, SerdeError> {
+        Ok(TextSeqSerializer(Vec::new()))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<TextSeqSerializer, SerdeError> {
+        Ok(TextSeqSerializer(Vec::new()))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<TextSeqSerializer, SerdeError> {
+        Ok(TextSeqSerializer(Vec::new()))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, SerdeError> {
+        unsupported("an enum variant with data as text")
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, SerdeError> {
+        unsupported("a map as text")
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, SerdeError> {
+        unsupported("a struct as text")
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, SerdeError> {
+        unsupported("an enum variant with data as text")
+    }
+}
+
+/// Joins the items of a sequence with spaces
+struct TextSeqSerializer(Vec<String>);
+
+impl ser::SerializeSeq for TextSeqSerializer {
+    type Ok = Option<String>;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.0.extend(value.serialize(TextSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Option<String>, SerdeError> {
+        Ok(Some(self.0.join(" ")))
+    }
+}
+
+impl ser::SerializeTuple for TextSeqSerializer {
+    type Ok = Option<String>;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Option<String>, SerdeError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for TextSeqSerializer {
+    type Ok = Option<String>;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Option<String>, SerdeError> {
+        ser::SerializeSeq::end(self)
+    }
+}
@@ -0,0 +1,91 @@
+use std::io::Read;
+
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::limits::Usage;
+use crate::{build, Element, ElementPredicate, ParseError, ParseOptions, ParserConfig};
+
+/// An iterator over the elements of a document that match a predicate, created by
+/// [`Element::stream`].
+///
+/// The document is read as the iterator advances.  Each matching element is built in full,
+/// together with its children, and everything outside of the matching elements is thrown away,
+/// so only one of them is in memory at a time.  Elements inside a matching element are part of
+/// it, and are not returned on their own even if they match too.
+///
+/// The predicate is tested as soon as the start tag of an element has been read, so the element
+/// it is given has its name, namespace and attributes, but no children yet.
+///
+/// [`ParseLimits`](crate::ParseLimits) apply to each element returned, rather than to the whole
+/// document.  Once an error has been returned, the iterator ends.
+///
+/// ```
+/// use xmltree::Element;
+///
+/// let data = r#"<feed><meta/><record id="1"><v>a</v></record><record id="2"/></feed>"#;
+/// let ids: Vec<String> = Element::stream(data.as_bytes(), "record")
+///     .map(|record| record.unwrap().get_attribute("id").unwrap().to_owned())
+///     .collect();
+/// assert_eq!(ids, ["1", "2"]);
+/// ```
+pub struct ElementStream<R: Read, P> {
+    reader: EventReader<R>,
+    predicate: P,
+    options: ParseOptions,
+    done: bool,
+}
+
+impl<R: Read, P: ElementPredicate> ElementStream<R, P> {
+    pub(crate) fn new(
+        r: R,
+        config: ParserConfig,
+        options: ParseOptions,
+        predicate: P,
+    ) -> ElementStream<R, P> {
+        ElementStream {
+            reader: EventReader::new_with_config(r, config),
+            predicate,
+            options,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read, P: ElementPredicate> Iterator for ElementStream<R, P> {
+    type Item = Result<Element, ParseError>;
+
+    fn next(&mut self) -> Option<Result<Element, ParseError>> {
+        while !self.done {
+            match self.reader.next() {
+                Ok(XmlEvent::StartElement {
+                    name,
+                    attributes,
+                    namespace,
+                }) => {
+                    let mut elem = Element::from_start_event(name, attributes, namespace);
+                    if !self.predicate.match_element(&elem) {
+                        continue;
+                    }
+                    elem.span = self.options.span_here(&self.reader);
+                    let reader = &mut self.reader;
+                    let options = &self.options;
+                    let mut usage = Usage::new(options.limits);
+                    let result = usage
+                        .add_element(reader, 1, 0, elem.attributes.len(), elem.attribute_bytes())
+                        .and_then(|_| build(reader, elem, options, &mut usage));
+                    self.done = result.is_err();
+                    return Some(result);
+                }
+                Ok(XmlEvent::EndDocument) => self.done = true,
+                Ok(_) => (),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(ParseError::MalformedXml(e)));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<R: Read, P: ElementPredicate> std::iter::FusedIterator for ElementStream<R, P> {}
@@ -0,0 +1,215 @@
+use std::collections::VecDeque;
+use std::slice::IterMut;
+
+use crate::{Element, ElementPredicate, XMLNode};
+
+/// An iterator over the descendant elements of an element, created by [`Element::descendants`].
+///
+/// Elements are visited depth-first, in document order, unless [`Descendants::breadth_first`] is
+/// used.
+#[derive(Debug, Clone)]
+pub struct Descendants<'a> {
+    pending: VecDeque<&'a Element>,
+    breadth_first: bool,
+}
+
+impl<'a> Descendants<'a> {
+    pub(crate) fn new(root: &'a Element) -> Descendants<'a> {
+        Descendants {
+            pending: child_elements(root).collect(),
+            breadth_first: false,
+        }
+    }
+
+    /// Visits all children before any grandchildren, and so on, instead of going depth-first
+    ///
+    /// This should be called before iterating.
+    pub fn breadth_first(mut self) -> Self {
+        self.breadth_first = true;
+        self
+    }
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = &'a Element;
+
+    fn next(&mut self) -> Option<&'a Element> {
+        let elem = self.pending.pop_front()?;
+        if self.breadth_first {
+            self.pending.extend(child_elements(elem));
+        } else {
+            for child in child_elements(elem).rev() {
+                self.pending.push_front(child);
+            }
+        }
+        Some(elem)
+    }
+}
+
+/// Visits the descendant elements of an element mutably, created by [`Element::descendants_mut`].
+///
+/// This is not an [`Iterator`], since every element yielded may be modified, including its
+/// children, before moving on to the next one.  Use it in a `while let` loop instead:
+///
+/// ```
+/// use xmltree::Element;
+///
+/// let mut root = Element::parse("<a><b/><c><b/></c></a>".as_bytes()).unwrap();
+/// let mut descendants = root.descendants_mut();
+/// while let Some(elem) = descendants.next() {
+///     if elem.name == "b" {
+///         elem.attributes.insert("seen".into(), "yes".to_owned());
+///     }
+/// }
+/// assert_eq!(root.find_all("b").filter(|b| b.get_attribute("seen").is_some()).count(), 2);
+/// ```
+///
+/// Changes to the children of an element are taken into account when they are visited, so
+/// children added to the element just returned will be visited as well.
+#[derive(Debug)]
+pub struct DescendantsMut<'a> {
+    /// The children of the elements visited so far that are still to be looked at
+    pending: VecDeque<IterMut<'a, XMLNode>>,
+    /// The element returned last, whose children haven't been looked at yet
+    last: Option<&'a mut Element>,
+    breadth_first: bool,
+}
+
+impl<'a> DescendantsMut<'a> {
+    pub(crate) fn new(root: &'a mut Element) -> DescendantsMut<'a> {
+        DescendantsMut {
+            pending: VecDeque::new(),
+            last: Some(root),
+            breadth_first: false,
+        }
+    }
+
+    /// Visits all children before any grandchildren, and so on, instead of going depth-first
+    ///
+    /// This should be called before calling [`DescendantsMut::next`].
+    pub fn breadth_first(mut self) -> Self {
+        self.breadth_first = true;
+        self
+    }
+
+    /// Returns the next descendant element, or `None` once all of them have been visited
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<&mut Element> {
+        if let Some(elem) = self.last.take() {
+            self.pending.push_back(elem.children.iter_mut());
+        }
+        loop {
+            // Depth-first, the children of the element returned last come first, and
+            // breadth-first they come after those of all the elements before it
+            let children = if self.breadth_first {
+                self.pending.front_mut()?
+            } else {
+                self.pending.back_mut()?
+            };
+            let next = children.find_map(|node| match node {
+                XMLNode::Element(elem) => Some(elem),
+                _ => None,
+            });
+            match next {
+                Some(elem) => return Some(self.last.insert(elem)),
+                None if self.breadth_first => self.pending.pop_front(),
+                None => self.pending.pop_back(),
+            };
+        }
+    }
+}
+
+/// An iterator over the descendant elements that match a predicate, created by
+/// [`Element::find_all`]
+#[derive(Debug, Clone)]
+pub struct FindAll<'a, P> {
+    descendants: Descendants<'a>,
+    predicate: P,
+}
+
+impl<'a, P: ElementPredicate> FindAll<'a, P> {
+    pub(crate) fn new(root: &'a Element, predicate: P) -> FindAll<'a, P> {
+        FindAll {
+            descendants: Descendants::new(root),
+            predicate,
+        }
+    }
+
+    /// Visits all children before any grandchildren, and so on, instead of going depth-first
+    ///
+    /// This should be called before iterating.
+    pub fn breadth_first(mut self) -> Self {
+        self.descendants = self.descendants.breadth_first();
+        self
+    }
+}
+
+impl<'a, P: ElementPredicate> Iterator for FindAll<'a, P> {
+    type Item = &'a Element;
+
+    fn next(&mut self) -> Option<&'a Element> {
+        let predicate = &self.predicate;
+        self.descendants.find(|e| predicate.match_element(e))
+    }
+}
+
+/// An edge of a traversal, as yielded by [`Traverse`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge<'a> {
+    /// The start of an element, before any of its children
+    Start(&'a Element),
+    /// The end of an element, after all of its children
+    End(&'a Element),
+    /// A node that is not an element, such as text or a comment
+    Leaf(&'a XMLNode),
+}
+
+/// A depth-first traversal of an element and everything in it, created by [`Element::traverse`]
+///
+/// Every element produces an [`Edge::Start`] and an [`Edge::End`], with the edges of its
+/// children in between, which makes it easy to keep track of where in the tree a node is.
+#[derive(Debug, Clone)]
+pub struct Traverse<'a> {
+    root: Option<&'a Element>,
+    stack: Vec<(&'a Element, std::slice::Iter<'a, XMLNode>)>,
+}
+
+impl<'a> Traverse<'a> {
+    pub(crate) fn new(root: &'a Element) -> Traverse<'a> {
+        Traverse {
+            root: Some(root),
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Iterator for Traverse<'a> {
+    type Item = Edge<'a>;
+
+    fn next(&mut self) -> Option<Edge<'a>> {
+        if let Some(root) = self.root.take() {
+            self.stack.push((root, root.children.iter()));
+            return Some(Edge::Start(root));
+        }
+        let (elem, children) = self.stack.last_mut()?;
+        match children.next() {
+            Some(XMLNode::Element(child)) => {
+                self.stack.push((child, child.children.iter()));
+                Some(Edge::Start(child))
+            }
+            Some(node) => Some(Edge::Leaf(node)),
+            None => {
+                let elem = *elem;
+                self.stack.pop();
+                Some(Edge::End(elem))
+            }
+        }
+    }
+}
+
+fn child_elements(elem: &Element) -> impl DoubleEndedIterator<Item = &Element> {
+    elem.children.iter().filter_map(|node| match node {
+        XMLNode::Element(e) => Some(e),
+        _ => None,
+    })
+}
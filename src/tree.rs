@@ -0,0 +1,386 @@
+use std::mem;
+
+use crate::{Element, XMLNode};
+
+/// Identifies a node of a [`Tree`].
+///
+/// Ids stay valid for as long as the tree exists, even when nodes are moved around or detached.
+/// Using an id with a tree other than the one that created it gives unspecified results, and may
+/// panic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+/// The position of a node in its tree
+#[derive(Debug, Clone, Copy, Default)]
+struct Links {
+    parent: Option<NodeId>,
+    prev_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
+    first_child: Option<NodeId>,
+    last_child: Option<NodeId>,
+}
+
+/// A tree of nodes stored in an arena, where every node knows its parent and siblings.
+///
+/// An [`Element`] owns its children, so there is no way to get from an element to its parent or
+/// siblings.  A `Tree` stores all of the nodes side by side instead, and refers to them by
+/// [`NodeId`], which allows moving around in every direction and detaching nodes in constant
+/// time.  Inserting a node takes time proportional to the depth of its new parent, whose
+/// ancestors are checked so that a node can't end up inside itself.  Convert an `Element` into a
+/// `Tree` to navigate or restructure it, and back again to write it out.
+///
+/// Elements are stored without their children, which are nodes of the tree of their own.  Children
+/// pushed directly onto the `children` of an element in the tree are kept, before the children in
+/// the tree, when converting back to an `Element`.
+///
+/// Detached nodes stay in the arena until the tree is dropped, so they can be inserted again
+/// later.
+///
+/// ```
+/// use xmltree::{Element, Tree};
+///
+/// let root: Element = "<list><a/><b/><c/></list>".parse().unwrap();
+/// let mut tree = Tree::new(root);
+/// let list = tree.root();
+/// let a = tree.first_child(list).unwrap();
+/// let b = tree.next_sibling(a).unwrap();
+/// assert_eq!(tree.parent(b), Some(list));
+///
+/// // Move `a` to the end
+/// tree.append(list, a);
+/// assert_eq!(tree.prev_sibling(b), None);
+/// assert_eq!(tree.into_element().to_string(), "<list><b /><c /><a /></list>");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Tree {
+    nodes: Vec<XMLNode>,
+    links: Vec<Links>,
+    root: NodeId,
+}
+
+impl Tree {
+    /// Creates a tree out of `root` and all of its descendants
+    pub fn new(root: Element) -> Tree {
+        let mut tree = Tree {
+            nodes: Vec::new(),
+            links: Vec::new(),
+            root: NodeId(0),
+        };
+        tree.root = tree.add(XMLNode::Element(root));
+        tree
+    }
+
+    /// The root element of the tree
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    /// Returns the node with the given id.  If it is an element, its children are not included.
+    pub fn get(&self, id: NodeId) -> &XMLNode {
+        &self.nodes[id.0]
+    }
+
+    /// Returns the node with the given id mutably.  If it is an element, its children are not
+    /// included.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` is the root of the tree, which has to stay an element; change it with
+    /// [`Tree::element_mut`] instead.
+    pub fn get_mut(&mut self, id: NodeId) -> &mut XMLNode {
+        assert!(id != self.root, "the root of a tree can't be replaced");
+        &mut self.nodes[id.0]
+    }
+
+    /// Returns the node with the given id if it is an element, without its children
+    pub fn element(&self, id: NodeId) -> Option<&Element> {
+        self.get(id).as_element()
+    }
+
+    /// Returns the node with the given id mutably if it is an element, without its children
+    pub fn element_mut(&mut self, id: NodeId) -> Option<&mut Element> {
+        self.nodes[id.0].as_mut_element()
+    }
+
+    /// The parent of a node, or `None` for the root and detached nodes
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.links[id.0].parent
+    }
+
+    /// The node before this one in its parent
+    pub fn prev_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.links[id.0].prev_sibling
+    }
+
+    /// The node after this one in its parent
+    pub fn next_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.links[id.0].next_sibling
+    }
+
+    /// The first child of a node, if it is an element with children
+    pub fn first_child(&self, id: NodeId) -> Option<NodeId> {
+        self.links[id.0].first_child
+    }
+
+    /// The last child of a node, if it is an element with children
+    pub fn last_child(&self, id: NodeId) -> Option<NodeId> {
+        self.links[id.0].last_child
+    }
+
+    /// Returns an iterator over the children of a node, in order
+    pub fn children(&self, id: NodeId) -> Children<'_> {
+        Children {
+            tree: self,
+            next: self.first_child(id),
+        }
+    }
+
+    /// Returns an iterator over the ancestors of a node, starting with its parent and ending with
+    /// the root of the tree, or the topmost detached node
+    pub fn ancestors(&self, id: NodeId) -> Ancestors<'_> {
+        Ancestors {
+            tree: self,
+            next: self.parent(id),
+        }
+    }
+
+    /// Adds a node to the tree, together with its children if it is an element, and returns its
+    /// id.  The node is detached; insert it with [`Tree::append`] or one of the other insertion
+    /// methods.
+    pub fn add(&mut self, node: XMLNode) -> NodeId {
+        let (top, children) = self.push(node);
+        let mut stack = vec![(top, children.into_iter())];
+        while let Some((parent, children)) = stack.last_mut() {
+            match children.next() {
+                Some(child) => {
+                    let parent = *parent;
+                    let (id, grandchildren) = self.push(child);
+                    let last = self.last_child(parent);
+                    self.link(id, parent, last, None);
+                    stack.push((id, grandchildren.into_iter()));
+                }
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+        top
+    }
+
+    /// Stores `node` without linking it, and returns its id and children
+    fn push(&mut self, mut node: XMLNode) -> (NodeId, Vec<XMLNode>) {
+        let children = match &mut node {
+            XMLNode::Element(elem) => mem::take(&mut elem.children),
+            _ => Vec::new(),
+        };
+        self.nodes.push(node);
+        self.links.push(Links::default());
+        (NodeId(self.nodes.len() - 1), children)
+    }
+
+    /// Removes a node, together with its descendants, from its parent.  It can be inserted again
+    /// elsewhere.  Does nothing if the node is already detached.
+    pub fn detach(&mut self, id: NodeId) {
+        let links = self.links[id.0];
+        let parent = match links.parent {
+            Some(parent) => parent,
+            None => return,
+        };
+        match links.prev_sibling {
+            Some(prev) => self.links[prev.0].next_sibling = links.next_sibling,
+            None => self.links[parent.0].first_child = links.next_sibling,
+        }
+        match links.next_sibling {
+            Some(next) => self.links[next.0].prev_sibling = links.prev_sibling,
+            None => self.links[parent.0].last_child = links.prev_sibling,
+        }
+        let links = &mut self.links[id.0];
+        links.parent = None;
+        links.prev_sibling = None;
+        links.next_sibling = None;
+    }
+
+    /// Inserts a node as the last child of `parent`, detaching it from where it was first.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `parent` is not an element, if `node` is the root of the tree, or if `parent` is
+    /// `node` or one of its descendants, as a node can't be inserted inside itself.
+    pub fn append(&mut self, parent: NodeId, node: NodeId) {
+        self.prepare(parent, node);
+        let last = self.last_child(parent);
+        self.link(node, parent, last, None);
+    }
+
+    /// Inserts a node as the first child of `parent`, detaching it from where it was first.
+    ///
+    /// # Panics
+    ///
+    /// Panics in the same cases as [`Tree::append`].
+    pub fn prepend(&mut self, parent: NodeId, node: NodeId) {
+        self.prepare(parent, node);
+        let first = self.first_child(parent);
+        self.link(node, parent, None, first);
+    }
+
+    /// Inserts a node just before `sibling`, detaching it from where it was first.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sibling` has no parent, and in the same cases as [`Tree::append`].
+    pub fn insert_before(&mut self, sibling: NodeId, node: NodeId) {
+        let parent = self
+            .parent(sibling)
+            .expect("the sibling must have a parent");
+        if node == sibling {
+            return;
+        }
+        self.prepare(parent, node);
+        let prev = self.prev_sibling(sibling);
+        self.link(node, parent, prev, Some(sibling));
+    }
+
+    /// Inserts a node just after `sibling`, detaching it from where it was first.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sibling` has no parent, and in the same cases as [`Tree::append`].
+    pub fn insert_after(&mut self, sibling: NodeId, node: NodeId) {
+        let parent = self
+            .parent(sibling)
+            .expect("the sibling must have a parent");
+        if node == sibling {
+            return;
+        }
+        self.prepare(parent, node);
+        let next = self.next_sibling(sibling);
+        self.link(node, parent, Some(sibling), next);
+    }
+
+    /// Checks that `node` can be inserted in `parent`, and detaches it.  This walks up from
+    /// `parent`, so it takes time proportional to its depth.
+    fn prepare(&mut self, parent: NodeId, node: NodeId) {
+        assert!(node != self.root, "the root of a tree can't be moved");
+        assert!(
+            matches!(self.get(parent), XMLNode::Element(_)),
+            "only elements can have children"
+        );
+        assert!(
+            parent != node && self.ancestors(parent).all(|ancestor| ancestor != node),
+            "a node can't be inserted inside itself"
+        );
+        self.detach(node);
+    }
+
+    /// Links a detached node into `parent`, between `prev` and `next`
+    fn link(&mut self, id: NodeId, parent: NodeId, prev: Option<NodeId>, next: Option<NodeId>) {
+        self.links[id.0].parent = Some(parent);
+        self.links[id.0].prev_sibling = prev;
+        self.links[id.0].next_sibling = next;
+        match prev {
+            Some(prev) => self.links[prev.0].next_sibling = Some(id),
+            None => self.links[parent.0].first_child = Some(id),
+        }
+        match next {
+            Some(next) => self.links[next.0].prev_sibling = Some(id),
+            None => self.links[parent.0].last_child = Some(id),
+        }
+    }
+
+    /// Returns a copy of a node, with its descendants if it is an element
+    pub fn to_node(&self, id: NodeId) -> XMLNode {
+        assemble(&self.links, id, |id| self.get(id).clone())
+    }
+
+    /// Converts the tree back into an element.  Detached nodes are dropped.
+    pub fn into_element(mut self) -> Element {
+        let nodes = &mut self.nodes;
+        let root = assemble(&self.links, self.root, |id| {
+            mem::replace(&mut nodes[id.0], XMLNode::Text(String::new()))
+        });
+        match root {
+            XMLNode::Element(root) => root,
+            _ => unreachable!("the root of a tree can't be replaced"),
+        }
+    }
+}
+
+/// Builds the node `top` with all of its descendants, getting each node from `take`.
+///
+/// Open elements are kept on an explicit stack, so that deep trees can't overflow the call stack.
+fn assemble<F: FnMut(NodeId) -> XMLNode>(links: &[Links], top: NodeId, mut take: F) -> XMLNode {
+    let mut elem = match take(top) {
+        XMLNode::Element(elem) => elem,
+        node => return node,
+    };
+    let mut stack = Vec::new();
+    let mut next = links[top.0].first_child;
+    loop {
+        match next {
+            Some(id) => match take(id) {
+                XMLNode::Element(child) => {
+                    stack.push((mem::replace(&mut elem, child), links[id.0].next_sibling));
+                    next = links[id.0].first_child;
+                }
+                node => {
+                    elem.children.push(node);
+                    next = links[id.0].next_sibling;
+                }
+            },
+            None => match stack.pop() {
+                Some((parent, sibling)) => {
+                    let child = mem::replace(&mut elem, parent);
+                    elem.children.push(XMLNode::Element(child));
+                    next = sibling;
+                }
+                None => return XMLNode::Element(elem),
+            },
+        }
+    }
+}
+
+impl From<Element> for Tree {
+    fn from(root: Element) -> Tree {
+        Tree::new(root)
+    }
+}
+
+impl From<Tree> for Element {
+    fn from(tree: Tree) -> Element {
+        tree.into_element()
+    }
+}
+
+/// An iterator over the children of a node, created by [`Tree::children`]
+#[derive(Debug, Clone)]
+pub struct Children<'a> {
+    tree: &'a Tree,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Children<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = self.tree.next_sibling(id);
+        Some(id)
+    }
+}
+
+/// An iterator over the ancestors of a node, created by [`Tree::ancestors`]
+#[derive(Debug, Clone)]
+pub struct Ancestors<'a> {
+    tree: &'a Tree,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Ancestors<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = self.tree.parent(id);
+        Some(id)
+    }
+}
@@ -0,0 +1,102 @@
+use std::io::Write;
+
+use xml::name::OwnedName;
+use xml::writer::events::XmlEvent;
+use xml::writer::EventWriter;
+
+use crate::{Element, EmitterConfig, Error, XMLNode};
+
+/// Writes a document piece by piece, mixing whole [`Element`]s with start and end tags and raw
+/// xml-rs events.
+///
+/// This is useful to write out documents too large to build as a single tree: start the root
+/// element, write the records inside it one at a time, and end it.
+///
+/// ```
+/// use xmltree::{Element, EmitterConfig, TreeWriter};
+///
+/// let config = EmitterConfig::new().write_document_declaration(false);
+/// let mut writer = TreeWriter::new_with_config(Vec::new(), config);
+/// writer.start_element(&Element::new("feed")).unwrap();
+/// for id in 1..=2 {
+///     let mut record = Element::new("record");
+///     record.attributes.insert("id".into(), id.to_string());
+///     writer.write_element(&record).unwrap();
+/// }
+/// writer.end_element().unwrap();
+///
+/// let out = String::from_utf8(writer.into_inner()).unwrap();
+/// assert_eq!(out, r#"<feed><record id="1" /><record id="2" /></feed>"#);
+/// ```
+pub struct TreeWriter<W: Write> {
+    emitter: EventWriter<W>,
+    /// The names of the elements started with `start_element` and not ended yet
+    open: Vec<OwnedName>,
+}
+
+impl<W: Write> TreeWriter<W> {
+    /// Creates a writer with the default configuration, which writes an XML declaration first
+    pub fn new(w: W) -> TreeWriter<W> {
+        TreeWriter::new_with_config(w, EmitterConfig::new())
+    }
+
+    /// Creates a writer using the provided configuration
+    pub fn new_with_config(w: W, config: EmitterConfig) -> TreeWriter<W> {
+        TreeWriter {
+            emitter: EventWriter::new_with_config(w, config),
+            open: Vec::new(),
+        }
+    }
+
+    /// Writes the start tag of `elem`, with its attributes and namespace declarations.  Its
+    /// children are not written; write the content with the other methods and then call
+    /// [`TreeWriter::end_element`].
+    pub fn start_element(&mut self, elem: &Element) -> Result<(), Error> {
+        elem.write_start(&mut self.emitter)?;
+        self.open.push(elem.xml_name().to_owned());
+        Ok(())
+    }
+
+    /// Writes the end tag of the element started last with [`TreeWriter::start_element`]
+    pub fn end_element(&mut self) -> Result<(), Error> {
+        let name = self.open.pop();
+        self.emitter.write(XmlEvent::EndElement {
+            name: name.as_ref().map(OwnedName::borrow),
+        })
+    }
+
+    /// Writes `elem` and all of its children
+    pub fn write_element(&mut self, elem: &Element) -> Result<(), Error> {
+        elem._write(&mut self.emitter)
+    }
+
+    /// Writes `node`, and its children if it is an element
+    pub fn write_node(&mut self, node: &XMLNode) -> Result<(), Error> {
+        node._write(&mut self.emitter)
+    }
+
+    /// Writes a raw xml-rs event.
+    ///
+    /// Start and end tags written this way are not tracked by [`TreeWriter::end_element`], so
+    /// end elements started by an event with an event as well.
+    pub fn write_event<'a, E: Into<XmlEvent<'a>>>(&mut self, event: E) -> Result<(), Error> {
+        self.emitter.write(event)
+    }
+
+    /// The number of elements started with [`TreeWriter::start_element`] that haven't been ended
+    pub fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    /// Returns a mutable reference to the underlying writer
+    ///
+    /// Anything written to it directly bypasses the checks and escaping of the writer.
+    pub fn inner_mut(&mut self) -> &mut W {
+        self.emitter.inner_mut()
+    }
+
+    /// Returns the underlying writer.  Elements that are still open are not ended.
+    pub fn into_inner(self) -> W {
+        self.emitter.into_inner()
+    }
+}
@@ -0,0 +1,797 @@
+//! Evaluation of parsed XPath expressions
+
+use std::ptr;
+
+use super::parser::{ArithmeticOp, Axis, CompareOp, Expr, Function, NodeTest, PathStart, Step};
+use super::{Context, Node, Value, XPathError};
+use crate::{Edge, Element, XMLNode};
+
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// A node of the tree, with the links needed to walk every axis
+struct Entry<'a> {
+    node: Node<'a>,
+    parent: Option<usize>,
+    /// The index of the first child, after any namespace and attribute nodes
+    children: usize,
+    /// One past the last index of this node's subtree
+    end: usize,
+}
+
+/// The tree being queried, flattened in document order.
+///
+/// Each node is identified by its index, so comparing indices compares document order.  The
+/// namespace and attribute nodes of an element come right after it, followed by its children.
+struct Tree<'a> {
+    entries: Vec<Entry<'a>>,
+}
+
+impl<'a> Tree<'a> {
+    fn new(root: &'a Element) -> Tree<'a> {
+        let mut tree = Tree {
+            entries: vec![Entry {
+                node: Node::Root(root),
+                parent: None,
+                children: 1,
+                end: 0,
+            }],
+        };
+        let first = tree.push_element(root, 0);
+        let mut stack = vec![(first, root.children.iter())];
+        while let Some((index, children)) = stack.last_mut() {
+            let index = *index;
+            match children.next() {
+                Some(XMLNode::Element(child)) => {
+                    let child_index = tree.push_element(child, index);
+                    stack.push((child_index, child.children.iter()));
+                }
+                Some(XMLNode::Text(s)) | Some(XMLNode::CData(s)) | Some(XMLNode::Whitespace(s)) => {
+                    tree.push_leaf(Node::Text(s), index)
+                }
+                Some(XMLNode::Comment(s)) => tree.push_leaf(Node::Comment(s), index),
+                Some(XMLNode::ProcessingInstruction(name, data)) => {
+                    tree.push_leaf(Node::ProcessingInstruction(name, data.as_deref()), index)
+                }
+                None => {
+                    tree.entries[index].end = tree.entries.len();
+                    stack.pop();
+                }
+            }
+        }
+        tree.entries[0].end = tree.entries.len();
+        tree
+    }
+
+    fn push_element(&mut self, elem: &'a Element, parent: usize) -> usize {
+        let index = self.entries.len();
+        self.entries.push(Entry {
+            node: Node::Element(elem),
+            parent: Some(parent),
+            children: 0,
+            end: 0,
+        });
+        if let Some(ref namespaces) = elem.namespaces {
+            for (prefix, uri) in namespaces {
+                if prefix != "xmlns" && !uri.is_empty() {
+                    self.push_leaf(Node::Namespace(prefix, uri), index);
+                }
+            }
+        }
+        for (name, value) in &elem.attributes {
+            self.push_leaf(Node::Attribute(name, value), index);
+        }
+        self.entries[index].children = self.entries.len();
+        index
+    }
+
+    fn push_leaf(&mut self, node: Node<'a>, parent: usize) {
+        let index = self.entries.len();
+        self.entries.push(Entry {
+            node,
+            parent: Some(parent),
+            children: index + 1,
+            end: index + 1,
+        });
+    }
+
+    /// Whether the node can be a child of another node, which attributes and namespaces can't
+    fn is_child(&self, index: usize) -> bool {
+        !matches!(
+            self.entries[index].node,
+            Node::Attribute(..) | Node::Namespace(..)
+        )
+    }
+
+    /// Appends the nodes on `axis` from `index` to `out`, in the order of the axis
+    fn axis(&self, axis: Axis, index: usize, out: &mut Vec<usize>) {
+        let entry = &self.entries[index];
+        match axis {
+            Axis::SelfNode => out.push(index),
+            Axis::Child => self.children(index, out),
+            Axis::Descendant => {
+                out.extend((entry.children..entry.end).filter(|&j| self.is_child(j)))
+            }
+            Axis::DescendantOrSelf => {
+                out.push(index);
+                self.axis(Axis::Descendant, index, out);
+            }
+            Axis::Parent => out.extend(entry.parent),
+            Axis::Ancestor => {
+                let mut current = entry.parent;
+                while let Some(parent) = current {
+                    out.push(parent);
+                    current = self.entries[parent].parent;
+                }
+            }
+            Axis::AncestorOrSelf => {
+                out.push(index);
+                self.axis(Axis::Ancestor, index, out);
+            }
+            Axis::FollowingSibling => {
+                if let (true, Some(parent)) = (self.is_child(index), entry.parent) {
+                    let mut j = entry.end;
+                    while j < self.entries[parent].end {
+                        out.push(j);
+                        j = self.entries[j].end;
+                    }
+                }
+            }
+            Axis::PrecedingSibling => {
+                if let (true, Some(parent)) = (self.is_child(index), entry.parent) {
+                    let start = out.len();
+                    let mut j = self.entries[parent].children;
+                    while j < index {
+                        out.push(j);
+                        j = self.entries[j].end;
+                    }
+                    out[start..].reverse();
+                }
+            }
+            Axis::Following => {
+                out.extend((entry.end..self.entries.len()).filter(|&j| self.is_child(j)))
+            }
+            Axis::Preceding => out.extend(
+                (0..index)
+                    .rev()
+                    .filter(|&j| self.is_child(j) && self.entries[j].end <= index),
+            ),
+            Axis::Attribute => out.extend(
+                (index + 1..entry.children)
+                    .filter(|&j| matches!(self.entries[j].node, Node::Attribute(..))),
+            ),
+            Axis::Namespace => out.extend(
+                (index + 1..entry.children)
+                    .filter(|&j| matches!(self.entries[j].node, Node::Namespace(..))),
+            ),
+        }
+    }
+
+    fn children(&self, index: usize, out: &mut Vec<usize>) {
+        let entry = &self.entries[index];
+        let mut j = entry.children;
+        while j < entry.end {
+            out.push(j);
+            j = self.entries[j].end;
+        }
+    }
+
+    fn string_value(&self, index: usize) -> String {
+        let entry = &self.entries[index];
+        match entry.node {
+            Node::Root(_) | Node::Element(_) => {
+                let mut text = String::new();
+                for j in entry.children..entry.end {
+                    if let Node::Text(s) = self.entries[j].node {
+                        text.push_str(s);
+                    }
+                }
+                text
+            }
+            ref node => node.string_value(),
+        }
+    }
+
+    /// Finds the index of a node that was handed out by an earlier evaluation
+    fn find(&self, node: &Node<'a>) -> Option<usize> {
+        self.entries.iter().position(|e| same_node(&e.node, node))
+    }
+}
+
+fn same_node(a: &Node, b: &Node) -> bool {
+    match (*a, *b) {
+        (Node::Root(x), Node::Root(y)) | (Node::Element(x), Node::Element(y)) => ptr::eq(x, y),
+        (Node::Attribute(_, x), Node::Attribute(_, y))
+        | (Node::Text(x), Node::Text(y))
+        | (Node::Comment(x), Node::Comment(y))
+        | (Node::ProcessingInstruction(x, _), Node::ProcessingInstruction(y, _))
+        | (Node::Namespace(_, x), Node::Namespace(_, y)) => ptr::eq(x, y),
+        _ => false,
+    }
+}
+
+/// The result of evaluating a subexpression, with node-sets as sorted indices into the tree
+#[derive(Debug, Clone)]
+enum Val {
+    Nodes(Vec<usize>),
+    String(String),
+    Number(f64),
+    Boolean(bool),
+}
+
+/// The context node, with its position and the size of the list it was taken from
+#[derive(Clone, Copy)]
+struct Focus {
+    node: usize,
+    position: usize,
+    size: usize,
+}
+
+struct Evaluator<'t, 'a> {
+    tree: &'t Tree<'a>,
+    context: &'t Context<'a>,
+}
+
+pub(crate) fn evaluate<'a>(
+    expr: &Expr,
+    elem: &'a Element,
+    context: &Context<'a>,
+) -> Result<Value<'a>, XPathError> {
+    let tree = Tree::new(elem);
+    let evaluator = Evaluator {
+        tree: &tree,
+        context,
+    };
+    let focus = Focus {
+        node: 1,
+        position: 1,
+        size: 1,
+    };
+    Ok(match evaluator.eval(expr, focus)? {
+        Val::Nodes(nodes) => {
+            Value::Nodes(nodes.into_iter().map(|i| tree.entries[i].node).collect())
+        }
+        Val::String(s) => Value::String(s),
+        Val::Number(n) => Value::Number(n),
+        Val::Boolean(b) => Value::Boolean(b),
+    })
+}
+
+impl<'t, 'a> Evaluator<'t, 'a> {
+    fn eval(&self, expr: &Expr, focus: Focus) -> Result<Val, XPathError> {
+        Ok(match expr {
+            Expr::Or(left, right) => Val::Boolean(
+                self.boolean(&self.eval(left, focus)?) || self.boolean(&self.eval(right, focus)?),
+            ),
+            Expr::And(left, right) => Val::Boolean(
+                self.boolean(&self.eval(left, focus)?) && self.boolean(&self.eval(right, focus)?),
+            ),
+            Expr::Compare(op, left, right) => {
+                let left = self.eval(left, focus)?;
+                let right = self.eval(right, focus)?;
+                Val::Boolean(self.compare(*op, &left, &right))
+            }
+            Expr::Arithmetic(op, left, right) => {
+                let left = self.number(&self.eval(left, focus)?);
+                let right = self.number(&self.eval(right, focus)?);
+                Val::Number(match op {
+                    ArithmeticOp::Add => left + right,
+                    ArithmeticOp::Subtract => left - right,
+                    ArithmeticOp::Multiply => left * right,
+                    ArithmeticOp::Divide => left / right,
+                    ArithmeticOp::Modulo => left % right,
+                })
+            }
+            Expr::Negate(expr) => Val::Number(-self.number(&self.eval(expr, focus)?)),
+            Expr::Union(left, right) => {
+                let mut nodes = self.eval_nodes(left, focus)?;
+                nodes.extend(self.eval_nodes(right, focus)?);
+                nodes.sort_unstable();
+                nodes.dedup();
+                Val::Nodes(nodes)
+            }
+            Expr::Literal(s) => Val::String(s.clone()),
+            Expr::Number(n) => Val::Number(*n),
+            Expr::Variable(name) => self.variable(name)?,
+            Expr::Function(function, args) => self.call(*function, args, focus)?,
+            Expr::Filter(primary, predicates) => {
+                let mut nodes = self.eval_nodes(primary, focus)?;
+                for predicate in predicates {
+                    nodes = self.filter(nodes, predicate)?;
+                }
+                Val::Nodes(nodes)
+            }
+            Expr::Path(start, steps) => {
+                let mut nodes = match start {
+                    PathStart::Context => vec![focus.node],
+                    PathStart::Root => vec![0],
+                    PathStart::Expr(expr) => self.eval_nodes(expr, focus)?,
+                };
+                for step in steps {
+                    nodes = self.step(&nodes, step)?;
+                }
+                Val::Nodes(nodes)
+            }
+        })
+    }
+
+    fn eval_nodes(&self, expr: &Expr, focus: Focus) -> Result<Vec<usize>, XPathError> {
+        match self.eval(expr, focus)? {
+            Val::Nodes(nodes) => Ok(nodes),
+            _ => Err(XPathError::NotANodeSet),
+        }
+    }
+
+    fn variable(&self, name: &str) -> Result<Val, XPathError> {
+        let value = match self.context.variables.get(name) {
+            Some(value) => value,
+            None => return Err(XPathError::UnknownVariable(name.to_owned())),
+        };
+        Ok(match value {
+            Value::Nodes(nodes) => {
+                let mut indices = nodes
+                    .iter()
+                    .map(|n| self.tree.find(n).ok_or(XPathError::ForeignNode))
+                    .collect::<Result<Vec<_>, _>>()?;
+                indices.sort_unstable();
+                indices.dedup();
+                Val::Nodes(indices)
+            }
+            Value::String(s) => Val::String(s.clone()),
+            Value::Number(n) => Val::Number(*n),
+            Value::Boolean(b) => Val::Boolean(*b),
+        })
+    }
+
+    fn step(&self, nodes: &[usize], step: &Step) -> Result<Vec<usize>, XPathError> {
+        let mut result = Vec::new();
+        let mut candidates = Vec::new();
+        for &node in nodes {
+            candidates.clear();
+            self.tree.axis(step.axis, node, &mut candidates);
+            let mut selected = Vec::with_capacity(candidates.len());
+            for &candidate in &candidates {
+                if self.node_test(&step.test, step.axis, candidate)? {
+                    selected.push(candidate);
+                }
+            }
+            for predicate in &step.predicates {
+                // Positions are counted in the order of the axis
+                selected = self.filter(selected, predicate)?;
+            }
+            result.extend(selected);
+        }
+        if nodes.len() > 1 || step.axis.is_reverse() {
+            result.sort_unstable();
+            result.dedup();
+        }
+        Ok(result)
+    }
+
+    fn filter(&self, nodes: Vec<usize>, predicate: &Expr) -> Result<Vec<usize>, XPathError> {
+        let size = nodes.len();
+        let mut kept = Vec::new();
+        for (i, node) in nodes.into_iter().enumerate() {
+            let focus = Focus {
+                node,
+                position: i + 1,
+                size,
+            };
+            let keep = match self.eval(predicate, focus)? {
+                Val::Number(n) => n == (i + 1) as f64,
+                other => self.boolean(&other),
+            };
+            if keep {
+                kept.push(node);
+            }
+        }
+        Ok(kept)
+    }
+
+    fn resolve_prefix(&self, prefix: &str) -> Result<&str, XPathError> {
+        match self.context.namespaces.get(prefix) {
+            Some(uri) => Ok(uri),
+            None if prefix == "xml" => Ok(XML_NAMESPACE),
+            None => Err(XPathError::UnknownPrefix(prefix.to_owned())),
+        }
+    }
+
+    fn node_test(&self, test: &NodeTest, axis: Axis, index: usize) -> Result<bool, XPathError> {
+        let node = self.tree.entries[index].node;
+        Ok(match test {
+            NodeTest::Node => true,
+            NodeTest::Text => matches!(node, Node::Text(_)),
+            NodeTest::Comment => matches!(node, Node::Comment(_)),
+            NodeTest::ProcessingInstruction(target) => match node {
+                Node::ProcessingInstruction(name, _) => {
+                    target.as_deref().is_none_or(|target| target == name)
+                }
+                _ => false,
+            },
+            NodeTest::Name { prefix, local_name } => {
+                let namespace = match prefix {
+                    Some(prefix) => Some(self.resolve_prefix(prefix)?),
+                    None if local_name.is_some()
+                        && !matches!(axis, Axis::Attribute | Axis::Namespace) =>
+                    {
+                        self.context.default_namespace.as_deref()
+                    }
+                    None => None,
+                };
+                // Without a prefix, `*` matches names in any namespace
+                let any_namespace = prefix.is_none() && local_name.is_none();
+                let (node_namespace, node_name) = match (axis, node) {
+                    (Axis::Attribute, Node::Attribute(name, _)) => {
+                        (name.namespace.as_deref(), name.local_name.as_str())
+                    }
+                    (Axis::Namespace, Node::Namespace(prefix, _)) => (None, prefix),
+                    (Axis::Attribute, _) | (Axis::Namespace, _) => return Ok(false),
+                    (_, Node::Element(elem)) => (elem.namespace.as_deref(), elem.name.as_str()),
+                    _ => return Ok(false),
+                };
+                let node_namespace = node_namespace.filter(|ns| !ns.is_empty());
+                (any_namespace || node_namespace == namespace)
+                    && local_name.as_deref().is_none_or(|name| name == node_name)
+            }
+        })
+    }
+
+    fn string(&self, val: &Val) -> String {
+        match val {
+            Val::Nodes(nodes) => nodes
+                .first()
+                .map_or(String::new(), |&n| self.tree.string_value(n)),
+            Val::String(s) => s.clone(),
+            Val::Number(n) => number_to_string(*n),
+            Val::Boolean(b) => b.to_string(),
+        }
+    }
+
+    fn number(&self, val: &Val) -> f64 {
+        match val {
+            Val::Number(n) => *n,
+            Val::Boolean(b) => {
+                if *b {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            _ => string_to_number(&self.string(val)),
+        }
+    }
+
+    fn boolean(&self, val: &Val) -> bool {
+        match val {
+            Val::Nodes(nodes) => !nodes.is_empty(),
+            Val::String(s) => !s.is_empty(),
+            Val::Number(n) => *n != 0.0 && !n.is_nan(),
+            Val::Boolean(b) => *b,
+        }
+    }
+
+    fn compare(&self, op: CompareOp, left: &Val, right: &Val) -> bool {
+        match (left, right) {
+            (Val::Nodes(a), Val::Nodes(b)) => {
+                let b: Vec<String> = b.iter().map(|&n| self.tree.string_value(n)).collect();
+                a.iter().any(|&n| {
+                    let a = Val::String(self.tree.string_value(n));
+                    b.iter()
+                        .any(|b| self.compare_values(op, &a, &Val::String(b.clone())))
+                })
+            }
+            (Val::Nodes(_), Val::Boolean(_)) | (Val::Boolean(_), Val::Nodes(_)) => {
+                let left = Val::Boolean(self.boolean(left));
+                let right = Val::Boolean(self.boolean(right));
+                self.compare_values(op, &left, &right)
+            }
+            (Val::Nodes(a), other) => a.iter().any(|&n| {
+                let a = self.atomize(n, other);
+                self.compare_values(op, &a, other)
+            }),
+            (other, Val::Nodes(b)) => b.iter().any(|&n| {
+                let b = self.atomize(n, other);
+                self.compare_values(op, other, &b)
+            }),
+            _ => self.compare_values(op, left, right),
+        }
+    }
+
+    /// Converts a node to the type of `other`, for comparing a node-set to a number or string
+    fn atomize(&self, node: usize, other: &Val) -> Val {
+        let s = self.tree.string_value(node);
+        match other {
+            Val::Number(_) => Val::Number(string_to_number(&s)),
+            _ => Val::String(s),
+        }
+    }
+
+    fn compare_values(&self, op: CompareOp, left: &Val, right: &Val) -> bool {
+        match op {
+            CompareOp::Eq | CompareOp::Ne => {
+                let equal = match (left, right) {
+                    (Val::Boolean(_), _) | (_, Val::Boolean(_)) => {
+                        self.boolean(left) == self.boolean(right)
+                    }
+                    (Val::Number(_), _) | (_, Val::Number(_)) => {
+                        self.number(left) == self.number(right)
+                    }
+                    _ => self.string(left) == self.string(right),
+                };
+                equal == (op == CompareOp::Eq)
+            }
+            CompareOp::Lt => self.number(left) < self.number(right),
+            CompareOp::Le => self.number(left) <= self.number(right),
+            CompareOp::Gt => self.number(left) > self.number(right),
+            CompareOp::Ge => self.number(left) >= self.number(right),
+        }
+    }
+
+    /// Evaluates the optional node-set argument of functions like `name()`, which defaults to the
+    /// context node, and returns the first node
+    fn first_node_arg(&self, args: &[Expr], focus: Focus) -> Result<Option<usize>, XPathError> {
+        match args.first() {
+            Some(arg) => Ok(self.eval_nodes(arg, focus)?.first().copied()),
+            None => Ok(Some(focus.node)),
+        }
+    }
+
+    /// Evaluates the optional string argument of functions like `string-length()`, which
+    /// defaults to the string-value of the context node
+    fn string_arg(&self, args: &[Expr], focus: Focus) -> Result<String, XPathError> {
+        match args.first() {
+            Some(arg) => Ok(self.string(&self.eval(arg, focus)?)),
+            None => Ok(self.tree.string_value(focus.node)),
+        }
+    }
+
+    fn string_args(&self, args: &[Expr], focus: Focus) -> Result<Vec<String>, XPathError> {
+        args.iter()
+            .map(|arg| Ok(self.string(&self.eval(arg, focus)?)))
+            .collect()
+    }
+
+    fn number_arg(&self, arg: &Expr, focus: Focus) -> Result<f64, XPathError> {
+        Ok(self.number(&self.eval(arg, focus)?))
+    }
+
+    fn call(&self, function: Function, args: &[Expr], focus: Focus) -> Result<Val, XPathError> {
+        Ok(match function {
+            Function::Last => Val::Number(focus.size as f64),
+            Function::Position => Val::Number(focus.position as f64),
+            Function::Count => Val::Number(self.eval_nodes(&args[0], focus)?.len() as f64),
+            Function::Id => {
+                let ids = match self.eval(&args[0], focus)? {
+                    Val::Nodes(nodes) => nodes
+                        .iter()
+                        .map(|&n| self.tree.string_value(n))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    other => self.string(&other),
+                };
+                Val::Nodes(self.elements_by_id(&ids))
+            }
+            Function::LocalName => Val::String(self.first_node_arg(args, focus)?.map_or(
+                String::new(),
+                |n| match self.tree.entries[n].node {
+                    Node::Element(e) => e.name.clone(),
+                    Node::Attribute(name, _) => name.local_name.clone(),
+                    Node::ProcessingInstruction(target, _) | Node::Namespace(target, _) => {
+                        target.to_owned()
+                    }
+                    _ => String::new(),
+                },
+            )),
+            Function::NamespaceUri => Val::String(
+                self.first_node_arg(args, focus)?
+                    .and_then(|n| match self.tree.entries[n].node {
+                        Node::Element(e) => e.namespace.clone(),
+                        Node::Attribute(name, _) => name.namespace.clone(),
+                        _ => None,
+                    })
+                    .unwrap_or_default(),
+            ),
+            Function::Name => Val::String(self.first_node_arg(args, focus)?.map_or(
+                String::new(),
+                |n| match self.tree.entries[n].node {
+                    Node::Element(e) => match e.prefix {
+                        Some(ref prefix) => format!("{}:{}", prefix, e.name),
+                        None => e.name.clone(),
+                    },
+                    Node::Attribute(name, _) => name.to_string(),
+                    Node::ProcessingInstruction(target, _) | Node::Namespace(target, _) => {
+                        target.to_owned()
+                    }
+                    _ => String::new(),
+                },
+            )),
+            Function::String => Val::String(self.string_arg(args, focus)?),
+            Function::Concat => Val::String(self.string_args(args, focus)?.concat()),
+            Function::StartsWith => {
+                let s = self.string_args(args, focus)?;
+                Val::Boolean(s[0].starts_with(&s[1]))
+            }
+            Function::Contains => {
+                let s = self.string_args(args, focus)?;
+                Val::Boolean(s[0].contains(&s[1]))
+            }
+            Function::SubstringBefore => {
+                let s = self.string_args(args, focus)?;
+                Val::String(s[0].find(&s[1]).map_or("", |i| &s[0][..i]).to_owned())
+            }
+            Function::SubstringAfter => {
+                let s = self.string_args(args, focus)?;
+                Val::String(
+                    s[0].find(&s[1])
+                        .map_or("", |i| &s[0][i + s[1].len()..])
+                        .to_owned(),
+                )
+            }
+            Function::Substring => {
+                let s = self.string(&self.eval(&args[0], focus)?);
+                let start = round(self.number_arg(&args[1], focus)?);
+                let end = match args.get(2) {
+                    Some(len) => start + round(self.number_arg(len, focus)?),
+                    None => f64::INFINITY,
+                };
+                Val::String(
+                    s.chars()
+                        .enumerate()
+                        .filter(|&(i, _)| {
+                            let position = (i + 1) as f64;
+                            position >= start && position < end
+                        })
+                        .map(|(_, c)| c)
+                        .collect(),
+                )
+            }
+            Function::StringLength => {
+                Val::Number(self.string_arg(args, focus)?.chars().count() as f64)
+            }
+            Function::NormalizeSpace => Val::String(
+                self.string_arg(args, focus)?
+                    .split(is_xml_whitespace)
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            Function::Translate => {
+                let s = self.string_args(args, focus)?;
+                let from: Vec<char> = s[1].chars().collect();
+                let to: Vec<char> = s[2].chars().collect();
+                Val::String(
+                    s[0].chars()
+                        .filter_map(|c| match from.iter().position(|&f| f == c) {
+                            Some(i) => to.get(i).copied(),
+                            None => Some(c),
+                        })
+                        .collect(),
+                )
+            }
+            Function::Boolean => Val::Boolean(self.boolean(&self.eval(&args[0], focus)?)),
+            Function::Not => Val::Boolean(!self.boolean(&self.eval(&args[0], focus)?)),
+            Function::True => Val::Boolean(true),
+            Function::False => Val::Boolean(false),
+            Function::Lang => {
+                let lang = self.string(&self.eval(&args[0], focus)?).to_lowercase();
+                Val::Boolean(self.lang(focus.node).is_some_and(|l| {
+                    let l = l.to_lowercase();
+                    l == lang || (l.starts_with(&lang) && l[lang.len()..].starts_with('-'))
+                }))
+            }
+            Function::Number => Val::Number(match args.first() {
+                Some(arg) => self.number_arg(arg, focus)?,
+                None => string_to_number(&self.tree.string_value(focus.node)),
+            }),
+            Function::Sum => Val::Number(
+                self.eval_nodes(&args[0], focus)?
+                    .iter()
+                    .map(|&n| string_to_number(&self.tree.string_value(n)))
+                    .sum(),
+            ),
+            Function::Floor => Val::Number(self.number_arg(&args[0], focus)?.floor()),
+            Function::Ceiling => Val::Number(self.number_arg(&args[0], focus)?.ceil()),
+            Function::Round => Val::Number(round(self.number_arg(&args[0], focus)?)),
+        })
+    }
+
+    /// Finds the elements whose `xml:id` or `id` attribute is one of the whitespace separated ids
+    fn elements_by_id(&self, ids: &str) -> Vec<usize> {
+        let ids: Vec<&str> = ids
+            .split(is_xml_whitespace)
+            .filter(|s| !s.is_empty())
+            .collect();
+        let mut found = Vec::new();
+        for entry in &self.tree.entries {
+            if let Node::Attribute(name, value) = entry.node {
+                let is_id = name.local_name == "id"
+                    && (name.namespace.is_none()
+                        || name.namespace.as_deref() == Some(XML_NAMESPACE));
+                if is_id && ids.contains(&value) {
+                    found.extend(entry.parent);
+                }
+            }
+        }
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+
+    /// Returns the `xml:lang` in scope at the given node
+    fn lang(&self, index: usize) -> Option<&'a str> {
+        let mut current = Some(index);
+        while let Some(index) = current {
+            if let Node::Element(elem) = self.tree.entries[index].node {
+                let lang = elem.attributes.iter().find(|(name, _)| {
+                    name.local_name == "lang" && name.namespace.as_deref() == Some(XML_NAMESPACE)
+                });
+                if let Some((_, value)) = lang {
+                    return Some(value);
+                }
+            }
+            current = self.tree.entries[index].parent;
+        }
+        None
+    }
+}
+
+fn is_xml_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\r' | '\n')
+}
+
+/// Rounds like the XPath `round()` function, which rounds halves towards positive infinity
+fn round(n: f64) -> f64 {
+    if n.is_nan() || n.is_infinite() {
+        n
+    } else if (-0.5..0.0).contains(&n) {
+        -0.0
+    } else {
+        (n + 0.5).floor()
+    }
+}
+
+/// Converts a number to a string, as the XPath `string()` function does
+pub(crate) fn number_to_string(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_owned()
+    } else if n.is_infinite() {
+        if n > 0.0 {
+            "Infinity".to_owned()
+        } else {
+            "-Infinity".to_owned()
+        }
+    } else if n == 0.0 {
+        // This includes negative zero
+        "0".to_owned()
+    } else {
+        n.to_string()
+    }
+}
+
+/// Converts a string to a number, as the XPath `number()` function does
+pub(crate) fn string_to_number(s: &str) -> f64 {
+    let s = s.trim_matches(is_xml_whitespace);
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let mut parts = digits.splitn(2, '.');
+    let integer = parts.next().unwrap_or("");
+    let fraction = parts.next();
+    let valid = integer.chars().all(|c| c.is_ascii_digit())
+        && fraction.is_none_or(|f| f.chars().all(|c| c.is_ascii_digit()))
+        && (!integer.is_empty() || fraction.is_some_and(|f| !f.is_empty()));
+    if valid {
+        s.parse().unwrap_or(f64::NAN)
+    } else {
+        f64::NAN
+    }
+}
+
+/// Returns the text of all descendant text nodes of an element
+pub(crate) fn element_text(elem: &Element) -> String {
+    elem.traverse()
+        .filter_map(|edge| match edge {
+            Edge::Leaf(XMLNode::Text(s))
+            | Edge::Leaf(XMLNode::CData(s))
+            | Edge::Leaf(XMLNode::Whitespace(s)) => Some(s.as_str()),
+            _ => None,
+        })
+        .collect()
+}
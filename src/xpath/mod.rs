@@ -0,0 +1,268 @@
+//! XPath 1.0 queries over a tree of [`Element`]s.
+//!
+//! An expression is compiled once with [`XPath::compile`], and can then be evaluated against any
+//! number of trees.  The result is a [`Value`], which is either a set of [`Node`]s, a string, a
+//! number or a boolean, as in the XPath data model.
+//!
+//! ```
+//! use xmltree::Element;
+//! use xmltree::xpath::XPath;
+//!
+//! let data = r#"
+//!     <library>
+//!         <book year="1995"><title>First</title></book>
+//!         <book year="2007"><title>Second</title></book>
+//!     </library>
+//! "#;
+//! let library = Element::parse(data.as_bytes()).unwrap();
+//!
+//! let xpath = XPath::compile("/library/book[@year > 2000]/title").unwrap();
+//! let titles = xpath.evaluate(&library).unwrap().into_nodes().unwrap();
+//! assert_eq!(titles.len(), 1);
+//! assert_eq!(titles[0].string_value(), "Second");
+//!
+//! let count = XPath::compile("count(//book)").unwrap();
+//! assert_eq!(count.evaluate(&library).unwrap().number(), 2.0);
+//! ```
+//!
+//! The element an expression is evaluated against is the context node, and is the only child of
+//! the root node that absolute paths start from.  So in the example above, `book` and
+//! `/library/book` select the same elements.
+//!
+//! As in XPath 1.0, a name without a prefix only matches elements that are not in any namespace,
+//! even if a default namespace is declared in the document.  To match elements in a namespace,
+//! bind a prefix to it in the [`Context`] and use that prefix in the expression.
+//!
+//! Every `Text`, `CData` and `Whitespace` node of the tree is a separate text node, adjacent ones
+//! are not merged.  The `id()` function finds elements by their `xml:id` or `id` attribute, since
+//! there is no DTD to declare ID attributes.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{AttributeName, Element, Namespace};
+
+mod eval;
+mod parser;
+
+use parser::Expr;
+
+/// A compiled XPath expression
+#[derive(Debug, Clone)]
+pub struct XPath {
+    expr: Expr,
+}
+
+impl XPath {
+    /// Compiles an XPath 1.0 expression
+    ///
+    /// Expressions nested more than 64 levels deep, counting parentheses, predicates, function
+    /// calls, unary minus and chains of operators, are rejected with a syntax error.
+    pub fn compile(expr: &str) -> Result<XPath, XPathError> {
+        Ok(XPath {
+            expr: parser::parse(expr)?,
+        })
+    }
+
+    /// Evaluates this expression with `elem` as the context node
+    pub fn evaluate<'a>(&self, elem: &'a Element) -> Result<Value<'a>, XPathError> {
+        self.evaluate_with_context(elem, &Context::new())
+    }
+
+    /// Evaluates this expression with `elem` as the context node, using the namespace bindings
+    /// and variables from `context`
+    pub fn evaluate_with_context<'a>(
+        &self,
+        elem: &'a Element,
+        context: &Context<'a>,
+    ) -> Result<Value<'a>, XPathError> {
+        eval::evaluate(&self.expr, elem, context)
+    }
+}
+
+/// Namespace bindings and variables available to an expression
+#[derive(Debug, Clone, Default)]
+pub struct Context<'a> {
+    namespaces: HashMap<String, String>,
+    default_namespace: Option<String>,
+    variables: HashMap<String, Value<'a>>,
+}
+
+impl<'a> Context<'a> {
+    /// Returns a context with no namespace bindings and no variables
+    pub fn new() -> Context<'a> {
+        Context::default()
+    }
+
+    /// Binds `prefix` to the namespace `uri`
+    pub fn namespace<P: Into<String>, U: Into<String>>(mut self, prefix: P, uri: U) -> Self {
+        self.namespaces.insert(prefix.into(), uri.into());
+        self
+    }
+
+    /// Binds all prefixes of `namespaces`, such as the [`Element::namespaces`] of a parsed element
+    ///
+    /// The default namespace is skipped, since XPath 1.0 has no notion of a default namespace.
+    pub fn namespaces(mut self, namespaces: &Namespace) -> Self {
+        for (prefix, uri) in namespaces {
+            if !prefix.is_empty() {
+                self.namespaces.insert(prefix.to_owned(), uri.to_owned());
+            }
+        }
+        self
+    }
+
+    /// Makes element names without a prefix match elements in the namespace `uri`, as in
+    /// XPath 2.0, instead of elements in no namespace.  Attribute names without a prefix are still
+    /// in no namespace.
+    pub fn default_element_namespace<U: Into<String>>(mut self, uri: U) -> Self {
+        self.default_namespace = Some(uri.into());
+        self
+    }
+
+    /// Sets the variable `$name` to `value`
+    ///
+    /// Node-set values must contain nodes of the tree the expression is evaluated against.
+    pub fn variable<N: Into<String>>(mut self, name: N, value: Value<'a>) -> Self {
+        self.variables.insert(name.into(), value);
+        self
+    }
+}
+
+/// The result of evaluating an expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value<'a> {
+    /// A set of nodes, in document order
+    Nodes(Vec<Node<'a>>),
+    String(String),
+    Number(f64),
+    Boolean(bool),
+}
+
+impl<'a> Value<'a> {
+    /// Converts this value to a string, like the `string()` function
+    pub fn string(&self) -> String {
+        match self {
+            Value::Nodes(nodes) => nodes.first().map_or(String::new(), |n| n.string_value()),
+            Value::String(s) => s.clone(),
+            Value::Number(n) => eval::number_to_string(*n),
+            Value::Boolean(b) => b.to_string(),
+        }
+    }
+
+    /// Converts this value to a number, like the `number()` function
+    pub fn number(&self) -> f64 {
+        match self {
+            Value::Number(n) => *n,
+            Value::Boolean(b) => {
+                if *b {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            _ => eval::string_to_number(&self.string()),
+        }
+    }
+
+    /// Converts this value to a boolean, like the `boolean()` function
+    pub fn boolean(&self) -> bool {
+        match self {
+            Value::Nodes(nodes) => !nodes.is_empty(),
+            Value::String(s) => !s.is_empty(),
+            Value::Number(n) => *n != 0.0 && !n.is_nan(),
+            Value::Boolean(b) => *b,
+        }
+    }
+
+    /// Returns the nodes if this value is a node-set
+    pub fn into_nodes(self) -> Option<Vec<Node<'a>>> {
+        match self {
+            Value::Nodes(nodes) => Some(nodes),
+            _ => None,
+        }
+    }
+}
+
+/// A node selected by an expression
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Node<'a> {
+    /// The root node, whose only child is the element the expression was evaluated against
+    Root(&'a Element),
+    Element(&'a Element),
+    Attribute(&'a AttributeName, &'a str),
+    /// A `Text`, `CData` or `Whitespace` node
+    Text(&'a str),
+    Comment(&'a str),
+    ProcessingInstruction(&'a str, Option<&'a str>),
+    /// A namespace binding in scope of an element, as `(prefix, uri)`
+    Namespace(&'a str, &'a str),
+}
+
+impl<'a> Node<'a> {
+    pub fn as_element(&self) -> Option<&'a Element> {
+        if let Node::Element(e) = self {
+            Some(e)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the string-value of this node, as defined by XPath
+    ///
+    /// For the root and for elements, this is the text of all descendant text nodes.
+    pub fn string_value(&self) -> String {
+        match *self {
+            Node::Root(e) | Node::Element(e) => eval::element_text(e),
+            Node::Attribute(_, s)
+            | Node::Text(s)
+            | Node::Comment(s)
+            | Node::ProcessingInstruction(_, Some(s))
+            | Node::Namespace(_, s) => s.to_owned(),
+            Node::ProcessingInstruction(_, None) => String::new(),
+        }
+    }
+}
+
+/// Errors that can occur compiling or evaluating an XPath expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XPathError {
+    /// The expression is not valid XPath
+    Syntax {
+        /// The byte offset in the expression at which the error was found
+        position: usize,
+        message: String,
+    },
+    /// The expression calls a function that is not part of the XPath 1.0 core library
+    UnknownFunction(String),
+    /// A function was called with the wrong number of arguments
+    WrongArgumentCount(String),
+    /// The expression uses a namespace prefix that is not bound in the context
+    UnknownPrefix(String),
+    /// The expression uses a variable that is not set in the context
+    UnknownVariable(String),
+    /// A node-set was required, but the expression evaluated to something else
+    NotANodeSet,
+    /// A variable contains a node that is not part of the tree being queried
+    ForeignNode,
+}
+
+impl fmt::Display for XPathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            XPathError::Syntax { position, message } => {
+                write!(f, "Syntax error at offset {}: {}", position, message)
+            }
+            XPathError::UnknownFunction(name) => write!(f, "Unknown function `{}`", name),
+            XPathError::WrongArgumentCount(name) => {
+                write!(f, "Wrong number of arguments for function `{}`", name)
+            }
+            XPathError::UnknownPrefix(prefix) => write!(f, "Unbound namespace prefix `{}`", prefix),
+            XPathError::UnknownVariable(name) => write!(f, "Unknown variable `${}`", name),
+            XPathError::NotANodeSet => write!(f, "Expression does not evaluate to a node-set"),
+            XPathError::ForeignNode => write!(f, "Variable refers to a node outside of the tree"),
+        }
+    }
+}
+
+impl std::error::Error for XPathError {}
@@ -0,0 +1,883 @@
+//! Tokenizer and recursive-descent parser for XPath 1.0 expressions
+
+use super::XPathError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Compare(CompareOp, Box<Expr>, Box<Expr>),
+    Arithmetic(ArithmeticOp, Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+    Union(Box<Expr>, Box<Expr>),
+    Literal(String),
+    Number(f64),
+    Variable(String),
+    Function(Function, Vec<Expr>),
+    /// A filter expression with predicates, such as `$nodes[1]`
+    Filter(Box<Expr>, Vec<Expr>),
+    /// A location path, optionally starting from the node-set of a filter expression
+    Path(PathStart, Vec<Step>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum PathStart {
+    /// A relative path, starting at the context node
+    Context,
+    /// An absolute path, starting at the root node
+    Root,
+    /// A path starting from the result of an expression, such as `id('x')/child`
+    Expr(Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ArithmeticOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Step {
+    pub(crate) axis: Axis,
+    pub(crate) test: NodeTest,
+    pub(crate) predicates: Vec<Expr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Axis {
+    Ancestor,
+    AncestorOrSelf,
+    Attribute,
+    Child,
+    Descendant,
+    DescendantOrSelf,
+    Following,
+    FollowingSibling,
+    Namespace,
+    Parent,
+    Preceding,
+    PrecedingSibling,
+    SelfNode,
+}
+
+impl Axis {
+    fn from_name(name: &str) -> Option<Axis> {
+        Some(match name {
+            "ancestor" => Axis::Ancestor,
+            "ancestor-or-self" => Axis::AncestorOrSelf,
+            "attribute" => Axis::Attribute,
+            "child" => Axis::Child,
+            "descendant" => Axis::Descendant,
+            "descendant-or-self" => Axis::DescendantOrSelf,
+            "following" => Axis::Following,
+            "following-sibling" => Axis::FollowingSibling,
+            "namespace" => Axis::Namespace,
+            "parent" => Axis::Parent,
+            "preceding" => Axis::Preceding,
+            "preceding-sibling" => Axis::PrecedingSibling,
+            "self" => Axis::SelfNode,
+            _ => return None,
+        })
+    }
+
+    /// Whether the axis lists nodes in reverse document order
+    pub(crate) fn is_reverse(self) -> bool {
+        matches!(
+            self,
+            Axis::Ancestor | Axis::AncestorOrSelf | Axis::Preceding | Axis::PrecedingSibling
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum NodeTest {
+    /// `node()`
+    Node,
+    /// `text()`
+    Text,
+    /// `comment()`
+    Comment,
+    /// `processing-instruction()`, optionally with a target name
+    ProcessingInstruction(Option<String>),
+    /// A name test; `None` for the local name means `*`
+    Name {
+        prefix: Option<String>,
+        local_name: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Function {
+    Last,
+    Position,
+    Count,
+    Id,
+    LocalName,
+    NamespaceUri,
+    Name,
+    String,
+    Concat,
+    StartsWith,
+    Contains,
+    SubstringBefore,
+    SubstringAfter,
+    Substring,
+    StringLength,
+    NormalizeSpace,
+    Translate,
+    Boolean,
+    Not,
+    True,
+    False,
+    Lang,
+    Number,
+    Sum,
+    Floor,
+    Ceiling,
+    Round,
+}
+
+impl Function {
+    /// Looks up a core library function, with its minimum and maximum number of arguments
+    fn from_name(name: &str) -> Option<(Function, usize, usize)> {
+        Some(match name {
+            "last" => (Function::Last, 0, 0),
+            "position" => (Function::Position, 0, 0),
+            "count" => (Function::Count, 1, 1),
+            "id" => (Function::Id, 1, 1),
+            "local-name" => (Function::LocalName, 0, 1),
+            "namespace-uri" => (Function::NamespaceUri, 0, 1),
+            "name" => (Function::Name, 0, 1),
+            "string" => (Function::String, 0, 1),
+            "concat" => (Function::Concat, 2, usize::MAX),
+            "starts-with" => (Function::StartsWith, 2, 2),
+            "contains" => (Function::Contains, 2, 2),
+            "substring-before" => (Function::SubstringBefore, 2, 2),
+            "substring-after" => (Function::SubstringAfter, 2, 2),
+            "substring" => (Function::Substring, 2, 3),
+            "string-length" => (Function::StringLength, 0, 1),
+            "normalize-space" => (Function::NormalizeSpace, 0, 1),
+            "translate" => (Function::Translate, 3, 3),
+            "boolean" => (Function::Boolean, 1, 1),
+            "not" => (Function::Not, 1, 1),
+            "true" => (Function::True, 0, 0),
+            "false" => (Function::False, 0, 0),
+            "lang" => (Function::Lang, 1, 1),
+            "number" => (Function::Number, 0, 1),
+            "sum" => (Function::Sum, 1, 1),
+            "floor" => (Function::Floor, 1, 1),
+            "ceiling" => (Function::Ceiling, 1, 1),
+            "round" => (Function::Round, 1, 1),
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LeftParen,
+    RightParen,
+    LeftBracket,
+    RightBracket,
+    Dot,
+    DotDot,
+    At,
+    Comma,
+    ColonColon,
+    Slash,
+    DoubleSlash,
+    Pipe,
+    Plus,
+    Minus,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Multiply,
+    And,
+    Or,
+    Mod,
+    Div,
+    Literal(String),
+    Number(f64),
+    Variable(String),
+    /// A name test: `*`, `prefix:*` or a qualified name
+    NameTest {
+        prefix: Option<String>,
+        local_name: Option<String>,
+    },
+    FunctionName(String),
+    NodeType(String),
+    AxisName(String),
+}
+
+impl Token {
+    /// Whether a `*` or name following this token is an operator, as opposed to a name test
+    fn precedes_operator(&self) -> bool {
+        !matches!(
+            self,
+            Token::At
+                | Token::ColonColon
+                | Token::LeftParen
+                | Token::LeftBracket
+                | Token::Comma
+                | Token::Slash
+                | Token::DoubleSlash
+                | Token::Pipe
+                | Token::Plus
+                | Token::Minus
+                | Token::Equal
+                | Token::NotEqual
+                | Token::Less
+                | Token::LessEqual
+                | Token::Greater
+                | Token::GreaterEqual
+                | Token::Multiply
+                | Token::And
+                | Token::Or
+                | Token::Mod
+                | Token::Div
+        )
+    }
+}
+
+fn syntax_error<T>(position: usize, message: &str) -> Result<T, XPathError> {
+    Err(XPathError::Syntax {
+        position,
+        message: message.to_owned(),
+    })
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.' || c == '\u{B7}'
+}
+
+struct Lexer<'s> {
+    input: &'s str,
+    pos: usize,
+    tokens: Vec<(usize, Token)>,
+}
+
+impl<'s> Lexer<'s> {
+    fn peek_char(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.input[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        self.input[self.pos..].starts_with(s)
+    }
+
+    /// Whether the next non-whitespace text starts with `s`, without consuming anything
+    fn followed_by(&self, s: &str) -> bool {
+        self.input[self.pos..].trim_start().starts_with(s)
+    }
+
+    fn ncname(&mut self) -> Option<String> {
+        let rest = &self.input[self.pos..];
+        let mut chars = rest.char_indices();
+        match chars.next() {
+            Some((_, c)) if is_name_start(c) => (),
+            _ => return None,
+        }
+        let end = chars
+            .find(|&(_, c)| !is_name_char(c))
+            .map_or(rest.len(), |(i, _)| i);
+        self.pos += end;
+        Some(rest[..end].to_owned())
+    }
+
+    fn previous_precedes_operator(&self) -> bool {
+        self.tokens
+            .last()
+            .is_some_and(|(_, t)| t.precedes_operator())
+    }
+
+    fn tokenize(mut self) -> Result<Vec<(usize, Token)>, XPathError> {
+        loop {
+            self.skip_whitespace();
+            let start = self.pos;
+            let c = match self.peek_char() {
+                Some(c) => c,
+                None => return Ok(self.tokens),
+            };
+            let token = match c {
+                '(' => self.symbol(1, Token::LeftParen),
+                ')' => self.symbol(1, Token::RightParen),
+                '[' => self.symbol(1, Token::LeftBracket),
+                ']' => self.symbol(1, Token::RightBracket),
+                '@' => self.symbol(1, Token::At),
+                ',' => self.symbol(1, Token::Comma),
+                '|' => self.symbol(1, Token::Pipe),
+                '+' => self.symbol(1, Token::Plus),
+                '-' => self.symbol(1, Token::Minus),
+                '=' => self.symbol(1, Token::Equal),
+                ':' if self.starts_with("::") => self.symbol(2, Token::ColonColon),
+                '/' if self.starts_with("//") => self.symbol(2, Token::DoubleSlash),
+                '/' => self.symbol(1, Token::Slash),
+                '!' if self.starts_with("!=") => self.symbol(2, Token::NotEqual),
+                '<' if self.starts_with("<=") => self.symbol(2, Token::LessEqual),
+                '<' => self.symbol(1, Token::Less),
+                '>' if self.starts_with(">=") => self.symbol(2, Token::GreaterEqual),
+                '>' => self.symbol(1, Token::Greater),
+                '.' if self.starts_with("..") => self.symbol(2, Token::DotDot),
+                '.' if !self.input[self.pos + 1..].starts_with(|c: char| c.is_ascii_digit()) => {
+                    self.symbol(1, Token::Dot)
+                }
+                '0'..='9' | '.' => self.number(),
+                '"' | '\'' => self.literal(c)?,
+                '$' => {
+                    self.pos += 1;
+                    match self.qname()? {
+                        Some(name) => Token::Variable(name),
+                        None => return syntax_error(self.pos, "expected a variable name"),
+                    }
+                }
+                '*' => {
+                    self.pos += 1;
+                    if self.previous_precedes_operator() {
+                        Token::Multiply
+                    } else {
+                        Token::NameTest {
+                            prefix: None,
+                            local_name: None,
+                        }
+                    }
+                }
+                c if is_name_start(c) => self.name()?,
+                _ => return syntax_error(start, "unexpected character"),
+            };
+            self.tokens.push((start, token));
+        }
+    }
+
+    fn symbol(&mut self, len: usize, token: Token) -> Token {
+        self.pos += len;
+        token
+    }
+
+    fn number(&mut self) -> Token {
+        let rest = &self.input[self.pos..];
+        let mut seen_dot = false;
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '.' && !seen_dot {
+                    seen_dot = true;
+                    false
+                } else {
+                    !c.is_ascii_digit()
+                }
+            })
+            .map_or(rest.len(), |(i, _)| i);
+        self.pos += end;
+        Token::Number(rest[..end].parse().unwrap_or(f64::NAN))
+    }
+
+    fn literal(&mut self, quote: char) -> Result<Token, XPathError> {
+        let start = self.pos;
+        let rest = &self.input[self.pos + 1..];
+        match rest.find(quote) {
+            Some(end) => {
+                self.pos += end + 2;
+                Ok(Token::Literal(rest[..end].to_owned()))
+            }
+            None => syntax_error(start, "unterminated string literal"),
+        }
+    }
+
+    /// Reads a qualified name, `prefix:local` or `local`
+    fn qname(&mut self) -> Result<Option<String>, XPathError> {
+        let mut name = match self.ncname() {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        if self.starts_with(":") && !self.starts_with("::") {
+            self.pos += 1;
+            match self.ncname() {
+                Some(local) => {
+                    name.push(':');
+                    name.push_str(&local);
+                }
+                None => return syntax_error(self.pos, "expected a local name after the prefix"),
+            }
+        }
+        Ok(Some(name))
+    }
+
+    fn name(&mut self) -> Result<Token, XPathError> {
+        let start = self.pos;
+        let name = self.ncname().expect("caller checked for a name start");
+
+        if self.previous_precedes_operator() {
+            return match name.as_str() {
+                "and" => Ok(Token::And),
+                "or" => Ok(Token::Or),
+                "mod" => Ok(Token::Mod),
+                "div" => Ok(Token::Div),
+                _ => syntax_error(start, "expected an operator"),
+            };
+        }
+
+        if self.followed_by("::") {
+            return Ok(Token::AxisName(name));
+        }
+
+        // A name test or function name may have a prefix
+        let (prefix, local_name) = if self.starts_with(":") && !self.starts_with("::") {
+            self.pos += 1;
+            if self.starts_with("*") {
+                self.pos += 1;
+                return Ok(Token::NameTest {
+                    prefix: Some(name),
+                    local_name: None,
+                });
+            }
+            match self.ncname() {
+                Some(local) => (Some(name), local),
+                None => return syntax_error(self.pos, "expected a local name after the prefix"),
+            }
+        } else {
+            (None, name)
+        };
+
+        if self.followed_by("(") {
+            return Ok(match (prefix, local_name.as_str()) {
+                (None, "node") | (None, "text") | (None, "comment") => Token::NodeType(local_name),
+                (None, "processing-instruction") => Token::NodeType(local_name),
+                (Some(prefix), _) => Token::FunctionName(format!("{}:{}", prefix, local_name)),
+                (None, _) => Token::FunctionName(local_name),
+            });
+        }
+
+        Ok(Token::NameTest {
+            prefix,
+            local_name: Some(local_name),
+        })
+    }
+}
+
+/// How deeply expressions can nest, counting parentheses, predicates, function arguments, unary
+/// minus and the operands of chained operators.  Parsing, evaluating and dropping an expression
+/// all recurse into its operands, so this keeps them from overflowing the stack.
+const MAX_DEPTH: usize = 64;
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    /// The length of the expression, used as the position of errors at the end of the input
+    len: usize,
+    /// How deeply the expression being parsed is nested, see [`MAX_DEPTH`]
+    depth: usize,
+}
+
+/// Parses an XPath expression
+pub(crate) fn parse(input: &str) -> Result<Expr, XPathError> {
+    let lexer = Lexer {
+        input,
+        pos: 0,
+        tokens: Vec::new(),
+    };
+    let mut parser = Parser {
+        tokens: lexer.tokenize()?,
+        pos: 0,
+        len: input.len(),
+        depth: 0,
+    };
+    let expr = parser.expr()?;
+    if parser.pos < parser.tokens.len() {
+        return syntax_error(parser.position(), "unexpected token");
+    }
+    Ok(expr)
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.len, |(p, _)| *p)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(_, t)| t.clone());
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &Token, message: &str) -> Result<(), XPathError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            syntax_error(self.position(), message)
+        }
+    }
+
+    /// Goes one level deeper into the expression, failing past [`MAX_DEPTH`]
+    fn nest(&mut self) -> Result<(), XPathError> {
+        if self.depth == MAX_DEPTH {
+            return syntax_error(self.position(), "expression is nested too deeply");
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn expr(&mut self) -> Result<Expr, XPathError> {
+        let depth = self.depth;
+        self.nest()?;
+        let mut left = self.and_expr()?;
+        while self.eat(&Token::Or) {
+            self.nest()?;
+            let right = self.and_expr()?;
+            left = Expr::Or(Box::new(left), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn and_expr(&mut self) -> Result<Expr, XPathError> {
+        let depth = self.depth;
+        let mut left = self.equality_expr()?;
+        while self.eat(&Token::And) {
+            self.nest()?;
+            let right = self.equality_expr()?;
+            left = Expr::And(Box::new(left), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn equality_expr(&mut self) -> Result<Expr, XPathError> {
+        let depth = self.depth;
+        let mut left = self.relational_expr()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Equal) => CompareOp::Eq,
+                Some(Token::NotEqual) => CompareOp::Ne,
+                _ => {
+                    self.depth = depth;
+                    return Ok(left);
+                }
+            };
+            self.pos += 1;
+            self.nest()?;
+            let right = self.relational_expr()?;
+            left = Expr::Compare(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn relational_expr(&mut self) -> Result<Expr, XPathError> {
+        let depth = self.depth;
+        let mut left = self.additive_expr()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Less) => CompareOp::Lt,
+                Some(Token::LessEqual) => CompareOp::Le,
+                Some(Token::Greater) => CompareOp::Gt,
+                Some(Token::GreaterEqual) => CompareOp::Ge,
+                _ => {
+                    self.depth = depth;
+                    return Ok(left);
+                }
+            };
+            self.pos += 1;
+            self.nest()?;
+            let right = self.additive_expr()?;
+            left = Expr::Compare(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn additive_expr(&mut self) -> Result<Expr, XPathError> {
+        let depth = self.depth;
+        let mut left = self.multiplicative_expr()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Plus) => ArithmeticOp::Add,
+                Some(Token::Minus) => ArithmeticOp::Subtract,
+                _ => {
+                    self.depth = depth;
+                    return Ok(left);
+                }
+            };
+            self.pos += 1;
+            self.nest()?;
+            let right = self.multiplicative_expr()?;
+            left = Expr::Arithmetic(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn multiplicative_expr(&mut self) -> Result<Expr, XPathError> {
+        let depth = self.depth;
+        let mut left = self.unary_expr()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Multiply) => ArithmeticOp::Multiply,
+                Some(Token::Div) => ArithmeticOp::Divide,
+                Some(Token::Mod) => ArithmeticOp::Modulo,
+                _ => {
+                    self.depth = depth;
+                    return Ok(left);
+                }
+            };
+            self.pos += 1;
+            self.nest()?;
+            let right = self.unary_expr()?;
+            left = Expr::Arithmetic(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn unary_expr(&mut self) -> Result<Expr, XPathError> {
+        if self.eat(&Token::Minus) {
+            self.nest()?;
+            let operand = self.unary_expr()?;
+            self.depth -= 1;
+            Ok(Expr::Negate(Box::new(operand)))
+        } else {
+            self.union_expr()
+        }
+    }
+
+    fn union_expr(&mut self) -> Result<Expr, XPathError> {
+        let depth = self.depth;
+        let mut left = self.path_expr()?;
+        while self.eat(&Token::Pipe) {
+            self.nest()?;
+            let right = self.path_expr()?;
+            left = Expr::Union(Box::new(left), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn path_expr(&mut self) -> Result<Expr, XPathError> {
+        match self.peek() {
+            Some(Token::Slash) => {
+                self.pos += 1;
+                let steps = if self.at_step_start() {
+                    self.relative_path()?
+                } else {
+                    Vec::new()
+                };
+                Ok(Expr::Path(PathStart::Root, steps))
+            }
+            Some(Token::DoubleSlash) => {
+                self.pos += 1;
+                let mut steps = vec![descendant_or_self_step()];
+                steps.extend(self.relative_path()?);
+                Ok(Expr::Path(PathStart::Root, steps))
+            }
+            _ if self.at_step_start() => Ok(Expr::Path(PathStart::Context, self.relative_path()?)),
+            _ => {
+                let primary = self.primary_expr()?;
+                let mut predicates = Vec::new();
+                while self.peek() == Some(&Token::LeftBracket) {
+                    predicates.push(self.predicate()?);
+                }
+                let filter = if predicates.is_empty() {
+                    primary
+                } else {
+                    Expr::Filter(Box::new(primary), predicates)
+                };
+                let mut steps = Vec::new();
+                match self.peek() {
+                    Some(Token::Slash) => {
+                        self.pos += 1;
+                    }
+                    Some(Token::DoubleSlash) => {
+                        self.pos += 1;
+                        steps.push(descendant_or_self_step());
+                    }
+                    _ => return Ok(filter),
+                }
+                steps.extend(self.relative_path()?);
+                Ok(Expr::Path(PathStart::Expr(Box::new(filter)), steps))
+            }
+        }
+    }
+
+    fn at_step_start(&self) -> bool {
+        matches!(
+            self.peek(),
+            Some(Token::Dot)
+                | Some(Token::DotDot)
+                | Some(Token::At)
+                | Some(Token::AxisName(_))
+                | Some(Token::NodeType(_))
+                | Some(Token::NameTest { .. })
+        )
+    }
+
+    fn relative_path(&mut self) -> Result<Vec<Step>, XPathError> {
+        let mut steps = vec![self.step()?];
+        loop {
+            match self.peek() {
+                Some(Token::Slash) => {
+                    self.pos += 1;
+                }
+                Some(Token::DoubleSlash) => {
+                    self.pos += 1;
+                    steps.push(descendant_or_self_step());
+                }
+                _ => return Ok(steps),
+            }
+            steps.push(self.step()?);
+        }
+    }
+
+    fn step(&mut self) -> Result<Step, XPathError> {
+        let axis = match self.peek() {
+            Some(Token::Dot) => {
+                self.pos += 1;
+                return Ok(Step {
+                    axis: Axis::SelfNode,
+                    test: NodeTest::Node,
+                    predicates: Vec::new(),
+                });
+            }
+            Some(Token::DotDot) => {
+                self.pos += 1;
+                return Ok(Step {
+                    axis: Axis::Parent,
+                    test: NodeTest::Node,
+                    predicates: Vec::new(),
+                });
+            }
+            Some(Token::At) => {
+                self.pos += 1;
+                Axis::Attribute
+            }
+            Some(Token::AxisName(name)) => {
+                let axis = match Axis::from_name(name) {
+                    Some(axis) => axis,
+                    None => return syntax_error(self.position(), "unknown axis"),
+                };
+                self.pos += 1;
+                self.expect(&Token::ColonColon, "expected `::` after the axis name")?;
+                axis
+            }
+            _ => Axis::Child,
+        };
+
+        let position = self.position();
+        let test = match self.next() {
+            Some(Token::NameTest { prefix, local_name }) => NodeTest::Name { prefix, local_name },
+            Some(Token::NodeType(name)) => {
+                self.expect(&Token::LeftParen, "expected `(`")?;
+                let test = match name.as_str() {
+                    "node" => NodeTest::Node,
+                    "text" => NodeTest::Text,
+                    "comment" => NodeTest::Comment,
+                    _ => match self.peek() {
+                        Some(Token::Literal(target)) => {
+                            let target = target.clone();
+                            self.pos += 1;
+                            NodeTest::ProcessingInstruction(Some(target))
+                        }
+                        _ => NodeTest::ProcessingInstruction(None),
+                    },
+                };
+                self.expect(&Token::RightParen, "expected `)`")?;
+                test
+            }
+            _ => return syntax_error(position, "expected a node test"),
+        };
+
+        let mut predicates = Vec::new();
+        while self.peek() == Some(&Token::LeftBracket) {
+            predicates.push(self.predicate()?);
+        }
+        Ok(Step {
+            axis,
+            test,
+            predicates,
+        })
+    }
+
+    fn predicate(&mut self) -> Result<Expr, XPathError> {
+        self.expect(&Token::LeftBracket, "expected `[`")?;
+        let expr = self.expr()?;
+        self.expect(&Token::RightBracket, "expected `]`")?;
+        Ok(expr)
+    }
+
+    fn primary_expr(&mut self) -> Result<Expr, XPathError> {
+        let position = self.position();
+        match self.next() {
+            Some(Token::Variable(name)) => Ok(Expr::Variable(name)),
+            Some(Token::LeftParen) => {
+                let expr = self.expr()?;
+                self.expect(&Token::RightParen, "expected `)`")?;
+                Ok(expr)
+            }
+            Some(Token::Literal(s)) => Ok(Expr::Literal(s)),
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::FunctionName(name)) => {
+                let (function, min, max) = match Function::from_name(&name) {
+                    Some(f) => f,
+                    None => return Err(XPathError::UnknownFunction(name)),
+                };
+                self.expect(&Token::LeftParen, "expected `(`")?;
+                let mut args = Vec::new();
+                if !self.eat(&Token::RightParen) {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.eat(&Token::RightParen) {
+                            break;
+                        }
+                        self.expect(&Token::Comma, "expected `,` or `)`")?;
+                    }
+                }
+                if args.len() < min || args.len() > max {
+                    return Err(XPathError::WrongArgumentCount(name));
+                }
+                Ok(Expr::Function(function, args))
+            }
+            Some(_) => syntax_error(position, "unexpected token"),
+            None => syntax_error(position, "unexpected end of expression"),
+        }
+    }
+}
+
+/// The step that `//` abbreviates: `descendant-or-self::node()`
+fn descendant_or_self_step() -> Step {
+    Step {
+        axis: Axis::DescendantOrSelf,
+        test: NodeTest::Node,
+        predicates: Vec::new(),
+    }
+}
@@ -0,0 +1,361 @@
+//! Checks that every parsing backend builds the same trees from the same documents, and rejects
+//! the same malformed ones.
+
+use std::mem::discriminant;
+
+use xmltree::*;
+
+/// Documents that all backends must parse, in every configuration
+const WELL_FORMED: &[&str] = &[
+    "<a/>",
+    "<a></a>",
+    "<a >text</a >",
+    "\u{feff}<a/>",
+    "<?xml version=\"1.0\"?><a/>",
+    "<?xml version=\"1.1\" encoding=\"utf-8\" standalone=\"yes\"?>\n<a>&#1;</a>",
+    "<?xml version='1.0' encoding='UTF-8' standalone='no'?>\r\n<a/>\r\n",
+    "<a x=\"1\" y='2'/>",
+    "<a x=\"&lt;&amp;&gt;&apos;&quot;\" y=\"  spaced\r\n\tvalue \"/>",
+    "<a>&lt;&amp;&gt;&apos;&quot;&#65;&#x42;&#x10000;</a>",
+    "<a>text &amp; more</a>",
+    "<a>  <b/>  <c>  </c>\n\t<d> x </d>\r\n</a>",
+    "<a>&#32;<b/>&#x20;&#10;</a>",
+    "<a>\u{a0}<b/>\u{2028}</a>",
+    "<a>x<![CDATA[y]]>z<!--c-->w</a>",
+    "<a> <![CDATA[ ]]> </a>",
+    "<a> <![CDATA[x]]> y</a>",
+    "<r><![CDATA[z]]>&#x20;</r>",
+    "<a><![CDATA[<not markup> & ]]]]><![CDATA[>]]></a>",
+    "<a>x<!--c-->y<!---c-->z</a>",
+    "<a>x<?pi data?>y<?pi  data  ?><?pi ?><?pi?></a>",
+    "<?xml-stylesheet href=\"s.css\"?><!--c--><a/><!--d--><?pi?>",
+    "\n<!--c-->\n<a/>\n<!--d-->\n",
+    "<a/><b/>",
+    "<a><b><c><d/></c></b></a>",
+    "<a>one<b>two</b>three<c/>four</a>",
+    "<a xmlns=\"urn:a\"><b/><c xmlns=\"\"><d/></c></a>",
+    "<p:a xmlns:p=\"urn:p\" p:x=\"1\" x=\"2\"><p:b/><b/></p:a>",
+    "<a xmlns:p=\"urn:p\" xmlns:q=\"urn:p\" p:b=\"1\" q:b=\"2\"/>",
+    "<a xmlns:p=\"urn:1\"><b xmlns:p=\"urn:2\"><p:c/></b><p:d/></a>",
+    "<a xml:lang=\"en\" xmlns:xml=\"http://www.w3.org/XML/1998/namespace\"/>",
+    "<a xmlns=\"urn:a\" xmlns:p=\"urn:p\"><p:b xmlns=\"urn:b\"><c/></p:b></a>",
+    "<é ü-ñ.ß_=\"ö\">日本語</é>",
+    "<_a.b-c/>",
+    "<!DOCTYPE a><a/>",
+    "<!DOCTYPE a SYSTEM \"a.dtd\"><a/>",
+    "<!DOCTYPE a [\n  <!ENTITY e \"entity\">\n  <!ENTITY f 'f&e;f'>\n]><a x=\"&e;\">&f;</a>",
+    "<!DOCTYPE a [<!ENTITY g \"&h;\"><!ENTITY h \"late\">]><a>&g;</a>",
+    "<!DOCTYPE a [<!ENTITY e \"one\"><!ENTITY e \"two\">]><a>&e;</a>",
+    "<!DOCTYPE a [<!ENTITY e \"x > y\"><!ENTITY f '\"]>'>]><a>&e;&f;</a>",
+    "<!DOCTYPE a [<!ENTITY lt \"x\"><!ENTITY e \"&#38;amp;\">]><a>&lt;&e;</a>",
+    "<!DOCTYPE a [<!ENTITY e \"&#60;\">]><a x=\"&e;\"/>",
+    "<!DOCTYPE a [<!-- <!ENTITY e \"no\"> --><!ENTITY % p \"x\"><!ENTITY e \"yes\">]><a>&e;</a>",
+    "<!DOCTYPE a [<!ELEMENT a (#PCDATA)><!ATTLIST a x CDATA \"1\"><!ENTITY e \"x\">]><a>&e;</a>",
+    "<a>\r\nline\rline\n</a>",
+    "<a><!--\r\n--><?pi a\rb?><![CDATA[\r]]></a>",
+];
+
+/// Documents that all backends must reject
+const MALFORMED: &[&str] = &[
+    "",
+    "   ",
+    "<!--c-->",
+    "text",
+    "<a>",
+    "<a><b></a>",
+    "<a></b>",
+    "</a>",
+    "x<a/>",
+    "<a/>x",
+    "<a/>&amp;",
+    "<![CDATA[x]]><a/>",
+    "<a>]]></a>",
+    "<a x=\"<\"/>",
+    "<a x=1/>",
+    "<a x/>",
+    "<a x=\"1\" x=\"2\"/>",
+    "<a><!--c--c--></a>",
+    "<a><!--c---></a>",
+    "<p:a/>",
+    "<a p:x=\"1\"/>",
+    "<xmlns:a/>",
+    "<a xmlns:xmlns=\"urn:x\"/>",
+    "<a xmlns:xml=\"urn:x\"/>",
+    "<a xmlns:p=\"\"/>",
+    "<a:b:c/>",
+    "<1a/>",
+    "<a>&unknown;</a>",
+    "<a>&#X41;</a>",
+    "<a>&#;</a>",
+    "<a>&#x;</a>",
+    "<a>&#-1;</a>",
+    "<a>&#0;</a>",
+    "<a>&#1;</a>",
+    "<a>&#xFFFE;</a>",
+    "<a>&#x110000;</a>",
+    "<a>&#xD800;</a>",
+    "<a>\u{1}</a>",
+    "<a x=\"\u{fffe}\"/>",
+    "<a/><?xml version=\"1.0\"?>",
+    "<a><?XML?></a>",
+    "<?xml version=\"2.0\"?><a/>",
+    "<?xml version=\"1.0\" standalone=\"maybe\"?><a/>",
+    "<!DOCTYPE a [<!ENTITY e \"&#60;\">]><a>&e;</a>",
+    "<!DOCTYPE a [<!ENTITY e \"&#0;\">]><a/>",
+    include_str!("data/05.xml"),
+];
+
+/// Documents that all backends must reject, unless unknown character references are replaced
+const INVALID_REFERENCES: &[&str] = &[
+    "<a>&#0;</a>",
+    "<a>&#xFFFE;</a>",
+    "<a x=\"&#x110000;\"/>",
+    "<a>&#xDFFF;</a>",
+];
+
+fn backends() -> Vec<Backend> {
+    vec![
+        Backend::XmlRs,
+        #[cfg(feature = "quick-xml")]
+        Backend::QuickXml,
+    ]
+}
+
+fn configs() -> Vec<ParserConfig> {
+    let base = ParserConfig::new;
+    vec![
+        base(),
+        base().trim_whitespace(true),
+        base().whitespace_to_characters(true),
+        base()
+            .whitespace_to_characters(true)
+            .ignore_root_level_whitespace(false),
+        base().cdata_to_characters(true),
+        base().cdata_to_characters(true).trim_whitespace(true),
+        base().ignore_comments(false),
+        base().ignore_comments(false).coalesce_characters(false),
+        base().coalesce_characters(false),
+        base().coalesce_characters(false).cdata_to_characters(true),
+        base().replace_unknown_entity_references(true),
+        base().add_entity("custom", "value"),
+    ]
+}
+
+fn options() -> Vec<ParseOptions> {
+    vec![
+        ParseOptions::new(),
+        ParseOptions::new().track_positions(true),
+        ParseOptions::new().preserve_whitespace(true),
+    ]
+}
+
+/// Parses `data` with every backend, and checks that they agree.  Returns the result of xml-rs.
+///
+/// Spans are not compared, as xml-rs reports some elements at the end of their start tag.
+fn parse(
+    data: &[u8],
+    config: &ParserConfig,
+    options: &ParseOptions,
+) -> Result<Vec<XMLNode>, ParseError> {
+    let mut results = backends().into_iter().map(|backend| {
+        let options = options.clone().backend(backend);
+        let nodes = Element::parse_all_with_options(data, config.clone(), options);
+        (backend, nodes)
+    });
+    let (_, expected) = results.next().unwrap();
+    #[cfg(feature = "quick-xml")]
+    if let Ok(text) = std::str::from_utf8(data) {
+        check_borrowed(text, config, options, &expected);
+    }
+    let data = String::from_utf8_lossy(data);
+    for (backend, result) in results {
+        match (&expected, &result) {
+            (Ok(expected), Ok(result)) => assert_eq!(
+                expected, result,
+                "{:?} built a different tree from {:?} with {:?} and {:?}",
+                backend, data, config, options
+            ),
+            (Err(expected), Err(result)) => assert_eq!(
+                discriminant(expected),
+                discriminant(result),
+                "{:?} failed differently on {:?}: {} instead of {}",
+                backend,
+                data,
+                result,
+                expected
+            ),
+            _ => panic!(
+                "{:?} disagrees on {:?} with {:?} and {:?}: {:?} instead of {:?}",
+                backend, data, config, options, result, expected
+            ),
+        }
+    }
+    expected
+}
+
+/// Checks that quick-xml builds the same root element into an `ElementRef` as xml-rs does into an
+/// `Element`.  `ElementRef` reads its input as UTF-8 whatever encoding the document declares, so
+/// documents that declare another one are skipped.
+#[cfg(feature = "quick-xml")]
+fn check_borrowed(
+    data: &str,
+    config: &ParserConfig,
+    options: &ParseOptions,
+    expected: &Result<Vec<XMLNode>, ParseError>,
+) {
+    let declaration = data.to_ascii_lowercase();
+    if declaration.contains("encoding=") && !declaration.contains("utf-8") {
+        return;
+    }
+    let expected = match expected {
+        Ok(nodes) => nodes
+            .iter()
+            .find_map(XMLNode::as_element)
+            .cloned()
+            .ok_or(ParseError::NoRootElement),
+        Err(ParseError::MalformedXml(e)) => Err(ParseError::MalformedXml(e.clone())),
+        Err(_) => return,
+    };
+    let result = ElementRef::parse_with_options(data, config.clone(), options.clone())
+        .map(ElementRef::into_owned);
+    match (&expected, &result) {
+        (Ok(expected), Ok(result)) => assert_eq!(
+            expected, result,
+            "built a different ElementRef from {:?} with {:?} and {:?}",
+            data, config, options
+        ),
+        (Err(expected), Err(result)) => assert_eq!(
+            discriminant(expected),
+            discriminant(result),
+            "failed differently on {:?} into an ElementRef: {} instead of {}",
+            data,
+            result,
+            expected
+        ),
+        _ => panic!(
+            "disagrees on {:?} into an ElementRef with {:?} and {:?}: {:?} instead of {:?}",
+            data, config, options, result, expected
+        ),
+    }
+}
+
+#[test]
+fn test_well_formed() {
+    for data in WELL_FORMED {
+        for config in configs() {
+            for options in options() {
+                if let Err(e) = parse(data.as_bytes(), &config, &options) {
+                    panic!("failed to parse {:?} with {:?}: {}", data, config, e);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_malformed() {
+    for data in MALFORMED {
+        let config = ParserConfig::new();
+        match parse(data.as_bytes(), &config, &ParseOptions::new()) {
+            Ok(nodes) => panic!("parsed {:?} into {:?}", data, nodes),
+            Err(ParseError::MalformedXml(_)) => (),
+            Err(e) => panic!("unexpected error for {:?}: {}", data, e),
+        }
+    }
+}
+
+#[test]
+fn test_invalid_references() {
+    for data in INVALID_REFERENCES {
+        assert!(parse(data.as_bytes(), &ParserConfig::new(), &ParseOptions::new()).is_err());
+        let config = ParserConfig::new().replace_unknown_entity_references(true);
+        let nodes = parse(data.as_bytes(), &config, &ParseOptions::new()).unwrap();
+        assert!(format!("{:?}", nodes).contains('\u{fffd}'), "{:?}", nodes);
+    }
+}
+
+#[test]
+fn test_encodings() {
+    let latin1 = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><a x=\"\xe9\">\xff&#xe9;</a>";
+    let nodes = parse(latin1, &ParserConfig::new(), &ParseOptions::new()).unwrap();
+    let root = nodes[0].as_element().unwrap();
+    assert_eq!(root.get_attribute("x"), Some("\u{e9}"));
+    assert_eq!(root.get_text().as_deref(), Some("\u{ff}\u{e9}"));
+
+    let ascii = "<?xml version=\"1.0\" encoding=\"US-ASCII\"?><a>\u{e9}</a>";
+    assert!(parse(ascii.as_bytes(), &ParserConfig::new(), &ParseOptions::new()).is_err());
+}
+
+/// Unlike xml-rs, quick-xml always reports elements at the `<` of their tags
+#[cfg(feature = "quick-xml")]
+#[test]
+fn test_quick_xml_spans() {
+    let data = "<a>&#65;<b x=\"1\" />\n  <c>\u{e9}</c></a>";
+    let options = ParseOptions::new()
+        .track_positions(true)
+        .backend(Backend::QuickXml);
+    let root =
+        Element::parse_with_options(data.as_bytes(), ParserConfig::new(), options.clone()).unwrap();
+    let position = |row, column| TextPosition { row, column };
+    let span = root.span.unwrap();
+    assert_eq!(
+        (span.start, span.end_tag),
+        (position(0, 0), position(1, 10))
+    );
+    let span = root.get_child("b").unwrap().span.unwrap();
+    assert_eq!((span.start, span.end_tag), (position(0, 8), position(0, 8)));
+    let span = root.get_child("c").unwrap().span.unwrap();
+    assert_eq!((span.start, span.end_tag), (position(1, 2), position(1, 6)));
+    let spans: Vec<_> = (0..root.children.len())
+        .map(|i| root.child_span(i).unwrap())
+        .collect();
+    let starts: Vec<_> = spans.iter().map(|span| span.start).collect();
+    assert_eq!(starts, vec![position(0, 3), position(0, 8), position(1, 2)]);
+
+    let borrowed = ElementRef::parse_with_options(data, ParserConfig::new(), options).unwrap();
+    assert_eq!(borrowed.span, root.span);
+    let borrowed_spans: Vec<_> = (0..borrowed.children.len())
+        .map(|i| borrowed.child_span(i).unwrap())
+        .collect();
+    assert_eq!(borrowed_spans, spans);
+    assert_eq!(
+        borrowed.get_child("b").unwrap().span,
+        root.get_child("b").unwrap().span
+    );
+    assert_eq!(
+        borrowed.get_child("c").unwrap().span,
+        root.get_child("c").unwrap().span
+    );
+}
+
+#[test]
+fn test_files() {
+    for file in &["01", "02", "03", "04", "ns1", "ns2", "rw", "doc"] {
+        let data = std::fs::read_to_string(format!("tests/data/{}.xml", file)).unwrap();
+        for config in configs() {
+            for options in options() {
+                parse(data.as_bytes(), &config, &options).unwrap();
+            }
+        }
+    }
+}
+
+#[test]
+fn test_document() {
+    let data = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<!--before-->\n\
+                <!DOCTYPE a [<!ENTITY e \"x\">]>\n<?pi data?>\n<a>&e;</a>\n<!--after-->\n";
+    let mut documents = backends().into_iter().map(|backend| {
+        let options = ParseOptions::new().backend(backend);
+        let config = ParserConfig::new().ignore_comments(false);
+        Document::parse_with_options(data.as_bytes(), config, options).unwrap()
+    });
+    let expected = documents.next().unwrap();
+    assert_eq!(
+        expected.doctype.as_deref(),
+        Some("<!DOCTYPE a [<!ENTITY e \"x\">]>")
+    );
+    assert_eq!(expected.doctype_index, 1);
+    for document in documents {
+        assert_eq!(document, expected);
+    }
+}
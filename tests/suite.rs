@@ -57,6 +57,548 @@ fn test_parse_all() {
     assert!(nodes[0].as_comment().is_some());
 }
 
+#[test]
+fn test_document() {
+    let doc = Document::parse(File::open("tests/data/doc.xml").unwrap()).unwrap();
+    assert_eq!(doc.version, XmlVersion::Version10);
+    assert_eq!(doc.encoding, "ISO-8859-1");
+    assert_eq!(doc.standalone, Some(false));
+    assert!(doc
+        .doctype
+        .as_ref()
+        .unwrap()
+        .starts_with("<!DOCTYPE config"));
+    assert_eq!(doc.prolog.len(), 2);
+    assert_eq!(doc.doctype_index, 1);
+    assert_eq!(doc.prolog[0].as_comment(), Some(" Header comment "));
+    assert_eq!(
+        doc.prolog[1].as_processing_instruction().unwrap().0,
+        "xml-stylesheet"
+    );
+    assert_eq!(doc.root.get_attribute("name"), Some("xmltree"));
+    assert_eq!(doc.epilog.len(), 1);
+    assert_eq!(doc.epilog[0].as_comment(), Some(" Trailing comment "));
+
+    let mut buf = Vec::new();
+    doc.write(&mut buf).unwrap();
+    let s = String::from_utf8(buf).unwrap();
+    // The output keeps the encoding, and the top-level nodes keep their order, each on its own
+    // line
+    assert!(s.starts_with(
+        "<?xml version=\"1.0\" encoding=\"ISO-8859-1\" standalone=\"no\"?>\n\
+         <!-- Header comment -->\n\
+         <!DOCTYPE config [\n  <!ENTITY product \"xmltree\">\n]>\n\
+         <?xml-stylesheet href=\"style.css\" type=\"text/css\"?>\n\
+         <config name=\"xmltree\">"
+    ));
+    assert!(s.ends_with("</config>\n<!-- Trailing comment -->"));
+    assert_eq!(Document::parse(s.as_bytes()).unwrap(), doc);
+
+    let mut buf = Vec::new();
+    doc.write_formatted(&mut buf, &FormatOptions::new())
+        .unwrap();
+    let s = String::from_utf8(buf).unwrap();
+    assert!(s.starts_with(
+        "<?xml version=\"1.0\" encoding=\"ISO-8859-1\" standalone=\"no\"?>\n\
+         <!-- Header comment -->\n\
+         <!DOCTYPE config [\n  <!ENTITY product \"xmltree\">\n]>\n\
+         <?xml-stylesheet href=\"style.css\" type=\"text/css\"?>\n\
+         <config name=\"xmltree\">"
+    ));
+
+    // Without a declaration, nothing precedes the first node
+    let mut buf = Vec::new();
+    let config = EmitterConfig::new().write_document_declaration(false);
+    doc.write_with_config(&mut buf, config).unwrap();
+    assert!(buf.starts_with(b"<!-- Header comment -->\n<!DOCTYPE config"));
+
+    let mut buf = Vec::new();
+    let config = EmitterConfig::new().perform_indent(true);
+    doc.write_with_config(&mut buf, config).unwrap();
+    let s = String::from_utf8(buf).unwrap();
+    assert!(s.starts_with(
+        "<?xml version=\"1.0\" encoding=\"ISO-8859-1\" standalone=\"no\"?>\n\
+         <!-- Header comment -->\n\
+         <!DOCTYPE config"
+    ));
+    assert_eq!(Document::parse(s.as_bytes()).unwrap(), doc);
+
+    // Text is transcoded to the document's encoding
+    let mut doc = doc;
+    doc.root.children = vec![XMLNode::Text("café".to_owned())];
+    let mut buf = Vec::new();
+    doc.write(&mut buf).unwrap();
+    assert!(buf.ends_with(b"<config name=\"xmltree\">caf\xE9</config>\n<!-- Trailing comment -->"));
+    assert_eq!(Document::parse(buf.as_slice()).unwrap(), doc);
+    let mut buf = Vec::new();
+    doc.write_formatted(&mut buf, &FormatOptions::new())
+        .unwrap();
+    assert_eq!(Document::parse(buf.as_slice()).unwrap(), doc);
+
+    // Characters and encodings that can't be written are errors, not silently UTF-8
+    doc.root.children = vec![XMLNode::Text("5 €".to_owned())];
+    let err = doc.write(Vec::new()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "emitter error: I/O error: `€` can't be written in ISO-8859-1"
+    );
+    doc.encoding = "UTF-16".to_owned();
+    let err = doc.write(Vec::new()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "emitter error: I/O error: can't write documents in encoding `UTF-16`"
+    );
+    assert!(doc
+        .write_formatted(Vec::new(), &FormatOptions::new())
+        .is_err());
+    doc.encoding = "utf-8".to_owned();
+    let mut buf = Vec::new();
+    doc.write(&mut buf).unwrap();
+    assert!(String::from_utf8(buf).unwrap().contains("5 €"));
+}
+
+#[test]
+fn test_parse_fragment() {
+    let mut namespaces = Namespace::empty();
+    namespaces.put("x", "urn:x");
+
+    let nodes = XMLNode::parse_fragment("<a/><!-- c --><x:b>1</x:b>text<![CDATA[<]]>", &namespaces)
+        .unwrap();
+    assert_eq!(nodes.len(), 5);
+    let a = nodes[0].as_element().unwrap();
+    assert_eq!((a.name.as_str(), a.namespace.as_deref()), ("a", None));
+    assert_eq!(nodes[1].as_comment(), Some(" c "));
+    let b = nodes[2].as_element().unwrap();
+    assert_eq!(b.namespace.as_deref(), Some("urn:x"));
+    assert_eq!(b.namespaces.as_ref().unwrap().get("x"), Some("urn:x"));
+    assert_eq!(nodes[3].as_text(), Some("text"));
+    assert_eq!(nodes[4].as_cdata(), Some("<"));
+
+    assert!(XMLNode::parse_fragment("", &namespaces).unwrap().is_empty());
+    assert!(XMLNode::parse_fragment("<y:a/>", &namespaces).is_err());
+    assert!(XMLNode::parse_fragment("<a>", &namespaces).is_err());
+
+    // The fragment can't close the element it is parsed in
+    let err = XMLNode::parse_fragment("x</xmltree-fragment><xmltree-fragment>y", &namespaces)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Malformed XML. 1:2 the fragment closes the element it is parsed in"
+    );
+
+    // Errors are reported relative to the fragment too, not the element it is parsed in
+    let err = XMLNode::parse_fragment("<a>", &namespaces).unwrap_err();
+    assert!(
+        err.to_string().starts_with("Malformed XML. 1:4 "),
+        "{}",
+        err
+    );
+    let err = XMLNode::parse_fragment("<a/>\n<b c>", &namespaces).unwrap_err();
+    assert!(
+        err.to_string().starts_with("Malformed XML. 2:5 "),
+        "{}",
+        err
+    );
+
+    // A default namespace applies to elements without a prefix
+    namespaces.put("", "urn:d");
+    let nodes = XMLNode::parse_fragment(r#"<a/><c xmlns=""/>"#, &namespaces).unwrap();
+    assert_eq!(
+        nodes[0].as_element().unwrap().namespace.as_deref(),
+        Some("urn:d")
+    );
+    assert_eq!(nodes[1].as_element().unwrap().namespace, None);
+
+    // Positions are relative to the fragment
+    let options = ParseOptions::new().track_positions(true);
+    let nodes = XMLNode::parse_fragment_with_options(
+        "<a/><b>\n<c/></b>",
+        &namespaces,
+        ParserConfig::new(),
+        options,
+    )
+    .unwrap();
+    let a = nodes[0].as_element().unwrap().span.unwrap();
+    assert_eq!((a.start.row, a.start.column), (0, 0));
+    let b = nodes[1].as_element().unwrap().span.unwrap();
+    assert_eq!((b.start.row, b.start.column), (0, 4));
+    let c = nodes[1].as_element().unwrap().children[0]
+        .as_element()
+        .unwrap()
+        .span
+        .unwrap();
+    assert_eq!((c.start.row, c.start.column), (1, 0));
+}
+
+#[test]
+fn test_stream() {
+    let data = r#"<feed xmlns:r="urn:r">
+        <meta><record id="0"/></meta>
+        <r:record id="1"><r:record id="nested"/><v>a</v></r:record>
+        <!-- skipped -->
+        <r:record id="2"/>
+    </feed>"#;
+    let records: Vec<Element> = Element::stream(data.as_bytes(), "record")
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let ids: Vec<&str> = records
+        .iter()
+        .map(|r| r.get_attribute("id").unwrap())
+        .collect();
+    assert_eq!(ids, ["0", "1", "2"]);
+    assert_eq!(records[1].children.len(), 2);
+    assert_eq!(records[1].get_child("v").unwrap().get_text().unwrap(), "a");
+
+    let records = Element::stream(data.as_bytes(), ("record", "urn:r"));
+    assert_eq!(records.count(), 2);
+
+    // Limits apply to each element on its own
+    let options = ParseOptions::new().limits(ParseLimits::new().max_nodes(4));
+    let records = Element::stream_with_options(
+        data.as_bytes(),
+        ParserConfig::new(),
+        options.clone(),
+        "record",
+    );
+    assert_eq!(records.filter(Result::is_ok).count(), 3);
+    let records =
+        Element::stream_with_options(data.as_bytes(), ParserConfig::new(), options, "feed");
+    assert!(matches!(
+        records.collect::<Vec<_>>()[..],
+        [Err(ParseError::LimitExceeded { .. })]
+    ));
+
+    // Errors end the stream, after the elements read before them
+    let broken = "<feed><record/><record></feed><record/>";
+    let mut records = Element::stream(broken.as_bytes(), "record");
+    assert!(records.next().unwrap().is_ok());
+    assert!(records.next().unwrap().is_err());
+    assert!(records.next().is_none());
+}
+
+#[test]
+fn test_tree_writer() {
+    use xml::writer::XmlEvent;
+
+    let mut writer = TreeWriter::new(Vec::new());
+    let mut feed = Element::new("feed");
+    feed.prefix = Some("f".to_owned());
+    feed.namespace = Some("urn:f".to_owned());
+    feed.namespaces = Some(Namespace::empty());
+    feed.namespaces.as_mut().unwrap().put("f", "urn:f");
+    feed.children.push(XMLNode::Text("not written".to_owned()));
+    writer.start_element(&feed).unwrap();
+    assert_eq!(writer.depth(), 1);
+
+    writer
+        .write_node(&XMLNode::Comment(" records ".to_owned()))
+        .unwrap();
+    let record: Element = r#"<record id="1"><v>a &amp; b</v></record>"#.parse().unwrap();
+    writer.write_element(&record).unwrap();
+    writer.start_element(&Element::new("group")).unwrap();
+    writer.write_event(XmlEvent::start_element("raw")).unwrap();
+    writer.write_event(XmlEvent::characters("text")).unwrap();
+    writer.write_event(XmlEvent::end_element()).unwrap();
+    writer.end_element().unwrap();
+    writer.end_element().unwrap();
+    assert_eq!(writer.depth(), 0);
+    assert!(writer.end_element().is_err());
+
+    let out = String::from_utf8(writer.into_inner()).unwrap();
+    assert_eq!(
+        out,
+        r#"<?xml version="1.0" encoding="UTF-8"?><f:feed xmlns:f="urn:f"><!-- records --><record id="1"><v>a &amp; b</v></record><group><raw>text</raw></group></f:feed>"#
+    );
+}
+
+/// An in-memory reader that returns a few bytes at a time, and is not ready every other time
+#[cfg(feature = "async")]
+struct Trickle<'a> {
+    data: &'a [u8],
+    chunk: usize,
+    ready: bool,
+}
+
+#[cfg(feature = "async")]
+impl futures::io::AsyncRead for Trickle<'_> {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context,
+        buf: &mut [u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        use std::task::Poll;
+
+        self.ready = !self.ready;
+        if !self.ready {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        let n = self.chunk.min(buf.len()).min(self.data.len());
+        buf[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        Poll::Ready(Ok(n))
+    }
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_async() {
+    use futures::executor::block_on;
+    use futures::stream::{StreamExt, TryStreamExt};
+
+    let data = r#"<?xml version="1.0"?>
+    <!DOCTYPE feed [ <!ENTITY e "a > b"> ]>
+    <feed xmlns:r="urn:r">
+        <meta><record id="0"/></meta>
+        <r:record id="1" note="a/>b"><r:record id="nested"/><v>a &gt; b</v></r:record>
+        <!-- <record id="commented"/> -->
+        <?pi <record id="pi"/> ?>
+        <![CDATA[ <record id="cdata"/> ]]>
+        <r:record id="2"/>
+    </feed>
+    <!-- after -->"#;
+    let expected: Vec<Element> = Element::stream(data.as_bytes(), "record")
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(expected.len(), 3);
+
+    // The result doesn't depend on how the input is split
+    for chunk in [1, 2, 3, 7, 64, 4096] {
+        let input = Trickle {
+            data: data.as_bytes(),
+            chunk,
+            ready: false,
+        };
+        let records: Vec<Element> =
+            block_on(Element::stream_async(input, "record").try_collect()).unwrap();
+        assert_eq!(records, expected, "chunks of {} bytes", chunk);
+
+        let input = Trickle {
+            data: data.as_bytes(),
+            chunk,
+            ready: false,
+        };
+        let feed = block_on(Element::parse_async(input)).unwrap();
+        assert_eq!(feed, Element::parse(data.as_bytes()).unwrap());
+    }
+
+    // Limits apply to each element on its own
+    let options = ParseOptions::new().limits(ParseLimits::new().max_nodes(4));
+    let records =
+        Element::stream_async_with_options(data.as_bytes(), ParserConfig::new(), options, "record");
+    assert_eq!(
+        block_on(
+            records
+                .filter(|r| futures::future::ready(r.is_ok()))
+                .count()
+        ),
+        3
+    );
+
+    // Errors end the stream, after the elements read before them
+    let broken = Trickle {
+        data: b"<feed><record/><record></feed><record/>",
+        chunk: 5,
+        ready: false,
+    };
+    let mut records = Element::stream_async(broken, "record");
+    assert!(block_on(records.next()).unwrap().is_ok());
+    assert!(block_on(records.next()).unwrap().is_err());
+    assert!(block_on(records.next()).is_none());
+    let mut records = Element::stream_async(&b"<feed><record>"[..], "record");
+    assert!(block_on(records.next()).unwrap().is_err());
+    let mut records = Element::stream_async(&b""[..], "record");
+    assert!(matches!(
+        block_on(records.next()),
+        Some(Err(ParseError::MalformedXml(_)))
+    ));
+
+    let mut out = Vec::new();
+    block_on(expected[1].write_async(&mut out)).unwrap();
+    let mut sync = Vec::new();
+    expected[1].write(&mut sync).unwrap();
+    assert_eq!(out, sync);
+    let mut out = Vec::new();
+    let config = EmitterConfig::new().write_document_declaration(false);
+    block_on(Element::new("a").write_async_with_config(&mut out, config)).unwrap();
+    assert_eq!(out, b"<a />");
+}
+
+#[test]
+fn test_tree() {
+    let root: Element = "<doc><h>Title</h><p>One <b>two</b></p><!--c--><p/></doc>"
+        .parse()
+        .unwrap();
+    let original = root.clone();
+    let mut tree = Tree::from(root);
+    let doc = tree.root();
+    let children: Vec<NodeId> = tree.children(doc).collect();
+    assert_eq!(children.len(), 4);
+    let (h, p1, comment, p2) = (children[0], children[1], children[2], children[3]);
+    assert_eq!(tree.get(comment).as_comment(), Some("c"));
+    assert_eq!(tree.first_child(doc), Some(h));
+    assert_eq!(tree.last_child(doc), Some(p2));
+    assert_eq!(tree.prev_sibling(h), None);
+    assert_eq!(tree.next_sibling(p1), Some(comment));
+    assert_eq!(tree.prev_sibling(p2), Some(comment));
+    assert_eq!(tree.next_sibling(p2), None);
+
+    let b = tree.last_child(p1).unwrap();
+    let two = tree.first_child(b).unwrap();
+    assert_eq!(tree.get(two).as_text(), Some("two"));
+    assert_eq!(tree.ancestors(two).collect::<Vec<_>>(), [b, p1, doc]);
+    assert_eq!(tree.parent(doc), None);
+    assert!(tree.element(b).unwrap().children.is_empty());
+    assert_eq!(tree.to_node(p1), original.children[1]);
+    assert_eq!(tree.clone().into_element(), original);
+
+    // Moving nodes around
+    tree.detach(comment);
+    assert_eq!(tree.parent(comment), None);
+    assert_eq!(tree.next_sibling(p1), Some(p2));
+    tree.insert_before(h, p2);
+    tree.insert_after(h, b);
+    tree.prepend(p2, comment);
+    tree.insert_before(h, h);
+    assert_eq!(tree.parent(b), Some(doc));
+    assert_eq!(tree.ancestors(two).collect::<Vec<_>>(), [b, doc]);
+    tree.element_mut(h).unwrap().name = "title".to_owned();
+    let extra = tree.add(XMLNode::Element("<i>x</i>".parse().unwrap()));
+    assert_eq!(tree.parent(extra), None);
+    tree.append(p1, extra);
+    assert_eq!(
+        Element::from(tree).to_string(),
+        "<doc><p><!-- c --></p><title>Title</title><b>two</b><p>One <i>x</i></p></doc>"
+    );
+
+    // Deep trees don't overflow the stack
+    let mut deep = Element::new("e");
+    for _ in 0..100_000 {
+        let mut parent = Element::new("e");
+        parent.children.push(XMLNode::Element(deep));
+        deep = parent;
+    }
+    let tree = Tree::new(deep.clone());
+    let back = tree.into_element();
+    assert_eq!(back, deep);
+}
+
+#[test]
+#[should_panic(expected = "a node can't be inserted inside itself")]
+fn test_tree_cycle() {
+    let root: Element = "<doc><p><b/></p></doc>".parse().unwrap();
+    let mut tree = Tree::from(root);
+    let p = tree.first_child(tree.root()).unwrap();
+    let b = tree.first_child(p).unwrap();
+    tree.append(b, p);
+}
+
+#[test]
+#[should_panic(expected = "the root of a tree can't be replaced")]
+fn test_tree_root_replaced() {
+    let root: Element = "<doc><p/></doc>".parse().unwrap();
+    let mut tree = Tree::from(root);
+    let doc = tree.root();
+    tree.element_mut(doc).unwrap().name = "body".to_owned();
+    *tree.get_mut(doc) = XMLNode::Text("text".to_owned());
+}
+
+#[cfg(feature = "quick-xml")]
+#[test]
+fn test_element_ref() {
+    let input = String::from(
+        "<doc xmlns:p=\"urn:p\" id=\"d1\" q=\"a &amp; b\">\
+           <p:title lang=\"en\">Plain</p:title>\
+           <item>one &lt; two</item>\
+           <item>x<![CDATA[y]]><!--c-->z</item>\
+         </doc>",
+    );
+    let doc = ElementRef::parse(&input).unwrap();
+    assert_eq!(doc.name, "doc");
+    assert_eq!(doc.get_attribute("id"), Some("d1"));
+    assert_eq!(doc.get_attribute_ns("q", None), Some("a & b"));
+    assert_eq!(doc.get_attribute("missing"), None);
+
+    let title = doc.get_child(("title", "urn:p")).unwrap();
+    assert_eq!(title.prefix.as_deref(), Some("p"));
+    assert!(title.matches("title"));
+    assert!(!title.matches(("title", "urn:q")));
+    assert_eq!(title.get_text().unwrap(), "Plain");
+    let item = doc.get_child(String::from("item")).unwrap();
+    assert_eq!(item.get_text().unwrap(), "one < two");
+    assert_eq!(
+        doc.children[2].as_element().unwrap().get_text().unwrap(),
+        "xyz"
+    );
+    assert!(doc.get_child(Cow::Borrowed("p:title")).is_none());
+
+    // Strings are only copied when they contain references
+    assert!(matches!(doc.name, Cow::Borrowed(_)));
+    assert!(matches!(doc.attributes[0].value, Cow::Borrowed("d1")));
+    assert!(matches!(doc.attributes[1].value, Cow::Owned(_)));
+    assert!(matches!(title.namespace, Some(Cow::Borrowed("urn:p"))));
+    assert!(matches!(
+        title.children[0],
+        XMLNodeRef::Text(Cow::Borrowed("Plain"))
+    ));
+    assert!(matches!(item.children[0], XMLNodeRef::Text(Cow::Owned(_))));
+
+    let expected = Element::parse(input.as_bytes()).unwrap();
+    assert_eq!(doc.clone().into_owned(), expected);
+    // Enabling quick-xml doesn't change the default backend, but ElementRef always uses quick-xml
+    assert_eq!(ParseOptions::new().backend, Backend::XmlRs);
+    let options = ParseOptions::new().backend(Backend::XmlRs);
+    let config = ParserConfig::new().ignore_comments(false);
+    let doc = ElementRef::parse_with_options(&input, config, options).unwrap();
+    assert!(matches!(doc.name, Cow::Borrowed(_)));
+    assert_eq!(doc.into_owned(), expected);
+
+    assert!(matches!(
+        ElementRef::parse("<a><b></a>"),
+        Err(ParseError::MalformedXml(_))
+    ));
+    assert!(matches!(
+        ElementRef::parse_with_options(
+            "<a><b/><c/></a>",
+            ParserConfig::new(),
+            ParseOptions::new().limits(ParseLimits::new().max_children(1)),
+        ),
+        Err(ParseError::LimitExceeded { .. })
+    ));
+
+    // Deep trees don't overflow the stack
+    let input = "<e>".repeat(2_000) + &"</e>".repeat(2_000);
+    let expected = Element::parse(input.as_bytes()).unwrap();
+    let handle = std::thread::Builder::new()
+        .stack_size(128 * 1024)
+        .spawn(move || {
+            let elem = ElementRef::parse(&input).unwrap();
+            let owned = elem.clone().into_owned();
+            assert!(format!("{:?}", elem).starts_with("ElementRef { prefix: None,"));
+            drop(elem);
+            owned
+        })
+        .unwrap();
+    assert_eq!(handle.join().unwrap(), expected);
+}
+
+#[test]
+fn test_element_ref_from_element() {
+    let elem: Element =
+        r#"<a xmlns:x="urn:x" xmlns:y="urn:y" y:id="3" x:id="2" href="1"><x:b/></a>"#
+            .parse()
+            .unwrap();
+    let elem_ref = ElementRef::from(elem.clone());
+    assert_eq!(Element::from(elem_ref.clone()), elem);
+
+    // Attributes without a namespace are preferred, like with Element
+    assert_eq!(elem_ref.get_attribute("href"), elem.get_attribute("href"));
+    assert_eq!(elem_ref.get_attribute("id"), Some("2"));
+    assert_eq!(elem_ref.get_attribute_ns("id", Some("urn:y")), Some("3"));
+    assert!(elem_ref.get_child(("b", "urn:x")).is_some());
+    assert!(elem_ref.get_child(("b", String::from("urn:y"))).is_none());
+}
+
 #[test]
 fn test_no_root_node() {
     let result = Element::parse_all(File::open("tests/data/05.xml").unwrap());
@@ -80,8 +622,12 @@ fn test_mut() {
     let mut e: Element = Element::parse(File::open("tests/data/rw.xml").unwrap()).unwrap();
     {
         let name = e.get_mut_child("name").unwrap();
-        name.attributes.insert("suffix".to_owned(), "mr".to_owned());
+        name.attributes.insert("suffix".into(), "mr".to_owned());
     }
+    assert_eq!(
+        e.get_child("name").unwrap().get_attribute("suffix"),
+        Some("mr")
+    );
 }
 
 #[test]
@@ -144,6 +690,280 @@ fn test_mal_03() {
     println!("{:?}", names_element);
 }
 
+#[test]
+fn test_preserve_whitespace() {
+    let data =
+        "<root a=\"1\">\n    <child />\n    <!-- comment -->\n    <other>text</other>\n</root>\n";
+
+    let e = Element::parse(data.as_bytes()).unwrap();
+    assert_eq!(e.children.len(), 3);
+
+    let options = ParseOptions::new().preserve_whitespace(true);
+    let config = ParserConfig::new().ignore_comments(false);
+    let e = Element::parse_with_options(data.as_bytes(), config, options).unwrap();
+    assert_eq!(e.children.len(), 7);
+    assert_eq!(e.children[0].as_whitespace(), Some("\n    "));
+    assert!(e.get_text().is_none());
+
+    let mut c = EmitterConfig::new();
+    c.write_document_declaration = false;
+    let mut output = Vec::new();
+    e.write_with_config(&mut output, c).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), data.trim_end());
+}
+
+#[test]
+fn test_positions() {
+    let data = "<?xml version=\"1.0\"?>\n<root>\n  <a>text</a>\n  <b/>\n</root>";
+
+    let e = Element::parse(data.as_bytes()).unwrap();
+    assert!(e.span.is_none());
+
+    let options = ParseOptions::new().track_positions(true);
+    let e2 = Element::parse_with_options(data.as_bytes(), ParserConfig::new(), options).unwrap();
+    let span = e2.span.unwrap();
+    assert_eq!((span.start.row, span.start.column), (1, 0));
+    assert_eq!((span.end_tag.row, span.end_tag.column), (4, 0));
+
+    let a = e2.get_child("a").unwrap().span.unwrap();
+    assert_eq!((a.start.row, a.start.column), (2, 2));
+    assert_eq!((a.end_tag.row, a.end_tag.column), (2, 9));
+
+    let b = e2.get_child("b").unwrap().span.unwrap();
+    assert_eq!((b.start.row, b.start.column), (3, 2));
+    assert_eq!(b.start, b.end_tag);
+
+    // spans are ignored by comparisons
+    assert_eq!(e, e2);
+
+    // Children other than elements have spans too
+    assert_eq!(e2.child_span(0), Some(a));
+    assert_eq!(e2.child_span(1), Some(b));
+    assert_eq!(e2.child_span(2), None);
+    let text = e2.get_child("a").unwrap().child_span(0).unwrap();
+    assert_eq!((text.start.row, text.start.column), (2, 5));
+    assert_eq!(text.start, text.end_tag);
+
+    // Elements keep their span when the children change, other nodes lose theirs
+    let mut e3 = e2.clone();
+    e3.children.reverse();
+    assert_eq!(e3.child_span(0), Some(b));
+    let a = e3.get_mut_child("a").unwrap();
+    a.children.insert(0, XMLNode::Comment("new".to_owned()));
+    assert_eq!(a.child_span(0), None);
+    assert_eq!(a.child_span(1), None);
+
+    let data = "<root><!-- c --><?pi data?>\n<![CDATA[x]]></root>";
+    let options = ParseOptions::new().track_positions(true);
+    let config = ParserConfig::new().ignore_comments(false);
+    let e = Element::parse_with_options(data.as_bytes(), config, options).unwrap();
+    let starts: Vec<_> = (0..e.children.len())
+        .map(|i| e.child_span(i).unwrap().start)
+        .map(|start| (start.row, start.column))
+        .collect();
+    assert_eq!(e.children.len(), 3);
+    assert_eq!(starts, vec![(0, 6), (0, 16), (1, 0)]);
+}
+
+#[test]
+fn test_error_source() {
+    use std::error::Error;
+
+    let err = Element::parse("<a><b></a>".as_bytes()).unwrap_err();
+    assert!(matches!(err, ParseError::MalformedXml(..)));
+    assert!(err.source().is_some());
+    assert!(err.to_string().starts_with("Malformed XML."));
+}
+
+#[test]
+fn test_deep_nesting() {
+    // Deep enough to overflow the stack if any of these operations recursed
+    const DEPTH: usize = 100_000;
+    let mut e = Element::new("a");
+    e.children.push(XMLNode::Text("leaf".to_owned()));
+    for _ in 0..DEPTH {
+        let mut parent = Element::new("a");
+        parent.children.push(XMLNode::Element(e));
+        e = parent;
+    }
+
+    let mut buf = Vec::new();
+    e.write(&mut buf).unwrap();
+    assert_eq!(buf.len(), 38 + (DEPTH + 1) * 7 + 4);
+
+    let mut copy = e.clone();
+    assert_eq!(e, copy);
+
+    let mut innermost = &copy;
+    while let Some(child) = innermost.get_child("a") {
+        innermost = child;
+    }
+    assert_eq!(innermost.get_text().unwrap(), "leaf");
+
+    let mut descendants = copy.descendants_mut();
+    let mut count = 0;
+    while let Some(elem) = descendants.next() {
+        elem.name.push('!');
+        count += 1;
+    }
+    assert_eq!(count, DEPTH);
+
+    let end = r#"Text("leaf")], span: None, child_spans: [] })"#.to_owned()
+        + &"], span: None, child_spans: [] })".repeat(DEPTH - 1)
+        + "], span: None, child_spans: [] }";
+    assert!(format!("{:?}", e).ends_with(&end));
+
+    let mut cleared = copy.clone();
+    cleared.clear_children();
+    assert!(cleared.children.is_empty());
+    drop(copy);
+    drop(e);
+}
+
+#[test]
+fn test_debug() {
+    let elem: Element = "<a><b/>t</a>".parse().unwrap();
+    assert_eq!(
+        format!("{:?}", elem),
+        "Element { prefix: None, namespace: None, namespaces: None, name: \"a\", attributes: {}, \
+         children: [Element(Element { prefix: None, namespace: None, namespaces: None, \
+         name: \"b\", attributes: {}, children: [], span: None, child_spans: [] }), \
+         Text(\"t\")], span: None, child_spans: [] }"
+    );
+    assert_eq!(
+        format!("{:#?}", elem),
+        r#"Element {
+    prefix: None,
+    namespace: None,
+    namespaces: None,
+    name: "a",
+    attributes: {},
+    children: [
+        Element(
+            Element {
+                prefix: None,
+                namespace: None,
+                namespaces: None,
+                name: "b",
+                attributes: {},
+                children: [],
+                span: None,
+                child_spans: [],
+            },
+        ),
+        Text(
+            "t",
+        ),
+    ],
+    span: None,
+    child_spans: [],
+}"#
+    );
+
+    // Inside other values, elements are indented like anything else
+    let node = XMLNode::Element(elem.get_child("b").unwrap().clone());
+    assert_eq!(
+        format!("{:#?}", Some(node)),
+        r#"Some(
+    Element(
+        Element {
+            prefix: None,
+            namespace: None,
+            namespaces: None,
+            name: "b",
+            attributes: {},
+            children: [],
+            span: None,
+            child_spans: [],
+        },
+    ),
+)"#
+    );
+
+    // Fields can be taken out of elements
+    let mut elem = elem;
+    let children = std::mem::take(&mut elem.children);
+    assert_eq!(children.len(), 2);
+}
+
+#[test]
+fn test_deep_nesting_parse() {
+    // xml-rs itself slows down with depth, so keep the document small and the stack smaller
+    const DEPTH: usize = 2_000;
+    let mut data = String::new();
+    for _ in 0..DEPTH {
+        data.push_str("<a>");
+    }
+    for _ in 0..DEPTH {
+        data.push_str("</a>");
+    }
+
+    let handle = std::thread::Builder::new()
+        .stack_size(128 * 1024)
+        .spawn(move || {
+            // Trees left over by errors are dropped without recursion too
+            let unclosed = format!("<r>{}<", data);
+            assert!(Element::parse(unclosed.as_bytes()).is_err());
+            let epilog = format!("{}<", data);
+            assert!(Document::parse(epilog.as_bytes()).is_err());
+
+            let e = Element::parse(data.as_bytes()).unwrap();
+            let depth = e.descendants().count();
+            drop(e);
+            depth
+        })
+        .unwrap();
+    assert_eq!(handle.join().unwrap(), DEPTH - 1);
+}
+
+#[test]
+fn test_limits() {
+    fn parse(data: &str, limits: ParseLimits) -> Result<Element, ParseError> {
+        let options = ParseOptions::new().limits(limits);
+        Element::parse_with_options(data.as_bytes(), ParserConfig::new(), options)
+    }
+    fn exceeded(result: Result<Element, ParseError>) -> Limit {
+        match result {
+            Err(ParseError::LimitExceeded { limit, .. }) => limit,
+            other => panic!("unexpected parse result {:?}", other),
+        }
+    }
+
+    let data = r#"<a x="1" y="2"><b><c>hello</c></b><d/><d/></a>"#;
+    assert!(parse(data, ParseLimits::new()).is_ok());
+
+    let fits = ParseLimits::new()
+        .max_depth(3)
+        .max_attributes(2)
+        .max_children(3)
+        .max_text_bytes(9)
+        .max_nodes(6);
+    assert!(parse(data, fits).is_ok());
+
+    assert_eq!(exceeded(parse(data, fits.max_depth(2))), Limit::Depth);
+    assert_eq!(
+        exceeded(parse(data, fits.max_attributes(1))),
+        Limit::Attributes
+    );
+    assert_eq!(exceeded(parse(data, fits.max_children(2))), Limit::Children);
+    assert_eq!(
+        exceeded(parse(data, fits.max_text_bytes(8))),
+        Limit::TextBytes
+    );
+    assert_eq!(exceeded(parse(data, fits.max_nodes(5))), Limit::Nodes);
+
+    // Attribute names and values count toward the text limit
+    let attrs = r#"<a x="0123456789" p:y="z" xmlns:p="urn:p"/>"#;
+    assert!(parse(attrs, ParseLimits::new().max_text_bytes(14)).is_ok());
+    assert_eq!(
+        exceeded(parse(attrs, ParseLimits::new().max_text_bytes(13))),
+        Limit::TextBytes
+    );
+
+    let err = parse(data, fits.max_depth(2)).unwrap_err();
+    assert_eq!(err.to_string(), "1:19: Maximum element depth exceeded");
+}
+
 #[test]
 fn test_new() {
     let e = Element::new("foo");
@@ -210,6 +1030,41 @@ fn test_ns_rw() {
     }
 }
 
+#[test]
+fn test_attribute_ns() {
+    let data = r#"
+        <svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink">
+            <a href="plain" xlink:href="linked" />
+        </svg>
+    "#;
+
+    let e = Element::parse(data.as_bytes()).unwrap();
+    let a = e.get_child("a").unwrap();
+    assert_eq!(a.attributes.len(), 2);
+    assert_eq!(a.get_attribute_ns("href", None), Some("plain"));
+    assert_eq!(
+        a.get_attribute_ns("href", Some("http://www.w3.org/1999/xlink")),
+        Some("linked")
+    );
+    assert_eq!(a.get_attribute("href"), Some("plain"));
+    let linked =
+        Element::parse(r#"<a xmlns:x="urn:x" xmlns:y="urn:y" y:href="y" x:href="x"/>"#.as_bytes())
+            .unwrap();
+    assert_eq!(linked.get_attribute("href"), Some("x"));
+
+    let key = a.attributes.keys().find(|k| k.namespace.is_some()).unwrap();
+    assert_eq!(key.prefix.as_deref(), Some("xlink"));
+    assert_eq!(key.to_string(), "xlink:href");
+
+    let mut buf = Vec::new();
+    e.write(&mut buf).unwrap();
+    let s = String::from_utf8(buf).unwrap();
+    assert!(s.contains(r#"xlink:href="linked""#));
+
+    let e2 = Element::parse(s.as_bytes()).unwrap();
+    assert_eq!(e, e2);
+}
+
 #[test]
 fn test_write_with_config() {
     let e: Element = Element::parse(File::open("tests/data/01.xml").unwrap()).unwrap();
@@ -303,3 +1158,1395 @@ fn test_decl() {
         "<?xml version=\"1.0\" encoding=\"UTF-8\"?><n />"
     );
 }
+
+#[test]
+fn test_xpath() {
+    use xmltree::xpath::{Context, Value, XPath, XPathError};
+
+    let data = r#"
+        <library xmlns:x="urn:extra">
+            <book id="b1" year="1995" xml:lang="en-GB"><title>First</title><x:note>old</x:note></book>
+            <!-- shelf two -->
+            <book id="b2" year="2007"><title>Second</title></book>
+            <book id="b3" year="2011"><title>Third</title><price>12.5</price></book>
+            <div>7</div>
+        </library>
+    "#;
+    let library = Element::parse(data.as_bytes()).unwrap();
+    let eval = |expr: &str| XPath::compile(expr).unwrap().evaluate(&library).unwrap();
+    let names = |expr: &str| -> Vec<String> {
+        eval(expr)
+            .into_nodes()
+            .unwrap()
+            .iter()
+            .map(|n| n.string_value())
+            .collect()
+    };
+
+    assert_eq!(names("book/title"), vec!["First", "Second", "Third"]);
+    assert_eq!(names("/library/book[2]/title"), vec!["Second"]);
+    assert_eq!(names("//book[last()]/title"), vec!["Third"]);
+    assert_eq!(names("//title[. = 'Third']/../@year"), vec!["2011"]);
+    assert_eq!(names("book[@year > 2000][1]/title"), vec!["Second"]);
+    assert_eq!(
+        names("//title[starts-with(., 'S')]/ancestor::*[1]/@id"),
+        vec!["b2"]
+    );
+    assert_eq!(names("book[3]/preceding-sibling::book[1]/@id"), vec!["b2"]);
+    assert_eq!(names("book[1]/following::title[1]"), vec!["Second"]);
+    assert_eq!(names("id('b3 b1')/title"), vec!["First", "Third"]);
+    assert_eq!(names("//comment()"), vec![" shelf two "]);
+    assert_eq!(
+        names("book[price] | book[1]"),
+        vec!["Firstold", "Third12.5"]
+    );
+
+    assert_eq!(eval("count(//book)"), Value::Number(3.0));
+    assert_eq!(eval("sum(//price) * 2"), Value::Number(25.0));
+    assert_eq!(eval("string(div div 2)"), Value::String("3.5".to_owned()));
+    assert_eq!(eval("div mod 4"), Value::Number(3.0));
+    assert_eq!(eval("-5 mod 3"), Value::Number(-2.0));
+    assert_eq!(
+        eval("string(1 div 0)"),
+        Value::String("Infinity".to_owned())
+    );
+    assert_eq!(eval("round(-2.5)"), Value::Number(-2.0));
+    assert_eq!(eval("number('1e3')").string(), "NaN");
+    assert_eq!(eval("substring('12345', 1.5, 2.6)").string(), "234");
+    assert_eq!(eval("translate('bar', 'abc', 'AB')").string(), "BAr");
+    assert_eq!(eval("normalize-space('  a \n b ')").string(), "a b");
+    assert_eq!(
+        eval("concat(name(*[1]), '-', local-name())").string(),
+        "book-library"
+    );
+    assert_eq!(
+        eval("//book[@year = 2007] = 'Second'"),
+        Value::Boolean(true)
+    );
+    assert_eq!(eval("//@year > 2010"), Value::Boolean(true));
+    assert_eq!(eval("//@year < 1990"), Value::Boolean(false));
+    assert!(eval("book[1]/title[lang('en')]").boolean());
+    assert!(!eval("book[2]/title[lang('en')]").boolean());
+
+    // Prefixes must be bound in the context, and the context can reuse the document's bindings
+    let note = XPath::compile("//x:note").unwrap();
+    assert_eq!(
+        note.evaluate(&library),
+        Err(XPathError::UnknownPrefix("x".to_owned()))
+    );
+    let context = Context::new().namespace("x", "urn:extra");
+    let notes = note.evaluate_with_context(&library, &context).unwrap();
+    assert_eq!(notes.string(), "old");
+    let context = Context::new().namespaces(library.namespaces.as_ref().unwrap());
+    assert!(note
+        .evaluate_with_context(&library, &context)
+        .unwrap()
+        .boolean());
+    assert_eq!(names("//note"), Vec::<String>::new());
+
+    // Variables can hold nodes selected by an earlier evaluation
+    let second = XPath::compile("book[2]")
+        .unwrap()
+        .evaluate(&library)
+        .unwrap();
+    let context = Context::new().variable("b", second);
+    let after = XPath::compile("$b/following-sibling::book/@id").unwrap();
+    assert_eq!(
+        after
+            .evaluate_with_context(&library, &context)
+            .unwrap()
+            .string(),
+        "b3"
+    );
+    assert_eq!(
+        after.evaluate(&library),
+        Err(XPathError::UnknownVariable("b".to_owned()))
+    );
+    assert_eq!(
+        XPath::compile("('a')/b").unwrap().evaluate(&library),
+        Err(XPathError::NotANodeSet)
+    );
+}
+
+#[test]
+fn test_xpath_errors() {
+    use xmltree::xpath::{XPath, XPathError};
+
+    match XPath::compile("book[") {
+        Err(XPathError::Syntax { position, .. }) => assert_eq!(position, 5),
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert!(matches!(
+        XPath::compile("//a/"),
+        Err(XPathError::Syntax { .. })
+    ));
+    assert_eq!(
+        XPath::compile("reverse(a)").unwrap_err(),
+        XPathError::UnknownFunction("reverse".to_owned())
+    );
+    assert_eq!(
+        XPath::compile("count()").unwrap_err(),
+        XPathError::WrongArgumentCount("count".to_owned())
+    );
+    assert_eq!(
+        XPath::compile("count(a, b)").unwrap_err(),
+        XPathError::WrongArgumentCount("count".to_owned())
+    );
+
+    // Deeply nested expressions are rejected instead of overflowing the stack
+    let elem = Element::new("a");
+    let nested = [
+        ("(", "1", ")"),
+        ("-", "1", ""),
+        ("a[", "1", "]"),
+        ("string(", "a", ")"),
+        ("", "1", " + 1"),
+        ("", "a", " or a"),
+        ("", "a", " | a"),
+    ];
+    for &(open, inner, close) in nested.iter() {
+        let expr = format!("{}{}{}", open.repeat(60), inner, close.repeat(60));
+        let xpath = XPath::compile(&expr).unwrap();
+        assert!(xpath.evaluate(&elem).is_ok());
+
+        let expr = format!("{}{}{}", open.repeat(100_000), inner, close.repeat(100_000));
+        match XPath::compile(&expr) {
+            Err(XPathError::Syntax { message, .. }) => {
+                assert_eq!(message, "expression is nested too deeply")
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn test_select() {
+    let data = r#"
+        <html xmlns="http://www.w3.org/1999/xhtml">
+            <body>
+                <div id="main" class="content wide">
+                    <p lang="en-US">One</p>
+                    <p class="note">Two</p>
+                    <!-- not an element -->
+                    <span data-x="prefix-middle-suffix">Three</span>
+                    <p>Four</p>
+                </div>
+                <ul><li>a</li><li>b</li><li>c</li><li>d</li><li>e</li></ul>
+            </body>
+        </html>
+    "#;
+    let html = Element::parse(data.as_bytes()).unwrap();
+    let texts = |selector: &str| -> Vec<String> {
+        html.select(selector)
+            .unwrap()
+            .iter()
+            .map(|e| e.get_text().unwrap_or_default().into_owned())
+            .collect()
+    };
+
+    assert_eq!(html.select("html").unwrap().len(), 1);
+    assert_eq!(texts("div p"), vec!["One", "Two", "Four"]);
+    assert_eq!(texts("#main > .note"), vec!["Two"]);
+    assert_eq!(texts("div.content.wide > p:first-child"), vec!["One"]);
+    assert_eq!(texts("p:last-child"), vec!["Four"]);
+    assert_eq!(texts("p:nth-last-child(4)"), vec!["One"]);
+    assert_eq!(texts("div > :last-child"), vec!["Four"]);
+    assert_eq!(texts("p + span"), vec!["Three"]);
+    assert_eq!(texts("p.note ~ p"), vec!["Four"]);
+    assert_eq!(texts("p ~ p"), vec!["Two", "Four"]);
+    assert_eq!(texts("[lang|=en]"), vec!["One"]);
+    assert_eq!(texts("[data-x^=prefix]"), vec!["Three"]);
+    assert_eq!(texts("[data-x$='suffix']"), vec!["Three"]);
+    assert_eq!(texts("[data-x*=\"middle\"]"), vec!["Three"]);
+    assert_eq!(texts("[class~=note]"), vec!["Two"]);
+    assert_eq!(texts("[class=note], [lang]"), vec!["One", "Two"]);
+    assert_eq!(texts("li:nth-child(2n+1)"), vec!["a", "c", "e"]);
+    assert_eq!(texts("li:nth-child(even)"), vec!["b", "d"]);
+    assert_eq!(texts("li:nth-child(-n + 2)"), vec!["a", "b"]);
+    assert_eq!(texts("li:nth-last-child(2)"), vec!["d"]);
+    assert_eq!(texts("body li:nth-child(3)"), vec!["c"]);
+    assert_eq!(texts("ul:only-child li:only-child"), Vec::<String>::new());
+    assert_eq!(html.select("body:only-child").unwrap().len(), 1);
+
+    let first = html.select_first("body *").unwrap().unwrap();
+    assert_eq!(first.name, "div");
+    assert!(html.select_first("table").unwrap().is_none());
+
+    let selector = Selector::parse("ul > li").unwrap();
+    assert_eq!(selector.select(&html).len(), 5);
+
+    for bad in &[
+        "",
+        "div >",
+        "p[",
+        "p[a=]",
+        "li:hover",
+        "li:nth-child(x)",
+        "a,",
+    ] {
+        assert!(Selector::parse(bad).is_err(), "{:?} should not parse", bad);
+    }
+    let err = Selector::parse("div !p").unwrap_err();
+    assert_eq!(err.position, 4);
+
+    // Arguments that could overflow are rejected, and the extremes that remain are harmless
+    let err =
+        Selector::parse("b:nth-child(-9223372036854775808n-9223372036854775808)").unwrap_err();
+    assert_eq!(err.message, "`an+b` argument out of range");
+    assert!(Selector::parse("li:nth-child(2147483648)").is_err());
+    assert!(texts("li:nth-child(-2147483648n-2147483648)").is_empty());
+    assert!(texts("li:nth-last-child(2147483647n+2147483647)").is_empty());
+    assert_eq!(texts("li:nth-child(-2147483648n+1)"), vec!["a"]);
+
+    // The nearest matching ancestor isn't always the one that the rest of the selector matches
+    let nested: Element = "<a><b><x><b><c/></b></x><y/><b/><z/></b></a>"
+        .parse()
+        .unwrap();
+    let names = |selector: &str| -> Vec<&str> {
+        nested
+            .select(selector)
+            .unwrap()
+            .iter()
+            .map(|e| e.name.as_str())
+            .collect()
+    };
+    assert_eq!(names("a > b c"), vec!["c"]);
+    assert_eq!(names("a > b > x b > c"), vec!["c"]);
+    assert_eq!(names("x ~ b + z"), vec!["z"]);
+    assert_eq!(names("a y ~ z, x + y"), vec!["y", "z"]);
+    assert!(names("a > x c").is_empty());
+
+    // Selectors that can't match don't try every combination of ancestors
+    let deep: Element = ("<a>".repeat(2_000) + &"</a>".repeat(2_000))
+        .parse()
+        .unwrap();
+    let selector = Selector::parse("x a a a a a a a a a a a a a a a a").unwrap();
+    assert!(selector.select(&deep).is_empty());
+    let selector = Selector::parse("a a a a a a a a a a a a a a a a > a").unwrap();
+    assert_eq!(selector.select(&deep).len(), 2_000 - 16);
+}
+
+#[test]
+fn test_descendants() {
+    let data = r#"<a><b><d/><e/></b><!-- c --><c><f/></c>text</a>"#;
+    let mut root = Element::parse(data.as_bytes()).unwrap();
+    let names = |iter: &mut dyn Iterator<Item = &Element>| -> String {
+        iter.map(|e| e.name.as_str()).collect::<Vec<_>>().join(",")
+    };
+
+    assert_eq!(names(&mut root.descendants()), "b,d,e,c,f");
+    assert_eq!(names(&mut root.descendants().breadth_first()), "b,c,d,e,f");
+    assert_eq!(names(&mut root.find_all("e")), "e");
+    assert_eq!(names(&mut Element::new("x").descendants()), "");
+
+    root.get_mut_child("c").unwrap().namespace = Some("urn:c".to_owned());
+    assert_eq!(names(&mut root.find_all(("c", "urn:c"))), "c");
+    assert_eq!(names(&mut root.find_all(("c", "urn:other"))), "");
+
+    // Elements added while visiting are visited too
+    let mut descendants = root.descendants_mut();
+    let mut visited = Vec::new();
+    while let Some(elem) = descendants.next() {
+        visited.push(elem.name.clone());
+        if elem.name == "f" {
+            elem.children.push(XMLNode::Element(Element::new("g")));
+        }
+    }
+    assert_eq!(visited, vec!["b", "d", "e", "c", "f", "g"]);
+
+    let mut descendants = root.descendants_mut().breadth_first();
+    let mut visited = Vec::new();
+    while let Some(elem) = descendants.next() {
+        visited.push(elem.name.clone());
+    }
+    assert_eq!(visited, vec!["b", "c", "d", "e", "f", "g"]);
+}
+
+#[test]
+fn test_traverse() {
+    let data = r#"<a><b>x</b><!--y--><c/></a>"#;
+    let root =
+        Element::parse_with_config(data.as_bytes(), ParserConfig::new().ignore_comments(false))
+            .unwrap();
+    let edges: Vec<String> = root
+        .traverse()
+        .map(|edge| match edge {
+            Edge::Start(e) => format!("<{}>", e.name),
+            Edge::End(e) => format!("</{}>", e.name),
+            Edge::Leaf(XMLNode::Text(t)) => t.clone(),
+            Edge::Leaf(XMLNode::Comment(c)) => format!("#{}", c),
+            Edge::Leaf(node) => panic!("unexpected node {:?}", node),
+        })
+        .collect();
+    assert_eq!(edges.concat(), "<a><b>x</b>#y<c></c></a>");
+}
+
+#[test]
+fn test_canonical() {
+    fn canonical(elem: &Element, options: &CanonicalOptions) -> String {
+        let mut out = Vec::new();
+        elem.write_canonical(&mut out, options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    // Example 3.3 of the Canonical XML 1.0 recommendation
+    let data = r#"<doc>
+   <e1   />
+   <e2   ></e2>
+   <e3   name = "elem3"   id="elem3"   />
+   <e4   name="elem4"   id="elem4"   ></e4>
+   <e5 a:attr="out" b:attr="sorted" attr2="all" attr="I'm"
+      xmlns:b="http://www.ietf.org"
+      xmlns:a="http://www.w3.org"
+      xmlns="http://example.org"/>
+   <e6 xmlns="" xmlns:a="http://www.w3.org">
+      <e7 xmlns="http://www.ietf.org">
+         <e8 xmlns="" xmlns:a="http://www.w3.org">
+            <e9 xmlns="" xmlns:a="http://www.ietf.org"/>
+         </e8>
+      </e7>
+   </e6>
+</doc>"#;
+    let expected = r#"<doc>
+   <e1></e1>
+   <e2></e2>
+   <e3 id="elem3" name="elem3"></e3>
+   <e4 id="elem4" name="elem4"></e4>
+   <e5 xmlns="http://example.org" xmlns:a="http://www.w3.org" xmlns:b="http://www.ietf.org" attr="I'm" attr2="all" b:attr="sorted" a:attr="out"></e5>
+   <e6 xmlns:a="http://www.w3.org">
+      <e7 xmlns="http://www.ietf.org">
+         <e8 xmlns="">
+            <e9 xmlns:a="http://www.ietf.org"></e9>
+         </e8>
+      </e7>
+   </e6>
+</doc>"#;
+    let options = ParseOptions::new().preserve_whitespace(true);
+    let doc =
+        Element::parse_with_options(data.as_bytes(), ParserConfig::new(), options.clone()).unwrap();
+    assert_eq!(canonical(&doc, &CanonicalOptions::new()), expected);
+
+    // The example from section 2.2 of Exclusive XML Canonicalization, canonicalizing `n1:elem2`
+    let data = r#"<n0:local xmlns:n0="foo:bar" xmlns:n3="ftp://example.org">
+  <n1:elem2 xmlns:n1="http://example.net" xml:lang="en">
+    <n3:stuff xmlns:n3="ftp://example.org"/>
+  </n1:elem2>
+</n0:local>"#;
+    let doc = Element::parse_with_options(data.as_bytes(), ParserConfig::new(), options).unwrap();
+    let elem2 = doc.get_child("elem2").unwrap();
+    assert_eq!(
+        canonical(elem2, &CanonicalOptions::new()),
+        "<n1:elem2 xmlns:n0=\"foo:bar\" xmlns:n1=\"http://example.net\" \
+         xmlns:n3=\"ftp://example.org\" xml:lang=\"en\">\n    \
+         <n3:stuff></n3:stuff>\n  </n1:elem2>"
+    );
+    assert_eq!(
+        canonical(elem2, &CanonicalOptions::new().exclusive(true)),
+        "<n1:elem2 xmlns:n1=\"http://example.net\" xml:lang=\"en\">\n    \
+         <n3:stuff xmlns:n3=\"ftp://example.org\"></n3:stuff>\n  </n1:elem2>"
+    );
+    let options = CanonicalOptions::new()
+        .exclusive(true)
+        .inclusive_prefixes(vec!["n0"]);
+    assert_eq!(
+        canonical(elem2, &options),
+        "<n1:elem2 xmlns:n0=\"foo:bar\" xmlns:n1=\"http://example.net\" xml:lang=\"en\">\n    \
+         <n3:stuff xmlns:n3=\"ftp://example.org\"></n3:stuff>\n  </n1:elem2>"
+    );
+
+    // Escaping, CDATA, comments and processing instructions
+    let data = "<!-- head --><?pi-without-data?><a>&lt;&amp;&gt;<![CDATA[<&>]]><!-- c --><?pi  x ?></a><!-- tail -->";
+    let doc = Document::parse(data.as_bytes()).unwrap();
+    let mut out = Vec::new();
+    doc.write_canonical(&mut out, &CanonicalOptions::new())
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "<?pi-without-data?>\n<a>&lt;&amp;&gt;&lt;&amp;&gt;<?pi x ?></a>"
+    );
+    let mut out = Vec::new();
+    doc.write_canonical(&mut out, &CanonicalOptions::new().with_comments(true))
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "<!-- head -->\n<?pi-without-data?>\n<a>&lt;&amp;&gt;&lt;&amp;&gt;<!-- c --><?pi x ?></a>\n<!-- tail -->"
+    );
+
+    // Trees built in code get declarations for the prefixes they use, and the output doesn't
+    // depend on the order of the attributes
+    let mut elem = Element::new("root");
+    elem.prefix = Some("p".to_owned());
+    elem.namespace = Some("urn:p".to_owned());
+    elem.attributes
+        .insert("z".into(), "tab\there \"quoted\"\r\n<&>".to_owned());
+    elem.attributes
+        .insert(AttributeName::qualified("a", "urn:q", "q"), "1".to_owned());
+    elem.attributes.insert("b".into(), "2".to_owned());
+    elem.children
+        .push(XMLNode::Text("carriage\rreturn".to_owned()));
+    let mut child = Element::new("child");
+    child.attributes.insert("y".into(), "".to_owned());
+    elem.children.push(XMLNode::Element(child));
+    assert_eq!(
+        canonical(&elem, &CanonicalOptions::new().exclusive(true)),
+        "<p:root xmlns:p=\"urn:p\" xmlns:q=\"urn:q\" b=\"2\" \
+         z=\"tab&#x9;here &quot;quoted&quot;&#xD;&#xA;&lt;&amp;>\" q:a=\"1\">\
+         carriage&#xD;return<child y=\"\"></child></p:root>"
+    );
+
+    assert_eq!(
+        CanonicalOptions::new().exclusive(true).algorithm(),
+        "http://www.w3.org/2001/10/xml-exc-c14n#"
+    );
+}
+
+#[test]
+fn test_format() {
+    fn parse(data: &str) -> Element {
+        let options = ParseOptions::new().preserve_whitespace(true);
+        let config = ParserConfig::new().ignore_comments(false);
+        Element::parse_with_options(data.as_bytes(), config, options).unwrap()
+    }
+    fn format(elem: &Element, options: FormatOptions) -> String {
+        let mut out = Vec::new();
+        let options = options.write_document_declaration(false);
+        elem.write_formatted(&mut out, &options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    let data = r#"<config xmlns="urn:c">
+        <server name="a"><port>80</port></server>
+    <!--logging--><log>stdout</log><empty>  </empty>
+  <p>Some <b>mixed</b> text</p><pre xml:space="preserve"> <x/> </pre></config>"#;
+    let formatted = format(&parse(data), FormatOptions::new());
+    assert_eq!(
+        formatted,
+        r#"<config xmlns="urn:c">
+  <server name="a">
+    <port>80</port>
+  </server>
+  <!--logging-->
+  <log>stdout</log>
+  <empty />
+  <p>Some <b>mixed</b> text</p>
+  <pre xml:space="preserve"> <x /> </pre>
+</config>"#
+    );
+    // Formatting is idempotent, and doesn't change the tree beyond whitespace between elements
+    assert_eq!(format(&parse(&formatted), FormatOptions::new()), formatted);
+    assert!(diff_with_options(
+        &parse(data),
+        &parse(&formatted),
+        &DiffOptions::new().ignore_whitespace(true)
+    )
+    .is_empty());
+
+    let options = FormatOptions::new()
+        .indent_string("\t")
+        .inline_short_elements(true)
+        .normalize_empty_elements(false);
+    assert_eq!(
+        format(&parse(data), options),
+        "<config xmlns=\"urn:c\">\n\t<server name=\"a\"><port>80</port></server>\n\t<!--logging-->\n\t\
+         <log>stdout</log>\n\t<empty></empty>\n\t<p>Some <b>mixed</b> text</p>\n\t\
+         <pre xml:space=\"preserve\"> <x></x> </pre>\n</config>"
+    );
+
+    // Long start tags and text
+    let mut elem = Element::new("element");
+    elem.attributes
+        .insert("second".into(), "a value that is long".to_owned());
+    elem.attributes
+        .insert("first".into(), "another long value".to_owned());
+    let options = FormatOptions::new().max_width(40).sort_attributes(true);
+    assert_eq!(
+        format(&elem, options.clone()),
+        r#"<element first="another long value" second="a value that is long" />"#
+    );
+    assert_eq!(
+        format(&elem, options.clone().wrap_attributes(true)),
+        "<element\n  first=\"another long value\"\n  second=\"a value that is long\" />"
+    );
+    assert_eq!(
+        format(
+            &elem,
+            options.clone().wrap_attributes(true).align_attributes(true)
+        ),
+        "<element first=\"another long value\"\n         second=\"a value that is long\" />"
+    );
+    let text = parse("<a>a text that is longer than the maximum width</a>");
+    assert_eq!(
+        format(&text, options.clone()),
+        "<a>a text that is longer than the maximum width</a>"
+    );
+    assert_eq!(
+        format(&text, options.collapse_text_only_elements(false)),
+        "<a>\n  a text that is longer than the maximum width\n</a>"
+    );
+
+    // Namespaces are declared where they are needed, also in trees built in code
+    let mut root = Element::new("root");
+    root.namespace = Some("urn:r".to_owned());
+    let mut child = Element::new("child");
+    child.prefix = Some("x".to_owned());
+    child.namespace = Some("urn:x".to_owned());
+    child.attributes.insert(
+        AttributeName::qualified("attr", "urn:y", "y"),
+        "1".to_owned(),
+    );
+    let mut grandchild = Element::new("plain");
+    grandchild.namespace = Some("urn:r".to_owned());
+    child.children.push(XMLNode::Element(grandchild));
+    root.children.push(XMLNode::Element(child));
+    assert_eq!(
+        format(&root, FormatOptions::new()),
+        r#"<root xmlns="urn:r">
+  <x:child xmlns:x="urn:x" xmlns:y="urn:y" y:attr="1">
+    <plain />
+  </x:child>
+</root>"#
+    );
+
+    // Documents keep their declaration and the nodes around the root element
+    let doc = Document::parse(
+        r#"<?xml version="1.0" encoding="UTF-8"?><!-- head --><?pi data?><root><a/></root><!-- tail -->"#
+            .as_bytes(),
+    )
+    .unwrap();
+    let mut out = Vec::new();
+    doc.write_formatted(&mut out, &FormatOptions::new())
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!-- head -->\n<?pi data?>\n<root>\n  <a />\n</root>\n<!-- tail -->\n"
+    );
+}
+
+#[test]
+fn test_display() {
+    let elem: Element = r#"<list><item id="1">one &amp; two</item><!-- c --><item/></list>"#
+        .parse()
+        .unwrap();
+    assert_eq!(
+        elem.to_string(),
+        r#"<list><item id="1">one &amp; two</item><!-- c --><item /></list>"#
+    );
+    assert_eq!(
+        format!("{:#}", elem),
+        "<list>\n  <item id=\"1\">one &amp; two</item>\n  <!-- c -->\n  <item />\n</list>"
+    );
+    assert_eq!(elem.to_string().parse::<Element>().unwrap(), elem);
+    assert!("<a><b></a>".parse::<Element>().is_err());
+
+    // Nodes other than elements can be written on their own
+    assert_eq!(XMLNode::Text("a < b".to_owned()).to_string(), "a &lt; b");
+    assert_eq!(
+        XMLNode::CData("a < b".to_owned()).to_string(),
+        "<![CDATA[a < b]]>"
+    );
+    assert_eq!(
+        XMLNode::ProcessingInstruction("pi".to_owned(), Some("data".to_owned())).to_string(),
+        "<?pi data?>"
+    );
+    assert_eq!(elem.children[1].to_string(), "<!-- c -->");
+    assert_eq!(
+        elem.children[0].to_string(),
+        r#"<item id="1">one &amp; two</item>"#
+    );
+
+    // And so can sequences of nodes, even with several elements
+    let p: Element = "<p>Some <b>bold</b> and <i>italic</i> text</p>"
+        .parse()
+        .unwrap();
+    let fragment = Fragment(&p.children);
+    assert_eq!(
+        fragment.to_string(),
+        "Some <b>bold</b> and <i>italic</i> text"
+    );
+    assert_eq!(
+        format!("{:#}", Fragment(&elem.children)),
+        "<item id=\"1\">one &amp; two</item>\n<!-- c -->\n<item />"
+    );
+    // Mixed content is written as it is, like it would be inside its element
+    assert_eq!(format!("{:#}", fragment), fragment.to_string());
+    let p: Element = "<p>Some <b>bold <i>and</i>  italic</b> text</p>"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        format!("{:#}", Fragment(&p.children)),
+        "Some <b>bold <i>and</i>  italic</b> text"
+    );
+    let mut out = Vec::new();
+    let config = EmitterConfig::new().write_document_declaration(true);
+    fragment.write_with_config(&mut out, config).unwrap();
+    assert_eq!(out, b"Some <b>bold</b> and <i>italic</i> text");
+    assert_eq!(Fragment(&[]).to_string(), "");
+}
+
+#[test]
+fn test_diff() {
+    fn parse(data: &str) -> Element {
+        let options = ParseOptions::new().preserve_whitespace(true);
+        let config = ParserConfig::new().ignore_comments(false);
+        Element::parse_with_options(data.as_bytes(), config, options).unwrap()
+    }
+    fn describe(changes: &[Change]) -> Vec<String> {
+        changes.iter().map(|c| c.to_string()).collect()
+    }
+
+    let old = parse(
+        r#"<config xmlns="urn:c" version="1">
+  <server name="a" port="80"/>
+  <server name="b" port="80"/>
+  <!-- logging -->
+  <log level="info">stdout</log>
+</config>"#,
+    );
+    assert!(diff(&old, &old.clone()).is_empty());
+
+    // Prefixes don't matter, only namespace URIs
+    let prefixed = parse(
+        r#"<c:config xmlns:c="urn:c" version="1">
+  <c:server port="80" name="a"/>
+  <c:server name="b" port="80"/>
+  <!-- logging -->
+  <c:log level="info">stdout</c:log>
+</c:config>"#,
+    );
+    assert!(diff(&old, &prefixed).is_empty());
+
+    let new = parse(
+        r#"<config xmlns="urn:c" version="2">
+  <server name="a" port="8080" tls="yes"/>
+  <server name="b" port="80"/>
+  <server name="c" port="80"/>
+  <!-- logging -->
+  <log>stderr</log>
+</config>"#,
+    );
+    let changes = diff(&old, &new);
+    assert_eq!(
+        describe(&changes),
+        [
+            r#"changed /config/@version from "1" to "2""#,
+            r#"changed /config/server[1]/@port from "80" to "8080""#,
+            r#"added /config/server[1]/@tls = "yes""#,
+            "inserted /config/server[3]",
+            "inserted /config/text()[4]",
+            r#"removed /config/log/@level = "info""#,
+            r#"changed /config/log/text() from "stdout" to "stderr""#,
+        ]
+    );
+    match changes[3].kind {
+        ChangeKind::Inserted(XMLNode::Element(ref e)) => {
+            assert_eq!(e.get_attribute("name"), Some("c"))
+        }
+        ref kind => panic!("unexpected {:?}", kind),
+    }
+
+    // Reordering identical elements is a move
+    let reordered = parse(
+        r#"<config xmlns="urn:c" version="1">
+  <server name="b" port="80"/>
+  <server name="a" port="80"/>
+  <!-- logging -->
+  <log level="info">stdout</log>
+</config>"#,
+    );
+    let changes = diff(&old, &reordered);
+    assert_eq!(
+        describe(&changes),
+        ["moved /config/server[2] from index 1 to 3"]
+    );
+
+    // Whitespace and comments
+    let compact = parse(
+        r#"<config xmlns="urn:c" version="1"><server name="a" port="80"/><server name="b" port="80"/><log level="info"> stdout </log></config>"#,
+    );
+    assert_eq!(diff(&old, &compact).len(), 7);
+    let options = DiffOptions::new()
+        .ignore_whitespace(true)
+        .ignore_comments(true);
+    assert!(diff_with_options(&old, &compact, &options).is_empty());
+    let options = DiffOptions::new().ignore_whitespace(true);
+    assert_eq!(
+        describe(&diff_with_options(&old, &compact, &options)),
+        ["removed /config/comment()"]
+    );
+
+    // Long lists of children only differ where they changed
+    let mut long = Element::new("list");
+    for i in 0..5000 {
+        let mut item = Element::new("item");
+        item.attributes.insert("n".into(), i.to_string());
+        long.children.push(XMLNode::Element(item));
+    }
+    let mut changed = long.clone();
+    changed.children.remove(1000);
+    changed.children[3000]
+        .as_mut_element()
+        .unwrap()
+        .attributes
+        .insert("n".into(), "x".to_owned());
+    changed.children.insert(4000, XMLNode::Text("t".to_owned()));
+    assert_eq!(
+        describe(&diff(&long, &changed)),
+        [
+            "removed /list/item[1001]",
+            r#"changed /list/item[3001]/@n from "3001" to "x""#,
+            "inserted /list/text()",
+        ]
+    );
+
+    // A different root element replaces the whole tree
+    let other = Element::new("other");
+    let changes = diff(&old, &other);
+    assert_eq!(describe(&changes), ["removed /config", "inserted /other"]);
+
+    #[cfg(feature = "attribute-order")]
+    {
+        let a = parse(r#"<a x="1" y="2"/>"#);
+        let b = parse(r#"<a y="2" x="1"/>"#);
+        assert!(diff(&a, &b).is_empty());
+        let options = DiffOptions::new().ignore_attribute_order(false);
+        assert_eq!(
+            describe(&diff_with_options(&a, &b, &options)),
+            ["reordered attributes of /a"]
+        );
+    }
+}
+
+#[test]
+fn test_patch() {
+    fn parse(data: &str) -> Element {
+        let options = ParseOptions::new().preserve_whitespace(true);
+        let config = ParserConfig::new().ignore_comments(false);
+        Element::parse_with_options(data.as_bytes(), config, options).unwrap()
+    }
+    fn write(elem: &Element) -> String {
+        let mut out = Vec::new();
+        let config = EmitterConfig::new().write_document_declaration(false);
+        elem.write_with_config(&mut out, config).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+    fn apply(doc: &str, patch: &str) -> Result<String, PatchError> {
+        let mut doc = parse(doc);
+        Patch::parse(patch.as_bytes())?.apply(&mut doc)?;
+        Ok(write(&doc))
+    }
+    fn error(doc: &str, patch: &str) -> PatchErrorKind {
+        apply(doc, patch).unwrap_err().kind
+    }
+
+    let doc = r#"<doc><note id="a">old</note><!--c--><?pi data?></doc>"#;
+
+    // Adding children, siblings, attributes and namespaces
+    assert_eq!(
+        apply(doc, r#"<diff><add sel="doc"><x/></add></diff>"#).unwrap(),
+        r#"<doc><note id="a">old</note><!-- c --><?pi data?><x /></doc>"#
+    );
+    assert_eq!(
+        apply(
+            doc,
+            r#"<diff><add sel="doc" pos="prepend"><x/>t</add></diff>"#
+        )
+        .unwrap(),
+        r#"<doc><x />t<note id="a">old</note><!-- c --><?pi data?></doc>"#
+    );
+    assert_eq!(
+        apply(
+            doc,
+            r#"<diff><add sel="doc/note" pos="before"><x/></add></diff>"#
+        )
+        .unwrap(),
+        r#"<doc><x /><note id="a">old</note><!-- c --><?pi data?></doc>"#
+    );
+    assert_eq!(
+        apply(
+            doc,
+            r#"<diff><add sel="doc/comment()" pos="after"><y/></add></diff>"#
+        )
+        .unwrap(),
+        r#"<doc><note id="a">old</note><!-- c --><y /><?pi data?></doc>"#
+    );
+    assert_eq!(
+        apply(doc, r#"<diff><add sel="doc" type="@lang">en</add></diff>"#).unwrap(),
+        r#"<doc lang="en"><note id="a">old</note><!-- c --><?pi data?></doc>"#
+    );
+    assert_eq!(
+        apply(
+            doc,
+            r#"<diff xmlns:p="urn:p"><add sel="doc" type="namespace::p">urn:p</add><add sel="doc" type="@p:q">1</add></diff>"#
+        )
+        .unwrap(),
+        r#"<doc xmlns:p="urn:p" p:q="1"><note id="a">old</note><!-- c --><?pi data?></doc>"#
+    );
+
+    // Replacing nodes and values
+    assert_eq!(
+        apply(
+            doc,
+            r#"<diff>
+                 <replace sel="doc/note/text()">new</replace>
+                 <replace sel="doc/note/@id">b</replace>
+                 <replace sel="doc/comment()"><!--d--></replace>
+               </diff>"#
+        )
+        .unwrap(),
+        r#"<doc><note id="b">new</note><!-- d --><?pi data?></doc>"#
+    );
+    assert_eq!(
+        apply(
+            doc,
+            r#"<diff><replace sel="doc/note"> <other/> </replace></diff>"#
+        )
+        .unwrap(),
+        r#"<doc><other /><!-- c --><?pi data?></doc>"#
+    );
+
+    // Removing nodes, optionally with the whitespace around them
+    assert_eq!(
+        apply(
+            doc,
+            r#"<diff><remove sel="doc/note/@id"/><remove sel="doc/processing-instruction('pi')"/></diff>"#
+        )
+        .unwrap(),
+        r#"<doc><note>old</note><!-- c --></doc>"#
+    );
+    let indented = "<doc>\n  <a/>\n  <b/>\n</doc>";
+    assert_eq!(
+        apply(indented, r#"<diff><remove sel="doc/a" ws="after"/></diff>"#).unwrap(),
+        "<doc>\n  <b />\n</doc>"
+    );
+    assert_eq!(
+        apply(indented, r#"<diff><remove sel="doc/b" ws="both"/></diff>"#).unwrap(),
+        "<doc>\n  <a /></doc>"
+    );
+
+    // Selectors resolve prefixes and the default namespace with the patch's declarations
+    let namespaced = r#"<doc xmlns="urn:d" xmlns:e="urn:e"><e:item/></doc>"#;
+    assert_eq!(
+        apply(
+            namespaced,
+            r#"<diff xmlns:x="urn:e"><add sel="*/x:item" type="@n">1</add></diff>"#
+        )
+        .unwrap(),
+        r#"<doc xmlns="urn:d" xmlns:e="urn:e"><e:item n="1" /></doc>"#
+    );
+    assert_eq!(
+        apply(
+            namespaced,
+            r#"<diff xmlns="urn:d"><add sel="doc"><new/></add></diff>"#
+        )
+        .unwrap(),
+        r#"<doc xmlns="urn:d" xmlns:e="urn:e"><e:item /><new /></doc>"#
+    );
+
+    // Errors
+    assert_eq!(
+        error(doc, r#"<diff><add sel="missing"><x/></add></diff>"#),
+        PatchErrorKind::UnlocatedNode
+    );
+    assert_eq!(
+        error(doc, r#"<diff><remove sel="doc/node()"/></diff>"#),
+        PatchErrorKind::UnlocatedNode
+    );
+    assert_eq!(
+        error(doc, r#"<diff><remove sel="doc"/></diff>"#),
+        PatchErrorKind::InvalidRootElementOperation
+    );
+    assert_eq!(
+        error(doc, r#"<diff><add sel="doc" pos="after"><x/></add></diff>"#),
+        PatchErrorKind::InvalidRootElementOperation
+    );
+    assert_eq!(
+        error(
+            doc,
+            r#"<diff><add sel="doc/note" type="@id">b</add></diff>"#
+        ),
+        PatchErrorKind::InvalidAttributeValue
+    );
+    assert_eq!(
+        error(
+            doc,
+            r#"<diff><add sel="doc" pos="middle"><x/></add></diff>"#
+        ),
+        PatchErrorKind::InvalidAttributeValue
+    );
+    assert_eq!(
+        error(
+            doc,
+            r#"<diff><replace sel="doc/note">text</replace></diff>"#
+        ),
+        PatchErrorKind::InvalidNodeTypes
+    );
+    assert_eq!(
+        error(doc, r#"<diff><remove sel="y:note"/></diff>"#),
+        PatchErrorKind::InvalidNamespacePrefix
+    );
+    assert_eq!(
+        error(doc, r#"<diff><remove sel="doc/note" ws="before"/></diff>"#),
+        PatchErrorKind::InvalidWhitespaceDirective
+    );
+    assert_eq!(
+        error(doc, r#"<diff><rename sel="doc"/></diff>"#),
+        PatchErrorKind::InvalidPatchDirective
+    );
+    assert_eq!(
+        error(doc, r#"<diff><remove/></diff>"#),
+        PatchErrorKind::InvalidDiffFormat
+    );
+
+    // A failed patch leaves the tree unchanged
+    let mut tree = parse(doc);
+    let patch =
+        Patch::parse(r#"<diff><remove sel="doc/note"/><remove sel="doc/note"/></diff>"#.as_bytes())
+            .unwrap();
+    let err = patch.apply(&mut tree).unwrap_err();
+    assert_eq!(err.kind, PatchErrorKind::UnlocatedNode);
+    assert_eq!(err.operation, Some(1));
+    assert_eq!(tree, parse(doc));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() {
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Rect { w: u32, h: u32 },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Label {
+        #[serde(rename = "@lang")]
+        lang: Option<String>,
+        #[serde(rename = "$text")]
+        text: String,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Drawing {
+        #[serde(rename = "@id")]
+        id: u32,
+        #[serde(rename = "@visible")]
+        visible: bool,
+        #[serde(rename = "@tags", default)]
+        tags: Vec<String>,
+        title: Option<String>,
+        #[serde(rename = "label", default)]
+        labels: Vec<Label>,
+        #[serde(rename = "shape", default)]
+        shapes: Vec<Shape>,
+        properties: BTreeMap<String, i32>,
+    }
+
+    let data = r#"
+        <drawing id="7" visible="true" tags="a b">
+            <label lang="en">Hello</label>
+            <shape>Empty</shape>
+            <label>Plain</label>
+            <shape><Circle>1.5</Circle></shape>
+            <shape><Rect><w>2</w><h>3</h></Rect></shape>
+            <properties><x>1</x><y>-2</y></properties>
+            <ignored/>
+        </drawing>
+    "#;
+    let elem = Element::parse(data.as_bytes()).unwrap();
+    let drawing: Drawing = from_element(&elem).unwrap();
+    let expected = Drawing {
+        id: 7,
+        visible: true,
+        tags: vec!["a".to_owned(), "b".to_owned()],
+        title: None,
+        labels: vec![
+            Label {
+                lang: Some("en".to_owned()),
+                text: "Hello".to_owned(),
+            },
+            Label {
+                lang: None,
+                text: "Plain".to_owned(),
+            },
+        ],
+        shapes: vec![Shape::Empty, Shape::Circle(1.5), Shape::Rect { w: 2, h: 3 }],
+        properties: vec![("x".to_owned(), 1), ("y".to_owned(), -2)]
+            .into_iter()
+            .collect(),
+    };
+    assert_eq!(drawing, expected);
+
+    let elem = to_element(&expected).unwrap();
+    assert_eq!(elem.name, "Drawing");
+    assert_eq!(elem.get_attribute("tags"), Some("a b"));
+    assert!(elem.get_child("title").is_none());
+    assert_eq!(elem.find_all("label").count(), 2);
+    let rect = elem.find_all("Rect").next().unwrap();
+    assert_eq!(rect.get_child("w").unwrap().get_text().unwrap(), "2");
+    let round_trip: Drawing = from_element(&elem).unwrap();
+    assert_eq!(round_trip, expected);
+
+    let elem = Element::parse(r#"<drawing id="x" visible="true"/>"#.as_bytes()).unwrap();
+    let err = from_element::<Drawing>(&elem).unwrap_err();
+    assert_eq!(err.to_string(), "invalid integer: `x`");
+    assert!(to_element(&5).is_err());
+
+    // Attributes in a namespace are keyed with their prefix, so they don't collide
+    let data = r#"<a xmlns:x="urn:x" href="1" x:href="2"/>"#;
+    let elem = Element::parse(data.as_bytes()).unwrap();
+    let attributes: BTreeMap<String, String> = from_element(&elem).unwrap();
+    let keys: Vec<_> = attributes.keys().map(String::as_str).collect();
+    assert_eq!(keys, vec!["@href", "@x:href"]);
+    assert_eq!(attributes["@x:href"], "2");
+
+    let mut elem = Element::new("a");
+    let mut name = AttributeName::local("href");
+    name.namespace = Some("urn:x".to_owned());
+    elem.attributes.insert(name, "3".to_owned());
+    let attributes: BTreeMap<String, String> = from_element(&elem).unwrap();
+    assert_eq!(attributes["@{urn:x}href"], "3");
+
+    // Namespaced attributes survive a round trip through writing and parsing
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Link {
+        #[serde(rename = "@xmlns:xl")]
+        xl: String,
+        #[serde(rename = "@xl:href")]
+        href: String,
+        #[serde(rename = "@{urn:y}id")]
+        id: String,
+        #[serde(rename = "@xml:lang")]
+        lang: String,
+    }
+    let data = r#"<link xmlns:xl="urn:xl" xmlns:y="urn:y" xl:href="a" y:id="b" xml:lang="en"/>"#;
+    let link: Link = from_element(&Element::parse(data.as_bytes()).unwrap()).unwrap();
+    let expected = Link {
+        xl: "urn:xl".to_owned(),
+        href: "a".to_owned(),
+        id: "b".to_owned(),
+        lang: "en".to_owned(),
+    };
+    assert_eq!(link, expected);
+    let elem = to_element(&link).unwrap();
+    assert_eq!(elem.get_attribute_ns("href", Some("urn:xl")), Some("a"));
+    assert_eq!(elem.get_attribute_ns("id", Some("urn:y")), Some("b"));
+    let written = elem.to_string();
+    let parsed = Element::parse(written.as_bytes()).unwrap();
+    assert_eq!(from_element::<Link>(&parsed).unwrap(), expected);
+
+    #[derive(Serialize)]
+    struct Undeclared {
+        #[serde(rename = "@xl:href")]
+        href: String,
+    }
+    let err = to_element(&Undeclared {
+        href: "a".to_owned(),
+    })
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "undeclared namespace prefix `xl` in `@xl:href`"
+    );
+}
+
+#[cfg(feature = "derive")]
+#[test]
+fn test_derive() {
+    #[derive(Debug, PartialEq, FromElement, IntoElement)]
+    struct Item {
+        #[xml(attr = "sku")]
+        sku: String,
+        #[xml(attr = "qty")]
+        quantity: Option<u32>,
+        #[xml(text)]
+        description: String,
+    }
+
+    #[derive(Debug, PartialEq, FromElement, IntoElement)]
+    #[xml(name = "order", namespace = "urn:shop")]
+    struct Order {
+        #[xml(attr = "id")]
+        id: u64,
+        #[xml(child = "customer", namespace = "urn:shop")]
+        customer: String,
+        #[xml(child = "item", namespace = "urn:shop")]
+        items: Vec<Item>,
+        #[xml(child = "note", namespace = "urn:shop")]
+        note: Option<String>,
+    }
+
+    let data = r#"
+        <order xmlns="urn:shop" id="12">
+            <customer>Ada</customer>
+            <item sku="a-1" qty="2">Widget</item>
+            <item sku="b-7">Gadget</item>
+        </order>
+    "#;
+    let elem = Element::parse(data.as_bytes()).unwrap();
+    let order = Order::from_element(&elem).unwrap();
+    let expected = Order {
+        id: 12,
+        customer: "Ada".to_owned(),
+        items: vec![
+            Item {
+                sku: "a-1".to_owned(),
+                quantity: Some(2),
+                description: "Widget".to_owned(),
+            },
+            Item {
+                sku: "b-7".to_owned(),
+                quantity: None,
+                description: "Gadget".to_owned(),
+            },
+        ],
+        note: None,
+    };
+    assert_eq!(order, expected);
+
+    let elem = expected.into_element();
+    assert_eq!(elem.name, "order");
+    assert_eq!(elem.namespace.as_deref(), Some("urn:shop"));
+    assert_eq!(elem.find_all(("item", "urn:shop")).count(), 2);
+    let mut buf = Vec::new();
+    elem.write(&mut buf).unwrap();
+    let reparsed = Element::parse(buf.as_slice()).unwrap();
+    assert_eq!(Order::from_element(&reparsed).unwrap().items.len(), 2);
+
+    let data = r#"<order xmlns="urn:shop" id="1"><customer/><item sku="x" qty="lots"/></order>"#;
+    let err = Order::from_element(&Element::parse(data.as_bytes()).unwrap()).unwrap_err();
+    assert_eq!(err.path, "item[1]/@qty");
+    assert!(matches!(
+        err.kind,
+        FromElementErrorKind::InvalidValue { .. }
+    ));
+
+    let data = r#"<order id="1"><customer/></order>"#;
+    let err = Order::from_element(&Element::parse(data.as_bytes()).unwrap()).unwrap_err();
+    assert_eq!(err.to_string(), "missing element `customer`");
+
+    let data = r#"<order xmlns="urn:shop"><customer/></order>"#;
+    let err = Order::from_element(&Element::parse(data.as_bytes()).unwrap()).unwrap_err();
+    assert_eq!(err.kind, FromElementErrorKind::MissingAttribute);
+    assert_eq!(err.path, "@id");
+
+    // Attributes without a namespace in the mapping don't match namespaced ones
+    let data = r#"<order xmlns="urn:shop" xmlns:s="urn:shop" s:id="1"><customer/></order>"#;
+    let err = Order::from_element(&Element::parse(data.as_bytes()).unwrap()).unwrap_err();
+    assert_eq!(err.kind, FromElementErrorKind::MissingAttribute);
+
+    // Namespaced attributes get a prefix declared for them when written
+    #[derive(Debug, Clone, PartialEq, FromElement, IntoElement)]
+    struct Part {
+        #[xml(attr = "ref", namespace = "urn:parts")]
+        reference: String,
+        #[xml(attr = "lang", namespace = "http://www.w3.org/XML/1998/namespace")]
+        lang: Option<String>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, FromElement, IntoElement)]
+    #[xml(name = "bom", namespace = "urn:shop")]
+    struct Bom {
+        #[xml(attr = "rev", namespace = "urn:parts")]
+        revision: u32,
+        #[xml(child = "part", namespace = "urn:shop")]
+        parts: Vec<Part>,
+    }
+
+    let bom = Bom {
+        revision: 3,
+        parts: vec![
+            Part {
+                reference: "p-1".to_owned(),
+                lang: Some("en".to_owned()),
+            },
+            Part {
+                reference: "p-2".to_owned(),
+                lang: None,
+            },
+        ],
+    };
+    let mut buf = Vec::new();
+    bom.clone().into_element().write(&mut buf).unwrap();
+    let reparsed = Element::parse(buf.as_slice()).unwrap();
+    assert_eq!(
+        reparsed.get_attribute_ns("rev", Some("urn:parts")),
+        Some("3")
+    );
+    assert_eq!(Bom::from_element(&reparsed).unwrap(), bom);
+}
+
+#[cfg(feature = "xmldsig")]
+#[test]
+fn test_xmldsig() {
+    use xmltree::xmldsig::{self, SignOptions, SignatureError, SigningKey, VerifyingKey};
+
+    fn reparse(elem: &Element) -> Element {
+        let mut out = Vec::new();
+        elem.write(&mut out).unwrap();
+        let options = ParseOptions::new().preserve_whitespace(true);
+        Element::parse_with_options(&out[..], ParserConfig::new(), options).unwrap()
+    }
+
+    let data = r#"<saml:Assertion xmlns:saml="urn:oasis:names:tc:SAML:2.0:assertion" ID="_a1">
+  <saml:Issuer>https://idp.example.org</saml:Issuer>
+  <saml:Subject><saml:NameID>alice</saml:NameID></saml:Subject>
+</saml:Assertion>"#;
+    let options = ParseOptions::new().preserve_whitespace(true);
+    let assertion =
+        Element::parse_with_options(data.as_bytes(), ParserConfig::new(), options).unwrap();
+
+    let rsa = SigningKey::from_pkcs8_pem(include_str!("data/dsig-rsa.pem")).unwrap();
+    let rsa_public =
+        VerifyingKey::from_public_key_pem(include_str!("data/dsig-rsa.pub.pem")).unwrap();
+    let ec = SigningKey::from_pkcs8_pem(include_str!("data/dsig-ec.pem")).unwrap();
+    let ec_public =
+        VerifyingKey::from_public_key_pem(include_str!("data/dsig-ec.pub.pem")).unwrap();
+    assert_eq!(rsa.verifying_key(), rsa_public);
+    assert_eq!(ec.verifying_key(), ec_public);
+
+    // A SAML signature references the assertion by ID and follows the issuer
+    let options = SignOptions::new().id_attribute("ID").position(3);
+    let mut signed = assertion.clone();
+    xmldsig::sign_with_options(&mut signed, &rsa, &options).unwrap();
+    let signature = signed.children[3].as_element().unwrap();
+    assert!(signature.matches(("Signature", xmldsig::NAMESPACE)));
+    let reference = signature
+        .get_child("SignedInfo")
+        .and_then(|e| e.get_child("Reference"))
+        .unwrap();
+    assert_eq!(reference.get_attribute("URI"), Some("#_a1"));
+
+    let signed = reparse(&signed);
+    assert_eq!(xmldsig::verify(&signed, &rsa_public), Ok(()));
+    assert_eq!(
+        xmldsig::verify(&signed, &ec_public),
+        Err(SignatureError::KeyMismatch {
+            method: xmldsig::SignatureMethod::RsaSha256
+        })
+    );
+
+    let mut tampered = signed.clone();
+    let name_id = tampered
+        .get_mut_child("Subject")
+        .and_then(|e| e.get_mut_child("NameID"))
+        .unwrap();
+    name_id.children = vec![XMLNode::Text("mallory".to_owned())];
+    assert_eq!(
+        xmldsig::verify(&tampered, &rsa_public),
+        Err(SignatureError::DigestMismatch)
+    );
+
+    let mut moved = signed.clone();
+    moved.attributes.insert("ID".into(), "_other".to_owned());
+    assert_eq!(
+        xmldsig::verify(&moved, &rsa_public),
+        Err(SignatureError::InvalidReference("#_a1".to_owned()))
+    );
+    assert_eq!(
+        xmldsig::verify(&assertion, &rsa_public),
+        Err(SignatureError::MissingSignature)
+    );
+
+    // ECDSA, and inclusive canonicalization with comments, which are still not digested
+    let options = SignOptions::new().canonicalization(CanonicalOptions::new().with_comments(true));
+    let mut signed = assertion.clone();
+    signed
+        .children
+        .push(XMLNode::Comment(" not signed ".to_owned()));
+    xmldsig::sign_with_options(&mut signed, &ec, &options).unwrap();
+    let mut signed = reparse(&signed);
+    assert_eq!(xmldsig::verify(&signed, &ec_public), Ok(()));
+    signed.children.retain(|node| node.as_comment().is_none());
+    assert_eq!(xmldsig::verify(&signed, &ec_public), Ok(()));
+
+    // HMAC, with a prefix list for exclusive canonicalization
+    let c14n = CanonicalOptions::new()
+        .exclusive(true)
+        .inclusive_prefixes(vec!["saml"]);
+    let mut signed = assertion.clone();
+    xmldsig::sign_with_options(
+        &mut signed,
+        &SigningKey::hmac(b"secret"),
+        &SignOptions::new().canonicalization(c14n),
+    )
+    .unwrap();
+    let signed = reparse(&signed);
+    assert_eq!(
+        xmldsig::verify(&signed, &VerifyingKey::hmac(b"secret")),
+        Ok(())
+    );
+    assert_eq!(
+        xmldsig::verify(&signed, &VerifyingKey::hmac(b"guess")),
+        Err(SignatureError::InvalidSignature)
+    );
+
+    assert_eq!(VerifyingKey::hmac(b"secret"), VerifyingKey::hmac(b"secret"));
+    assert_ne!(VerifyingKey::hmac(b"secret"), VerifyingKey::hmac(b"secreT"));
+    assert_ne!(
+        VerifyingKey::hmac(b"secret"),
+        VerifyingKey::hmac(b"secrets")
+    );
+
+    // Secrets are kept out of debug output
+    assert_eq!(
+        format!("{:?}", VerifyingKey::hmac(b"secret")),
+        "VerifyingKey(HmacSha256)"
+    );
+    assert_eq!(
+        format!("{:?}", SigningKey::hmac(b"secret")),
+        "SigningKey(HmacSha256)"
+    );
+
+    // A `ds` prefix bound to another namespace is left alone
+    let data = r#"<doc xmlns:ds="urn:other" xmlns:ec="urn:also-other"><ds:a/></doc>"#;
+    let mut doc = Element::parse(data.as_bytes()).unwrap();
+    let c14n = CanonicalOptions::new()
+        .exclusive(true)
+        .inclusive_prefixes(vec!["ds"]);
+    let options = SignOptions::new().canonicalization(c14n);
+    xmldsig::sign_with_options(&mut doc, &rsa, &options).unwrap();
+    let doc = reparse(&doc);
+    assert!(doc.get_child(("a", "urn:other")).is_some());
+    let signature = doc.get_child(("Signature", xmldsig::NAMESPACE)).unwrap();
+    assert_eq!(signature.prefix.as_deref(), Some("ds1"));
+    assert_eq!(xmldsig::verify(&doc, &rsa_public), Ok(()));
+}
+
+/// Documents signed by xmlsec1, with the keys in `tests/data`
+#[cfg(feature = "xmldsig")]
+#[test]
+fn test_xmldsig_interop() {
+    use xmltree::xmldsig::{self, SignatureError, VerifyingKey};
+
+    fn parse(data: &str) -> Element {
+        let options = ParseOptions::new().preserve_whitespace(true);
+        Element::parse_with_options(data.as_bytes(), ParserConfig::new(), options).unwrap()
+    }
+
+    let rsa = VerifyingKey::from_public_key_pem(include_str!("data/dsig-rsa.pub.pem")).unwrap();
+    let ec = VerifyingKey::from_public_key_pem(include_str!("data/dsig-ec.pub.pem")).unwrap();
+    let hmac = VerifyingKey::hmac(b"interop secret");
+    let vectors = [
+        (include_str!("data/dsig-rsa-sha256.xml"), &rsa),
+        (include_str!("data/dsig-ecdsa-sha256.xml"), &ec),
+        (include_str!("data/dsig-hmac-sha256.xml"), &hmac),
+    ];
+    for (data, key) in vectors.iter() {
+        let mut elem = parse(data);
+        assert_eq!(xmldsig::verify(&elem, key), Ok(()));
+
+        elem.children.push(XMLNode::Element(Element::new("added")));
+        assert_eq!(
+            xmldsig::verify(&elem, key),
+            Err(SignatureError::DigestMismatch)
+        );
+    }
+    assert_eq!(
+        xmldsig::verify(&parse(vectors[2].0), &VerifyingKey::hmac(b"interop secreT")),
+        Err(SignatureError::InvalidSignature)
+    );
+}
@@ -0,0 +1,278 @@
+//! Derive macros for the `FromElement` and `IntoElement` traits of the `xmltree` crate.
+//!
+//! Don't depend on this crate directly, enable the `derive` feature of `xmltree` instead, which
+//! re-exports the macros next to the traits.  See the documentation of those traits for the
+//! `#[xml(...)]` attributes that are understood.
+
+extern crate proc_macro;
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, Data, DeriveInput, Fields, GenericArgument, LitStr, PathArguments, Type,
+};
+
+#[proc_macro_derive(FromElement, attributes(xml))]
+pub fn derive_from_element(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_from_element(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(IntoElement, attributes(xml))]
+pub fn derive_into_element(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_into_element(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Where the value of a field comes from
+enum Source {
+    Attribute(String),
+    Child(String),
+    Text,
+}
+
+/// How a field's type wraps the value it holds
+enum Shape {
+    Plain,
+    Option,
+    Vec,
+}
+
+struct Field<'a> {
+    ident: &'a syn::Ident,
+    source: Source,
+    namespace: Option<String>,
+    shape: Shape,
+}
+
+/// The `#[xml(...)]` attributes of the struct itself
+struct Container {
+    name: String,
+    namespace: Option<String>,
+}
+
+fn parse_container(input: &DeriveInput) -> syn::Result<Container> {
+    let mut container = Container {
+        name: input.ident.to_string(),
+        namespace: None,
+    };
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("xml")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                container.name = meta.value()?.parse::<LitStr>()?.value();
+            } else if meta.path.is_ident("namespace") {
+                container.namespace = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else {
+                return Err(meta.error("expected `name` or `namespace`"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(container)
+}
+
+fn parse_fields(input: &DeriveInput) -> syn::Result<Vec<Field<'_>>> {
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new(
+                    input.ident.span(),
+                    "only structs with named fields are supported",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                input.ident.span(),
+                "only structs with named fields are supported",
+            ))
+        }
+    };
+
+    let mut result = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().expect("named field");
+        let mut source = None;
+        let mut namespace = None;
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("xml")) {
+            attr.parse_nested_meta(|meta| {
+                let new_source = if meta.path.is_ident("attr") {
+                    Source::Attribute(meta.value()?.parse::<LitStr>()?.value())
+                } else if meta.path.is_ident("child") {
+                    Source::Child(meta.value()?.parse::<LitStr>()?.value())
+                } else if meta.path.is_ident("text") {
+                    Source::Text
+                } else if meta.path.is_ident("namespace") {
+                    namespace = Some(meta.value()?.parse::<LitStr>()?.value());
+                    return Ok(());
+                } else {
+                    return Err(meta.error("expected `attr`, `child`, `text` or `namespace`"));
+                };
+                if source.is_some() {
+                    return Err(meta.error("only one of `attr`, `child` and `text` can be used"));
+                }
+                source = Some(new_source);
+                Ok(())
+            })?;
+        }
+        let source = source.unwrap_or_else(|| Source::Child(ident.to_string()));
+        let shape = shape_of(&field.ty);
+        match (&source, &shape) {
+            (Source::Attribute(_), Shape::Vec) | (Source::Text, Shape::Vec) => {
+                return Err(syn::Error::new(
+                    field.ty.span(),
+                    "a `Vec` can only be used with `child`",
+                ))
+            }
+            (Source::Text, _) if namespace.is_some() => {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    "`namespace` can't be used with `text`",
+                ))
+            }
+            _ => (),
+        }
+        result.push(Field {
+            ident,
+            source,
+            namespace,
+            shape,
+        });
+    }
+    Ok(result)
+}
+
+fn shape_of(ty: &Type) -> Shape {
+    let segment = match ty {
+        Type::Path(path) if path.qself.is_none() => match path.path.segments.last() {
+            Some(segment) => segment,
+            None => return Shape::Plain,
+        },
+        _ => return Shape::Plain,
+    };
+    let has_one_type_argument = match segment.arguments {
+        PathArguments::AngleBracketed(ref args) => {
+            args.args.len() == 1 && matches!(args.args[0], GenericArgument::Type(_))
+        }
+        _ => false,
+    };
+    if !has_one_type_argument {
+        Shape::Plain
+    } else if segment.ident == "Option" {
+        Shape::Option
+    } else if segment.ident == "Vec" {
+        Shape::Vec
+    } else {
+        Shape::Plain
+    }
+}
+
+fn namespace_tokens(namespace: &Option<String>) -> TokenStream {
+    match namespace {
+        Some(ns) => quote!(::std::option::Option::Some(#ns)),
+        None => quote!(::std::option::Option::None),
+    }
+}
+
+fn expand_from_element(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let fields = parse_fields(input)?;
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let values = fields.iter().map(|field| {
+        let name = field.ident;
+        let ns = namespace_tokens(&field.namespace);
+        let value = match (&field.source, &field.shape) {
+            (Source::Attribute(attr), Shape::Option) => {
+                quote!(__derive::optional_attribute(elem, #attr, #ns)?)
+            }
+            (Source::Attribute(attr), _) => quote!(__derive::attribute(elem, #attr, #ns)?),
+            (Source::Child(child), Shape::Option) => {
+                quote!(__derive::optional_child(elem, #child, #ns)?)
+            }
+            (Source::Child(child), Shape::Vec) => quote!(__derive::children(elem, #child, #ns)?),
+            (Source::Child(child), Shape::Plain) => quote!(__derive::child(elem, #child, #ns)?),
+            (Source::Text, Shape::Option) => quote!(__derive::optional_text(elem)?),
+            (Source::Text, _) => quote!(__derive::text(elem)?),
+        };
+        quote!(#name: #value)
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::xmltree::FromElement for #ident #ty_generics #where_clause {
+            fn from_element(
+                elem: &::xmltree::Element,
+            ) -> ::std::result::Result<Self, ::xmltree::FromElementError> {
+                use ::xmltree::__derive;
+                ::std::result::Result::Ok(#ident {
+                    #(#values,)*
+                })
+            }
+        }
+    })
+}
+
+fn expand_into_element(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let container = parse_container(input)?;
+    let fields = parse_fields(input)?;
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let element_name = &container.name;
+    let set_namespace = container
+        .namespace
+        .as_ref()
+        .map(|ns| quote!(__derive::set_namespace(&mut elem, #ns);));
+
+    let statements = fields.iter().map(|field| {
+        let name = field.ident;
+        let ns = namespace_tokens(&field.namespace);
+        match (&field.source, &field.shape) {
+            (Source::Attribute(attr), Shape::Option) => quote! {
+                if let ::std::option::Option::Some(ref value) = self.#name {
+                    __derive::set_attribute(&mut elem, #attr, #ns, value);
+                }
+            },
+            (Source::Attribute(attr), _) => {
+                quote!(__derive::set_attribute(&mut elem, #attr, #ns, &self.#name);)
+            }
+            (Source::Child(child), Shape::Option) => quote! {
+                if let ::std::option::Option::Some(value) = self.#name {
+                    __derive::push_child(&mut elem, #child, #ns, value);
+                }
+            },
+            (Source::Child(child), Shape::Vec) => quote! {
+                for value in self.#name {
+                    __derive::push_child(&mut elem, #child, #ns, value);
+                }
+            },
+            (Source::Child(child), Shape::Plain) => {
+                quote!(__derive::push_child(&mut elem, #child, #ns, self.#name);)
+            }
+            (Source::Text, Shape::Option) => quote! {
+                if let ::std::option::Option::Some(ref value) = self.#name {
+                    __derive::push_text(&mut elem, value);
+                }
+            },
+            (Source::Text, _) => quote!(__derive::push_text(&mut elem, &self.#name);),
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::xmltree::IntoElement for #ident #ty_generics #where_clause {
+            fn into_element(self) -> ::xmltree::Element {
+                use ::xmltree::__derive;
+                let mut elem = ::xmltree::Element::new(#element_name);
+                #set_namespace
+                #(#statements)*
+                elem
+            }
+        }
+    })
+}